@@ -0,0 +1,26 @@
+// Times every backend on the sample logs at 1, 2 and 4 threads, with `cargo bench`. It only uses
+// std::time, so the numbers are the best of a few runs rather than a statistical estimate; for a
+// table on your own logs, see `logdict bench`.
+use std::time::Instant;
+use logram::{Backend, DictionaryBuilder, LogFormat};
+
+const RUNS: u32 = 5;
+
+fn main() {
+    let logs = [("data/HDFS_2k.log", LogFormat::HDFS), ("data/Linux_2k.log", LogFormat::Linux), ("data/HealthApp_2k.log", LogFormat::HealthApp)];
+    let mut results = vec![];
+    for (log, format) in &logs {
+        for backend in [Backend::SeparateMaps, Backend::ConcurrentMap, Backend::Buffered] {
+            for threads in [1, 2, 4] {
+                let builder = DictionaryBuilder::new().backend(backend).num_threads(Some(threads)).min_chunk_lines(1);
+                let best = (0..RUNS).map(|_| {
+                    let start = Instant::now();
+                    builder.parse_raw(log.to_string(), format);
+                    start.elapsed()
+                }).min().unwrap();
+                results.push(format!("{} {:?} x{}: {:.2} ms", log, backend, threads, best.as_secs_f64() * 1000.0));
+            }
+        }
+    }
+    println!("{}", results.join("\n"));
+}
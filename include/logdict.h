@@ -0,0 +1,42 @@
+/* The C interface to logram's dictionary builder (src/packages/ffi.rs). Build the library with
+ * `cargo build --release` and link against target/release/liblogram.so (or .dylib, or .dll). */
+#ifndef LOGDICT_H
+#define LOGDICT_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct LogdictResult LogdictResult;
+typedef struct LogdictEntries LogdictEntries;
+
+/* Builds the dictionaries of the log at path with `threads` workers (0 for one per CPU). format is a
+ * format name such as "hdfs" or a <Field> format string. NULL on failure; see logdict_last_error. */
+LogdictResult *logdict_build(const char *path, const char *format, uint32_t threads);
+
+/* Why the last failing call on this thread failed, or NULL. */
+const char *logdict_last_error(void);
+
+size_t logdict_token_count(const LogdictResult *result);
+
+/* The number of distinct n-grams of size n, 0 if there's no such dictionary. */
+size_t logdict_len(const LogdictResult *result, size_t n);
+
+/* A cursor over the n-grams of size n, most frequent first; NULL if there's no such dictionary. */
+LogdictEntries *logdict_entries(const LogdictResult *result, size_t n);
+
+/* Sets key (valid until the cursor is freed) and count to the next n-gram, or returns false at the end. */
+bool logdict_next(LogdictEntries *entries, const char **key, uint64_t *count);
+
+void logdict_entries_free(LogdictEntries *entries);
+void logdict_free(LogdictResult *result);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
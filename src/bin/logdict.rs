@@ -0,0 +1,327 @@
+use clap::{Parser, Subcommand};
+use std::error::Error;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use logram::LogFormat;
+use logram::packages::diff;
+use logram::packages::parser::{self, Backend, DictionaryBuilder, DictionaryResult, Order};
+use logram::packages::pretty::{print_table, TableStyle};
+use logram::packages::tokenizer::Normalization;
+
+/// Builds, merges, compares and inspects n-gram dictionaries of log files. Dictionaries are read and
+/// written as JSON when the file name ends in .json, and in logram's --save binary format otherwise.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Parse a log file, or every file in a directory, into dictionaries
+    Build {
+        /// The log file or directory, or - for stdin
+        input: PathBuf,
+
+        /// linux, openstack, spark, hdfs, hpc, proxifier, android, healthapp, apache, nginx, syslog3164, syslog5424, kubernetes, kubernetes-json, windows-event, zookeeper, kafka, cassandra, bgl, thunderbird or mac
+        #[arg(long)]
+        format: LogFormat,
+
+        /// Worker threads; defaults to the number of CPUs
+        #[arg(long)]
+        threads: Option<u32>,
+
+        /// Count into shared DashMaps instead of merging per-thread HashMaps
+        #[arg(long)]
+        concurrent: bool,
+
+        /// Extra n-gram sizes to build alongside the doubles and triples, e.g. 4,5
+        #[arg(long,value_delimiter=',')]
+        ngram_sizes: Vec<usize>,
+
+        /// Rewrite the censored tokens: a comma-separated list of lowercase, uuids, paths, hex and numbers, or all
+        #[arg(long)]
+        normalize: Option<Normalization>,
+
+        /// Memory-map the (uncompressed) input files instead of reading them into memory; needs the mmap feature
+        #[arg(long)]
+        mmap: bool,
+
+        #[arg(long)]
+        output: PathBuf,
+    },
+    /// Add up several dictionaries
+    Merge {
+        #[arg(required=true)]
+        inputs: Vec<PathBuf>,
+
+        #[arg(long)]
+        output: PathBuf,
+    },
+    /// List the n-grams that are new, gone, or counted differently in the second dictionary
+    Diff {
+        old: PathBuf,
+        new: PathBuf,
+
+        /// Only list the n-grams in both whose share of their dictionary grew or shrank by at least this factor
+        #[arg(long)]
+        min_ratio: Option<f64>,
+
+        /// Print the differences as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print summary numbers for a dictionary
+    Stats {
+        input: PathBuf,
+
+        /// Print everything, the token frequencies and count histograms included, as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print a dictionary
+    Print {
+        input: PathBuf,
+
+        /// Only print the N most frequent n-grams of each size, most frequent first
+        #[arg(long)]
+        top: Option<usize>,
+
+        /// Print tables, one n-gram a row, colored and cut to fit when on a terminal
+        #[arg(long)]
+        pretty: bool,
+    },
+    /// Write a dictionary out as CSV files, one per n-gram size plus tokens.csv
+    Csv {
+        input: PathBuf,
+        dir: PathBuf,
+    },
+    /// Time every backend on one log file at 1, 2, 4... threads and print a table of throughput and peak memory
+    Bench {
+        input: PathBuf,
+
+        #[arg(long)]
+        format: LogFormat,
+
+        /// The most threads to try; defaults to the number of CPUs
+        #[arg(long)]
+        max_threads: Option<u32>,
+
+        /// Runs per backend and thread count; the fastest counts
+        #[arg(long, default_value = "3")]
+        runs: u32,
+
+        /// Print CSV instead of a Markdown table
+        #[arg(long)]
+        csv: bool,
+    },
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "json")
+}
+
+fn load(path: &Path) -> Result<DictionaryResult, Box<dyn Error>> {
+    if is_json(path) {
+        Ok(serde_json::from_reader(io::BufReader::new(File::open(path)?))?)
+    } else {
+        Ok(DictionaryResult::load(path)?)
+    }
+}
+
+fn save(result: &DictionaryResult, path: &Path) -> Result<(), Box<dyn Error>> {
+    if is_json(path) {
+        serde_json::to_writer(io::BufWriter::new(File::create(path)?), result)?;
+    } else {
+        result.save(path)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_save_load_json() {
+    let result = DictionaryBuilder::new().parse_raw_conc("data/from_paper.log".to_string(), &LogFormat::Linux);
+    let path = std::env::temp_dir().join(format!("logdict-test-{}.json", std::process::id()));
+    save(&result, &path).unwrap();
+    let loaded = load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded, result);
+}
+
+fn build(input: &Path, format: &LogFormat, threads: Option<u32>, concurrent: bool, ngram_sizes: &[usize], normalization: Normalization, mmap: bool) -> Result<DictionaryResult, Box<dyn Error>> {
+    let backend = if concurrent { Backend::ConcurrentMap } else { Backend::SeparateMaps };
+    let builder = DictionaryBuilder::new()
+        .num_threads(threads)
+        .ngram_sizes(&[&[2, 3], ngram_sizes].concat())
+        .backend(backend)
+        .normalize(normalization)
+        .mmap(mmap);
+    let raw_fn = input.to_string_lossy().to_string();
+    Ok(if input.is_dir() {
+        builder.parse_raw_dir_checked(input, None, format)?
+    } else if concurrent {
+        builder.parse_raw_conc_checked(raw_fn, format)?
+    } else {
+        builder.parse_raw_single_checked(raw_fn, format)?
+    })
+}
+
+fn diff(old: &DictionaryResult, new: &DictionaryResult, min_ratio: Option<f64>, json: bool) -> Result<(), Box<dyn Error>> {
+    let mut d = diff::diff(old, new);
+    if let Some(min_ratio) = min_ratio {
+        for size in d.sizes.values_mut() {
+            size.changed = size.large_changes(min_ratio).cloned().collect();
+        }
+    }
+    if json {
+        serde_json::to_writer(io::stdout().lock(), &d)?;
+        println!();
+        return Ok(());
+    }
+    for (n, size) in &d.sizes {
+        println!("--- {}", parser::ngram_name(*n));
+        for (key, count) in &size.added {
+            println!("+ {} {}", key, count);
+        }
+        for (key, count) in &size.removed {
+            println!("- {} {}", key, count);
+        }
+        for change in &size.changed {
+            println!("~ {} {} -> {} (x{:.2})", change.ngram, change.before, change.after, change.ratio);
+        }
+    }
+    Ok(())
+}
+
+fn stats(result: &DictionaryResult, json: bool) -> Result<(), Box<dyn Error>> {
+    let stats = result.stats("^");
+    if json {
+        serde_json::to_writer(io::stdout().lock(), &stats)?;
+        println!();
+        return Ok(());
+    }
+    for (n, dict) in &result.ngram_dicts {
+        let total: u64 = dict.values().sum();
+        print!("{}: {} distinct, {} in all", parser::ngram_name(*n), dict.len(), total);
+        match result.most_frequent(*n).first() {
+            Some((key, count)) => println!(", most frequent {} ({})", key, count),
+            None => println!(),
+        }
+    }
+    println!("tokens: {}", result.all_token_list.len());
+    println!("Zipf exponent: {:.3}", stats.zipf_exponent);
+    println!("double entropy: {:.3} bits", stats.double_entropy);
+    Ok(())
+}
+
+fn print(result: &DictionaryResult, top: Option<usize>, pretty: Option<TableStyle>) {
+    for (n, dict) in &result.ngram_dicts {
+        let name = parser::ngram_name(*n);
+        match (top, pretty) {
+            (Some(top), Some(style)) => print_table(&name, dict, Order::Descending, Some(top), style),
+            (Some(top), None) => {
+                let entries = result.most_frequent(*n);
+                parser::print_most_frequent(&name, &entries[..top.min(entries.len())]);
+            },
+            (None, Some(style)) => print_table(&name, dict, Order::Ascending, None, style),
+            (None, None) => parser::print_dict(&name, dict, Order::Ascending, None),
+        }
+    }
+}
+
+// the thread counts to try: the powers of two below max, then max itself
+fn thread_counts(max: u32) -> Vec<u32> {
+    let mut counts: Vec<u32> = (0..).map(|i| 1 << i).take_while(|n| *n < max).collect();
+    counts.push(max.max(1));
+    counts
+}
+
+#[test]
+fn test_thread_counts() {
+    assert_eq!(thread_counts(1), [1]);
+    assert_eq!(thread_counts(6), [1, 2, 4, 6]);
+    assert_eq!(thread_counts(8), [1, 2, 4, 8]);
+}
+
+// peak RSS in KiB since the last reset_peak_rss, where /proc tells (Linux)
+fn peak_rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmHWM:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
+
+fn reset_peak_rss() {
+    // writing 5 to clear_refs resets VmHWM to the current RSS; nothing to do where it doesn't exist
+    let _ = std::fs::write("/proc/self/clear_refs", "5");
+}
+
+fn bench(input: &Path, format: &LogFormat, max_threads: Option<u32>, runs: u32, csv: bool) -> Result<(), Box<dyn Error>> {
+    let raw_fn = input.to_string_lossy().to_string();
+    // also warms the page cache, so the first backend isn't charged for the disk
+    let (_, parse_stats) = DictionaryBuilder::new().parse_raw_stats_checked(raw_fn.clone(), format)?;
+    let max_threads = max_threads.unwrap_or_else(|| std::thread::available_parallelism().map_or(8, |n| n.get() as u32));
+    let rows = [("sequential", Backend::SeparateMaps, vec![1])].into_iter()
+        .chain([("hashmap-merge", Backend::SeparateMaps), ("dashmap", Backend::ConcurrentMap), ("buffered", Backend::Buffered),
+               ("count-min", Backend::CountMin), ("external", Backend::External)]
+            .map(|(name, backend)| (name, backend, thread_counts(max_threads))));
+    // the table comes once every run is done, after what the runs print themselves
+    let mut table = vec![];
+    if csv {
+        table.push("backend,threads,seconds,lines_per_second,peak_rss_kib".to_string());
+    } else {
+        table.push("| backend | threads | seconds | lines/s | peak RSS (MiB) |".to_string());
+        table.push("|---|---:|---:|---:|---:|".to_string());
+    }
+    for (name, backend, threads) in rows {
+        for threads in threads {
+            // one thread in one chunk is the sequential run, however big the file
+            let builder = DictionaryBuilder::new().backend(backend).num_threads(Some(threads)).min_chunk_lines(if threads == 1 { usize::MAX } else { 1000 });
+            let mut best = f64::INFINITY;
+            reset_peak_rss();
+            for _ in 0..runs.max(1) {
+                let start = std::time::Instant::now();
+                builder.parse_raw_checked(raw_fn.clone(), format)?;
+                best = best.min(start.elapsed().as_secs_f64());
+            }
+            let throughput = parse_stats.total as f64 / best;
+            let peak = peak_rss();
+            table.push(if csv {
+                format!("{},{},{:.3},{:.0},{}", name, threads, best, throughput, peak.map_or(String::new(), |kib| kib.to_string()))
+            } else {
+                format!("| {} | {} | {:.3} | {:.0} | {} |", name, threads, best, throughput, peak.map_or("-".to_string(), |kib| format!("{:.1}", kib as f64 / 1024.0)))
+            });
+        }
+    }
+    println!("{}", table.join("\n"));
+    Ok(())
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    match cli.command {
+        Command::Build { input, format, threads, concurrent, ngram_sizes, normalize, mmap, output } => {
+            save(&build(&input, &format, threads, concurrent, &ngram_sizes, normalize.unwrap_or_default(), mmap)?, &output)?;
+        },
+        Command::Merge { inputs, output } => {
+            let mut merged = DictionaryResult::default();
+            for input in inputs {
+                merged.merge(load(&input)?);
+            }
+            save(&merged, &output)?;
+        },
+        Command::Diff { old, new, min_ratio, json } => diff(&load(&old)?, &load(&new)?, min_ratio, json)?,
+        Command::Stats { input, json } => stats(&load(&input)?, json)?,
+        Command::Print { input, top, pretty } => print(&load(&input)?, top, pretty.then(TableStyle::detect)),
+        Command::Csv { input, dir } => load(&input)?.write_csv(dir)?,
+        Command::Bench { input, format, max_threads, runs, csv } => bench(&input, &format, max_threads, runs, csv)?,
+    }
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run(Cli::parse()) {
+        eprintln!("logdict: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,163 @@
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+pub mod packages;
+
+// What most callers need, without knowing which module it lives in: a builder, its result and
+// errors, the formats and tokenizers it parses with, and the one-line parsers. The rest stays under
+// `packages`.
+pub use packages::error::ParserError;
+pub use packages::parser::{
+    format_string, parse_line, parse_line_checked, validate_format, validate_format_file, Backend, ContextWindow, DictionaryBuilder,
+    DictionaryResult, FormatReport, LineParser, MemoryLimit, NGramDict, ParseReport, ParseStats, ParsedLine, Progress, Sample, SketchSize,
+    TokenIdResult,
+};
+pub use packages::tokenizer::{Censor, GroupBy, Normalization, Normalize, RegexTokenizer, Tokenizer};
+
+#[derive(Clone, Debug)]
+pub enum LogFormat {
+    Linux,
+    OpenStack,
+    Spark,
+    HDFS,
+    HPC,
+    Proxifier,
+    Android,
+    HealthApp,
+    Apache,
+    Nginx,
+    Syslog3164,
+    Syslog5424,
+    /// Container logs as the kubelet leaves them: CRI-formatted lines, or with `json` the records of
+    /// Docker's json-file driver.
+    Kubernetes { json: bool },
+    WindowsEvent,
+    Zookeeper,
+    Kafka,
+    Cassandra,
+    BGL,
+    Thunderbird,
+    Mac,
+    /// A `<Field>`-style format string (which must have a `<Content>` field) plus the regexes to
+    /// censor in the content, for logs that don't fit any of the built-in formats.
+    Custom { format: String, censor: Vec<Regex> },
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    /// Parses the lowercase name of a built-in format, e.g. "linux" or "syslog5424".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linux" => Ok(LogFormat::Linux),
+            "openstack" => Ok(LogFormat::OpenStack),
+            "spark" => Ok(LogFormat::Spark),
+            "hdfs" => Ok(LogFormat::HDFS),
+            "hpc" => Ok(LogFormat::HPC),
+            "proxifier" => Ok(LogFormat::Proxifier),
+            "android" => Ok(LogFormat::Android),
+            "healthapp" => Ok(LogFormat::HealthApp),
+            "apache" => Ok(LogFormat::Apache),
+            "nginx" => Ok(LogFormat::Nginx),
+            "syslog3164" => Ok(LogFormat::Syslog3164),
+            "syslog5424" => Ok(LogFormat::Syslog5424),
+            "kubernetes" => Ok(LogFormat::Kubernetes { json: false }),
+            "kubernetes-json" => Ok(LogFormat::Kubernetes { json: true }),
+            "windows-event" => Ok(LogFormat::WindowsEvent),
+            "zookeeper" => Ok(LogFormat::Zookeeper),
+            "kafka" => Ok(LogFormat::Kafka),
+            "cassandra" => Ok(LogFormat::Cassandra),
+            "bgl" => Ok(LogFormat::BGL),
+            "thunderbird" => Ok(LogFormat::Thunderbird),
+            "mac" => Ok(LogFormat::Mac),
+            _ => Err(format!("unknown log format {}", s)),
+        }
+    }
+}
+
+impl LogFormat {
+    /// The name `from_str` takes for a built-in format; `None` for `Custom`.
+    pub fn name(&self) -> Option<&'static str> {
+        Some(match self {
+            LogFormat::Linux => "linux",
+            LogFormat::OpenStack => "openstack",
+            LogFormat::Spark => "spark",
+            LogFormat::HDFS => "hdfs",
+            LogFormat::HPC => "hpc",
+            LogFormat::Proxifier => "proxifier",
+            LogFormat::Android => "android",
+            LogFormat::HealthApp => "healthapp",
+            LogFormat::Apache => "apache",
+            LogFormat::Nginx => "nginx",
+            LogFormat::Syslog3164 => "syslog3164",
+            LogFormat::Syslog5424 => "syslog5424",
+            LogFormat::Kubernetes { json: false } => "kubernetes",
+            LogFormat::Kubernetes { json: true } => "kubernetes-json",
+            LogFormat::WindowsEvent => "windows-event",
+            LogFormat::Zookeeper => "zookeeper",
+            LogFormat::Kafka => "kafka",
+            LogFormat::Cassandra => "cassandra",
+            LogFormat::BGL => "bgl",
+            LogFormat::Thunderbird => "thunderbird",
+            LogFormat::Mac => "mac",
+            LogFormat::Custom { .. } => return None,
+        })
+    }
+}
+
+// how a LogFormat serializes: a built-in one by name, a custom one with its censor regexes as patterns
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum LogFormatRepr {
+    Name(String),
+    Custom { format: String, censor: Vec<String> },
+}
+
+impl Serialize for LogFormat {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let repr = match self {
+            LogFormat::Custom { format, censor } => LogFormatRepr::Custom {
+                format: format.clone(),
+                censor: censor.iter().map(|re| re.as_str().to_string()).collect(),
+            },
+            _ => LogFormatRepr::Name(self.name().unwrap().to_string()),
+        };
+        repr.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for LogFormat {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match LogFormatRepr::deserialize(deserializer)? {
+            LogFormatRepr::Name(name) => name.parse().map_err(serde::de::Error::custom),
+            LogFormatRepr::Custom { format, censor } => {
+                let censor = censor.iter().map(|re| Regex::new(re)).collect::<Result<_, _>>().map_err(serde::de::Error::custom)?;
+                Ok(LogFormat::Custom { format, censor })
+            },
+        }
+    }
+}
+
+#[test]
+fn test_log_format_from_str() {
+    assert!(matches!("hdfs".parse(), Ok(LogFormat::HDFS)));
+    assert!(matches!("syslog5424".parse(), Ok(LogFormat::Syslog5424)));
+    assert!("HDFS".parse::<LogFormat>().is_err());
+}
+
+#[test]
+fn test_log_format_serde() {
+    for name in ["linux", "kubernetes-json", "windows-event", "mac"] {
+        let lf: LogFormat = name.parse().unwrap();
+        assert_eq!(lf.name(), Some(name));
+        let json = serde_json::to_string(&lf).unwrap();
+        assert_eq!(json, format!("\"{}\"", name));
+        assert_eq!(serde_json::from_str::<LogFormat>(&json).unwrap().name(), Some(name));
+    }
+    let custom = LogFormat::Custom { format: "<Level> <Content>".to_string(), censor: vec![Regex::new(r"\d+").unwrap()] };
+    let json = serde_json::to_string(&custom).unwrap();
+    assert_eq!(json, r#"{"format":"<Level> <Content>","censor":["\\d+"]}"#);
+    let LogFormat::Custom { format, censor } = serde_json::from_str(&json).unwrap() else { panic!("not custom") };
+    assert_eq!((format.as_str(), censor[0].as_str()), ("<Level> <Content>", r"\d+"));
+    assert!(serde_json::from_str::<LogFormat>("\"klingon\"").is_err());
+}
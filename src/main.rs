@@ -1,16 +1,35 @@
 use clap::Parser;
+use regex::Regex;
 use std::collections::HashMap;
 use std::collections::HashSet;
-use crate::LogFormat::Linux;
-use crate::LogFormat::OpenStack;
-use crate::LogFormat::Spark;
-use crate::LogFormat::HDFS;
-use crate::LogFormat::HPC;
-use crate::LogFormat::Proxifier;
-use crate::LogFormat::Android;
-use crate::LogFormat::HealthApp;
-
-mod packages;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use logram::LogFormat;
+use logram::packages;
+use logram::packages::pretty::{print_table, write_table, TableStyle};
+use logram::packages::template::TemplateMiner;
+use logram::packages::time::{parse_time, Locale, SessionGap, TimeRange};
+use logram::packages::tokenizer::{Censor, GroupBy, Normalization, Normalize, RegexTokenizer, Tokenizer};
+use logram::LogFormat::Linux;
+use logram::LogFormat::OpenStack;
+use logram::LogFormat::Spark;
+use logram::LogFormat::HDFS;
+use logram::LogFormat::HPC;
+use logram::LogFormat::Proxifier;
+use logram::LogFormat::Android;
+use logram::LogFormat::HealthApp;
+use logram::LogFormat::Apache;
+use logram::LogFormat::Nginx;
+use logram::LogFormat::Syslog3164;
+use logram::LogFormat::Syslog5424;
+use logram::LogFormat::Kubernetes;
+use logram::LogFormat::WindowsEvent;
+use logram::LogFormat::Zookeeper;
+use logram::LogFormat::Kafka;
+use logram::LogFormat::Cassandra;
+use logram::LogFormat::BGL;
+use logram::LogFormat::Thunderbird;
+use logram::LogFormat::Mac;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -40,6 +59,178 @@ struct Args {
    #[arg(long)]
    raw_healthapp: Option<String>,
 
+   /// Apache error log
+   #[arg(long)]
+   raw_apache: Option<String>,
+
+   /// Nginx access log, in the default "combined" format
+   #[arg(long)]
+   raw_nginx: Option<String>,
+
+   /// BSD syslog (RFC 3164), with or without the <PRI>, e.g. /var/log/syslog
+   #[arg(long)]
+   raw_syslog3164: Option<String>,
+
+   /// IETF syslog (RFC 5424)
+   #[arg(long)]
+   raw_syslog5424: Option<String>,
+
+   /// Container log, by default in the CRI format (containerd, CRI-O)
+   #[arg(long)]
+   raw_kubernetes: Option<String>,
+
+   /// With --raw-kubernetes: the lines are JSON records from Docker's json-file driver
+   #[arg(long)]
+   docker_json: bool,
+
+   /// Windows Event Viewer text export (tab or comma separated)
+   #[arg(long)]
+   raw_windows_event: Option<String>,
+
+   #[arg(long)]
+   raw_zookeeper: Option<String>,
+
+   /// Kafka broker server.log
+   #[arg(long)]
+   raw_kafka: Option<String>,
+
+   /// Cassandra system.log
+   #[arg(long)]
+   raw_cassandra: Option<String>,
+
+   #[arg(long)]
+   raw_bgl: Option<String>,
+
+   #[arg(long)]
+   raw_thunderbird: Option<String>,
+
+   #[arg(long)]
+   raw_mac: Option<String>,
+
+   /// Raw logfile in the format given by --format
+   #[arg(long,requires="format")]
+   raw_custom: Option<String>,
+
+   /// Format string for --raw-custom, e.g. "<Date> <Time> <Level> <Component>: <Content>"
+   #[arg(long)]
+   format: Option<String>,
+
+   /// Only count every Nth line, for a quick approximate dictionary
+   #[arg(long, value_name = "N", conflicts_with = "sample_rate")]
+   sample_every: Option<usize>,
+
+   /// Only count each line with probability P (e.g. 0.01), picked with --seed
+   #[arg(long, value_name = "P")]
+   sample_rate: Option<f64>,
+
+   #[arg(long, default_value = "0")]
+   seed: u64,
+
+   /// Leave out the first N lines of the input
+   #[arg(long, value_name = "N", default_value = "0")]
+   skip_lines: usize,
+
+   /// Stop after N lines (once --skip-lines are skipped), without reading the rest of the input
+   #[arg(long, value_name = "N")]
+   max_lines: Option<usize>,
+
+   /// Only parse the lines that start at or after this byte of each input file
+   #[arg(long, value_name = "BYTES", default_value = "0", conflicts_with = "offset")]
+   start_offset: u64,
+
+   /// Only parse the lines that start before this byte of each input file
+   #[arg(long, value_name = "BYTES", conflicts_with = "offset")]
+   end_offset: Option<u64>,
+
+   /// Take the raw input as the current file of a rotated log (app.log, with app.log.1, app.log.2.gz... next to it) and parse them all as one, oldest first
+   #[arg(long)]
+   rotated: bool,
+
+   /// When the raw input is a directory, only parse the files whose names match this glob, e.g. "*.log"
+   #[arg(long)]
+   glob: Option<String>,
+
+   /// Regex to censor in the content of --raw-custom lines (repeatable)
+   #[arg(long)]
+   censor: Vec<String>,
+
+   /// Write the dictionaries to this file once they're built
+   #[arg(long)]
+   save: Option<String>,
+
+   /// Also write the dictionaries to this directory as CSV files (double.csv, triple.csv, tokens.csv...)
+   #[arg(long)]
+   csv: Option<String>,
+
+   /// Also write the dictionaries to this Parquet file, a row of n, ngram and count per n-gram
+   #[cfg(feature = "arrow")]
+   #[arg(long)]
+   parquet: Option<String>,
+
+   /// Also write the dictionaries to this SQLite database, one table per n-gram size plus tokens
+   #[cfg(feature = "sqlite")]
+   #[arg(long)]
+   sqlite: Option<String>,
+
+   /// Also write the dictionaries to this DuckDB database, one table per n-gram size
+   #[cfg(feature = "duckdb")]
+   #[arg(long)]
+   duckdb: Option<String>,
+
+   /// Also insert the n-gram counts into ClickHouse at this DSN, http[s]://[user[:password]@]host[:port][/database]
+   #[cfg(feature = "clickhouse")]
+   #[arg(long)]
+   clickhouse: Option<String>,
+
+   /// Put this in front of the ClickHouse table names
+   #[cfg(feature = "clickhouse")]
+   #[arg(long, default_value = "")]
+   clickhouse_table_prefix: String,
+
+   /// Rows per ClickHouse insert
+   #[cfg(feature = "clickhouse")]
+   #[arg(long, default_value_t = 100_000)]
+   clickhouse_batch_rows: usize,
+
+   /// How many times to retry a ClickHouse request that failed on the network or the server
+   #[cfg(feature = "clickhouse")]
+   #[arg(long, default_value_t = 3)]
+   clickhouse_retries: u32,
+
+   /// Also index the n-grams into Elasticsearch or OpenSearch at this URL, http[s]://[user[:password]@]host[:port]
+   #[cfg(feature = "elasticsearch")]
+   #[arg(long)]
+   elasticsearch: Option<String>,
+
+   /// The Elasticsearch index for the n-grams
+   #[cfg(feature = "elasticsearch")]
+   #[arg(long, default_value = "logram-ngrams")]
+   elasticsearch_index: String,
+
+   /// Also index a record of every input line (as --records writes) into this Elasticsearch index
+   #[cfg(feature = "elasticsearch")]
+   #[arg(long, value_name = "INDEX", requires = "elasticsearch")]
+   elasticsearch_records: Option<String>,
+
+   /// Documents per Elasticsearch bulk request
+   #[cfg(feature = "elasticsearch")]
+   #[arg(long, default_value_t = 5000)]
+   elasticsearch_bulk_docs: usize,
+
+   /// How many times to retry an Elasticsearch bulk request that failed on the network or the cluster
+   #[cfg(feature = "elasticsearch")]
+   #[arg(long, default_value_t = 3)]
+   elasticsearch_retries: u32,
+
+   /// Read the dictionaries written by --save instead of parsing (unless --offset is given); the --raw-* flag then only picks the format
+   #[arg(long)]
+   load: Option<String>,
+
+   /// Only parse the raw file from this byte offset on, adding to the --load'ed dictionaries if any,
+   /// and print the offset to pass next time
+   #[arg(long)]
+   offset: Option<u64>,
+
    #[arg(long)]
    to_parse: String,
 
@@ -56,43 +247,339 @@ struct Args {
    after_line: Option<String>,
 
    #[arg(long,default_value="3")]
-   cutoff: Option<i32>,
+   cutoff: Option<u64>,
 
    #[arg(long,require_equals=true,num_args=0..=1,default_missing_value_os="true")]
    single_map: Option<bool>,
 
-   #[arg(long,default_value="8")]
+   /// Count into per-thread maps that are flushed into the shared ones every so often
+   #[arg(long, conflicts_with = "single_map")]
+   buffered: bool,
+
+   /// Estimate the counts with count-min sketches, in bounded memory, and only keep the most frequent n-grams
+   #[arg(long, conflicts_with_all = ["single_map", "buffered"])]
+   count_min: bool,
+
+   /// Count exactly, spilling each thread's counts to sorted runs on disk as they grow, then merging the runs
+   #[arg(long, conflicts_with_all = ["single_map", "buffered", "count_min"])]
+   external: bool,
+
+   /// Roughly how many bytes the n-gram counts may take while counting; past it the run fails, or with --external spills
+   #[arg(long, value_name = "BYTES")]
+   max_memory_bytes: Option<usize>,
+
+   /// Past --max-memory-bytes, drop the least frequent n-grams so far instead of failing
+   #[arg(long, requires = "max_memory_bytes")]
+   prune_on_memory_limit: bool,
+
+   /// Where --external writes its runs; defaults to the system's temporary directory
+   #[arg(long)]
+   spill_dir: Option<PathBuf>,
+
+   /// How many distinct n-grams each --external thread holds before it writes them out
+   #[arg(long, default_value = "1000000")]
+   spill_entries: usize,
+
+   /// Counters per row of each --count-min sketch (8 bytes each)
+   #[arg(long, default_value = "1048576")]
+   sketch_width: usize,
+
+   /// Rows of each --count-min sketch
+   #[arg(long, default_value = "4")]
+   sketch_depth: usize,
+
+   /// How many of the most frequent n-grams of each size --count-min keeps
+   #[arg(long, default_value = "10000")]
+   heavy_hitters: usize,
+
+   /// Worker threads; defaults to the number of CPUs
+   #[arg(long)]
    num_threads: Option<u32>,
+
+   /// How many shards to split each concurrent map into, a power of two; defaults to 4 per CPU
+   #[arg(long)]
+   shards: Option<usize>,
+
+   /// About how many distinct n-grams of each size to expect, to size the maps up front
+   #[arg(long)]
+   estimated_ngrams: Option<usize>,
+
+   /// Don't split the input into chunks of fewer lines than this (files this short get one worker)
+   #[arg(long,default_value="1000")]
+   min_chunk_lines: usize,
+
+   /// Split each input into this many chunks per thread, for the threads to take as they finish
+   #[arg(long, default_value = "1")]
+   chunks_per_thread: u32,
+
+   /// Count each distinct line once, times how often it comes up, leaving out the n-grams across lines
+   #[arg(long)]
+   dedupe_lines: bool,
+
+   /// Count the input as it's read, this many lines at a time, instead of reading all of it first
+   #[arg(long)]
+   pipeline: Option<usize>,
+
+   /// How many tokens to borrow from the neighbouring lines on each side
+   #[arg(long,default_value="2")]
+   context_tokens: usize,
+
+   /// How many neighbouring lines those tokens may come from
+   #[arg(long,default_value="1")]
+   context_lines: usize,
+
+   /// Don't stitch tokens across line boundaries at all
+   #[arg(long)]
+   independent_lines: bool,
+
+   /// What the tokens of an n-gram key are joined with (a \ or the separator's first character inside a token is escaped with a \)
+   #[arg(long,default_value="^")]
+   separator: String,
+
+   /// Drop the n-grams seen fewer times than this from the dictionaries
+   #[arg(long,default_value="1")]
+   min_count: u64,
+
+   /// Only keep the K most frequent n-grams of each size, and print them most frequent first
+   #[arg(long)]
+   top_k: Option<usize>,
+
+   /// Print the dictionaries highest count first
+   #[arg(long)]
+   descending: bool,
+
+   /// Print the dictionaries as tables, one n-gram a row, colored and cut to fit when on a terminal
+   #[arg(long)]
+   pretty: bool,
+
+   /// Extra n-gram sizes to build alongside the doubles and triples, e.g. 4,5
+   #[arg(long,value_delimiter=',')]
+   ngram_sizes: Vec<usize>,
+
+   /// Also count the pairs of tokens on a line with up to this many tokens between them
+   #[arg(long,default_value="0")]
+   skip_grams: usize,
+
+   /// Rewrite the censored tokens: a comma-separated list of lowercase, uuids, paths, hex and numbers, or all
+   #[arg(long)]
+   normalize: Option<Normalization>,
+
+   /// Memory-map the (uncompressed) raw files instead of reading them into memory; needs the mmap feature
+   #[arg(long)]
+   mmap: bool,
+
+   /// Build separate dictionaries for each <Component> of the log and use the one of the --to-parse line
+   #[arg(long)]
+   by_component: bool,
+
+   /// Print the log templates of the input (most frequent first), with how many lines have each
+   #[arg(long)]
+   templates: bool,
+
+   /// Write the event (template) of every input line to this CSV file, LogPAI style
+   #[arg(long)]
+   events: Option<String>,
+
+   /// Write every token, labelled static or dynamic by how common the n-grams around it are (see --cutoff), to this CSV file
+   #[arg(long)]
+   token_classes: Option<String>,
+
+   /// Write a record of every input line (its fields, censored content and tokens) to this file: CSV if it ends in .csv, JSON lines otherwise
+   #[arg(long)]
+   records: Option<String>,
+
+   /// Print how unusual the --to-parse line is next to the input, from 0 (common) to 1 (never seen)
+   #[arg(long)]
+   score: bool,
+
+   /// Keep watching the input for new lines, printing those that score at least --alert-score
+   #[arg(long)]
+   follow: bool,
+
+   /// With --follow, serve Prometheus metrics (lines parsed, unmatched lines, distinct n-grams and tokens) at http://ADDR/metrics
+   #[cfg(feature = "prometheus")]
+   #[arg(long, value_name = "ADDR")]
+   metrics_addr: Option<String>,
+
+   /// Build from the input, then serve HTTP on ADDR: POST lines to /ingest, GET /dict and /score?line=...
+   #[cfg(feature = "server")]
+   #[arg(long, value_name = "ADDR")]
+   serve: Option<String>,
+
+   /// Read the input (e.g. - for stdin, piped from a Kafka consumer) as a stream, counting it a batch at a time and printing the lines that score at least --alert-score
+   #[arg(long)]
+   stream: bool,
+
+   /// Build from the input, then receive syslog messages on this UDP address (e.g. 0.0.0.0:514) and count them like --stream
+   #[arg(long, value_name = "ADDR")]
+   listen_udp: Option<String>,
+
+   /// The same for syslog over TCP, newline-terminated or octet-counted
+   #[arg(long, value_name = "ADDR")]
+   listen_tcp: Option<String>,
+
+   /// Build from the input, then consume this Kafka topic (comma-separated for several) in a consumer group and count it like --stream, committing the offsets of each batch once it's counted
+   #[cfg(feature = "kafka")]
+   #[arg(long, value_name = "TOPIC")]
+   kafka_topic: Option<String>,
+
+   /// The brokers of --kafka-topic
+   #[cfg(feature = "kafka")]
+   #[arg(long, value_name = "HOST:PORT,...", default_value = "localhost:9092")]
+   kafka_brokers: String,
+
+   /// The consumer group of --kafka-topic
+   #[cfg(feature = "kafka")]
+   #[arg(long, value_name = "GROUP", default_value = "logram")]
+   kafka_group: String,
+
+   /// How many lines --stream (or a listener or --kafka-topic, within a second) counts at a time
+   #[arg(long, default_value = "10000")]
+   batch_lines: usize,
+
+   /// Keep the lines that aren't UTF-8, with U+FFFD for the bad bytes, instead of leaving them out
+   #[arg(long)]
+   lossy_utf8: bool,
+
+   /// Print how many lines were read, weren't UTF-8 and didn't match the format
+   #[arg(long)]
+   stats: bool,
+
+   /// Print the backend and threads the run used, the lines of each chunk, and how long reading, tokenizing and counting took
+   #[arg(long)]
+   report: bool,
+
+   /// Print the vocabulary size, Zipf exponent, double entropy and the n-grams seen once of the dictionaries
+   #[arg(long)]
+   corpus_stats: bool,
+
+   /// Print the distinct tokens after every N lines of the input, to see how fast the vocabulary grows
+   #[arg(long)]
+   vocabulary_growth: Option<usize>,
+
+   /// Write the first --max-unmatched lines that don't match the format to this file (implies --stats)
+   #[arg(long)]
+   unmatched: Option<String>,
+
+   #[arg(long, default_value = "100")]
+   max_unmatched: usize,
+
+   /// Only try the format on the first --sample lines of the input and show what it captured
+   #[arg(long)]
+   dry_run: bool,
+
+   #[arg(long, default_value = "100")]
+   sample: usize,
+
+   #[arg(long, default_value = "0.4")]
+   alert_score: f64,
+
+   /// Only count the lines at these levels, e.g. ERROR,WARN; the others still break the context
+   #[arg(long,value_delimiter=',')]
+   levels: Vec<String>,
+
+   /// Also count the n-grams of each value of this field, e.g. Level or Component, and print them
+   #[arg(long)]
+   breakdown_by: Option<String>,
+
+   /// Show how many lines have been counted so far on stderr
+   #[arg(long)]
+   progress: bool,
+
+   /// Only count the lines from this time on, e.g. "2017-12-23 22:00:00" (UTC unless it ends in an offset)
+   #[arg(long,value_parser=parse_datetime)]
+   start: Option<i64>,
+
+   /// Only count the lines before this time
+   #[arg(long,value_parser=parse_datetime)]
+   end: Option<i64>,
+
+   /// Start a new session after a pause of more than this many seconds between two lines, so no n-gram spans the pause
+   #[arg(long)]
+   session_gap: Option<u64>,
+
+   /// Start a new session wherever one of these fields changes between two lines, e.g. Component,Pid
+   #[arg(long,value_delimiter=',')]
+   split_on_change: Vec<String>,
+
+   /// Count the n-grams of the lines with each value of this field on their own, e.g. Pid
+   #[arg(long,conflicts_with="group_by_pattern")]
+   group_by: Option<String>,
+
+   /// Count the n-grams of the lines with each match of this regex on their own, e.g. 'blk_-?\d+'
+   #[arg(long)]
+   group_by_pattern: Option<String>,
+
+   /// Censor dates and times in any format, with the month and day names of these locales, e.g. fr,de (English if none)
+   #[arg(long,num_args=0..,value_delimiter=',')]
+   censor_timestamps: Option<Vec<Locale>>,
+
+   /// Censor what a regex matches in any format with a token of your own, e.g. 'cust-\d+=<CUSTOMER>' (repeatable)
+   #[arg(long,value_parser=parse_mask)]
+   mask: Vec<Censor>,
+
+   /// Redact emails, IP and MAC addresses, card numbers and JWTs with typed placeholders, and print how many of each (implies --stats)
+   #[arg(long)]
+   redact_pii: bool,
+}
+
+fn parse_mask(s: &str) -> Result<Censor, String> {
+    let (regex, replacement) = s.rsplit_once('=').ok_or_else(|| format!("expected REGEX=TOKEN, not {}", s))?;
+    Ok(Censor::new(Regex::new(regex).map_err(|e| e.to_string())?, replacement))
+}
+
+fn parse_datetime(s: &str) -> Result<i64, String> {
+    ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f%z", "%Y-%m-%d"].iter()
+        .find_map(|layout| parse_time(s, layout))
+        .ok_or_else(|| format!("expected a time like 2017-12-23 22:00:00, not {}", s))
 }
 
 #[test]
 fn test_derive_2grams_from_trigram() {
     let twograms_oracle = vec![("one^two"), 
                                ("two^three")];
-    let twograms = derive_2grams_from_trigram("one^two^three");
+    let twograms = derive_2grams_from_trigram("one^two^three", "^");
     assert_eq!(twograms_oracle, twograms);
+    assert_eq!(derive_2grams_from_trigram(r"a\^b^c^d", "^"), vec![r"a\^b^c", "c^d"]);
 }
 
-fn derive_2grams_from_trigram(trigram:&str) -> Vec<String> {
-    let grams : Vec<&str> = trigram.split("^").collect();
-    return vec![format!("{}^{}", grams[0], grams[1]), 
-                format!("{}^{}", grams[1], grams[2])];
+fn derive_2grams_from_trigram(trigram:&str, sep:&str) -> Vec<String> {
+    let grams = packages::parser::split_ngram(trigram, sep);
+    vec![packages::parser::join_ngram(&grams[0..2], sep),
+         packages::parser::join_ngram(&grams[1..3], sep)]
 }
 
-pub enum LogFormat {
-    Linux,
-    OpenStack,
-    Spark,
-    HDFS,
-    HPC,
-    Proxifier,
-    Android,
-    HealthApp,
+fn view_dict<'a>(name:&str, dict:&'a packages::parser::NGramDict, most_frequent:Option<Vec<(&'a str, u64)>>, order:packages::parser::Order, pretty:Option<TableStyle>) {
+    match (most_frequent, pretty) {
+        (Some(entries), Some(style)) => write_table(&mut std::io::stdout().lock(), name, entries, style).unwrap(),
+        (Some(entries), None) => packages::parser::print_most_frequent(name, &entries),
+        (None, Some(style)) => print_table(name, dict, order, None, style),
+        (None, None) => packages::parser::print_dict(name, dict, order, None),
+    }
 }
 
-fn view_double_and_triple_dicts(double_dict:&HashMap<String, i32>, triple_dict:&HashMap<String, i32>) {
-    packages::parser::print_dict("double", double_dict);
-    packages::parser::print_dict("triple", triple_dict);
+// "double dictionary list len 12, triple 34, all tokens 56" for the default sizes
+fn print_lens(result: &packages::parser::DictionaryResult) {
+    let lens: Vec<String> = result.ngram_dicts.iter().enumerate()
+        .map(|(i, (n, dict))| if i == 0 { format!("{} dictionary list len {}", packages::parser::ngram_name(*n), dict.len()) } else { format!("{} {}", packages::parser::ngram_name(*n), dict.len()) })
+        .collect();
+    println!("{}, all tokens {}", lens.join(", "), result.all_token_list.len());
+}
+
+fn view_dicts(result:&packages::parser::DictionaryResult, by_frequency:bool, order:packages::parser::Order, pretty:Option<TableStyle>) {
+    for (n, dict) in &result.ngram_dicts {
+        view_dict(&packages::parser::ngram_name(*n), dict, by_frequency.then(|| result.most_frequent(*n)), order, pretty);
+    }
+    for (d, dict) in &result.skip_grams {
+        let name = format!("skip-gram (distance {})", d);
+        view_dict(&name, dict, by_frequency.then(|| result.most_frequent_skip_grams(*d)), order, pretty);
+    }
+    for (value, dicts) in &result.breakdown {
+        for (n, dict) in dicts {
+            let name = format!("{} ({})", packages::parser::ngram_name(*n), value);
+            view_dict(&name, dict, None, order, pretty);
+        }
+    }
 }
 
 fn main() {
@@ -125,51 +612,365 @@ fn main() {
     } else if let Some(raw_healthapp) = args.raw_healthapp {
         log_format_opt = Some(HealthApp);
         input_fn = Some(raw_healthapp);
+    } else if let Some(raw_apache) = args.raw_apache {
+        log_format_opt = Some(Apache);
+        input_fn = Some(raw_apache);
+    } else if let Some(raw_nginx) = args.raw_nginx {
+        log_format_opt = Some(Nginx);
+        input_fn = Some(raw_nginx);
+    } else if let Some(raw_syslog3164) = args.raw_syslog3164 {
+        log_format_opt = Some(Syslog3164);
+        input_fn = Some(raw_syslog3164);
+    } else if let Some(raw_syslog5424) = args.raw_syslog5424 {
+        log_format_opt = Some(Syslog5424);
+        input_fn = Some(raw_syslog5424);
+    } else if let Some(raw_kubernetes) = args.raw_kubernetes {
+        log_format_opt = Some(Kubernetes { json: args.docker_json });
+        input_fn = Some(raw_kubernetes);
+    } else if let Some(raw_windows_event) = args.raw_windows_event {
+        log_format_opt = Some(WindowsEvent);
+        input_fn = Some(raw_windows_event);
+    } else if let Some(raw_zookeeper) = args.raw_zookeeper {
+        log_format_opt = Some(Zookeeper);
+        input_fn = Some(raw_zookeeper);
+    } else if let Some(raw_kafka) = args.raw_kafka {
+        log_format_opt = Some(Kafka);
+        input_fn = Some(raw_kafka);
+    } else if let Some(raw_cassandra) = args.raw_cassandra {
+        log_format_opt = Some(Cassandra);
+        input_fn = Some(raw_cassandra);
+    } else if let Some(raw_bgl) = args.raw_bgl {
+        log_format_opt = Some(BGL);
+        input_fn = Some(raw_bgl);
+    } else if let Some(raw_thunderbird) = args.raw_thunderbird {
+        log_format_opt = Some(Thunderbird);
+        input_fn = Some(raw_thunderbird);
+    } else if let Some(raw_mac) = args.raw_mac {
+        log_format_opt = Some(Mac);
+        input_fn = Some(raw_mac);
+    } else if let Some(raw_custom) = args.raw_custom {
+        let censor = args.censor.iter()
+            .map(|r| Regex::new(r).unwrap_or_else(|e| panic!("bad censoring regex {}: {}", r, e)))
+            .collect();
+        log_format_opt = Some(LogFormat::Custom { format: args.format.unwrap(), censor });
+        input_fn = Some(raw_custom);
     }
     let log_format = match log_format_opt {
         None => panic!("must specify a raw input file"),
         Some(lf) => lf,
     };
     let cutoff = args.cutoff.unwrap();
+    if args.dry_run {
+        let input_fn = input_fn.unwrap();
+        let report = packages::parser::validate_format_file(&packages::parser::format_string(&log_format), &input_fn, args.sample)
+            .unwrap_or_else(|e| panic!("{}", e));
+        print!("{}", report);
+        return;
+    }
 
-    let mut double_dict: HashMap<String, i32> = Default::default();
-    let mut triple_dict: HashMap<String, i32> = Default::default();
-    let mut _all_token_list: Vec<String> = vec![];
-    match args.single_map {
-        Some(x) => {
-            if x {
-                (double_dict, triple_dict, _all_token_list) =
-                    packages::parser::parse_raw_single(input_fn.unwrap(), &log_format, args.num_threads);
+    let mut result = packages::parser::DictionaryResult::default();
+    let context = if args.independent_lines {
+        packages::parser::ContextWindow::disabled()
+    } else {
+        packages::parser::ContextWindow { tokens: args.context_tokens, lines: args.context_lines }
+    };
+    let mut builder = packages::parser::DictionaryBuilder::new()
+        .num_threads(args.num_threads)
+        .min_chunk_lines(args.min_chunk_lines)
+        .chunks_per_thread(args.chunks_per_thread)
+        .pipeline(args.pipeline)
+        .dedupe_lines(args.dedupe_lines)
+        .shard_amount(args.shards)
+        .estimated_ngrams(args.estimated_ngrams)
+        .max_memory_bytes(args.max_memory_bytes)
+        .on_memory_limit(if args.prune_on_memory_limit { packages::parser::MemoryLimit::Prune } else { packages::parser::MemoryLimit::Fail })
+        .spill_dir(args.spill_dir.clone())
+        .spill_entries(args.spill_entries)
+        .sketch(packages::parser::SketchSize { width: args.sketch_width, depth: args.sketch_depth, heavy_hitters: args.heavy_hitters })
+        .context(context)
+        .separator(&args.separator)
+        .min_count(args.min_count)
+        .top_k(args.top_k)
+        .normalize(args.normalize.unwrap_or_default())
+        .mmap(args.mmap)
+        .lossy_utf8(args.lossy_utf8)
+        .levels(&args.levels.iter().map(String::as_str).collect::<Vec<_>>())
+        .breakdown_by(args.breakdown_by.as_deref())
+        .group_by(match (&args.group_by, &args.group_by_pattern) {
+            (Some(field), _) => Some(GroupBy::Field(field.clone())),
+            (_, Some(pattern)) => Some(GroupBy::Pattern(Regex::new(pattern).unwrap_or_else(|e| panic!("bad --group-by-pattern {}: {}", pattern, e)))),
+            _ => None,
+        })
+        .extra_censors(&args.mask)
+        .redact_pii(args.redact_pii)
+        .censor_timestamps(args.censor_timestamps.as_deref())
+        .split_on_change(&args.split_on_change.iter().map(String::as_str).collect::<Vec<_>>())
+        .session_gap(args.session_gap.map(|secs| SessionGap::new(secs as i64 * 1000)))
+        .time_range((args.start.is_some() || args.end.is_some()).then(|| TimeRange::new(args.start.unwrap_or(i64::MIN), args.end.unwrap_or(i64::MAX))))
+        .ngram_sizes(&[&[2, 3], args.ngram_sizes.as_slice()].concat())
+        .skip_grams(args.skip_grams)
+        .sample(match (args.sample_every, args.sample_rate) {
+            (Some(n), _) => Some(packages::parser::Sample::EveryNth(n)),
+            (_, Some(p)) => Some(packages::parser::Sample::Bernoulli { p, seed: args.seed }),
+            _ => None,
+        })
+        .skip_lines(args.skip_lines)
+        .max_lines(args.max_lines)
+        .start_offset(args.start_offset)
+        .end_offset(args.end_offset);
+    if args.progress {
+        builder = builder.progress(|done, total| {
+            eprint!("\r{}/{} lines ({}%)", done, total, done * 100 / total);
+            if done == total {
+                eprintln!();
             }
-            else {
-                // println!("Error");
+        });
+    }
+    let input_fn = input_fn.unwrap();
+    if args.follow {
+        let tokenizer = Normalize::new(RegexTokenizer::new(&log_format).unwrap_or_else(|e| panic!("{}", e)), args.normalize.unwrap_or_default());
+        // the lines already there when it starts are the baseline, not news
+        let mut first = true;
+        let on_update = |result: &packages::parser::DictionaryResult, lines: &[String]| {
+            for line in lines.iter().filter(|_| !first) {
+                let score = result.score_line(line, &tokenizer, &args.separator);
+                if score >= args.alert_score {
+                    println!("{:.4} {}", score, line);
+                }
             }
-
-        },
-        None => {
-            (double_dict, triple_dict, _all_token_list) =
-                packages::parser::parse_raw_conc(input_fn.unwrap(), &log_format, args.num_threads);
+            first = false;
+            true
+        };
+        #[cfg(feature = "prometheus")]
+        if let Some(addr) = &args.metrics_addr {
+            let metrics = std::sync::Arc::new(packages::metrics::Metrics::new());
+            metrics.serve(addr.as_str()).unwrap_or_else(|e| panic!("couldn't listen on {}: {}", addr, e));
+            builder.follow_with_metrics(&mut result, &input_fn, &log_format, Duration::from_secs(1), &metrics, on_update).unwrap_or_else(|e| panic!("{}", e));
+            return;
+        }
+        builder.follow(&mut result, &input_fn, &log_format, Duration::from_secs(1), on_update).unwrap_or_else(|e| panic!("{}", e));
+        return;
+    }
+    let listening = args.listen_udp.is_some() || args.listen_tcp.is_some();
+    #[cfg(feature = "kafka")]
+    let listening = listening || args.kafka_topic.is_some();
+    if args.stream || listening {
+        let tokenizer = Normalize::new(RegexTokenizer::new(&log_format).unwrap_or_else(|e| panic!("{}", e)), args.normalize.unwrap_or_default());
+        let mut source: Box<dyn packages::source::LineSource> = if listening {
+            // the input is the baseline the messages are scored against
+            result = builder.parse_raw_checked(input_fn.clone(), &log_format).unwrap_or_else(|e| panic!("{}", e));
+            #[cfg(feature = "kafka")]
+            let kafka = args.kafka_topic.as_ref().map(|topics| {
+                let topics: Vec<&str> = topics.split(',').collect();
+                packages::kafka::KafkaSource::new(&args.kafka_brokers, &args.kafka_group, &topics, args.batch_lines, Duration::from_secs(1))
+                    .unwrap_or_else(|e| panic!("couldn't consume {}: {}", topics.join(","), e))
+            });
+            #[cfg(not(feature = "kafka"))]
+            let kafka: Option<packages::source::SyslogListener> = None;
+            if let Some(kafka) = kafka {
+                Box::new(kafka)
+            } else {
+                let mut listener = packages::source::SyslogListener::new(args.batch_lines, Duration::from_secs(1))
+                    .strip_pri(!matches!(log_format, Syslog5424));
+                if let Some(addr) = &args.listen_udp {
+                    listener = listener.udp(addr.as_str()).unwrap_or_else(|e| panic!("couldn't listen on {}: {}", addr, e));
+                }
+                if let Some(addr) = &args.listen_tcp {
+                    listener = listener.tcp(addr.as_str()).unwrap_or_else(|e| panic!("couldn't listen on {}: {}", addr, e));
+                }
+                Box::new(listener)
+            }
+        } else {
+            let reader: Box<dyn std::io::BufRead> = if input_fn == "-" {
+                Box::new(std::io::stdin().lock())
+            } else {
+                Box::new(std::io::BufReader::new(std::fs::File::open(&input_fn).unwrap_or_else(|e| panic!("couldn't open {}: {}", input_fn, e))))
+            };
+            Box::new(packages::source::ReaderSource::new(reader, args.batch_lines))
+        };
+        builder.consume(&mut result, &mut *source, &log_format, |result, lines| {
+            for line in lines {
+                let score = result.score_line(line, &tokenizer, &args.separator);
+                if score >= args.alert_score {
+                    println!("{:.4} {}", score, line);
+                }
+            }
+            true
+        }).unwrap_or_else(|e| panic!("{}", e));
+        return;
+    }
+    #[cfg(feature = "server")]
+    if let Some(addr) = &args.serve {
+        let baseline = builder.parse_raw_checked(input_fn.clone(), &log_format).unwrap_or_else(|e| panic!("{}", e));
+        let server = packages::server::Server::new(builder, &log_format, baseline).unwrap_or_else(|e| panic!("{}", e));
+        let e = std::sync::Arc::new(server).serve(addr.as_str());
+        panic!("couldn't serve on {}: {}", addr, e);
+    }
+    if let Some(path) = &args.records {
+        builder.write_records(&input_fn, &log_format, path).unwrap_or_else(|e| panic!("couldn't write {}: {}", path, e));
+    }
+    #[cfg(feature = "elasticsearch")]
+    let elasticsearch = args.elasticsearch.as_ref().map(|url| {
+        let mut es = packages::elasticsearch::Elasticsearch::new(url).unwrap_or_else(|e| panic!("{}", e))
+            .ngram_index(&args.elasticsearch_index)
+            .bulk_docs(args.elasticsearch_bulk_docs)
+            .retries(args.elasticsearch_retries);
+        if let Some(index) = &args.elasticsearch_records {
+            es = es.record_index(index);
+            builder.index_records(&input_fn, &log_format, &es).unwrap_or_else(|e| panic!("couldn't index the records into Elasticsearch: {}", e));
+        }
+        es
+    });
+    if let Some(every) = args.vocabulary_growth {
+        println!("lines tokens");
+        for (lines, tokens) in builder.vocabulary_growth(input_fn.clone(), &log_format, every) {
+            println!("{} {}", lines, tokens);
         }
+    }
+    let templates_fn = (args.templates || args.events.is_some()).then(|| input_fn.clone());
+    if let Some(load) = &args.load {
+        result = packages::parser::DictionaryResult::load(load).unwrap_or_else(|e| panic!("couldn't load {}: {}", load, e));
+    }
+    let backend = match args.single_map {
+        Some(true) => packages::parser::Backend::SeparateMaps,
+        _ if args.buffered => packages::parser::Backend::Buffered,
+        _ if args.count_min => packages::parser::Backend::CountMin,
+        _ if args.external => packages::parser::Backend::External,
+        _ => packages::parser::Backend::ConcurrentMap,
     };
+    if let Some(offset) = args.offset {
+        let next = builder.update(&mut result, input_fn, offset, &log_format).unwrap_or_else(|e| panic!("{}", e));
+        println!("next offset {}", next);
+    } else if args.load.is_some() {
+        // already have the dictionaries
+    } else if args.by_component {
+        let component = logram::parse_line(&args.to_parse, &log_format).as_ref().and_then(|line| line.get("Component"))
+            .unwrap_or_else(|| panic!("--to-parse has no <Component> to pick the dictionaries with"))
+            .to_string();
+        let mut by_component = builder.parse_raw_by_component(input_fn, &log_format);
+        println!("{} components, using {}", by_component.len(), component);
+        result = by_component.remove(&component).unwrap_or_default();
+    } else if args.rotated {
+        result = builder.backend(backend).parse_rotated(&input_fn, &log_format);
+    } else if Path::new(&input_fn).is_dir() {
+        result = builder.backend(backend).parse_raw_dir(Path::new(&input_fn), args.glob.as_deref(), &log_format);
+    } else if args.stats || args.unmatched.is_some() || args.redact_pii {
+        let stats;
+        let keep = if args.unmatched.is_some() { args.max_unmatched } else { 0 };
+        (result, stats) = builder.backend(backend).keep_unmatched(keep).parse_raw_stats(input_fn, &log_format);
+        println!("{} lines, {} not UTF-8{}, {} not matching the format ({:.1}% matched)", stats.total, stats.invalid_utf8,
+                 if args.lossy_utf8 { "" } else { " (left out)" }, stats.unmatched, stats.match_rate() * 100.0);
+        for (placeholder, hits) in &stats.redactions {
+            println!("redacted {} {}", hits, placeholder);
+        }
+        if let Some(path) = &args.unmatched {
+            let lines: String = stats.unmatched_lines.iter().map(|(i, line)| format!("{}: {}\n", i, line)).collect();
+            std::fs::write(path, lines).unwrap_or_else(|e| panic!("couldn't write {}: {}", path, e));
+        }
+    } else if args.report {
+        let report;
+        (result, report) = builder.backend(backend).parse_raw_report(input_fn, &log_format);
+        println!("{}", report);
+    } else if args.buffered || args.count_min || args.external {
+        result = builder.backend(backend).parse_raw(input_fn, &log_format);
+    } else {
+        match args.single_map {
+            Some(x) => {
+                if x {
+                    result = builder.parse_raw_single(input_fn, &log_format);
+                }
+                else {
+                    // println!("Error");
+                }
+
+            },
+            None => {
+                result = builder.parse_raw_conc(input_fn, &log_format);
+            }
+        };
+    }
+    if args.load.is_none() || args.offset.is_some() {
+        print_lens(&result);
+    }
+
+
+    if let Some(save) = &args.save {
+        result.save(save).unwrap_or_else(|e| panic!("couldn't save {}: {}", save, e));
+    }
+    if let Some(csv) = &args.csv {
+        result.write_csv(csv).unwrap_or_else(|e| panic!("couldn't write the CSV files to {}: {}", csv, e));
+    }
+    #[cfg(feature = "arrow")]
+    if let Some(parquet) = &args.parquet {
+        result.write_parquet(parquet).unwrap_or_else(|e| panic!("couldn't write {}: {}", parquet, e));
+    }
+    #[cfg(feature = "sqlite")]
+    if let Some(sqlite) = &args.sqlite {
+        result.write_sqlite(sqlite).unwrap_or_else(|e| panic!("couldn't write {}: {}", sqlite, e));
+    }
+    #[cfg(feature = "duckdb")]
+    if let Some(duckdb) = &args.duckdb {
+        result.write_duckdb(duckdb).unwrap_or_else(|e| panic!("couldn't write {}: {}", duckdb, e));
+    }
+    #[cfg(feature = "clickhouse")]
+    if let Some(dsn) = &args.clickhouse {
+        let sink = packages::clickhouse::ClickHouse::new(dsn).unwrap_or_else(|e| panic!("{}", e))
+            .table_prefix(&args.clickhouse_table_prefix)
+            .batch_rows(args.clickhouse_batch_rows)
+            .retries(args.clickhouse_retries);
+        result.write_clickhouse(&sink).unwrap_or_else(|e| panic!("couldn't insert into ClickHouse: {}", e));
+    }
+    #[cfg(feature = "elasticsearch")]
+    if let Some(es) = &elasticsearch {
+        result.write_elasticsearch(es, &args.separator).unwrap_or_else(|e| panic!("couldn't index the n-grams into Elasticsearch: {}", e));
+    }
+    if args.corpus_stats {
+        let stats = result.stats(&args.separator);
+        println!("{} tokens, Zipf exponent {:.3}, double entropy {:.3} bits", stats.vocabulary, stats.zipf_exponent, stats.double_entropy);
+        for (n, histogram) in &stats.count_histograms {
+            println!("{}: {} distinct, {} seen once", packages::parser::ngram_name(*n), histogram.values().sum::<usize>(), histogram.get(&1).unwrap_or(&0));
+        }
+    }
+    let order = if args.descending { packages::parser::Order::Descending } else { packages::parser::Order::Ascending };
+    view_dicts(&result, args.top_k.is_some(), order, args.pretty.then(TableStyle::detect));
+    let empty_dict = HashMap::new();
+    let double_dict = result.ngram_dict(2).unwrap_or(&empty_dict);
+    let triple_dict = result.ngram_dict(3).unwrap_or(&empty_dict);
 
+    // the same tokens the dictionaries were built from
+    let tokenizer = Normalize::new(RegexTokenizer::new(&log_format).unwrap_or_else(|e| panic!("{}", e)), args.normalize.unwrap_or_default());
+    let tokenize = |line: &str| -> Vec<String> { tokenizer.tokenize(line).into_iter().map(|t| t.into_owned()).collect() };
 
-    view_double_and_triple_dicts(&double_dict, &triple_dict);
+    if let Some(templates_fn) = templates_fn {
+        let miner = TemplateMiner::new(&result).separator(&args.separator).cutoff(cutoff).context(context);
+        let events = miner.events_of_file(&templates_fn, &tokenizer).unwrap_or_else(|e| panic!("{}", e));
+        if args.templates {
+            for template in &events.templates {
+                println!("{:>8} {}", template.count, template);
+            }
+        }
+        if let Some(path) = &args.events {
+            events.write_csv(path).unwrap_or_else(|e| panic!("couldn't write {}: {}", path, e));
+        }
+    }
 
-    let (format_string_re, censored_regexps) =
-        (packages::parser::regex_generator(packages::parser::format_string(&log_format)), packages::parser::censored_regexps(&log_format));
+    if let Some(path) = &args.token_classes {
+        let classes = TemplateMiner::new(&result).separator(&args.separator).cutoff(cutoff).token_classes();
+        packages::template::write_token_classes(&classes, path).unwrap_or_else(|e| panic!("couldn't write {}: {}", path, e));
+    }
+
+    if args.score {
+        println!("score {:.4}", result.score_line(&args.to_parse, &tokenizer, &args.separator));
+    }
 
     //let sample_string = "Jun 23 23:30:05 combo sshd(pam_unix)[26190]: authentication failure; logname= uid=0 euid=0 tty=NODEVssh ruser= rhost=218.22.3.51  user=root authentication".to_string();
     // add befores and afters to the sample string, yielding extended_sample_string
-    let mut sample_string_tokens = packages::parser::token_splitter(args.to_parse, 
-                                                                    &format_string_re,
-                                                                    &censored_regexps);
+    let mut sample_string_tokens = tokenize(&args.to_parse);
     let mut befores = match (args.before, args.before_line) {
         (None, None) => vec![],
         (Some(b), None) => b.split_whitespace().map(|s| s.to_string()).collect(),
         (None, Some(b)) | (Some(_), Some(b)) => {
-            let r = packages::parser::token_splitter(b,
-                                                     &format_string_re,
-                                                     &censored_regexps);
+            let r = tokenize(&b);
             r[r.len()-2..r.len()].to_vec()
         }
     };
@@ -177,9 +978,7 @@ fn main() {
         (None, None) => vec![],
         (Some(a), None) => a.split_whitespace().map(|s| s.to_string()).collect(),
         (None, Some(a)) | (Some(_), Some(a)) => {
-            let r = packages::parser::token_splitter(a,
-                                                     &format_string_re,
-                                                     &censored_regexps);
+            let r = tokenize(&a);
             r[0..2].to_vec()
         }
     };
@@ -194,7 +993,7 @@ fn main() {
     let mut uncommon_3grams = vec![];
 
     for triple in extended_sample_string_tokens.windows(3) {
-        let three_gram = format!("{}^{}^{}", triple[0], triple[1], triple[2]);
+        let three_gram = packages::parser::join_ngram(triple, &args.separator);
         if triple_dict.contains_key(&three_gram) && triple_dict.get(&three_gram).unwrap() < &cutoff {
             // println!("3-gram {}, count {}", three_gram, &triple_dict.get(&three_gram).unwrap());
             uncommon_3grams.push(three_gram);
@@ -203,13 +1002,14 @@ fn main() {
 
     let mut deduped_2grams_from_uncommon_3grams : HashSet<String> = HashSet::new();
     for three_g in uncommon_3grams {
-        for two_g in derive_2grams_from_trigram(&three_g) {
+        for two_g in derive_2grams_from_trigram(&three_g, &args.separator) {
             deduped_2grams_from_uncommon_3grams.insert(two_g);
         }
     }
     let mut uncommon_2grams : Vec<String> = vec![];
     for two_g in deduped_2grams_from_uncommon_3grams {
-        let two_g_count = double_dict.get(&two_g).unwrap();
+        // a 2-gram pruned by --min-count was rarer than that
+        let two_g_count = double_dict.get(&two_g).unwrap_or(&0);
         println!("2-gram {}, count {}", two_g, two_g_count);
         if two_g_count < &cutoff {
             uncommon_2grams.push(two_g);
@@ -220,8 +1020,8 @@ fn main() {
     // now, iterate on the original tokens again and look for uncommon 2grams that appear 
     let mut dynamic_tokens = vec![];
     for triple in extended_sample_string_tokens.windows(3) {
-        let two_gram1 = format!("{}^{}", triple[0], triple[1]);
-        let two_gram2 = format!("{}^{}", triple[1], triple[2]);
+        let two_gram1 = packages::parser::join_ngram(&triple[0..2], &args.separator);
+        let two_gram2 = packages::parser::join_ngram(&triple[1..3], &args.separator);
         if uncommon_2grams.contains(&two_gram1) && uncommon_2grams.contains(&two_gram2) {
             dynamic_tokens.push(triple[1].to_string());
         }
@@ -0,0 +1,113 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::packages::parser::{DictionaryResult, NGramDict};
+
+// Comparing two dictionaries, e.g. of the logs of two releases: what the second one has that the
+// first doesn't (new messages, new errors), what it lost, and what it says much more or less often.
+
+/// The n-grams of one size that differ between two dictionaries.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SizeDiff {
+    /// In the new dictionary only, with their counts, most frequent first.
+    pub added: Vec<(String, u64)>,
+    /// In the old dictionary only, most frequent first.
+    pub removed: Vec<(String, u64)>,
+    /// In both with different counts, the biggest relative change first.
+    pub changed: Vec<Change>,
+}
+
+/// An n-gram counted in both dictionaries, but not the same number of times.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Change {
+    pub ngram: String,
+    pub before: u64,
+    pub after: u64,
+    /// How many times more of its dictionary the n-gram makes up after than before: each count is
+    /// taken as a share of all the n-grams of its size, so that a log twice as long doesn't double
+    /// everything. Below 1 when it got rarer.
+    pub ratio: f64,
+}
+
+impl SizeDiff {
+    /// The changes by a factor of at least `min_ratio` either way (2 for doubled or halved).
+    pub fn large_changes(&self, min_ratio: f64) -> impl Iterator<Item = &Change> {
+        self.changed.iter().filter(move |change| change.ratio.max(1.0 / change.ratio) >= min_ratio)
+    }
+}
+
+/// What differs between two dictionaries, by n-gram size.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DictDiff {
+    pub sizes: BTreeMap<usize, SizeDiff>,
+}
+
+impl DictDiff {
+    /// Whether the dictionaries have the same n-grams with the same counts.
+    pub fn is_empty(&self) -> bool {
+        self.sizes.values().all(|size| size.added.is_empty() && size.removed.is_empty() && size.changed.is_empty())
+    }
+}
+
+fn most_frequent_first(entries: &mut [(String, u64)]) {
+    entries.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+}
+
+fn diff_dicts(old: &NGramDict, new: &NGramDict) -> SizeDiff {
+    let (old_total, new_total) = (old.values().sum::<u64>() as f64, new.values().sum::<u64>() as f64);
+    let mut diff = SizeDiff::default();
+    for (ngram, &after) in new {
+        match old.get(ngram) {
+            None => diff.added.push((ngram.clone(), after)),
+            Some(&before) if before != after => {
+                let ratio = (after as f64 / new_total) / (before as f64 / old_total);
+                diff.changed.push(Change { ngram: ngram.clone(), before, after, ratio });
+            },
+            Some(_) => {},
+        }
+    }
+    diff.removed = old.iter().filter(|(ngram, _)| !new.contains_key(*ngram)).map(|(ngram, count)| (ngram.clone(), *count)).collect();
+    most_frequent_first(&mut diff.added);
+    most_frequent_first(&mut diff.removed);
+    diff.changed.sort_unstable_by(|a, b| b.ratio.ln().abs().total_cmp(&a.ratio.ln().abs()).then_with(|| a.ngram.cmp(&b.ngram)));
+    diff
+}
+
+/// The n-grams new in `new`, missing from it, and counted differently, for every n-gram size either
+/// dictionary has.
+pub fn diff(old: &DictionaryResult, new: &DictionaryResult) -> DictDiff {
+    let empty = NGramDict::new();
+    let sizes: BTreeSet<usize> = old.ngram_dicts.keys().chain(new.ngram_dicts.keys()).copied().collect();
+    let sizes = sizes.into_iter()
+        .map(|n| (n, diff_dicts(old.ngram_dict(n).unwrap_or(&empty), new.ngram_dict(n).unwrap_or(&empty))))
+        .collect();
+    DictDiff { sizes }
+}
+
+#[test]
+fn test_diff() {
+    let dict = |entries: &[(&str, u64)]| entries.iter().map(|(k, v)| (k.to_string(), *v)).collect::<NGramDict>();
+    let mut old = DictionaryResult::default();
+    old.ngram_dicts.insert(2, dict(&[("a^b", 10), ("b^c", 10), ("c^d", 5), ("gone^now", 3)]));
+    let mut new = DictionaryResult::default();
+    // twice the log: a^b just kept up, b^c got rarer, c^d commoner
+    new.ngram_dicts.insert(2, dict(&[("a^b", 20), ("b^c", 9), ("c^d", 40), ("disk^error", 2), ("new^one", 7)]));
+    new.ngram_dicts.insert(3, dict(&[("a^b^c", 1)]));
+
+    let d = diff(&old, &new);
+    let doubles = &d.sizes[&2];
+    assert_eq!(doubles.added, [("new^one".to_string(), 7), ("disk^error".to_string(), 2)]);
+    assert_eq!(doubles.removed, [("gone^now".to_string(), 3)]);
+    let changed: Vec<(&str, f64)> = doubles.changed.iter().map(|c| (c.ngram.as_str(), c.ratio)).collect();
+    let (old_total, new_total) = (28.0, 78.0);
+    assert_eq!(changed, [
+        ("b^c", (9.0 / new_total) / (10.0 / old_total)),
+        ("c^d", (40.0 / new_total) / (5.0 / old_total)),
+        ("a^b", (20.0 / new_total) / (10.0 / old_total)),
+    ]);
+    assert_eq!(doubles.large_changes(2.0).map(|c| c.ngram.as_str()).collect::<Vec<_>>(), ["b^c", "c^d"]);
+    assert_eq!(d.sizes[&3].added, [("a^b^c".to_string(), 1)]);
+    assert!(!d.is_empty());
+    assert!(diff(&new, &new).is_empty());
+}
@@ -0,0 +1,57 @@
+use std::hash::BuildHasher;
+use std::io;
+use std::path::Path;
+
+use ::duckdb::{params, Connection};
+
+use crate::packages::parser::{ngram_name, DictionaryResult};
+
+// DuckDB files written through the duckdb crate, which links DuckDB itself in (built from its
+// bundled sources) and fills the tables with DuckDB's appender rather than row-by-row INSERTs.
+
+impl<S: BuildHasher + Default> DictionaryResult<S> {
+    /// Writes the result into the DuckDB database at `path`, replacing the tables it writes: one per
+    /// n-gram size (`doubles`, `triples`, `"4-grams"`...) of `(ngram VARCHAR, count BIGINT)` rows.
+    /// The token list isn't written. It all goes in one transaction, so the database never holds
+    /// half a result. Needs the duckdb feature, which compiles DuckDB into logram.
+    pub fn write_duckdb<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut conn = Connection::open(path).map_err(io::Error::other)?;
+        let tx = conn.transaction().map_err(io::Error::other)?;
+        for (n, dict) in &self.ngram_dicts {
+            let table = format!("{}s", ngram_name(*n));
+            tx.execute_batch(&format!("CREATE OR REPLACE TABLE \"{}\" (ngram VARCHAR NOT NULL, count BIGINT NOT NULL)", table))
+                .map_err(io::Error::other)?;
+            let mut appender = tx.appender(&table).map_err(io::Error::other)?;
+            for (key, count) in dict {
+                let count = i64::try_from(*count).map_err(io::Error::other)?;
+                appender.append_row(params![key, count]).map_err(io::Error::other)?;
+            }
+            appender.flush().map_err(io::Error::other)?;
+        }
+        tx.commit().map_err(io::Error::other)
+    }
+}
+
+#[test]
+fn test_write_duckdb() {
+    use crate::LogFormat::HDFS;
+    use crate::packages::parser::DictionaryBuilder;
+
+    let result = DictionaryBuilder::new().ngram_sizes(&[2, 3, 4]).parse_raw("data/HDFS_2k.log".to_string(), &HDFS);
+    let path = std::env::temp_dir().join(format!("logram-test-{}.duckdb", std::process::id()));
+    result.write_duckdb(&path).unwrap();
+    // writing again replaces the tables
+    result.write_duckdb(&path).unwrap();
+
+    let conn = Connection::open(&path).unwrap();
+    let totals = |table: &str| conn.query_row(&format!("SELECT COUNT(*), SUM(count) FROM \"{}\"", table), [], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)));
+    let (doubles, fourgrams) = (totals("doubles").unwrap(), totals("4-grams").unwrap());
+    let (key, count) = result.most_frequent(3)[0];
+    let top: i64 = conn.query_row("SELECT count FROM triples WHERE ngram = ?", [key], |row| row.get(0)).unwrap();
+    drop(conn);
+    std::fs::remove_file(&path).unwrap();
+    for (n, (rows, sum)) in [(2, doubles), (4, fourgrams)] {
+        assert_eq!((rows as usize, sum as u64), (result.ngram_dicts[&n].len(), result.ngram_dicts[&n].values().sum::<u64>()));
+    }
+    assert_eq!(top as u64, count);
+}
@@ -0,0 +1,57 @@
+use std::fmt;
+use std::io;
+
+use crate::packages::parser::DictionaryResult;
+
+/// What can go wrong in the `_checked` parsing entry points.
+#[derive(Debug)]
+pub enum ParserError {
+    /// The log file couldn't be opened or read.
+    Io(io::Error),
+    /// The regex generated from a format string (or a censoring regex) didn't compile.
+    RegexCompile(regex::Error),
+    /// The format string can't be used to tokenize lines, e.g. it has no `<Content>` field.
+    FormatMismatch(String),
+    /// A worker thread, or the thread reading the input for them, panicked.
+    Worker(String),
+    /// The builder's options can't make a run, e.g. `num_threads(Some(0))`.
+    InvalidConfig(String),
+    /// The dictionaries outgrew `DictionaryBuilder::max_memory_bytes` with `MemoryLimit::Fail`; holds
+    /// what was counted up to then.
+    MemoryLimitExceeded(Box<DictionaryResult>),
+}
+
+impl fmt::Display for ParserError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParserError::Io(e) => write!(f, "couldn't read log file: {}", e),
+            ParserError::RegexCompile(e) => write!(f, "couldn't compile regex: {}", e),
+            ParserError::FormatMismatch(msg) => write!(f, "unusable format string: {}", msg),
+            ParserError::Worker(msg) => write!(f, "worker failed: {}", msg),
+            ParserError::InvalidConfig(msg) => write!(f, "invalid options: {}", msg),
+            ParserError::MemoryLimitExceeded(_) => write!(f, "the dictionaries got bigger than the memory limit"),
+        }
+    }
+}
+
+impl std::error::Error for ParserError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ParserError::Io(e) => Some(e),
+            ParserError::RegexCompile(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ParserError {
+    fn from(e: io::Error) -> Self {
+        ParserError::Io(e)
+    }
+}
+
+impl From<regex::Error> for ParserError {
+    fn from(e: regex::Error) -> Self {
+        ParserError::RegexCompile(e)
+    }
+}
@@ -0,0 +1,171 @@
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use crate::LogFormat;
+use crate::packages::parser::{DictionaryBuilder, DictionaryResult};
+
+// The C interface declared in include/logdict.h: opaque handles to a DictionaryResult (a
+// LogdictResult in C) and to a cursor over one of its dictionaries, created and freed only
+// through these functions.
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(message: String) {
+    let message = CString::new(message.replace('\0', " ")).unwrap();
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(message));
+}
+
+// a format name like "hdfs", or a <Field> format string with no censoring
+fn log_format(format: &str) -> Result<LogFormat, String> {
+    if format.contains('<') {
+        Ok(LogFormat::Custom { format: format.to_string(), censor: vec![] })
+    } else {
+        format.parse()
+    }
+}
+
+/// The entries of one dictionary, most frequent first, as `logdict_next` hands them out.
+pub struct LogdictEntries {
+    entries: Vec<(CString, u64)>,
+    next: usize,
+}
+
+/// Builds the dictionaries of the log at `path` with the concurrent-map backend and `threads`
+/// workers (0 for one per CPU). `format` is a format name as `--format` takes it ("hdfs") or a
+/// `<Field>` format string. Returns null on failure, with the reason in `logdict_last_error`.
+///
+/// # Safety
+/// `path` and `format` must be NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn logdict_build(path: *const c_char, format: *const c_char, threads: u32) -> *mut DictionaryResult {
+    if path.is_null() || format.is_null() {
+        set_error("null path or format".to_string());
+        return ptr::null_mut();
+    }
+    let (path, format) = (CStr::from_ptr(path).to_string_lossy(), CStr::from_ptr(format).to_string_lossy());
+    let built = panic::catch_unwind(AssertUnwindSafe(|| {
+        let lf = log_format(&format)?;
+        let builder = DictionaryBuilder::new().num_threads((threads > 0).then_some(threads));
+        builder.parse_raw_checked(path.to_string(), &lf).map_err(|e| e.to_string())
+    }));
+    match built {
+        Ok(Ok(result)) => Box::into_raw(Box::new(result)),
+        Ok(Err(e)) => {
+            set_error(e);
+            ptr::null_mut()
+        },
+        Err(_) => {
+            set_error("the build panicked".to_string());
+            ptr::null_mut()
+        },
+    }
+}
+
+/// Why the last call on this thread that could fail did, or null if none has.
+#[no_mangle]
+pub extern "C" fn logdict_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
+
+/// How many distinct tokens the result has.
+///
+/// # Safety
+/// `result` must come from `logdict_build` and not have been freed.
+#[no_mangle]
+pub unsafe extern "C" fn logdict_token_count(result: *const DictionaryResult) -> usize {
+    (*result).all_token_list.len()
+}
+
+/// How many distinct n-grams of size `n` the result has; 0 if it has no such dictionary.
+///
+/// # Safety
+/// As for `logdict_token_count`.
+#[no_mangle]
+pub unsafe extern "C" fn logdict_len(result: *const DictionaryResult, n: usize) -> usize {
+    (*result).ngram_dict(n).map_or(0, |dict| dict.len())
+}
+
+/// A cursor over the n-grams of size `n`, most frequent first, or null if there's no such
+/// dictionary. The keys are copied, so it outlives the result; free it with `logdict_entries_free`.
+///
+/// # Safety
+/// As for `logdict_token_count`.
+#[no_mangle]
+pub unsafe extern "C" fn logdict_entries(result: *const DictionaryResult, n: usize) -> *mut LogdictEntries {
+    let result = &*result;
+    if result.ngram_dict(n).is_none() {
+        return ptr::null_mut();
+    }
+    let entries = result.most_frequent(n).into_iter()
+        // NULs can't be in a C string; the separator escaping never makes one
+        .map(|(key, count)| (CString::new(key.replace('\0', "\u{fffd}")).unwrap(), count))
+        .collect();
+    Box::into_raw(Box::new(LogdictEntries { entries, next: 0 }))
+}
+
+/// Puts the next n-gram and its count in `key` and `count` and returns true, or returns false once
+/// there are none left. `key` stays valid until the cursor is freed.
+///
+/// # Safety
+/// `entries` must come from `logdict_entries` and not have been freed; `key` and `count` must be
+/// valid to write to.
+#[no_mangle]
+pub unsafe extern "C" fn logdict_next(entries: *mut LogdictEntries, key: *mut *const c_char, count: *mut u64) -> bool {
+    let entries = &mut *entries;
+    let Some((k, c)) = entries.entries.get(entries.next) else { return false };
+    entries.next += 1;
+    *key = k.as_ptr();
+    *count = *c;
+    true
+}
+
+/// # Safety
+/// `entries` must come from `logdict_entries` (or be null) and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn logdict_entries_free(entries: *mut LogdictEntries) {
+    if !entries.is_null() {
+        drop(Box::from_raw(entries));
+    }
+}
+
+/// # Safety
+/// `result` must come from `logdict_build` (or be null) and not have been freed already.
+#[no_mangle]
+pub unsafe extern "C" fn logdict_free(result: *mut DictionaryResult) {
+    if !result.is_null() {
+        drop(Box::from_raw(result));
+    }
+}
+
+#[test]
+fn test_ffi() {
+    let path = CString::new("data/HDFS_2k.log").unwrap();
+    let format = CString::new("hdfs").unwrap();
+    unsafe {
+        let result = logdict_build(path.as_ptr(), format.as_ptr(), 2);
+        assert!(!result.is_null());
+        let expected = DictionaryBuilder::new().parse_raw("data/HDFS_2k.log".to_string(), &LogFormat::HDFS);
+        assert_eq!(logdict_token_count(result), expected.all_token_list.len());
+        assert_eq!(logdict_len(result, 3), expected.ngram_dicts[&3].len());
+        assert!(logdict_entries(result, 5).is_null());
+
+        let entries = logdict_entries(result, 2);
+        let (mut key, mut count) = (ptr::null(), 0);
+        let mut seen = vec![];
+        while logdict_next(entries, &mut key, &mut count) {
+            seen.push((CStr::from_ptr(key).to_str().unwrap().to_string(), count));
+        }
+        logdict_entries_free(entries);
+        logdict_free(result);
+        let most_frequent: Vec<(String, u64)> = expected.most_frequent(2).into_iter().map(|(k, c)| (k.to_string(), c)).collect();
+        assert_eq!(seen, most_frequent);
+
+        let bad = CString::new("klingon").unwrap();
+        assert!(logdict_build(path.as_ptr(), bad.as_ptr(), 0).is_null());
+        assert_eq!(CStr::from_ptr(logdict_last_error()).to_str().unwrap(), "unknown log format klingon");
+    }
+}
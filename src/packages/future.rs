@@ -0,0 +1,190 @@
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use crate::LogFormat;
+use crate::packages::error::ParserError;
+use crate::packages::parser::{DictionaryBuilder, DictionaryResult};
+
+/// A build running on a thread of its own, as a `Future` that any async runtime can await without
+/// blocking its own threads; the build itself still uses the builder's worker pool. On tokio, the
+/// `tokio` feature's `DictionaryBuilder::parse_raw_async` reads with `tokio::fs` and counts on the
+/// runtime instead, handing back each chunk's counts as they're done.
+pub struct Build<T> {
+    state: Arc<Mutex<State<T>>>,
+}
+
+struct State<T> {
+    // what the build returned, or what it panicked with
+    output: Option<thread::Result<T>>,
+    waker: Option<Waker>,
+}
+
+impl<T: Send + 'static> Build<T> {
+    fn spawn<F: FnOnce() -> T + Send + 'static>(f: F) -> Self {
+        let state = Arc::new(Mutex::new(State { output: None, waker: None }));
+        let shared = state.clone();
+        thread::spawn(move || {
+            let output = panic::catch_unwind(AssertUnwindSafe(f));
+            let mut state = shared.lock().unwrap();
+            state.output = Some(output);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        });
+        Build { state }
+    }
+}
+
+impl<T> Future for Build<T> {
+    type Output = T;
+
+    /// Panics with the build's panic, if it had one.
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut state = self.state.lock().unwrap();
+        match state.output.take() {
+            Some(Ok(output)) => Poll::Ready(output),
+            Some(Err(panic)) => panic::resume_unwind(panic),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            },
+        }
+    }
+}
+
+impl DictionaryBuilder {
+    /// `parse_raw_checked` as a future, for async services on any runtime. It resolves once, with the
+    /// whole result.
+    pub fn parse_raw_future(&self, raw_fn: String, lf: &LogFormat) -> Build<Result<DictionaryResult, ParserError>> {
+        let (builder, lf) = (self.clone(), lf.clone());
+        Build::spawn(move || builder.parse_raw_checked(raw_fn, &lf))
+    }
+}
+
+#[test]
+fn test_parse_raw_future() {
+    use std::task::Wake;
+    use crate::LogFormat::HDFS;
+
+    // just enough of an executor: park until woken
+    struct Unpark(thread::Thread);
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    let builder = DictionaryBuilder::new().num_threads(Some(2));
+    let result = block_on(builder.parse_raw_future("data/HDFS_2k.log".to_string(), &HDFS)).unwrap();
+    assert_eq!(result, builder.parse_raw("data/HDFS_2k.log".to_string(), &HDFS));
+    let missing = block_on(builder.parse_raw_future("data/does_not_exist.log".to_string(), &HDFS));
+    assert!(matches!(missing, Err(ParserError::Io(_))));
+}
+
+/// The counts of each chunk of a file, as `parse_raw_async` finishes them: a `Stream` of
+/// `DictionaryResult`s in the order they're done, not the order of the chunks. Each holds every
+/// n-gram of its chunk, the ones spanning it and the chunk before included, and nothing's pruned,
+/// so merging all of them gives what `parse_raw_checked` would without `min_count` or `top_k`
+/// (`DictionaryBuilder::prune` applies those to the merged result). A chunk that fails ends the stream
+/// with its error.
+#[cfg(feature = "tokio")]
+pub struct Partials {
+    chunks: tokio::sync::mpsc::UnboundedReceiver<Result<Result<DictionaryResult, ParserError>, tokio::task::JoinError>>,
+    failed: bool,
+}
+
+#[cfg(feature = "tokio")]
+impl futures_core::Stream for Partials {
+    type Item = Result<DictionaryResult, ParserError>;
+
+    /// Panics with a chunk's panic, if it had one.
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.failed {
+            return Poll::Ready(None);
+        }
+        match self.chunks.poll_recv(cx) {
+            Poll::Ready(Some(Ok(partial))) => {
+                self.failed = partial.is_err();
+                Poll::Ready(Some(partial))
+            },
+            Poll::Ready(Some(Err(e))) => panic::resume_unwind(e.into_panic()),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl DictionaryBuilder {
+    /// Reads `raw_fn` with `tokio::fs` and counts its chunks on the runtime's blocking threads, as
+    /// many at a time as the builder has worker threads; the `Partials` stream hands back each
+    /// chunk's counts as they're done. It must be awaited inside a tokio runtime. The file is read
+    /// whole and as it is, so it can't be compressed, `-` or a URL (`parse_raw_future` reads those);
+    /// the byte offsets, `skip_lines`, `max_lines` and `sample` pick its lines as usual.
+    pub async fn parse_raw_async<P: AsRef<std::path::Path>>(&self, raw_fn: P, lf: &LogFormat) -> Result<Partials, ParserError> {
+        let buf = tokio::fs::read(raw_fn).await?;
+        let chunks = self.picked_chunks(&buf)?;
+        drop(buf);
+        let workers = Arc::new(tokio::sync::Semaphore::new(self.num_workers()? as usize));
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let unpruned = Arc::new(self.unpruned());
+        for [before, chunk] in chunks {
+            let (builder, lf, tx, workers) = (unpruned.clone(), lf.clone(), tx.clone(), workers.clone());
+            tokio::spawn(async move {
+                let _worker = workers.acquire_owned().await.unwrap();
+                let partial = tokio::task::spawn_blocking(move || {
+                    let mut result = DictionaryResult::default();
+                    builder.update_lines(&mut result, &before, chunk, &lf).map(|()| result)
+                }).await;
+                // nothing's listening once the stream is dropped
+                let _ = tx.send(partial);
+            });
+        }
+        Ok(Partials { chunks: rx, failed: false })
+    }
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn test_parse_raw_async() {
+    use std::future::poll_fn;
+    use futures_core::Stream;
+    use crate::LogFormat::HDFS;
+
+    let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+    let builder = DictionaryBuilder::new().num_threads(Some(2)).skip_lines(100);
+    let mut partials = runtime.block_on(builder.parse_raw_async("data/HDFS_2k.log", &HDFS)).unwrap();
+    let (mut merged, mut count) = (DictionaryResult::default(), 0);
+    while let Some(partial) = runtime.block_on(poll_fn(|cx| Pin::new(&mut partials).poll_next(cx))) {
+        merged.merge(partial.unwrap());
+        count += 1;
+    }
+    assert!(count > 1 || cfg!(not(feature = "concurrent")));
+    assert_eq!(merged, builder.parse_raw("data/HDFS_2k.log".to_string(), &HDFS));
+    let pruned = builder.clone().min_count(3);
+    pruned.prune(&mut merged);
+    assert_eq!(merged, pruned.parse_raw("data/HDFS_2k.log".to_string(), &HDFS));
+
+    let missing = runtime.block_on(builder.parse_raw_async("data/does_not_exist.log", &HDFS));
+    assert!(matches!(missing, Err(ParserError::Io(_))));
+    // a chunk that can't be counted ends the stream
+    let grouped = builder.group_by(Some(crate::GroupBy::Field("Pid".to_string())));
+    let mut partials = runtime.block_on(grouped.parse_raw_async("data/HDFS_2k.log", &HDFS)).unwrap();
+    assert!(runtime.block_on(poll_fn(|cx| Pin::new(&mut partials).poll_next(cx))).unwrap().is_err());
+    assert!(runtime.block_on(poll_fn(|cx| Pin::new(&mut partials).poll_next(cx))).is_none());
+}
@@ -0,0 +1,10 @@
+/// What the builders' internal maps (the n-gram counts while they're keyed by token ids, and the
+/// token interner) hash with: the `rustc-hash` crate's FxHash with the fxhash feature, the standard
+/// library's `RandomState` otherwise. FxHash is several times cheaper than SipHash on their short
+/// integer keys, but easy to flood with collisions on purpose, which is why the feature has to ask
+/// for it. The maps of a `DictionaryResult` hash with its own `S`, `RandomState` unless the run was
+/// asked for another; see `DictionaryBuilder::parse_raw_hashed`.
+#[cfg(feature = "fxhash")]
+pub type BuildNGramHasher = rustc_hash::FxBuildHasher;
+#[cfg(not(feature = "fxhash"))]
+pub type BuildNGramHasher = std::collections::hash_map::RandomState;
@@ -0,0 +1,57 @@
+use std::fmt::Display;
+use std::io;
+use std::net::TcpListener;
+use std::sync::Arc;
+
+use axum::extract::{DefaultBodyLimit, Request};
+use axum::http::StatusCode;
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::Router;
+use tokio::sync::Semaphore;
+
+// What the metrics endpoint and the ingestion server share: serving an axum router on a Tokio
+// runtime of its own, so their callers don't need one.
+
+// the largest request body read, to keep a bad client from taking all the memory
+const MAX_BODY: usize = 256 << 20;
+// the most requests answered at once; the ones past it get a 503 straight away
+const MAX_REQUESTS: usize = 64;
+
+/// A plain-text error response.
+pub(crate) fn error(status: StatusCode, message: impl Display) -> Response {
+    (status, format!("{}\n", message)).into_response()
+}
+
+/// Runs `f` on a thread that's allowed to block, for the work that takes the state lock or a while.
+#[cfg_attr(not(feature = "server"), allow(dead_code))]
+pub(crate) async fn blocking<T, F>(f: F) -> Result<T, Response>
+where T: Send + 'static, F: FnOnce() -> T + Send + 'static, {
+    tokio::task::spawn_blocking(f).await.map_err(|e| error(StatusCode::INTERNAL_SERVER_ERROR, e))
+}
+
+/// Answers the connections to `listener` with `router`, up to `MAX_REQUESTS` requests at once;
+/// never returns unless serving fails.
+pub(crate) fn serve(listener: TcpListener, router: Router) -> io::Error {
+    let runtime = match tokio::runtime::Builder::new_multi_thread().enable_all().build() {
+        Ok(runtime) => runtime,
+        Err(e) => return e,
+    };
+    let permits = Arc::new(Semaphore::new(MAX_REQUESTS));
+    let router = router
+        .layer(DefaultBodyLimit::max(MAX_BODY))
+        .layer(middleware::from_fn(move |request: Request, next: Next| {
+            let permits = permits.clone();
+            async move {
+                match permits.try_acquire_owned() {
+                    Ok(_permit) => next.run(request).await,
+                    Err(_) => error(StatusCode::SERVICE_UNAVAILABLE, "too many requests"),
+                }
+            }
+        }));
+    let served = runtime.block_on(async {
+        listener.set_nonblocking(true)?;
+        axum::serve(tokio::net::TcpListener::from_std(listener)?, router).await
+    });
+    served.err().unwrap_or_else(|| io::Error::other("the server stopped"))
+}
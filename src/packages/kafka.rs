@@ -0,0 +1,148 @@
+use std::borrow::Cow;
+use std::io;
+use std::time::{Duration, Instant};
+
+use rdkafka::consumer::{BaseConsumer, CommitMode, Consumer};
+use rdkafka::message::Message;
+use rdkafka::{ClientConfig, Offset, TopicPartitionList};
+
+use crate::packages::parser::decode_line;
+use crate::packages::source::LineSource;
+
+/// The messages of Kafka topics as lines, for `DictionaryBuilder::consume`: an rdkafka consumer in
+/// a consumer group, polled for batches of up to `batch_lines` messages that it doesn't hold back
+/// longer than `max_wait` after the first one arrives. Offsets aren't committed automatically:
+/// `commit` commits a batch's once `consume` has it in the result, so a restart picks up after the
+/// last batch that made it into the dictionaries (and counts the one it was on again). A message
+/// is a line, without the line ending it may have; bytes that aren't UTF-8 become U+FFFD, and
+/// messages without a payload are left out.
+pub struct KafkaSource {
+    consumer: BaseConsumer,
+    batch_lines: usize,
+    max_wait: Duration,
+    // the offsets after the messages of the last batch, for commit
+    batch: TopicPartitionList,
+}
+
+fn kafka_error(e: rdkafka::error::KafkaError) -> io::Error {
+    io::Error::other(format!("kafka: {}", e))
+}
+
+impl KafkaSource {
+    /// Subscribes to `topics` on the brokers of `bootstrap_servers` (`host:port,...`) as a member of
+    /// `group`; a group that hasn't committed anything yet starts from the earliest offsets.
+    pub fn new(bootstrap_servers: &str, group: &str, topics: &[&str], batch_lines: usize, max_wait: Duration) -> io::Result<Self> {
+        let mut config = ClientConfig::new();
+        config.set("bootstrap.servers", bootstrap_servers)
+            .set("group.id", group)
+            .set("auto.offset.reset", "earliest");
+        Self::with_config(config, topics, batch_lines, max_wait)
+    }
+
+    /// `new` with the consumer settings of `config` (security, timeouts...); `enable.auto.commit`
+    /// is turned off whatever it says.
+    pub fn with_config(mut config: ClientConfig, topics: &[&str], batch_lines: usize, max_wait: Duration) -> io::Result<Self> {
+        assert!(batch_lines > 0, "batches need at least one line");
+        let consumer: BaseConsumer = config.set("enable.auto.commit", "false").create().map_err(kafka_error)?;
+        consumer.subscribe(topics).map_err(kafka_error)?;
+        Ok(KafkaSource { consumer, batch_lines, max_wait, batch: TopicPartitionList::new() })
+    }
+
+    // the next message, waiting up to timeout for it: its line (None without a payload) after
+    // noting its offset for commit
+    fn poll(&mut self, timeout: Duration) -> io::Result<Option<Option<String>>> {
+        let Some(message) = self.consumer.poll(timeout) else { return Ok(None) };
+        let message = message.map_err(kafka_error)?;
+        let next = Offset::Offset(message.offset() + 1);
+        if self.batch.find_partition(message.topic(), message.partition()).is_some() {
+            self.batch.set_partition_offset(message.topic(), message.partition(), next)
+        } else {
+            self.batch.add_partition_offset(message.topic(), message.partition(), next)
+        }.map_err(kafka_error)?;
+        Ok(Some(message.payload().map(message_line)))
+    }
+}
+
+// a message's payload as a line
+fn message_line(payload: &[u8]) -> String {
+    let line = payload.strip_suffix(b"\n").unwrap_or(payload);
+    decode_line(line, true, &mut 0).map_or_else(String::new, Cow::into_owned)
+}
+
+impl LineSource for KafkaSource {
+    /// Waits for as long as it takes for a first message.
+    fn next_batch(&mut self) -> io::Result<Option<Vec<String>>> {
+        let first = loop {
+            if let Some(first) = self.poll(Duration::from_secs(1))? {
+                break first;
+            }
+        };
+        let deadline = Instant::now() + self.max_wait;
+        let mut lines: Vec<String> = first.into_iter().collect();
+        let mut polled = 1;
+        while polled < self.batch_lines {
+            match self.poll(deadline.saturating_duration_since(Instant::now()))? {
+                Some(line) => lines.extend(line),
+                None => break,
+            }
+            polled += 1;
+        }
+        Ok(Some(lines))
+    }
+
+    fn commit(&mut self) -> io::Result<()> {
+        if self.batch.count() == 0 {
+            return Ok(());
+        }
+        self.consumer.commit(&self.batch, CommitMode::Sync).map_err(kafka_error)?;
+        self.batch = TopicPartitionList::new();
+        Ok(())
+    }
+}
+
+#[test]
+fn test_message_line() {
+    assert_eq!(message_line(b"081109 203615 148 INFO dfs.DataNode$PacketResponder: Received block\r\n"),
+               "081109 203615 148 INFO dfs.DataNode$PacketResponder: Received block");
+    assert_eq!(message_line(b"no newline"), "no newline");
+    assert_eq!(message_line(b"bad \xff byte"), "bad \u{fffd} byte");
+
+    // nothing consumed yet, so nothing to commit, and no broker needed for that
+    let mut source = KafkaSource::new("127.0.0.1:1", "logram-test", &["app-logs"], 10, Duration::from_millis(10)).unwrap();
+    source.commit().unwrap();
+}
+
+// needs a broker: LOGRAM_KAFKA_BROKERS=localhost:9092 cargo test --features kafka -- --ignored
+#[test]
+#[ignore]
+fn test_kafka_source() {
+    use rdkafka::producer::{BaseProducer, BaseRecord, Producer};
+    use crate::LogFormat::HDFS;
+    use crate::packages::parser::{read_log, DictionaryBuilder, DictionaryResult};
+
+    let brokers = std::env::var("LOGRAM_KAFKA_BROKERS").expect("LOGRAM_KAFKA_BROKERS");
+    let unique = format!("logram-test-{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos());
+    let lines = read_log("data/HDFS_2k.log").unwrap();
+    let producer: BaseProducer = ClientConfig::new().set("bootstrap.servers", &brokers).create().unwrap();
+    for line in &lines {
+        producer.send(BaseRecord::<(), _>::to(&unique).payload(line.as_str())).unwrap();
+    }
+    producer.flush(Duration::from_secs(30)).unwrap();
+
+    // one partition, so the messages come in the order they went
+    let builder = DictionaryBuilder::new().num_threads(Some(2));
+    let mut source = KafkaSource::new(&brokers, &unique, &[&unique], 700, Duration::from_secs(5)).unwrap();
+    let (mut result, mut consumed) = (DictionaryResult::default(), 0);
+    builder.consume(&mut result, &mut source, &HDFS, |_, batch| {
+        consumed += batch.len();
+        consumed < lines.len()
+    }).unwrap();
+    assert_eq!(result, builder.parse_raw("data/HDFS_2k.log".to_string(), &HDFS));
+    drop(source);
+
+    // everything was committed, so the group starts past it
+    producer.send(BaseRecord::<(), _>::to(&unique).payload("after the commit")).unwrap();
+    producer.flush(Duration::from_secs(30)).unwrap();
+    let mut source = KafkaSource::new(&brokers, &unique, &[&unique], 700, Duration::from_secs(1)).unwrap();
+    assert_eq!(source.next_batch().unwrap().unwrap(), ["after the commit"]);
+}
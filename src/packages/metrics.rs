@@ -0,0 +1,129 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io;
+use std::net::{TcpListener, ToSocketAddrs};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use axum::extract::State as Shared;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use axum::Router;
+
+use crate::LogFormat;
+use crate::packages::error::ParserError;
+use crate::packages::http;
+use crate::packages::parser::{DictionaryBuilder, DictionaryResult, LineParser};
+
+/// What a long-running `follow` has done so far, in the Prometheus text format: counters of the
+/// lines parsed and of those that didn't match the format, and gauges of the distinct n-grams of
+/// each size and of the distinct tokens. Shared between the follower and `serve`.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    lines: u64,
+    unmatched: u64,
+    ngrams: BTreeMap<usize, usize>,
+    tokens: usize,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Counts a batch of `lines`, `unmatched` of which didn't match the format, added to `result`.
+    pub fn observe(&self, result: &DictionaryResult, lines: usize, unmatched: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.lines += lines as u64;
+        state.unmatched += unmatched as u64;
+        state.ngrams = result.ngram_dicts.iter().map(|(n, dict)| (*n, dict.len())).collect();
+        state.tokens = result.all_token_list.len();
+    }
+
+    /// The metrics as a Prometheus scrape wants them.
+    pub fn render(&self) -> String {
+        let state = self.state.lock().unwrap();
+        let mut text = String::new();
+        // writing to a String can't fail
+        let _ = writeln!(text, "# HELP logram_lines_parsed_total Lines read from the followed log.");
+        let _ = writeln!(text, "# TYPE logram_lines_parsed_total counter");
+        let _ = writeln!(text, "logram_lines_parsed_total {}", state.lines);
+        let _ = writeln!(text, "# HELP logram_unmatched_lines_total Lines that didn't match the log format.");
+        let _ = writeln!(text, "# TYPE logram_unmatched_lines_total counter");
+        let _ = writeln!(text, "logram_unmatched_lines_total {}", state.unmatched);
+        let _ = writeln!(text, "# HELP logram_unique_ngrams Distinct n-grams in the dictionary of each size.");
+        let _ = writeln!(text, "# TYPE logram_unique_ngrams gauge");
+        for (n, len) in &state.ngrams {
+            let _ = writeln!(text, "logram_unique_ngrams{{n=\"{}\"}} {}", n, len);
+        }
+        let _ = writeln!(text, "# HELP logram_unique_tokens Distinct tokens seen.");
+        let _ = writeln!(text, "# TYPE logram_unique_tokens gauge");
+        let _ = writeln!(text, "logram_unique_tokens {}", state.tokens);
+        text
+    }
+
+    /// Answers `GET /metrics` on `addr` with `render`, on threads of its own for as long as the
+    /// program runs. Returns once it's listening.
+    pub fn serve<A: ToSocketAddrs>(self: &Arc<Self>, addr: A) -> io::Result<std::net::SocketAddr> {
+        let listener = TcpListener::bind(addr)?;
+        let local = listener.local_addr()?;
+        let router = Router::new().route("/", get(scrape)).route("/metrics", get(scrape)).with_state(self.clone());
+        thread::spawn(move || http::serve(listener, router));
+        Ok(local)
+    }
+}
+
+async fn scrape(Shared(metrics): Shared<Arc<Metrics>>) -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], metrics.render())
+}
+
+impl DictionaryBuilder {
+    /// `follow`, keeping `metrics` up to date with every batch.
+    pub fn follow_with_metrics<P, F>(&self, result: &mut DictionaryResult, raw_fn: P, lf: &LogFormat, interval: Duration,
+                                     metrics: &Metrics, mut on_update: F) -> Result<(), ParserError>
+    where P: AsRef<Path>, F: FnMut(&DictionaryResult, &[String]) -> bool, {
+        let parser = LineParser::new(lf)?;
+        self.follow(result, raw_fn, lf, interval, |result, lines| {
+            let unmatched = lines.iter().filter(|line| !parser.matches(line)).count();
+            metrics.observe(result, lines.len(), unmatched);
+            on_update(result, lines)
+        })
+    }
+}
+
+#[test]
+fn test_metrics() {
+    use crate::LogFormat::HDFS;
+    let path = std::env::temp_dir().join(format!("logram-test-{}-metrics.log", std::process::id()));
+    let mut log = std::fs::read_to_string("data/HDFS_2k.log").unwrap();
+    log.push_str("not an HDFS line\n");
+    std::fs::write(&path, &log).unwrap();
+    let metrics = Arc::new(Metrics::new());
+    let mut result = DictionaryResult::default();
+    let builder = DictionaryBuilder::new();
+    builder.follow_with_metrics(&mut result, &path, &HDFS, Duration::from_millis(5), &metrics, |_, _| false).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let text = metrics.render();
+    assert!(text.contains("logram_lines_parsed_total 2001\n"), "{}", text);
+    assert!(text.contains("logram_unmatched_lines_total 1\n"), "{}", text);
+    assert!(text.contains(&format!("logram_unique_ngrams{{n=\"3\"}} {}\n", result.ngram_dicts[&3].len())), "{}", text);
+    assert!(text.contains(&format!("logram_unique_tokens {}\n", result.all_token_list.len())), "{}", text);
+
+    use std::io::{Read, Write};
+    let addr = metrics.serve("127.0.0.1:0").unwrap();
+    let mut stream = std::net::TcpStream::connect(addr).unwrap();
+    stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+    assert!(response.ends_with(&text));
+}
@@ -1 +1,42 @@
+#[cfg(feature = "clickhouse")]
+pub mod clickhouse;
+#[cfg(any(feature = "clickhouse", feature = "elasticsearch"))]
+mod client;
+pub mod diff;
+#[cfg(feature = "duckdb")]
+mod duckdb;
+#[cfg(feature = "elasticsearch")]
+pub mod elasticsearch;
+pub mod error;
+pub mod ffi;
+pub mod future;
+pub mod hash;
+#[cfg(any(feature = "prometheus", feature = "server"))]
+mod http;
+#[cfg(feature = "prometheus")]
+pub mod metrics;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+#[cfg(all(unix, feature = "mmap"))]
+pub(crate) mod mmap;
+#[cfg(feature = "object_store")]
+mod object;
+#[cfg(feature = "arrow")]
+mod parquet;
 pub mod parser;
+pub mod pretty;
+#[cfg(feature = "pyo3")]
+mod python;
+pub mod record;
+#[cfg(feature = "server")]
+pub mod server;
+mod sketch;
+pub mod source;
+mod spill;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+pub mod stats;
+pub mod template;
+pub mod time;
+pub mod tokenizer;
+mod watch;
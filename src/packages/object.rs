@@ -0,0 +1,130 @@
+use std::io::{self, Read};
+
+use bytes::Bytes;
+use futures_util::stream::{BoxStream, StreamExt};
+use object_store::aws::AmazonS3Builder;
+use object_store::azure::MicrosoftAzureBuilder;
+use object_store::gcp::GoogleCloudStorageBuilder;
+use object_store::http::HttpBuilder;
+use object_store::path::Path;
+use object_store::{ClientOptions, ObjectStore, ObjectStoreExt};
+use tokio::runtime::Runtime;
+use url::Url;
+
+// Objects in a store, streamed with the object_store crate as they're read: nothing is downloaded
+// to disk first. The stores take their credentials and settings from the environment (AWS_*,
+// GOOGLE_* and AZURE_* variables, and the instance metadata of the cloud it runs in).
+
+// the store an object at url is in, and where in the store; None if the scheme isn't one we know
+fn store(url: &str) -> io::Result<Option<(Box<dyn ObjectStore>, Path)>> {
+    let parsed = Url::parse(url).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", url, e)))?;
+    let key = || Path::from_url_path(parsed.path()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e));
+    let store: Box<dyn ObjectStore> = match parsed.scheme() {
+        "s3" => Box::new(AmazonS3Builder::from_env().with_url(url).build()?),
+        "gs" => Box::new(GoogleCloudStorageBuilder::from_env().with_url(url).build()?),
+        "az" | "abfs" | "abfss" => Box::new(MicrosoftAzureBuilder::from_env().with_url(url).build()?),
+        // the whole URL is the store, so the query of a presigned S3 URL or an Azure SAS token stays on
+        "http" | "https" => {
+            let options = ClientOptions::new().with_allow_http(parsed.scheme() == "http");
+            return Ok(Some((Box::new(HttpBuilder::new().with_url(url).with_client_options(options).build()?), Path::default())));
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some((store, key()?)))
+}
+
+/// The object at `url`, as it streams in.
+pub(crate) struct ObjectReader {
+    runtime: Runtime,
+    stream: BoxStream<'static, object_store::Result<Bytes>>,
+    chunk: Bytes,
+}
+
+impl ObjectReader {
+    /// Starts reading the object at `url`; an `Unsupported` error if the scheme isn't s3, gs, az
+    /// (or abfs[s]), http or https.
+    pub(crate) fn open(url: &str) -> io::Result<Self> {
+        let (store, key) = store(url)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, format!("can't read {}: no store for that scheme", url)))?;
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        let stream = runtime.block_on(store.get(&key))
+            .map_err(|e| {
+                let e = io::Error::from(e);
+                io::Error::new(e.kind(), format!("couldn't read {}: {}", url, e))
+            })?
+            .into_stream();
+        Ok(ObjectReader { runtime, stream, chunk: Bytes::new() })
+    }
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.runtime.block_on(self.stream.next()) {
+                Some(chunk) => self.chunk = chunk?,
+                None => return Ok(0),
+            }
+        }
+        let read = buf.len().min(self.chunk.len());
+        buf[..read].copy_from_slice(&self.chunk.split_to(read));
+        Ok(read)
+    }
+}
+
+// serves each response to one request on a local port, and hands back the requests' first lines
+#[cfg(test)]
+fn serve(responses: Vec<Vec<u8>>) -> (u16, std::thread::JoinHandle<Vec<String>>) {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = std::thread::spawn(move || {
+        let mut requests = vec![];
+        for response in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            requests.push(line.trim_end().to_string());
+            while line != "\r\n" {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+            }
+            stream.write_all(&response).unwrap();
+        }
+        requests
+    });
+    (port, server)
+}
+
+#[test]
+fn test_object_reader_http() {
+    let log = std::fs::read("data/HDFS_2k.log").unwrap();
+    let mut ok = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", log.len()).into_bytes();
+    ok.extend_from_slice(&log);
+    let (port, server) = serve(vec![ok]);
+    let mut read = vec![];
+    ObjectReader::open(&format!("http://127.0.0.1:{}/logs/app.log?sig=a%2Fb", port)).unwrap().read_to_end(&mut read).unwrap();
+    assert_eq!(read, log);
+    assert_eq!(server.join().unwrap(), ["GET /logs/app.log?sig=a%2Fb HTTP/1.1"]);
+}
+
+#[test]
+fn test_object_reader_errors() {
+    let missing = b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec();
+    let (port, server) = serve(vec![missing]);
+    let err = ObjectReader::open(&format!("http://127.0.0.1:{}/missing.log", port)).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    assert!(err.to_string().contains("/missing.log"));
+    server.join().unwrap();
+
+    assert_eq!(ObjectReader::open("ftp://logs/app.log").err().unwrap().kind(), io::ErrorKind::Unsupported);
+    assert_eq!(ObjectReader::open("not a url").err().unwrap().kind(), io::ErrorKind::InvalidInput);
+    // a known store that can't be reached, or that we have no credentials for, fails too
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    assert!(ObjectReader::open(&format!("http://127.0.0.1:{}/app.log", closed)).is_err());
+    if std::env::var_os("AZURE_STORAGE_ACCOUNT_NAME").is_none() {
+        assert_ne!(ObjectReader::open("az://logs/app.log").err().unwrap().kind(), io::ErrorKind::Unsupported);
+    }
+}
@@ -0,0 +1,102 @@
+use std::fs::File;
+use std::hash::BuildHasher;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use parquet::arrow::ArrowWriter;
+
+use crate::packages::parser::DictionaryResult;
+
+impl<S: BuildHasher + Default> DictionaryResult<S> {
+    /// Writes every dictionary to one Parquet file at `path`, for pandas, Polars, DuckDB and the like:
+    /// a row per n-gram with the columns `n` (its size, an int32), `ngram` (the key, a string) and
+    /// `count` (an int64), the sizes in order and each most frequent first. The token list isn't
+    /// written. The file is uncompressed; re-save it from your dataframe library to compress it.
+    pub fn write_parquet<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let batch = self.to_arrow_record_batch();
+        let mut writer = ArrowWriter::try_new(File::create(path)?, batch.schema(), None).map_err(io::Error::other)?;
+        writer.write(&batch).map_err(io::Error::other)?;
+        writer.close().map_err(io::Error::other)?;
+        Ok(())
+    }
+
+    /// The rows `write_parquet` writes, as one Arrow `RecordBatch` with the same schema: `n` (an
+    /// Int32), `ngram` (a Utf8) and `count` (an Int64), the sizes in order and each most frequent
+    /// first. For handing the dictionaries to Arrow-based libraries without going through a file.
+    pub fn to_arrow_record_batch(&self) -> arrow_array::RecordBatch {
+        use arrow_array::{Int32Array, Int64Array, StringArray};
+        use arrow_schema::{DataType, Field, Schema};
+
+        let rows = self.to_sorted_vec();
+        let schema = Schema::new(vec![
+            Field::new("n", DataType::Int32, false),
+            Field::new("ngram", DataType::Utf8, false),
+            Field::new("count", DataType::Int64, false),
+        ]);
+        let n = Int32Array::from_iter_values(rows.iter().map(|r| r.0 as i32));
+        let ngram = StringArray::from_iter_values(rows.iter().map(|r| r.1));
+        let count = Int64Array::from_iter_values(rows.iter().map(|r| r.2 as i64));
+        arrow_array::RecordBatch::try_new(Arc::new(schema), vec![Arc::new(n), Arc::new(ngram), Arc::new(count)])
+            .expect("the columns match the schema and are all as long")
+    }
+}
+
+#[test]
+fn test_write_parquet() {
+    use crate::LogFormat::Linux;
+    use crate::packages::parser::DictionaryBuilder;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::RowAccessor;
+
+    let result = DictionaryBuilder::new().parse_raw_conc("data/Linux_2k.log".to_string(), &Linux);
+    let path = std::env::temp_dir().join(format!("logram-test-{}.parquet", std::process::id()));
+    result.write_parquet(&path).unwrap();
+    let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+    let rows: Vec<(usize, String, u64)> = reader.get_row_iter(None).unwrap()
+        .map(|row| {
+            let row = row.unwrap();
+            (row.get_int(0).unwrap() as usize, row.get_string(1).unwrap().clone(), row.get_long(2).unwrap() as u64)
+        })
+        .collect();
+    let schema = reader.metadata().file_metadata().schema_descr();
+    let names: Vec<&str> = schema.columns().iter().map(|c| c.name()).collect();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(names, ["n", "ngram", "count"]);
+    let expected: Vec<(usize, String, u64)> = result.to_sorted_vec().into_iter().map(|(n, key, count)| (n, key.to_string(), count)).collect();
+    assert!(expected.len() > 10);
+    assert_eq!(rows, expected);
+}
+
+#[test]
+fn test_write_parquet_empty() {
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    let path = std::env::temp_dir().join(format!("logram-test-{}-empty.parquet", std::process::id()));
+    let empty: DictionaryResult = DictionaryResult::default();
+    empty.write_parquet(&path).unwrap();
+    let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+    let num_rows = reader.metadata().file_metadata().num_rows();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(num_rows, 0);
+}
+
+#[test]
+fn test_to_arrow_record_batch() {
+    use crate::LogFormat::Linux;
+    use crate::packages::parser::DictionaryBuilder;
+    use arrow_array::{Array, Int32Array, Int64Array, StringArray};
+
+    let result = DictionaryBuilder::new().parse_raw_conc("data/Linux_2k.log".to_string(), &Linux);
+    let batch = result.to_arrow_record_batch();
+    let rows = result.to_sorted_vec();
+    assert_eq!(batch.num_rows(), rows.len());
+    let n = batch.column_by_name("n").unwrap().as_any().downcast_ref::<Int32Array>().unwrap();
+    let ngram = batch.column_by_name("ngram").unwrap().as_any().downcast_ref::<StringArray>().unwrap();
+    let count = batch.column_by_name("count").unwrap().as_any().downcast_ref::<Int64Array>().unwrap();
+    for (i, (size, key, c)) in rows.into_iter().enumerate() {
+        assert_eq!((n.value(i) as usize, ngram.value(i), count.value(i) as u64), (size, key, c));
+    }
+    assert_eq!(n.null_count() + ngram.null_count() + count.null_count(), 0);
+}
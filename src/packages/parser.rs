@@ -1,9 +1,17 @@
+use std::borrow::Cow;
 use std::fs::File;
-use std::io::{self, BufRead, BufReader, Lines};
-use std::path::Path;
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::fs;
+use std::path::{Path, PathBuf};
 use regex::Regex;
 use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::collections::BTreeSet;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::collections::BinaryHeap;
+use std::cmp::Reverse;
+use std::convert::Infallible;
 
 use crate::LogFormat;
 use crate::LogFormat::Linux;
@@ -14,21 +22,55 @@ use crate::LogFormat::HPC;
 use crate::LogFormat::Proxifier;
 use crate::LogFormat::Android;
 use crate::LogFormat::HealthApp;
+use crate::LogFormat::Apache;
+use crate::LogFormat::Nginx;
+use crate::LogFormat::Syslog3164;
+use crate::LogFormat::Syslog5424;
+use crate::LogFormat::Kubernetes;
+use crate::LogFormat::WindowsEvent;
+use crate::LogFormat::Zookeeper;
+use crate::LogFormat::Kafka;
+use crate::LogFormat::Cassandra;
+use crate::LogFormat::BGL;
+use crate::LogFormat::Thunderbird;
+use crate::LogFormat::Mac;
 
-use std::sync::mpsc;
-use std::sync::Arc;
-use std::sync::Mutex;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::panic::{self, AssertUnwindSafe};
 use std::thread;
-use threadpool::ThreadPool;
-use threadpool_scope::scope_with;
-use dashmap::{DashMap, DashSet};
-use crate::packages::parser::Map::{TypeHash, TypeDash};
-use crate::packages::parser::Set::{TypeVec, TypeDSet};
+use std::time::{Duration, Instant};
+use std::sync::{Arc, Mutex, OnceLock};
+#[cfg(feature = "concurrent")]
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+#[cfg(feature = "concurrent")]
+use dashmap::DashMap;
+use smallvec::SmallVec;
+use crate::packages::error::ParserError;
+use crate::packages::hash::BuildNGramHasher;
+use crate::packages::sketch::{CountMin, HeavyHitters};
+use crate::packages::spill::{self, Run};
+use crate::packages::time::{Locale, SessionGap, TimeRange};
+use crate::packages::watch::FileWatch;
+use crate::packages::tokenizer::{pii_censors, Censor, GroupBy, Normalization, Normalize, RegexTokenizer, Tokenizer};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "concurrent")]
+use crate::packages::parser::Map::TypeDash;
+use crate::packages::parser::Map::TypeHash;
+
+// with the tracing feature, an info span entered for the rest of the block; nothing without it
+macro_rules! span {
+    ($($args:tt)*) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!($($args)*).entered();
+    };
+}
 
 pub fn format_string(lf: &LogFormat) -> String {
     match lf {
         Linux =>
-            r"<Month> <Date> <Time> <Level> <Component>(\\[<PID>\\])?: <Content>".to_string(),
+            r"<Month> <Date> <Time> <Level> <Component>(\[<PID>\])?: <Content>".to_string(),
         OpenStack =>
             r"'<Logrecord> <Date> <Time> <Pid> <Level> <Component> \[<ADDR>\] <Content>'".to_string(),
         Spark =>
@@ -42,11 +84,60 @@ pub fn format_string(lf: &LogFormat) -> String {
         Android =>
             r"<Date> <Time>  <Pid>  <Tid> <Level> <Component>: <Content>".to_string(),
         HealthApp =>
-            "<Time>\\|<Component>\\|<Pid>\\|<Content>".to_string()
+            "<Time>\\|<Component>\\|<Pid>\\|<Content>".to_string(),
+        // the Loghub-style error log: [Sun Dec 04 04:47:44 2005] [notice] workerEnv.init() ok ...
+        Apache =>
+            r"\[<Time>\] \[<Level>\] <Content>".to_string(),
+        // the default "combined" access log; the content runs from the request line to the bytes sent
+        Nginx =>
+            r#"<Addr> <Ident> <User> \[<Time>\] "<Content> "<Referer>" "<Agent>""#.to_string(),
+        // the <PRI> is optional here (files written by syslogd drop it), so it just ends up in <Month>
+        Syslog3164 =>
+            r"<Month> <Date> <Time> <Hostname> <Tag>(\[<Pid>\])?: <Content>".to_string(),
+        // the structured data stays in the content: it's usually what tells the events apart
+        Syslog5424 =>
+            r"<<Pri>><Version> <Timestamp> <Hostname> <AppName> <ProcId> <MsgId> <Content>".to_string(),
+        // CRI (containerd, CRI-O): 2023-01-01T00:00:00.000Z stdout F <content>; or Docker's json-file
+        // records, with the trailing \n inside the "log" string left out of the content (and the { in a
+        // field of its own, since regex_generator drops anything before the first field)
+        Kubernetes { json: false } =>
+            r"<Time> <Stream> <Tag> <Content>".to_string(),
+        Kubernetes { json: true } =>
+            r#"<Brace>"log":"<Content>(\\n)?","stream":"<Stream>","time":"<Time>"}"#.to_string(),
+        // Event Viewer's "Save All Events As" text export, tab or comma separated: Level, Date and Time,
+        // Source, Event ID, Task Category and then the message
+        WindowsEvent =>
+            r"<Level>[\t,]<Date>[\t,]<Source>[\t,]<EventID>[\t,]<Category>[\t,]<Content>".to_string(),
+        Zookeeper =>
+            r"<Date> <Time> - <Level>  \[<Node>:<Component>@<Id>\] - <Content>".to_string(),
+        // the broker's server.log
+        Kafka =>
+            r"\[<Date> <Time>\] <Level> <Content>".to_string(),
+        // system.log
+        Cassandra =>
+            r"<Level>  \[<Thread>\] <Date> <Time> <Component> - <Content>".to_string(),
+        BGL =>
+            r"<Label> <Timestamp> <Date> <Node> <Time> <NodeRepeat> <Type> <Component> <Level> <Content>".to_string(),
+        Thunderbird =>
+            r"<Label> <Timestamp> <Date> <User> <Month> <Day> <Time> <Location> <Component>(\[<PID>\])?: <Content>".to_string(),
+        Mac =>
+            r"<Month>  <Date> <Time> <User> <Component>\[<PID>\]( \(<Address>\))?: <Content>".to_string(),
+        LogFormat::Custom { format, .. } =>
+            format.clone(),
+    }
+}
+
+pub(crate) fn censored_regexps(lf: &LogFormat) -> Vec<Regex> {
+    if let LogFormat::Custom { censor, .. } = lf {
+        return censor.clone();
     }
+    // the built-in ones only differ by format, and a Regex shares its compiled program with its clones
+    static CENSORS: OnceLock<Mutex<HashMap<std::mem::Discriminant<LogFormat>, Vec<Regex>>>> = OnceLock::new();
+    let mut censors = CENSORS.get_or_init(Default::default).lock().unwrap();
+    censors.entry(std::mem::discriminant(lf)).or_insert_with(|| compile_censors(lf)).clone()
 }
 
-pub fn censored_regexps(lf: &LogFormat) -> Vec<Regex> {
+fn compile_censors(lf: &LogFormat) -> Vec<Regex> {
     match lf {
         Linux =>
             vec![Regex::new(r"(\d+\.){3}\d+").unwrap(),
@@ -78,16 +169,129 @@ pub fn censored_regexps(lf: &LogFormat) -> Vec<Regex> {
                  Regex::new(r"([\w-]+\.){2,}[\w-]+").unwrap(),
                  Regex::new(r"\b(\-?\+?\d+)\b|\b0[Xx][a-fA-F\d]+\b|\b[a-fA-F\d]{4,}\b").unwrap()],
         HealthApp => vec![],
+        Apache | Nginx =>
+            vec![Regex::new(r"\[\w{3} \w{3} \d{2} \d{2}:\d{2}:\d{2}(\.\d+)? \d{4}\]|\[\d{2}/\w{3}/\d{4}(:\d{2}){3} [+-]\d{4}\]").unwrap(), // [timestamps]
+                 Regex::new(r"\w+://\S+|/\S*").unwrap(), // URLs and paths
+                 Regex::new(r"(\d+\.){3}\d+(:\d+)?").unwrap(), // IP
+                 Regex::new(r"\b\d+( bytes|\s?[KMG]B\b)|\b\d+$").unwrap()], // sizes, and the bytes sent at the end of an access line
+        Syslog3164 =>
+            vec![Regex::new(r"<\d{1,3}>").unwrap(), // PRI of a relayed message
+                 Regex::new(r"(\d+\.){3}\d+").unwrap(),
+                 Regex::new(r"\d{2}:\d{2}:\d{2}").unwrap()],
+        Syslog5424 =>
+            vec![Regex::new(r"<\d{1,3}>").unwrap(), // PRI of a relayed message
+                 Regex::new(r"[\w.-]+@\d+").unwrap(), // SD-ID with private enterprise number
+                 Regex::new(r#""(\\.|[^"\\])*""#).unwrap(), // SD-PARAM values (and any other quoted string)
+                 Regex::new(r"\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}(\.\d+)?(Z|[+-]\d{2}:\d{2})").unwrap(),
+                 Regex::new(r"(\d+\.){3}\d+").unwrap()],
+        Kubernetes { .. } =>
+            vec![Regex::new(r"\b[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}\b").unwrap(), // pod UID
+                 Regex::new(r"\d{4}-\d{2}-\d{2}[T ]\d{2}:\d{2}:\d{2}(\.\d+)?(Z|[+-]\d{2}:?\d{2})?").unwrap(),
+                 Regex::new(r"(\d+\.){3}\d+(:\d+)?").unwrap()], // IP
+        WindowsEvent =>
+            vec![Regex::new(r"\{?\b[0-9a-fA-F]{8}-([0-9a-fA-F]{4}-){3}[0-9a-fA-F]{12}\b\}?").unwrap(), // GUID
+                 Regex::new(r"\bS-1-\d+(-\d+)+\b").unwrap(), // SID
+                 Regex::new(r"\b0[xX][0-9a-fA-F]+\b").unwrap()],
+        Zookeeper =>
+            vec![Regex::new(r"(/|)(\d+\.){3}\d+(:\d+)?").unwrap(), // IP
+                 Regex::new(r"\b0x[0-9a-fA-F]+\b").unwrap()], // session id, zxid
+        Kafka =>
+            vec![Regex::new(r"(/|)(\d+\.){3}\d+(:\d+)?").unwrap(), // IP
+                 Regex::new(r"\b[\w.]+-\d+\b").unwrap(), // topic-partition
+                 Regex::new(r"\b\d+\b").unwrap()], // offsets, epochs, ids
+        Cassandra =>
+            vec![Regex::new(r"[(\[]-?\d+,\s*-?\d+[)\]]").unwrap(), // token range
+                 Regex::new(r"(/|)(\d+\.){3}\d+(:\d+)?").unwrap(), // IP
+                 Regex::new(r"-?\b\d{6,}\b").unwrap()], // token
+        BGL =>
+            vec![Regex::new(r"core\.\d+").unwrap()],
+        Thunderbird =>
+            vec![Regex::new(r"(\d+\.){3}\d+").unwrap()],
+        Mac =>
+            vec![Regex::new(r"([\w-]+\.){2,}[\w-]+").unwrap()],
+        LogFormat::Custom { censor, .. } => censor.clone(),
     }
 }
 
-// https://doc.rust-lang.org/rust-by-example/std_misc/file/read_lines.html
-// The output is wrapped in a Result to allow matching on errors
-// Returns an Iterator to the Reader of the lines of the file.
-fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
+// A reader of the file, to read its lines from. .gz and .zst files are decompressed on the fly when built
+// with the flate2/zstd features, "-" is stdin, and with the object_store feature s3://, gs://, az://
+// and http(s):// URLs are streamed from the store.
+pub(crate) fn read_lines<P>(filename: P) -> io::Result<io::BufReader<Box<dyn Read>>>
 where P: AsRef<Path>, {
-    let file = File::open(filename)?;
-    Ok(io::BufReader::new(file).lines())
+    let path = filename.as_ref();
+    if path == Path::new("-") {
+        return Ok(io::BufReader::new(Box::new(io::stdin()) as Box<dyn Read>));
+    }
+    if let Some(url) = path.to_str().filter(|p| p.contains("://")) {
+        return Ok(io::BufReader::new(decompressor(path, read_object(url)?)?));
+    }
+    let file = File::open(path)?;
+    Ok(io::BufReader::new(decompressor(path, file)?))
+}
+
+#[cfg(feature = "object_store")]
+fn read_object(url: &str) -> io::Result<crate::packages::object::ObjectReader> {
+    crate::packages::object::ObjectReader::open(url)
+}
+
+#[cfg(not(feature = "object_store"))]
+fn read_object(url: &str) -> io::Result<File> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, format!("reading {} needs the object_store feature", url)))
+}
+
+fn decompressor<R: Read + 'static>(path: &Path, file: R) -> io::Result<Box<dyn Read>> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        #[cfg(feature = "flate2")]
+        Some("gz") => Ok(Box::new(flate2::read::MultiGzDecoder::new(file))),
+        #[cfg(not(feature = "flate2"))]
+        Some("gz") => Err(io::Error::new(io::ErrorKind::Unsupported, "reading .gz files needs the flate2 feature")),
+        #[cfg(feature = "zstd")]
+        Some("zst") => Ok(Box::new(zstd::stream::read::Decoder::new(file)?)),
+        #[cfg(not(feature = "zstd"))]
+        Some("zst") => Err(io::Error::new(io::ErrorKind::Unsupported, "reading .zst files needs the zstd feature")),
+        _ => Ok(Box::new(file)),
+    }
+}
+
+#[cfg(feature = "flate2")]
+#[test]
+fn test_read_lines_gz() {
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    let path = std::env::temp_dir().join(format!("logram-test-{}.log.gz", std::process::id()));
+    let mut encoder = GzEncoder::new(File::create(&path).unwrap(), Compression::default());
+    encoder.write_all(b"first line\nsecond line\n").unwrap();
+    encoder.finish().unwrap();
+    let lines: Vec<String> = read_lines(&path).unwrap().lines().map(|l| l.unwrap()).collect();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(lines, vec!["first line", "second line"]);
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_read_lines_zst() {
+    let path = std::env::temp_dir().join(format!("logram-test-{}.log.zst", std::process::id()));
+    fs::write(&path, zstd::encode_all(&b"first line\r\nsecond line"[..], 0).unwrap()).unwrap();
+    let lines: Vec<String> = read_lines(&path).unwrap().lines().map(|l| l.unwrap()).collect();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(lines, vec!["first line", "second line"]);
+}
+
+#[cfg(not(feature = "zstd"))]
+#[test]
+fn test_read_lines_zst_without_feature() {
+    let path = std::env::temp_dir().join(format!("logram-test-{}.log.zst", std::process::id()));
+    File::create(&path).unwrap();
+    let err = read_lines(&path).err().unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+}
+
+#[cfg(not(feature = "object_store"))]
+#[test]
+fn test_read_lines_url_without_feature() {
+    assert_eq!(read_lines("s3://logs/app.log").err().unwrap().kind(), io::ErrorKind::Unsupported);
 }
 
 fn regex_generator_helper(format: String) -> String {
@@ -106,11 +310,193 @@ fn regex_generator_helper(format: String) -> String {
         r.push_str(format!("(?P<{}>.*?)", header).as_str());
         prev_end = Some(m.end());
     }
-    return r;
+    r
+}
+
+// the formats compiled so far, so that the runs after the first (and every tokenizer of a run) share
+// the compiled regex rather than compiling the format again; forgotten past FORMATS_CACHED of them
+static FORMATS: OnceLock<Mutex<HashMap<String, Regex>>> = OnceLock::new();
+const FORMATS_CACHED: usize = 256;
+
+fn compile_format(format: String) -> Result<Regex, regex::Error> {
+    let formats = FORMATS.get_or_init(Default::default);
+    if let Some(re) = formats.lock().unwrap().get(&format) {
+        return Ok(re.clone());
+    }
+    let re = Regex::new(format!("^{}$", regex_generator_helper(format.clone())).as_str())?;
+    let mut formats = formats.lock().unwrap();
+    if formats.len() >= FORMATS_CACHED {
+        formats.clear();
+    }
+    formats.insert(format, re.clone());
+    Ok(re)
+}
+
+// the regex of a format string, compiled once per process: later calls with the same format get
+// (a clone of) the same one
+#[cfg(test)]
+pub(crate) fn regex_generator(format: String) -> Regex {
+    compile_format(format).unwrap()
+}
+
+// the regex of a format string, or what's wrong with one that doesn't compile or has no <Content>
+// field to tokenize
+pub(crate) fn regex_generator_checked(format: String) -> Result<Regex, ParserError> {
+    let re = compile_format(format.clone())?;
+    if !re.capture_names().any(|name| name == Some("Content")) {
+        return Err(ParserError::FormatMismatch(format!("no <Content> field in {}", format)));
+    }
+    Ok(re)
+}
+
+/// The fields of a line that matched a format (`Month`, `Level`, `Content`...) with what they
+/// captured, in the format's order; an optional field that didn't take part is left out.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParsedLine {
+    pub fields: Vec<(String, String)>,
+}
+
+impl ParsedLine {
+    pub fn get(&self, field: &str) -> Option<&str> {
+        self.fields.iter().find(|(name, _)| name == field).map(|(_, value)| value.as_str())
+    }
+
+    /// The `<Content>` field, which every format has.
+    pub fn content(&self) -> &str {
+        self.get("Content").unwrap_or_default()
+    }
+}
+
+/// Splits lines into the fields of a format, the way the dictionary builders see them, without
+/// building anything.
+#[derive(Clone, Debug)]
+pub struct LineParser {
+    regex: Regex,
+}
+
+impl LineParser {
+    pub fn new(lf: &LogFormat) -> Result<Self, ParserError> {
+        Ok(LineParser { regex: regex_generator_checked(format_string(lf))? })
+    }
+
+    /// The names of the format's fields, in order.
+    pub fn fields(&self) -> impl Iterator<Item = &str> {
+        self.regex.capture_names().flatten()
+    }
+
+    /// Whether `line` matches the format, without pulling out its fields.
+    pub fn matches(&self, line: &str) -> bool {
+        self.regex.is_match(line.trim())
+    }
+
+    /// The fields of `line`, or `None` if it doesn't match the format.
+    pub fn parse(&self, line: &str) -> Option<ParsedLine> {
+        let captures = self.regex.captures(line.trim())?;
+        let fields = self.regex.capture_names().flatten()
+            .filter_map(|name| captures.name(name).map(|m| (name.to_string(), m.as_str().to_string())))
+            .collect();
+        Some(ParsedLine { fields })
+    }
+}
+
+/// Panics on any `ParserError`; see `parse_line_checked`.
+pub fn parse_line(line: &str, lf: &LogFormat) -> Option<ParsedLine> {
+    parse_line_checked(line, lf).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// `LineParser::parse` for one line. The compiled format is cached, but keep a `LineParser` around
+/// for more than a few lines all the same.
+pub fn parse_line_checked(line: &str, lf: &LogFormat) -> Result<Option<ParsedLine>, ParserError> {
+    Ok(LineParser::new(lf)?.parse(line))
+}
+
+#[test]
+fn test_parse_line() {
+    let parsed = parse_line("Jun 14 15:16:02 combo sshd(pam_unix)[19937]: check pass; user unknown", &Linux).unwrap();
+    assert_eq!(parsed.get("Component"), Some("sshd(pam_unix)"));
+    assert_eq!(parsed.get("PID"), Some("19937"));
+    assert_eq!(parsed.content(), "check pass; user unknown");
+    assert_eq!(parsed.fields[0], ("Month".to_string(), "Jun".to_string()));
+    // the optional [PID] isn't there
+    let parsed = parse_line("Jun 14 15:16:02 combo kernel: Linux version 2.6.5-1.358", &Linux).unwrap();
+    assert_eq!(parsed.get("PID"), None);
+    assert_eq!(parsed.fields.len(), 6);
+    assert_eq!(parse_line("not a linux line", &Linux), None);
+
+    let bad = LogFormat::Custom { format: "<Date> <Time>".to_string(), censor: vec![] };
+    assert!(matches!(parse_line_checked("a b", &bad), Err(ParserError::FormatMismatch(_))));
+}
+
+/// What `validate_format` found out about a format string on some sample lines: how many it
+/// matched, and the first few distinct values each field captured, in the format's order.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FormatReport {
+    pub matched: usize,
+    pub sampled: usize,
+    pub fields: Vec<(String, Vec<String>)>,
+}
+
+// how many distinct values of a field a FormatReport shows
+const FORMAT_EXAMPLES: usize = 3;
+
+impl FormatReport {
+    /// The fraction of the sample lines the format matched; 0 for no lines.
+    pub fn match_rate(&self) -> f64 {
+        if self.sampled == 0 { 0.0 } else { self.matched as f64 / self.sampled as f64 }
+    }
+}
+
+impl std::fmt::Display for FormatReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "matched {}/{} lines ({:.1}%)", self.matched, self.sampled, self.match_rate() * 100.0)?;
+        for (field, examples) in &self.fields {
+            writeln!(f, "  <{}>: {}", field, examples.iter().map(|e| format!("{:?}", e)).collect::<Vec<_>>().join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Tries the format string `format` on `sample_lines`, failing as `regex_generator_checked` does if it
+/// doesn't compile or has no `<Content>`. A field that captured nothing shows no examples; an
+/// optional one that didn't take part is left out.
+pub fn validate_format<S: AsRef<str>>(format: &str, sample_lines: &[S]) -> Result<FormatReport, ParserError> {
+    let re = regex_generator_checked(format.to_string())?;
+    let mut fields: Vec<(String, Vec<String>)> = re.capture_names().flatten().map(|name| (name.to_string(), vec![])).collect();
+    let mut matched = 0;
+    for line in sample_lines {
+        let Some(captures) = re.captures(line.as_ref().trim()) else { continue };
+        matched += 1;
+        for (name, examples) in &mut fields {
+            if let Some(value) = captures.name(name).map(|m| m.as_str()).filter(|v| !v.is_empty()) {
+                if examples.len() < FORMAT_EXAMPLES && !examples.iter().any(|e| e == value) {
+                    examples.push(value.to_string());
+                }
+            }
+        }
+    }
+    Ok(FormatReport { matched, sampled: sample_lines.len(), fields })
+}
+
+/// `validate_format` on the first `sample` lines of `raw_fn`.
+pub fn validate_format_file<P: AsRef<Path>>(format: &str, raw_fn: P, sample: usize) -> Result<FormatReport, ParserError> {
+    let lines = decode_lines(read_lines(raw_fn)?.split(b'\n').take(sample), false, &mut 0, &mut Pick::all())?;
+    validate_format(format, &lines)
 }
 
-pub fn regex_generator(format: String) -> Regex {
-    return Regex::new(format!("^{}$", regex_generator_helper(format)).as_str()).unwrap();
+#[test]
+fn test_validate_format() {
+    let report = validate_format_file(&format_string(&HDFS), "data/HDFS_2k.log", 100).unwrap();
+    assert_eq!((report.matched, report.sampled), (100, 100));
+    assert_eq!(report.fields[0], ("Date".to_string(), vec!["081109".to_string()]));
+    assert_eq!(report.fields.iter().map(|(f, _)| f.as_str()).collect::<Vec<_>>(), ["Date", "Time", "Pid", "Level", "Component", "Content"]);
+    assert_eq!(report.fields[3].1, ["INFO", "WARN"]);
+
+    let lines = ["a|b|c", "no pipes here", "x|y|z"];
+    let report = validate_format(r"<Left>\|<Middle>\|<Content>", &lines).unwrap();
+    assert_eq!(report.match_rate(), 2.0 / 3.0);
+    assert_eq!(report.fields[1].1, ["b", "y"]);
+    assert!(report.to_string().starts_with("matched 2/3 lines (66.7%)\n  <Left>: \"a\", \"x\""));
+    assert!(matches!(validate_format("<Left> <Right>", &lines), Err(ParserError::FormatMismatch(_))));
 }
 
 #[test]
@@ -122,13 +508,127 @@ fn test_regex_generator_helper() {
     assert_eq!(regex_generator_helper(openstack_format), r"(?P<Logrecord>.*?)\s+(?P<Date>.*?)\s+(?P<Time>.*?)\s+(?P<Pid>.*?)\s+(?P<Level>.*?)\s+(?P<Component>.*?)\s+(\[(?P<ADDR>.*?)\])?\s+(?P<Content>.*?)");
 }
 
+#[test]
+fn test_format_cache() {
+    let format = format_string(&HDFS);
+    let re = regex_generator(format.clone());
+    assert_eq!(FORMATS.get().unwrap().lock().unwrap()[&format].as_str(), re.as_str());
+    assert_eq!(regex_generator_checked(format).unwrap().as_str(), re.as_str());
+    assert!(regex_generator_checked("<A>(<Content>".to_string()).is_err());
+    assert!(!FORMATS.get().unwrap().lock().unwrap().contains_key("<A>(<Content>"));
+    let censors: Vec<String> = censored_regexps(&HDFS).iter().map(|re| re.to_string()).collect();
+    assert_eq!(censors, compile_censors(&HDFS).iter().map(|re| re.to_string()).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_custom_format() {
+    let nginx = LogFormat::Custom {
+        format: r#"<Addr> - <User> \[<Time>\] "<Content>" <Status> <Bytes>"#.to_string(),
+        censor: vec![Regex::new(r"/[\w/.-]*").unwrap()],
+    };
+    let line = r#"10.0.0.7 - - [14/Jun/2023:15:16:02 +0000] "GET /static/app.js HTTP/1.1" 200 512"#.to_string();
+    let re = regex_generator(format_string(&nginx));
+    assert_eq!(token_splitter(line, &re, &censored_regexps(&nginx)), vec!["GET", "<*>", "HTTP<*>"]);
+}
+
+#[test]
+fn test_apache_format() {
+    let line = "[Sun Dec 04 04:51:18 2005] [error] [client 81.199.21.119] Directory index forbidden by rule: /var/www/html/".to_string();
+    let re = regex_generator(format_string(&Apache));
+    assert_eq!(token_splitter(line, &re, &censored_regexps(&Apache)), vec!["[client", "<*>]", "Directory", "index", "forbidden", "by", "rule:", "<*>"]);
+}
+
+#[test]
+fn test_nginx_format() {
+    let line = r#"10.0.0.7 - - [14/Jun/2023:15:16:02 +0000] "GET /static/app.js?v=3 HTTP/1.1" 200 5120 "https://example.com/" "curl/8.0""#.to_string();
+    let re = regex_generator(format_string(&Nginx));
+    assert_eq!(token_splitter(line, &re, &censored_regexps(&Nginx)), vec!["GET", "<*>", "HTTP<*>", "200", "<*>"]);
+}
+
+#[test]
+fn test_syslog_formats() {
+    let re = regex_generator(format_string(&Syslog3164));
+    let line = "<34>Oct 11 22:14:15 mymachine su: 'su root' failed for lonvick on /dev/pts/8".to_string();
+    assert_eq!(token_splitter(line, &re, &censored_regexps(&Syslog3164)), vec!["'su", "root'", "failed", "for", "lonvick", "on", "/dev/pts/8"]);
+    let line = "Oct 11 22:14:15 mymachine sshd[26190]: Accepted password for root from 10.0.0.7 port 22 ssh2".to_string();
+    assert_eq!(token_splitter(line, &re, &censored_regexps(&Syslog3164)), vec!["Accepted", "password", "for", "root", "from", "<*>", "port", "22", "ssh2"]);
+
+    let re = regex_generator(format_string(&Syslog5424));
+    let line = r#"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 [exampleSDID@32473 iut="3" eventSource="Application"] An application event"#.to_string();
+    assert_eq!(token_splitter(line, &re, &censored_regexps(&Syslog5424)), vec!["[<*>", "iut=<*>", "eventSource=<*>]", "An", "application", "event"]);
+}
+
+#[test]
+fn test_kubernetes_format() {
+    let cri = Kubernetes { json: false };
+    let re = regex_generator(format_string(&cri));
+    let line = "2023-01-01T00:00:00.000000001Z stdout F deleted pod 3f9b1c2e-8d7a-4b6c-9e5f-0a1b2c3d4e5f from 10.244.1.7:8080".to_string();
+    assert_eq!(token_splitter(line, &re, &censored_regexps(&cri)), vec!["deleted", "pod", "<*>", "from", "<*>"]);
+
+    let docker = Kubernetes { json: true };
+    let re = regex_generator(format_string(&docker));
+    let line = r#"{"log":"deleted pod 3f9b1c2e-8d7a-4b6c-9e5f-0a1b2c3d4e5f\n","stream":"stderr","time":"2023-01-01T00:00:00.000000001Z"}"#.to_string();
+    assert_eq!(token_splitter(line, &re, &censored_regexps(&docker)), vec!["deleted", "pod", "<*>"]);
+}
+
+#[test]
+fn test_windows_event_format() {
+    let re = regex_generator(format_string(&WindowsEvent));
+    let tabbed = "Information\t10/14/2026 9:15:02 AM\tMicrosoft-Windows-Security-Auditing\t4624\tLogon\tAn account was logged on. Security ID: S-1-5-21-3623811015-3361044348-30300820-1013 Logon GUID: {6a1c3e47-2b8f-4d0e-9c5a-7f3e1b2d4c6a} Status: 0xC000006D".to_string();
+    assert_eq!(token_splitter(tabbed, &re, &censored_regexps(&WindowsEvent)),
+               vec!["An", "account", "was", "logged", "on.", "Security", "ID:", "<*>", "Logon", "GUID:", "<*>", "Status:", "<*>"]);
+    let commas = "Error,10/14/2026 9:15:02 AM,Service Control Manager,7000,None,The Foo service failed to start, error 0x80070005.".to_string();
+    assert_eq!(token_splitter(commas, &re, &censored_regexps(&WindowsEvent)), vec!["The", "Foo", "service", "failed", "to", "start,", "error", "<*>."]);
+}
+
+#[test]
+fn test_zookeeper_kafka_cassandra_formats() {
+    let re = regex_generator(format_string(&Zookeeper));
+    let line = "2015-07-29 19:04:12,394 - INFO  [NIOServerCxn.Factory:0.0.0.0/0.0.0.0:2181:NIOServerCnxn@1001] - Closed socket connection for client /10.10.34.11:48213 which had sessionid 0x14ed93111f2002e".to_string();
+    assert_eq!(token_splitter(line, &re, &censored_regexps(&Zookeeper)),
+               vec!["Closed", "socket", "connection", "for", "client", "<*>", "which", "had", "sessionid", "<*>"]);
+
+    let re = regex_generator(format_string(&Kafka));
+    let line = "[2023-01-01 00:00:00,123] INFO [Log partition=orders-3, dir=/var/kafka] Truncating to offset 1234 (kafka.log.Log)".to_string();
+    assert_eq!(token_splitter(line, &re, &censored_regexps(&Kafka)),
+               vec!["[Log", "partition=<*>,", "dir=/var/kafka]", "Truncating", "to", "offset", "<*>", "(kafka.log.Log)"]);
+
+    let re = regex_generator(format_string(&Cassandra));
+    let line = "INFO  [main] 2023-01-01 00:00:00,123 StorageService.java:1529 - Repairing range (-9223372036854775808,-3074457345618258603] with /10.0.0.2".to_string();
+    assert_eq!(token_splitter(line, &re, &censored_regexps(&Cassandra)), vec!["Repairing", "range", "<*>", "with", "<*>"]);
+}
+
+#[test]
+fn test_bgl_thunderbird_mac_formats() {
+    let re = regex_generator(format_string(&BGL));
+    let line = "- 1117838570 2005.06.03 R02-M1-N0-C:J12-U11 2005-06-03-15.42.50.675872 R02-M1-N0-C:J12-U11 RAS KERNEL INFO instruction cache parity error corrected in core.2275".to_string();
+    assert_eq!(token_splitter(line, &re, &censored_regexps(&BGL)),
+               vec!["instruction", "cache", "parity", "error", "corrected", "in", "<*>"]);
+
+    let re = regex_generator(format_string(&Thunderbird));
+    let line = "- 1131566461 2005.11.09 dn228 Nov 9 12:01:01 dn228/dn228 crond(pam_unix)[2915]: session opened for user root by (uid=0) from 10.100.20.250".to_string();
+    assert_eq!(token_splitter(line, &re, &censored_regexps(&Thunderbird)),
+               vec!["session", "opened", "for", "user", "root", "by", "(uid=0)", "from", "<*>"]);
+
+    let re = regex_generator(format_string(&Mac));
+    let line = "Jul  1 09:00:55 calvisitor-10-105-160-95 com.apple.CDScheduler[43]: Thermal pressure state: 1 Memory pressure state: 0".to_string();
+    assert_eq!(token_splitter(line, &re, &censored_regexps(&Mac)),
+               vec!["Thermal", "pressure", "state:", "1", "Memory", "pressure", "state:", "0"]);
+    let line = "Jul  1 09:01:05 calvisitor-10-105-160-95 corecaptured[32]: CCFile::captureLog Received Capture notice id: 1498838465.067488 from com.apple.driver.AirPort.Brcm4360.0".to_string();
+    assert_eq!(token_splitter(line, &re, &censored_regexps(&Mac)),
+               vec!["CCFile::captureLog", "Received", "Capture", "notice", "id:", "1498838465.067488", "from", "<*>"]);
+}
+
 /// Replaces provided (domain-specific) regexps with <*> in the log_line.
+#[cfg(test)]
 fn apply_domain_specific_re(log_line: String, domain_specific_re:&Vec<Regex>) -> String {
     let mut line = format!(" {}", log_line);
     for s in domain_specific_re {
-        line = s.replace_all(&line, "<*>").to_string();
+        if let Cow::Owned(replaced) = s.replace_all(&line, "<*>") {
+            line = replaced;
+        }
     }
-    return line;
+    line
 }
 
 #[test]
@@ -138,14 +638,16 @@ fn test_apply_domain_specific_re() {
     assert_eq!(censored_line, " q<*> Jun 14 <*> combo sshd(pam_unix)[19937]: check pass; <*> user unknown");
 }
 
-pub fn token_splitter(log_line: String, re:&Regex, domain_specific_re:&Vec<Regex>) -> Vec<String> {
+// the tokenizer RegexTokenizer started out as, which its tests check it against
+#[cfg(test)]
+pub(crate) fn token_splitter(log_line: String, re:&Regex, domain_specific_re:&Vec<Regex>) -> Vec<String> {
     if let Some(m) = re.captures(log_line.trim()) {
         let message = m.name("Content").unwrap().as_str().to_string();
         // println!("{}", &message);
         let line = apply_domain_specific_re(message, domain_specific_re);
-        return line.trim().split_whitespace().map(|s| s.to_string()).collect();
+        crate::packages::tokenizer::split_tokens(&line).map(|s| s.to_string()).collect()
     } else {
-        return vec![];
+        vec![]
     }
 }
 
@@ -157,417 +659,4727 @@ fn test_token_splitter() {
     assert_eq!(split_line, vec!["check", "pass;", "user", "unknown"]);
 }
 
-enum Map<'a> {
-    TypeHash(&'a mut HashMap<String, i32>),
-    TypeDash(&'a DashMap<String, i32>),
-}
-
-enum Set<'a> {
-    TypeVec(&'a mut Vec<String>),
-    TypeDSet(&'a DashSet<String>),
-}
-
-// processes line, adding to the end of line the first two tokens from lookahead_line, and returns the first 2 tokens on this line
-fn process_dictionary_builder_line(line: String, lookahead_line: Option<String>, regexp:&Regex,
-                                   regexps:&Vec<Regex>, dbl: Map,
-                                   trpl: Map, all_token_list: Set,
-                                   prev1: Option<String>, prev2: Option<String>) -> (Option<String>, Option<String>) {
-    let (next1, next2) = match lookahead_line {
-        None => (None, None),
-        Some(ll) => {
-            let next_tokens = token_splitter(ll, &regexp, &regexps);
-            match next_tokens.len() {
-                0 => (None, None),
-                1 => (Some(next_tokens[0].clone()), None),
-                _ => (Some(next_tokens[0].clone()), Some(next_tokens[1].clone()))
-            }
+/// Joins the tokens of an n-gram into a dictionary key with `sep`, escaping any `\` or first character
+/// of `sep` inside a token with a `\`, so that `split_ngram` can always take the key apart again.
+pub fn join_ngram<S: AsRef<str>>(tokens: &[S], sep: &str) -> String {
+    let first = sep.chars().next();
+    let mut key = String::new();
+    for (i, t) in tokens.iter().enumerate() {
+        if i > 0 {
+            key.push_str(sep);
         }
-    };
-
-    let mut tokens = token_splitter(line, &regexp, &regexps);
-    if tokens.is_empty() {
-        return (None, None);
-    }
-    match all_token_list {
-        TypeVec(all_token_list) => {
-            tokens.iter().for_each(|t| if !all_token_list.contains(t) { all_token_list.push(t.clone()) } );
-        },
-        TypeDSet(all_token_list) => {
-            tokens.iter().for_each(|t| if !all_token_list.contains(t) { all_token_list.insert(t.clone()); } );
+        let t = t.as_ref();
+        if !t.contains(|c| c == '\\' || Some(c) == first) {
+            key.push_str(t);
+            continue;
         }
-    }
-
-    // keep this for later when we'll return it
-    let last1 = match tokens.len() {
-        0 => None,
-        n => Some(tokens[n-1].clone())
-    };
-    let last2 = match tokens.len() {
-        0 => None,
-        1 => None,
-        n => Some(tokens[n-2].clone())
-    };
-
-    let mut tokens2_ = match prev1 {
-        None => tokens,
-        Some(x) => { let mut t = vec![x]; t.append(&mut tokens); t}
-    };
-    let mut tokens2 = match next1 {
-        None => tokens2_,
-        Some(x) => { tokens2_.push(x); tokens2_ }
-    };
-
-    match dbl {
-        TypeHash(dbl) => {
-            for doubles in tokens2.windows(2) {
-                let double_tmp = format!("{}^{}", doubles[0], doubles[1]);
-                *dbl.entry(double_tmp.to_owned()).or_default() += 1;
-            }
-        },
-        TypeDash(dbl) => {
-            for doubles in tokens2.windows(2) {
-                let double_tmp = format!("{}^{}", doubles[0], doubles[1]);
-                *dbl.entry(double_tmp.to_owned()).or_default() += 1;
+        for c in t.chars() {
+            if c == '\\' || Some(c) == first {
+                key.push('\\');
             }
+            key.push(c);
         }
     }
+    key
+}
 
-    let mut tokens3_ = match prev2 {
-        None => tokens2,
-        Some(x) => { let mut t = vec![x]; t.append(&mut tokens2); t}
-    };
-    let tokens3 = match next2 {
-        None => tokens3_,
-        Some(x) => { tokens3_.push(x); tokens3_ }
-    };
-    match trpl {
-        TypeHash(trpl) => {
-            for triples in tokens3.windows(3) {
-                let triple_tmp = format!("{}^{}^{}", triples[0], triples[1], triples[2]);
-                *trpl.entry(triple_tmp.to_owned()).or_default() += 1;
-            }
-        },
-        TypeDash(trpl) => {
-            for triples in tokens3.windows(3) {
-                let triple_tmp = format!("{}^{}^{}", triples[0], triples[1], triples[2]);
-                *trpl.entry(triple_tmp.to_owned()).or_default() += 1;
-            }
+/// The inverse of `join_ngram`.
+pub fn split_ngram(key: &str, sep: &str) -> Vec<String> {
+    let mut tokens = vec![String::new()];
+    let mut rest = key;
+    while let Some(c) = rest.chars().next() {
+        if c == '\\' {
+            let escaped = &rest[1..];
+            let len = escaped.chars().next().map_or(0, char::len_utf8);
+            tokens.last_mut().unwrap().push_str(&escaped[..len]);
+            rest = &escaped[len..];
+        } else if rest.starts_with(sep) {
+            tokens.push(String::new());
+            rest = &rest[sep.len()..];
+        } else {
+            tokens.last_mut().unwrap().push(c);
+            rest = &rest[c.len_utf8()..];
         }
     }
-    return (last1, last2); // returns the positions of the last two tokens of the "prev" line for the next iteration
+    tokens
 }
 
-fn dictionary_builder(raw_fn: String, format: String, regexps: Vec<Regex>, num_threads: Option<u32>) -> (HashMap<String, i32>, HashMap<String, i32>, Vec<String>) {
-    let mut dbl = HashMap::new();
-    let mut trpl = HashMap::new();
-    let mut all_token_list = vec![];
-    // let regex = regex_generator(format.clone());
-    let mut vec_lines = vec![];
+#[test]
+fn test_join_split_ngram() {
+    let plain = ["check", "pass;"];
+    assert_eq!(join_ngram(&plain, "^"), "check^pass;");
+    assert_eq!(split_ngram("check^pass;", "^"), plain);
 
-    // let mut prev1 = None; let mut prev2 = None;
+    let tricky = ["a^b", r"c\", "^", ""];
+    let key = join_ngram(&tricky, "^");
+    assert_eq!(key, r"a\^b^c\\^\^^");
+    assert_eq!(split_ngram(&key, "^"), tricky);
+    assert_eq!(split_ngram(&join_ngram(&tricky, " | "), " | "), tricky);
 
-    if let Ok(lines) = read_lines(raw_fn) {
-        let mut lp = lines.peekable();
-        loop {
-            match lp.next() {
-                None => break,
-                Some(Ok(ip)) => vec_lines.push(ip),
-                Some(Err(_)) => {} // meh, some weirdly-encoded line, throw it out
-            }
-        }
+    // tokens that end or start with part of a longer separator, which mustn't run into it
+    for (sep, tokens) in [("||", ["a|", "b"]), ("||", ["a", "|b"]), ("||", ["|", "|"]), (" | ", ["a |", "b"]), (" | ", ["a", "| b"]), (" | ", ["a ", " b"])] {
+        let key = join_ngram(&tokens, sep);
+        assert_eq!(split_ngram(&key, sep), tokens, "{:?}", key);
     }
+    assert_ne!(join_ngram(&["a|", "b"], "||"), join_ngram(&["a", "|b"], "||"));
+    assert_eq!(join_ngram(&["a|", "b"], "||"), r"a\|||b");
+}
 
-    let mut num_workers:u32 = 8;
-    match num_threads {
-        Some(x) => num_workers = x,
-        _ => {}
-    };
-    let mut pool = ThreadPool::new(num_workers.try_into().unwrap());
-    let (tx, rx) = mpsc::channel();
-
-    let chunks = vec_lines.chunks((vec_lines.len() / usize::try_from(num_workers).unwrap()).max(1));
-
-    scope_with(&pool, |scope| {
-        for chunk in chunks {
-            let tx = tx.clone();
-            let format_clone = format.clone();
-            let regexps_clone = regexps.clone();
-            scope.execute(move || {
-                tx.send(worker(chunk.to_vec(), format_clone, regexps_clone)).unwrap();
-            });
-        };
-        pool.join();
-    });
-
-    drop(tx);
+// while building, an n-gram is the interned ids of its tokens; resolve turns them into join_ngram keys
+type NGram = Vec<u32>;
+type NGramMap = HashMap<NGram, u64, BuildNGramHasher>;
+#[cfg(feature = "concurrent")]
+type NGramDashMap = DashMap<NGram, u64, BuildNGramHasher>;
 
-    for received in rx {
-        let arcs = received;
-        let (dbl_rx, trpl_rx, all_token_list_rx) = arcs;
-        let arc_dbl = dbl_rx;
-        let arc_trpl = trpl_rx;
-        let arc_all_token_list = all_token_list_rx;
-        let dbl_guard = arc_dbl.lock().unwrap().to_owned();
-        let trpl_guard = arc_trpl.lock().unwrap().to_owned();
-        let arc_all_token_guard = arc_all_token_list.lock().unwrap().to_vec();
+// the dictionaries the workers of the concurrent-map and buffered backends share
+#[cfg(feature = "concurrent")]
+type SharedDicts = BTreeMap<usize, NGramDashMap>;
+// which don't exist without the concurrent feature
+#[cfg(not(feature = "concurrent"))]
+type SharedDicts = Infallible;
 
-        for (key, value) in dbl_guard {
-            *dbl.entry(key).or_default() += value;
-        }
-        for (key, value) in trpl_guard {
-            *trpl.entry(key).or_default() += value;
-        }
-        for token in arc_all_token_guard {
-            all_token_list.push(token);
-        }
+// the skip-grams of tokens d apart are counted under the key SKIP_GRAM + d, next to the n-gram sizes
+const SKIP_GRAM: usize = 1 << 16;
 
-    }
-    all_token_list.sort_unstable();
-    all_token_list.dedup();
-    return (dbl, trpl, all_token_list)
+fn skip_distance(key: usize) -> Option<usize> {
+    key.checked_sub(SKIP_GRAM).filter(|_| key < BREAKDOWN)
 }
 
-fn worker(blocks: Vec<String>, format: String, regexps: Vec<Regex>) -> (Arc<Mutex<HashMap<String, i32>>>, Arc<Mutex<HashMap<String, i32>>>, Arc<Mutex<Vec<String>>>) {
-    let mut dbl = HashMap::new();
-    let mut trpl = HashMap::new();
-    let mut all_token_list = vec![];
-    let regex = regex_generator(format);
+// and the n-grams of size n broken down by a field under BREAKDOWN + n, each with the id of its line's
+// value in front; the value is interned as a token with a space in front, which no real token has
+const BREAKDOWN: usize = 1 << 17;
 
-    let mut prev1 = None; let mut prev2 = None;
+fn breakdown_size(key: usize) -> Option<usize> {
+    key.checked_sub(BREAKDOWN)
+}
 
-    let mut lp = blocks.iter().peekable();
-    loop {
-        match lp.next() {
-            None => break,
-            Some(ip) => {
-                match lp.peek() {
-                    None =>
-                        (prev1, prev2) = process_dictionary_builder_line(ip.to_string(), None, &regex, &regexps, Map::TypeHash(&mut dbl), Map::TypeHash(&mut trpl), Set::TypeVec(&mut all_token_list), prev1, prev2),
-                    Some(next_line) =>
-                        (prev1, prev2) = process_dictionary_builder_line(ip.to_string(), Some(next_line.to_string()), &regex, &regexps, Map::TypeHash(&mut dbl), Map::TypeHash(&mut trpl), Set::TypeVec(&mut all_token_list), prev1, prev2),
-                }
-            },
-        }
-    }
-    return (Arc::new(Mutex::new(dbl)), Arc::new(Mutex::new(trpl)), Arc::new(Mutex::new(all_token_list)))
+// the id of the value tokenizer breaks line down by, if any
+fn breakdown_id(tokenizer: &dyn Tokenizer, interner: &Interner, line: &str) -> Option<u32> {
+    tokenizer.breakdown(line).map(|value| interner.intern(&format!(" {}", value)))
 }
 
-fn dictionary_builder_conc(raw_fn: String, format: String, regexps: Vec<Regex>, num_threads: Option<u32>) -> (HashMap<String, i32>, HashMap<String, i32>, Vec<String>) {
-    let mut dbl = DashMap::new();
-    let mut trpl = DashMap::new();
-    let mut all_token_list = DashSet::new();
-    let mut vec_lines = vec![];
-    let mut dbl_hash = HashMap::new();
-    let mut trpl_hash = HashMap::new();
-    let mut vec_all_token_list = vec![];
+// the tokens of a run, sorted, without the breakdown values
+fn token_list(mut names: Vec<String>) -> TokenList {
+    names.retain(|name| !name.starts_with(' '));
+    names.sort_unstable();
+    names
+}
 
-    if let Ok(lines) = read_lines(raw_fn) {
-        let mut lp = lines.peekable();
-        loop {
-            match lp.next() {
-                None => break,
-                Some(Ok(ip)) =>
-                    {
-                        vec_lines.push(ip);
-                    }
-                Some(Err(_)) => {} // meh, some weirdly-encoded line, throw it out
-            }
+// the breakdown dictionaries split off the others, resolved into n-gram dictionaries by value
+fn resolve_breakdown<I, H: BuildHasher + Default>(dicts: BTreeMap<usize, I>, names: &[String], sep: &str) -> Breakdown<H>
+where I: IntoIterator<Item = (NGram, u64)>, {
+    let mut breakdown = Breakdown::new();
+    for (key, dict) in dicts {
+        let n = key - BREAKDOWN;
+        for (ngram, count) in dict {
+            let value = &names[ngram[0] as usize][1..];
+            let tokens: Vec<&str> = ngram[1..].iter().map(|id| names[*id as usize].as_str()).collect();
+            let dicts = match breakdown.get_mut(value) {
+                Some(dicts) => dicts,
+                None => breakdown.entry(value.to_string()).or_default(),
+            };
+            dicts.entry(n).or_default().insert(join_ngram(&tokens, sep), count);
         }
     }
+    breakdown
+}
 
-    let mut num_workers:u32 = 8;
-    match num_threads {
-        Some(x) => num_workers = x,
-        _ => {}
-    };
-    let mut pool = ThreadPool::new(num_workers.try_into().unwrap());
-    let (tx, rx) = mpsc::channel();
-
-    let chunks = vec_lines.chunks((vec_lines.len() / usize::try_from(num_workers).unwrap()).max(1));
-
-    scope_with(&pool, |scope| {
-        for chunk in chunks {
-            let tx = tx.clone();
-            let format_clone = format.clone();
-            let regexps_clone = regexps.clone();
-            let dbl_clone = dbl.clone();
-            let trpl_clone = trpl.clone();
-            let dset = all_token_list.clone();
-            scope.execute(move || {
-                tx.send(worker_conc(chunk.to_vec(), format_clone, regexps_clone, dbl_clone, trpl_clone, dset)).unwrap();
-            });
-        };
-        pool.join();
-    });
+// takes the skip-grams out of dicts, keyed by their distance
+fn split_skip_grams<V>(dicts: &mut BTreeMap<usize, V>) -> BTreeMap<usize, V> {
+    dicts.split_off(&SKIP_GRAM).into_iter().map(|(key, dict)| (key - SKIP_GRAM, dict)).collect()
+}
 
-    drop(tx);
+// one dictionary per n-gram size, keyed by that size (and per skip-gram distance)
+enum Map<'a> {
+    TypeHash(&'a mut BTreeMap<usize, NGramMap>),
+    #[cfg(feature = "concurrent")]
+    TypeDash(&'a SharedDicts),
+}
 
-    for received in rx {
-        let arcs = received;
-        let (dbl_rx, trpl_rx, all_token_list_rx) = arcs;
-        let arc_dbl = dbl_rx;
-        let arc_trpl = trpl_rx;
-        let arc_all_token_list = all_token_list_rx;
-        let dbl_guard = arc_dbl.lock().unwrap().to_owned();
-        let trpl_guard = arc_trpl.lock().unwrap().to_owned();
-        let arc_all_token_guard = arc_all_token_list.lock().unwrap().to_owned();
+/// Hands out a dense id per distinct token, shared by all the workers of a run, so that counting an
+/// n-gram hashes a few integers instead of allocating a freshly joined key. Every token of every line
+/// goes through here, which makes the interned tokens the run's token list too.
+#[derive(Debug, Default)]
+struct Interner {
+    #[cfg(feature = "concurrent")]
+    ids: DashMap<String, u32, BuildNGramHasher>,
+    // without the concurrent feature there's only ever the one worker, which never waits for the lock
+    #[cfg(not(feature = "concurrent"))]
+    ids: Mutex<HashMap<String, u32, BuildNGramHasher>>,
+    #[cfg(feature = "concurrent")]
+    next: std::sync::atomic::AtomicU32,
+}
 
-        for (key, value) in dbl_guard {
-            *dbl.entry(key).or_default() += value;
-        }
-        for (key, value) in trpl_guard {
-            *trpl.entry(key).or_default() += value;
-        }
-        for token in arc_all_token_guard {
-            all_token_list.insert(token);
+#[cfg(not(feature = "concurrent"))]
+impl Interner {
+    fn intern(&self, token: &str) -> u32 {
+        let mut ids = self.ids.lock().unwrap();
+        if let Some(id) = ids.get(token) {
+            return *id;
         }
+        let id = ids.len() as u32;
+        ids.insert(token.to_string(), id);
+        id
     }
 
-    for (key, value) in dbl {
-        *dbl_hash.entry(key).or_default() += value;
-    }
-    for (key, value) in trpl {
-        *trpl_hash.entry(key).or_default() += value;
-    }
-    for token in all_token_list {
-        vec_all_token_list.push(token);
+    // the tokens, indexed by id
+    fn names_so_far(&self) -> Vec<Option<String>> {
+        let ids = self.ids.lock().unwrap();
+        let mut names = vec![None; ids.len()];
+        for (name, id) in ids.iter() {
+            names[*id as usize] = Some(name.clone());
+        }
+        names
     }
-    vec_all_token_list.sort_unstable();
-    vec_all_token_list.dedup();
-    return (dbl_hash, trpl_hash, vec_all_token_list)
 }
 
-fn worker_conc(blocks: Vec<String>, format: String, regexps: Vec<Regex>, dbl: DashMap<String, i32>, trpl: DashMap<String, i32>, all_token_list: DashSet<String>) -> (Arc<Mutex<DashMap<String, i32>>>, Arc<Mutex<DashMap<String, i32>>>, Arc<Mutex<DashSet<String>>>) {
-    let regex = regex_generator(format);
-
-    let mut prev1 = None; let mut prev2 = None;
+#[cfg(feature = "concurrent")]
+impl Interner {
+    fn intern(&self, token: &str) -> u32 {
+        if let Some(id) = self.ids.get(token) {
+            return *id;
+        }
+        *self.ids.entry(token.to_string()).or_insert_with(|| self.next.fetch_add(1, Ordering::Relaxed))
+    }
 
-    let mut lp = blocks.iter().peekable();
-    loop {
-        match lp.next() {
-            None => break,
-            Some(ip) =>
-                match lp.peek() {
-                    None =>
-                        (prev1, prev2) = process_dictionary_builder_line(ip.to_string(), None, &regex, &regexps, Map::TypeDash(&dbl), Map::TypeDash(&trpl), Set::TypeDSet(&all_token_list), prev1, prev2),
-                    Some(next_line) =>
-                        (prev1, prev2) = process_dictionary_builder_line(ip.to_string(), Some(next_line.to_string()), &regex, &regexps, Map::TypeDash(&dbl), Map::TypeDash(&trpl), Set::TypeDSet(&all_token_list), prev1, prev2),
-                }
+    // the tokens, indexed by id. An id is handed out under the lock of its token's shard, so while
+    // the workers run, a later id can be in the map before an earlier one is: that one is None
+    fn names_so_far(&self) -> Vec<Option<String>> {
+        let mut names = vec![None; self.next.load(Ordering::Relaxed) as usize];
+        for entry in self.ids.iter() {
+            let id = *entry.value() as usize;
+            if id >= names.len() {
+                names.resize(id + 1, None);
+            }
+            names[id] = Some(entry.key().clone());
         }
+        names
     }
-    return (Arc::new(Mutex::new(dbl)), Arc::new(Mutex::new(trpl)), Arc::new(Mutex::new(all_token_list)))
 }
 
-#[test]
-fn test_dictionary_builder_process_line_lookahead_is_none() {
-    let line = "Jun 14 15:16:02 combo sshd(pam_unix)[19937]: check pass; user unknown".to_string();
-    let re = regex_generator(format_string(&Linux));
-    let mut dbl = HashMap::new();
-    let mut trpl = HashMap::new();
-    let mut all_token_list = vec![];
-    let (last1, last2) = process_dictionary_builder_line(line, None, &re, &censored_regexps(&Linux), &mut dbl, &mut trpl, &mut all_token_list, None, None);
-    assert_eq!((last1, last2), (Some("unknown".to_string()), Some("user".to_string())));
-
-    let mut dbl_oracle = HashMap::new();
-    dbl_oracle.insert("user^unknown".to_string(), 1);
-    dbl_oracle.insert("pass;^user".to_string(), 1);
-    dbl_oracle.insert("check^pass;".to_string(), 1);
-    assert_eq!(dbl, dbl_oracle);
-
-    let mut trpl_oracle = HashMap::new();
-    trpl_oracle.insert("pass;^user^unknown".to_string(), 1);
-    trpl_oracle.insert("check^pass;^user".to_string(), 1);
-    assert_eq!(trpl, trpl_oracle);
+impl Interner {
+    // the tokens, indexed by id, once the workers are done with the interner
+    fn names(&self) -> Vec<String> {
+        self.names_so_far().into_iter().map(Option::unwrap_or_default).collect()
+    }
 }
 
-#[test]
-fn test_dictionary_builder_process_line_lookahead_is_some() {
-    let line = "Jun 14 15:16:02 combo sshd(pam_unix)[19937]: check pass; user unknown".to_string();
-    let next_line = "Jun 14 15:16:02 combo sshd(pam_unix)[19937]: baz bad".to_string();
-    let re = regex_generator(format_string(&Linux));
-    let mut dbl = HashMap::new();
-    let mut trpl = HashMap::new();
-    let mut all_token_list = vec![];
-    let (last1, last2) = process_dictionary_builder_line(line, Some(next_line), &re, &censored_regexps(&Linux), &mut dbl, &mut trpl, &mut all_token_list, Some("foo".to_string()), Some("bar".to_string()));
-    assert_eq!((last1, last2), (Some("unknown".to_string()), Some("user".to_string())));
-
-    let mut dbl_oracle = HashMap::new();
-    dbl_oracle.insert("unknown^baz".to_string(), 1);
-    dbl_oracle.insert("foo^check".to_string(), 1);
-    dbl_oracle.insert("user^unknown".to_string(), 1);
-    dbl_oracle.insert("pass;^user".to_string(), 1);
-    dbl_oracle.insert("check^pass;".to_string(), 1);
-    assert_eq!(dbl, dbl_oracle);
+// the token ids of a line, or of its context; most log lines have fewer than 32 tokens, and those
+// stay off the heap
+type LineIds = SmallVec<[u32; 32]>;
 
-    let mut trpl_oracle = HashMap::new();
-    trpl_oracle.insert("pass;^user^unknown".to_string(), 1);
-    trpl_oracle.insert("check^pass;^user".to_string(), 1);
-    trpl_oracle.insert("unknown^baz^bad".to_string(), 1);
-    trpl_oracle.insert("foo^check^pass;".to_string(), 1);
-    trpl_oracle.insert("bar^foo^check".to_string(), 1);
-    trpl_oracle.insert("user^unknown^baz".to_string(), 1);
-    assert_eq!(trpl, trpl_oracle);
+// the tokens of line, interned
+fn token_ids(tokenizer: &dyn Tokenizer, interner: &Interner, line: &str) -> LineIds {
+    let mut ids = LineIds::new();
+    tokenizer.for_each_token(line, &mut |t| ids.push(interner.intern(t)));
+    ids
 }
 
-pub fn parse_raw_single(raw_fn: String, lf:&LogFormat, num_threads: Option<u32>) -> (HashMap<String, i32>, HashMap<String, i32>, Vec<String>) {
-    let (double_dict, triple_dict, all_token_list) = dictionary_builder(raw_fn, format_string(&lf), censored_regexps(&lf), num_threads);
-    println!("double dictionary list len {}, triple {}, all tokens {}", double_dict.len(), triple_dict.len(), all_token_list.len());
-    return (double_dict, triple_dict, all_token_list);
+// the string-keyed dictionaries, with the tokens of each n-gram joined with sep
+fn resolve<I, H: BuildHasher + Default>(dicts: BTreeMap<usize, I>, names: &[String], sep: &str) -> BTreeMap<usize, HashMap<String, u64, H>>
+where I: IntoIterator<Item = (NGram, u64)>, {
+    dicts.into_iter()
+        .map(|(n, dict)| (n, dict.into_iter().map(|(ngram, count)| {
+            let tokens: Vec<&str> = ngram.iter().map(|id| names[*id as usize].as_str()).collect();
+            (join_ngram(&tokens, sep), count)
+        }).collect()))
+        .collect()
 }
 
-pub fn parse_raw_conc(raw_fn: String, lf:&LogFormat, num_threads: Option<u32>) -> (HashMap<String, i32>, HashMap<String, i32>, Vec<String>) {
-    let (double_dict, triple_dict, all_token_list) = dictionary_builder_conc(raw_fn, format_string(&lf), censored_regexps(&lf), num_threads);
-    println!("double dictionary list len {}, triple {}, all tokens {}", double_dict.len(), triple_dict.len(), all_token_list.len());
-    return (double_dict, triple_dict, all_token_list);
+// what the builders can turn their final counts into; names are the tokens, indexed by id
+trait FromCounts: Sized {
+    fn from_counts<I: IntoIterator<Item = (NGram, u64)>>(dicts: BTreeMap<usize, I>, names: Vec<String>, opts: &DictionaryBuilder) -> Self;
+    // for top_k, which can only be applied to a whole result
+    fn prune(&mut self, opts: &DictionaryBuilder);
 }
 
-#[test]
-fn test_parse_raw_linux() {
-    let (double_dict, triple_dict, all_token_list) = parse_raw_single("data/from_paper.log".to_string(), &Linux);
-    let all_token_list_oracle = vec![
-        "hdfs://hostname/2kSOSP.log:21876+7292".to_string(),
-        "hdfs://hostname/2kSOSP.log:14584+7292".to_string(),
-        "hdfs://hostname/2kSOSP.log:0+7292".to_string(),
-        "hdfs://hostname/2kSOSP.log:7292+7292".to_string(),
-        "hdfs://hostname/2kSOSP.log:29168+7292".to_string()
-    ];
-    assert_eq!(all_token_list, all_token_list_oracle);
-    let mut double_dict_oracle = HashMap::new();
-    double_dict_oracle.insert("hdfs://hostname/2kSOSP.log:14584+7292^hdfs://hostname/2kSOSP.log:0+7292".to_string(), 2);
-    double_dict_oracle.insert("hdfs://hostname/2kSOSP.log:21876+7292^hdfs://hostname/2kSOSP.log:14584+7292".to_string(), 2);
-    double_dict_oracle.insert("hdfs://hostname/2kSOSP.log:7292+7292^hdfs://hostname/2kSOSP.log:29168+7292".to_string(), 2);
-    double_dict_oracle.insert("hdfs://hostname/2kSOSP.log:0+7292^hdfs://hostname/2kSOSP.log:7292+7292".to_string(), 2);
-    assert_eq!(double_dict, double_dict_oracle);
-    let mut triple_dict_oracle = HashMap::new();
-    triple_dict_oracle.insert("hdfs://hostname/2kSOSP.log:0+7292^hdfs://hostname/2kSOSP.log:7292+7292^hdfs://hostname/2kSOSP.log:29168+7292".to_string(), 1);
-    triple_dict_oracle.insert("hdfs://hostname/2kSOSP.log:14584+7292^hdfs://hostname/2kSOSP.log:0+7292^hdfs://hostname/2kSOSP.log:7292+7292".to_string(), 1);
-    triple_dict_oracle.insert("hdfs://hostname/2kSOSP.log:21876+7292^hdfs://hostname/2kSOSP.log:14584+7292^hdfs://hostname/2kSOSP.log:0+7292".to_string(), 1);
-    assert_eq!(triple_dict, triple_dict_oracle);
+impl<S: BuildHasher + Default> FromCounts for DictionaryResult<S> {
+    fn from_counts<I: IntoIterator<Item = (NGram, u64)>>(dicts: BTreeMap<usize, I>, names: Vec<String>, opts: &DictionaryBuilder) -> Self {
+        let mut dicts = dicts;
+        let breakdown = resolve_breakdown(dicts.split_off(&BREAKDOWN), &names, &opts.separator);
+        let mut ngram_dicts = resolve(dicts, &names, &opts.separator);
+        let skip_grams = split_skip_grams(&mut ngram_dicts);
+        DictionaryResult { ngram_dicts, skip_grams, breakdown, all_token_list: token_list(names) }
+    }
+
+    fn prune(&mut self, opts: &DictionaryBuilder) {
+        opts.prune(self);
+    }
 }
 
-/// standard mapreduce invert map: given {<k1, v1>, <k2, v2>, <k3, v1>}, returns ([v1, v2] (sorted), {<v1, [k1, k3]>, <v2, [k2]>})
-pub fn reverse_dict(d: &HashMap<String, i32>) -> (BTreeSet<i32>, HashMap<i32, Vec<String>>) {
-    let mut reverse_d: HashMap<i32, Vec<String>> = HashMap::new();
-    let mut val_set: BTreeSet<i32> = BTreeSet::new();
+impl FromCounts for TokenIdResult {
+    fn from_counts<I: IntoIterator<Item = (NGram, u64)>>(mut dicts: BTreeMap<usize, I>, names: Vec<String>, _: &DictionaryBuilder) -> Self {
+        // no breakdown for ids
+        dicts.split_off(&BREAKDOWN);
+        // renumber the tokens in sorted order, so the ids index all_token_list
+        let mut order: Vec<u32> = (0..names.len() as u32).filter(|id| !names[*id as usize].starts_with(' ')).collect();
+        order.sort_unstable_by(|a, b| names[*a as usize].cmp(&names[*b as usize]));
+        let mut ids = vec![0; names.len()];
+        for (id, old) in order.iter().enumerate() {
+            ids[*old as usize] = id as u32;
+        }
+        let mut ngram_dicts: BTreeMap<usize, HashMap<NGram, u64>> = dicts.into_iter()
+            .map(|(n, dict)| (n, dict.into_iter().map(|(mut ngram, count)| {
+                ngram.iter_mut().for_each(|id| *id = ids[*id as usize]);
+                (ngram, count)
+            }).collect()))
+            .collect();
+        let skip_grams = split_skip_grams(&mut ngram_dicts);
+        let all_token_list = token_list(names);
+        TokenIdResult { ngram_dicts, skip_grams, all_token_list }
+    }
 
-    for (key, val) in d.iter() {
-        if reverse_d.contains_key(val) {
-            let existing_keys = reverse_d.get_mut(val).unwrap();
-            existing_keys.push(key.to_string());
-        } else {
-            reverse_d.insert(*val, vec![key.to_string()]);
-            val_set.insert(*val);
+    fn prune(&mut self, opts: &DictionaryBuilder) {
+        if let Some(k) = opts.top_k {
+            for dict in self.ngram_dicts.values_mut().chain(self.skip_grams.values_mut()) {
+                *dict = top_k_entries(dict.iter(), k).into_iter().collect();
+            }
         }
     }
-    return (val_set, reverse_d);
 }
 
-pub fn print_dict(s: &str, d: &HashMap<String, i32>) {
-    let (val_set, reverse_d) = reverse_dict(d);
+/// How much of the neighbouring lines gets stitched onto each line before windowing: up to `tokens`
+/// tokens on either side, drawn from at most `lines` adjacent lines. An n-gram dictionary of size n
+/// never borrows more than n-1 tokens per side, so the default (2 tokens, 1 line) matches the
+/// original prev1/prev2 and next1/next2 behaviour.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ContextWindow {
+    pub tokens: usize,
+    pub lines: usize,
+}
 
-    println!("printing dict: {}", s);
-    for val in &val_set {
-        println!("{}: {:?}", val, reverse_d.get(val).unwrap());
+impl Default for ContextWindow {
+    fn default() -> Self {
+        ContextWindow { tokens: 2, lines: 1 }
     }
-    println!("---");
+}
+
+impl ContextWindow {
+    /// Treats every line on its own: no n-gram crosses a line boundary.
+    pub fn disabled() -> Self {
+        ContextWindow { tokens: 0, lines: 0 }
+    }
+}
+
+/// Pads `tokens` with at most `n` tokens of cross-line context on either side.
+fn with_context(tokens: &[u32], prev: &[u32], next: &[u32], n: usize) -> LineIds {
+    let mut padded = LineIds::from_slice(&prev[prev.len() - n.min(prev.len())..]);
+    padded.extend_from_slice(tokens);
+    padded.extend_from_slice(&next[..n.min(next.len())]);
+    padded
+}
+
+/// Flattens the tails of the previous lines (oldest first) and keeps the last `n` tokens.
+fn trailing_context(history: &VecDeque<LineIds>, n: usize) -> LineIds {
+    let mut prev: LineIds = history.iter().flatten().copied().collect();
+    prev.drain(..prev.len().saturating_sub(n));
+    prev
+}
+
+// whether blocks[i] starts a session, which the context of the lines before it stops short of
+fn starts_session<S: AsRef<str>>(blocks: &[S], i: usize, tokenizer: &dyn Tokenizer) -> bool {
+    i > 0 && tokenizer.breaks_context(blocks[i - 1].as_ref(), blocks[i].as_ref())
+}
+
+// the lines after blocks[i] its context can take tokens from: up to context.lines of them, in the same
+// session
+fn session_lookahead<'a, S: AsRef<str>>(blocks: &'a [S], i: usize, tokenizer: &dyn Tokenizer, context: ContextWindow) -> &'a [S] {
+    let end = (i + 1 + context.lines).min(blocks.len());
+    let end = (i + 1..end).find(|j| starts_session(blocks, *j, tokenizer)).unwrap_or(end);
+    &blocks[i + 1..end]
+}
+
+// processes line, adding to the start of line the tail of the previous lines (prev) and to the end of line the
+// first tokens from lookahead_lines, counting every n-gram size in dicts, and returns the last context.tokens
+// tokens on this line
+fn process_dictionary_builder_line<S: AsRef<str>>(line: &str, lookahead_lines: &[S], tokenizer: &dyn Tokenizer,
+                                   interner: &Interner, dicts: Map, prev: &[u32], context: ContextWindow) -> LineIds {
+    let mut next = LineIds::new();
+    for ll in lookahead_lines.iter().take(context.lines) {
+        if next.len() >= context.tokens {
+            break;
+        }
+        next.extend(token_ids(tokenizer, interner, ll.as_ref()));
+    }
+    next.truncate(context.tokens);
+
+    let tokens = token_ids(tokenizer, interner, line);
+    if tokens.is_empty() {
+        return LineIds::new();
+    }
+    let value = breakdown_id(tokenizer, interner, line);
+
+    // keep this for later when we'll return it
+    let last = LineIds::from_slice(&tokens[tokens.len() - context.tokens.min(tokens.len())..]);
+
+    // the context is never copied in with the line: each size only joins up the windows that reach into it
+    let line = Padded { prev, line: &tokens, next: &next };
+    match dicts {
+        TypeHash(dicts) => {
+            for (n, dict) in dicts.iter_mut() {
+                line.for_each_key(*n, value, context, |ngram| match dict.get_mut(ngram) {
+                    Some(count) => *count += 1,
+                    // the key is only allocated the first time the n-gram is seen
+                    None => { dict.insert(ngram.to_vec(), 1); },
+                });
+            }
+        },
+        #[cfg(feature = "concurrent")]
+        TypeDash(dicts) => {
+            for (n, dict) in dicts.iter() {
+                line.for_each_key(*n, value, context, |ngram| match dict.get_mut(ngram) {
+                    Some(mut count) => *count += 1,
+                    None => *dict.entry(ngram.to_vec()).or_default() += 1,
+                });
+            }
+        }
+    }
+    last // returns the last tokens of the "prev" line for the next iteration
+}
+
+// a line's tokens with the context on both sides, which are at most context.tokens long
+struct Padded<'a> {
+    prev: &'a [u32],
+    line: &'a [u32],
+    next: &'a [u32],
+}
+
+impl Padded<'_> {
+    // for_each_ngram for the dictionary keyed by key, which for a breakdown means the n-grams of its
+    // size with the line's value in front, and none if the line has no value
+    fn for_each_key(&self, key: usize, value: Option<u32>, context: ContextWindow, mut f: impl FnMut(&[u32])) {
+        match breakdown_size(key) {
+            Some(n) => if let Some(value) = value {
+                let mut ngram = vec![value];
+                self.for_each_ngram(n, context, |window| {
+                    ngram.truncate(1);
+                    ngram.extend_from_slice(window);
+                    f(&ngram)
+                })
+            },
+            None => self.for_each_ngram(key, context, f),
+        }
+    }
+
+    // calls f with every n-gram of the dictionary keyed by key
+    fn for_each_ngram(&self, key: usize, context: ContextWindow, mut f: impl FnMut(&[u32])) {
+        let line = self.line;
+        match skip_distance(key) {
+            // skip-grams stay within their line
+            Some(d) => (d..line.len()).for_each(|i| f(&[line[i - d], line[i]])),
+            // an n-gram borrows at most n-1 tokens from each side, more would only recount the neighbouring lines
+            None => {
+                let k = context.tokens.min(key - 1);
+                let prev = &self.prev[self.prev.len() - k.min(self.prev.len())..];
+                joined_windows([prev, line, &self.next[..k.min(self.next.len())]], key, f)
+            },
+        }
+    }
+}
+
+/// Calls `f` with every window of `n` tokens of `parts` joined end to end, in order, as
+/// `[parts[0], parts[1], parts[2]].concat().windows(n)` would, without joining them: a window inside
+/// one part is a slice of it, and only the few across two parts are copied into a buffer.
+fn joined_windows(parts: [&[u32]; 3], n: usize, mut f: impl FnMut(&[u32])) {
+    let len: usize = parts.iter().map(|part| part.len()).sum();
+    if n == 0 || len < n {
+        return;
+    }
+    let token = |mut i: usize| {
+        for part in parts {
+            if i < part.len() {
+                return part[i];
+            }
+            i -= part.len();
+        }
+        unreachable!()
+    };
+    let mut across = LineIds::new();
+    let mut start = 0;
+    for part in parts {
+        // the windows that start in this part: the ones inside it, then the ones that end past it
+        let inside = part.len().saturating_sub(n - 1);
+        part.windows(n).for_each(&mut f);
+        for s in start + inside..(start + part.len()).min(len - n + 1) {
+            across.clear();
+            across.extend((s..s + n).map(token));
+            f(&across);
+        }
+        start += part.len();
+    }
+}
+
+#[test]
+fn test_joined_windows() {
+    let (prev, line, next) = ([1, 2], [3, 4, 5], [6]);
+    for parts in [[&prev[..], &line[..], &next[..]], [&[], &line[..], &[]], [&prev[..], &[], &next[..]], [&[], &[], &[]], [&[7], &[8], &[9]]] {
+        let joined = parts.concat();
+        for n in 1..6 {
+            let mut windows = vec![];
+            joined_windows(parts, n, |w| windows.push(w.to_vec()));
+            assert_eq!(windows, joined.windows(n).map(<[u32]>::to_vec).collect::<Vec<_>>(), "{:?} {}", parts, n);
+        }
+    }
+}
+
+// the lines of raw_fn, without their line endings or the ones that aren't UTF-8
+pub(crate) fn read_log<P>(raw_fn: P) -> Result<Vec<String>, ParserError>
+where P: AsRef<Path>, {
+    read_log_from(read_lines(raw_fn)?, false, &mut 0, &mut Pick::all())
+}
+
+// the lines of reader that pick takes, without their line endings; the ones that aren't UTF-8 are counted
+// in invalid, and decoded lossily if asked to or thrown out
+fn read_log_from<R: BufRead>(reader: R, lossy: bool, invalid: &mut usize, pick: &mut Pick) -> Result<Vec<String>, ParserError> {
+    decode_lines(reader.split(b'\n'), lossy, invalid, pick)
+}
+
+// read_log_from for the lines split off already, with their line endings; stops reading once pick
+// is done, and drops the lines it leaves out as they're read
+fn decode_lines<I>(mut lines: I, lossy: bool, invalid: &mut usize, pick: &mut Pick) -> Result<Vec<String>, ParserError>
+where I: Iterator<Item = io::Result<Vec<u8>>>, {
+    let mut vec_lines = vec![];
+    while !pick.done() {
+        let Some(line) = lines.next() else { break };
+        let mut line = line?;
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        let line = match String::from_utf8(line) {
+            Ok(line) => line,
+            Err(e) => {
+                *invalid += 1;
+                if !lossy {
+                    continue;
+                }
+                String::from_utf8_lossy(e.as_bytes()).into_owned()
+            },
+        };
+        if pick.takes() {
+            vec_lines.push(line);
+        }
+    }
+    Ok(vec_lines)
+}
+
+// a line without its line ending, as read_log_from decodes it
+pub(crate) fn decode_line<'a>(line: &'a [u8], lossy: bool, invalid: &mut usize) -> Option<Cow<'a, str>> {
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    match std::str::from_utf8(line) {
+        Ok(line) => Some(Cow::Borrowed(line)),
+        Err(_) => {
+            *invalid += 1;
+            lossy.then(|| String::from_utf8_lossy(line))
+        },
+    }
+}
+
+// the lines of a whole file in memory that pick takes, as read_log_from would read them
+fn str_lines<'a>(buf: &'a [u8], lossy: bool, invalid: &mut usize, pick: &mut Pick) -> Vec<Cow<'a, str>> {
+    if buf.is_empty() {
+        return vec![];
+    }
+    let mut lines = vec![];
+    for line in buf.strip_suffix(b"\n").unwrap_or(buf).split(|b| *b == b'\n') {
+        if pick.done() {
+            break;
+        }
+        if let Some(line) = decode_line(line, lossy, invalid).filter(|_| pick.takes()) {
+            lines.push(line);
+        }
+    }
+    lines
+}
+
+#[test]
+fn test_str_lines() {
+    let mut invalid = 0;
+    assert!(str_lines(b"", false, &mut invalid, &mut Pick::all()).is_empty());
+    assert_eq!(str_lines(b"\n", false, &mut invalid, &mut Pick::all()), vec![""]);
+    assert_eq!(str_lines(b"one\r\ntwo\n\xff\nthree", false, &mut invalid, &mut Pick::all()), vec!["one", "two", "three"]);
+    assert_eq!(invalid, 1);
+    assert_eq!(str_lines(b"one\r\ntwo\n\xff\nthree", true, &mut invalid, &mut Pick::all()), vec!["one", "two", "\u{fffd}", "three"]);
+    assert_eq!(invalid, 2);
+    let log = fs::read("data/Linux_2k.log").unwrap();
+    assert_eq!(str_lines(&log, false, &mut 0, &mut Pick::all()), read_log("data/Linux_2k.log").unwrap());
+    assert_eq!(read_log_from(&b"one\r\ntwo\n\xff\nthree"[..], true, &mut invalid, &mut Pick::all()).unwrap(), vec!["one", "two", "\u{fffd}", "three"]);
+    assert_eq!(invalid, 3);
+    // the lines left out aren't kept, and none are read past the last one taken
+    let mut pick = Pick { range: 1..usize::MAX, sample: Some(Sample::EveryNth(2)), line: 0 };
+    assert_eq!(str_lines(b"a\nb\n\xff\nc\nd\ne\n", false, &mut invalid, &mut pick), vec!["b", "d"]);
+    assert_eq!(invalid, 4);
+    let mut pick = Pick { range: 0..2, sample: None, line: 0 };
+    assert_eq!(read_log_from(&b"one\r\ntwo\n\xff\nthree"[..], false, &mut invalid, &mut pick).unwrap(), vec!["one", "two"]);
+    assert!(pick.done());
+    assert_eq!(invalid, 4);
+}
+
+// a memory-mapped input
+#[cfg(all(unix, feature = "mmap"))]
+type Mapped = crate::packages::mmap::Mmap;
+#[cfg(not(all(unix, feature = "mmap")))]
+type Mapped = Vec<u8>;
+
+#[cfg(all(unix, feature = "mmap"))]
+fn map_file(path: &Path) -> io::Result<Mapped> {
+    if path == Path::new("-") || matches!(path.extension().and_then(|ext| ext.to_str()), Some("gz" | "zst")) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("can't memory-map {}, only uncompressed files", path.display())));
+    }
+    crate::packages::mmap::Mmap::open(path)
+}
+
+#[cfg(not(all(unix, feature = "mmap")))]
+fn map_file(_path: &Path) -> io::Result<Mapped> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "memory-mapping log files needs the mmap feature on a unix"))
+}
+
+// splits every input into at most num_workers chunks of at least min_lines lines (an input shorter than
+// that is one chunk); no n-gram spans two inputs
+fn chunk_inputs<S>(inputs: &[Vec<S>], num_workers: u32, min_lines: usize) -> Vec<Vec<&[S]>> {
+    inputs.iter()
+        .map(|vec_lines| vec_lines.chunks(vec_lines.len().div_ceil(usize::try_from(num_workers).unwrap()).max(min_lines).max(1)).collect())
+        .collect()
+}
+
+#[test]
+fn test_chunk_inputs() {
+    let lines: Vec<u32> = (0..10).collect();
+    let inputs = [lines.clone(), vec![1, 2], vec![]];
+    let chunks = chunk_inputs(&inputs, 4, 1);
+    assert_eq!(chunks[0], vec![&lines[0..3], &lines[3..6], &lines[6..9], &lines[9..]]);
+    assert_eq!(chunks[1].len(), 2);
+    assert!(chunks[2].is_empty());
+    assert_eq!(chunk_inputs(&inputs, 64, 1)[0].len(), 10);
+    assert_eq!(chunk_inputs(&inputs, 4, 4)[0], vec![&lines[0..4], &lines[4..8], &lines[8..]]);
+    assert_eq!(chunk_inputs(&inputs, 4, 100)[0], vec![&lines[..]]);
+}
+
+// the lines of each input that have a group, each group's together, in the order the groups first
+// come up and then the order the lines came in
+fn group_lines<'a, S: AsRef<str>>(inputs: &'a [Vec<S>], tokenizer: &dyn Tokenizer) -> Vec<Vec<&'a str>> {
+    inputs.iter().map(|lines| {
+        let mut groups: Vec<Vec<&str>> = vec![];
+        let mut index: HashMap<&str, usize> = HashMap::new();
+        for line in lines {
+            let line = line.as_ref();
+            if let Some(group) = tokenizer.group(line) {
+                let i = *index.entry(group).or_insert_with(|| { groups.push(vec![]); groups.len() - 1 });
+                groups[i].push(line);
+            }
+        }
+        groups.concat()
+    }).collect()
+}
+
+// f's result for every chunk, in order, computed on as many threads as there are chunks (up to num_workers), each
+// taking the next chunk nobody has started yet; or right here for a single worker, which spares the thread and is
+// the only way where there are none (wasm, or without the concurrent feature)
+fn run_chunks<'a, S: Sync, T: Send>(chunks: &[Vec<&'a [S]>], num_workers: u32, f: impl Fn(&'a [S]) -> T + Sync) -> Result<Vec<T>, ParserError> {
+    let panicked = || ParserError::Worker("a worker panicked".to_string());
+    let chunks: Vec<&'a [S]> = chunks.iter().flatten().copied().collect();
+    if num_workers == 1 {
+        return panic::catch_unwind(AssertUnwindSafe(|| chunks.iter().map(|chunk| f(chunk)).collect())).map_err(|_| panicked());
+    }
+    #[cfg(not(feature = "concurrent"))]
+    unreachable!("num_workers is always 1 without the concurrent feature");
+    #[cfg(feature = "concurrent")]
+    {
+        let num_threads = chunks.len().clamp(1, usize::try_from(num_workers).unwrap());
+        let next = AtomicUsize::new(0);
+        let (chunks, f, next) = (&chunks, &f, &next);
+        #[cfg(feature = "tracing")]
+        let parent = &TraceContext::current();
+        let done: Vec<Option<Vec<(usize, T)>>> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..num_threads).map(|_| scope.spawn(move || {
+                #[cfg(feature = "tracing")]
+                let _parent = parent.enter();
+                let mut done = vec![];
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(chunk) = chunks.get(i) else { return done };
+                    done.push((i, f(chunk)));
+                }
+            })).collect();
+            // joined by hand, a panicking worker is an Err here instead of a panic of the whole scope
+            handles.into_iter().map(|handle| handle.join().ok()).collect()
+        });
+        let mut results: Vec<Option<T>> = std::iter::repeat_with(|| None).take(chunks.len()).collect();
+        for (i, result) in done.into_iter().map(|done| done.ok_or_else(panicked)).collect::<Result<Vec<_>, _>>()?.into_iter().flatten() {
+            results[i] = Some(result);
+        }
+        Ok(results.into_iter().map(Option::unwrap).collect())
+    }
+}
+
+// the subscriber and span of the thread starting some workers, for the spans of the workers to go
+// to that subscriber under that span rather than to the global one at the top
+#[cfg(all(feature = "tracing", feature = "concurrent"))]
+struct TraceContext(tracing::Dispatch, tracing::Span);
+
+#[cfg(all(feature = "tracing", feature = "concurrent"))]
+impl TraceContext {
+    fn current() -> Self {
+        TraceContext(tracing::dispatcher::get_default(Clone::clone), tracing::Span::current())
+    }
+
+    fn enter(&self) -> (tracing::dispatcher::DefaultGuard, tracing::span::Entered<'_>) {
+        (tracing::dispatcher::set_default(&self.0), self.1.enter())
+    }
+}
+
+// where a build's lines come from: inputs already in memory, which get split into chunks, or the
+// batches a reader thread sends while the workers count, each with the number of its input
+enum Lines<'a, S> {
+    Chunks(&'a [Vec<S>]),
+    #[cfg(feature = "concurrent")]
+    Batches(Receiver<(usize, Vec<S>)>),
+}
+
+// f on every chunk or batch of lines, with init's state for each chunk, or for each thread taking
+// batches; the states, then f's results by input, in order
+#[cfg_attr(not(feature = "concurrent"), allow(unused_variables))]
+fn run_lines<S, A, E>(lines: Lines<S>, num_workers: u32, counter: &LineCounter, opts: &DictionaryBuilder, init: impl Fn() -> A + Sync,
+                      f: impl Fn(&mut A, &[S]) -> E + Sync) -> Result<(Vec<A>, Vec<Vec<E>>), ParserError>
+where S: Send + Sync, A: Send, E: Send, {
+    match lines {
+        Lines::Chunks(inputs) => {
+            let chunks = opts.chunks(inputs, num_workers);
+            let mut done = run_chunks(&chunks, num_workers, |chunk| {
+                let mut state = init();
+                let result = f(&mut state, chunk);
+                (state, result)
+            })?.into_iter();
+            let mut states = vec![];
+            let results = chunks.iter().map(|input_chunks| input_chunks.iter().map(|_| {
+                let (state, result) = done.next().unwrap();
+                states.push(state);
+                result
+            }).collect()).collect();
+            Ok((states, results))
+        },
+        #[cfg(feature = "concurrent")]
+        Lines::Batches(batches) => run_batches(batches, num_workers, counter, init, f),
+    }
+}
+
+// the batches on num_workers threads, each taking the next one that comes in as soon as it's done
+// with the last
+#[cfg(feature = "concurrent")]
+fn run_batches<S, A, E>(batches: Receiver<(usize, Vec<S>)>, num_workers: u32, counter: &LineCounter, init: impl Fn() -> A + Sync,
+                        f: impl Fn(&mut A, &[S]) -> E + Sync) -> Result<(Vec<A>, Vec<Vec<E>>), ParserError>
+where S: Send, A: Send, E: Send, {
+    let batches = Mutex::new(batches.into_iter().enumerate());
+    let (batches, init, f) = (&batches, &init, &f);
+    #[cfg(feature = "tracing")]
+    let parent = &TraceContext::current();
+    let done = thread::scope(|scope| {
+        let handles: Vec<_> = (0..num_workers).map(|_| scope.spawn(move || {
+            #[cfg(feature = "tracing")]
+            let _parent = parent.enter();
+            let (mut state, mut results) = (init(), vec![]);
+            loop {
+                // only waiting for a batch holds the lock, not counting it
+                let next = batches.lock().unwrap().next();
+                let Some((i, (input, lines))) = next else { return (state, results) };
+                counter.read(lines.len());
+                results.push((i, input, f(&mut state, &lines)));
+            }
+        })).collect();
+        handles.into_iter().map(|handle| handle.join().ok()).collect::<Vec<_>>()
+    });
+    let mut states = vec![];
+    let mut results = vec![];
+    for done in done {
+        let (state, worker_results) = done.ok_or_else(|| ParserError::Worker("a worker panicked".to_string()))?;
+        states.push(state);
+        results.extend(worker_results);
+    }
+    results.sort_unstable_by_key(|(i, _, _)| *i);
+    let mut by_input: Vec<Vec<E>> = vec![];
+    for (_, input, result) in results {
+        by_input.resize_with(by_input.len().max(input + 1), Vec::new);
+        by_input[input].push(result);
+    }
+    Ok((states, by_input))
+}
+
+/// The tokens of the first and last 2 * context.lines lines of a chunk (all of them, for short
+/// chunks). The lines within context.lines of a chunk boundary are the only ones whose n-grams a
+/// worker can get wrong, and these are all the lines their context can reach.
+#[derive(Clone, Debug, Default)]
+struct ChunkEdges {
+    len: usize,
+    head: Vec<EdgeLine>,
+    tail: Vec<EdgeLine>,
+    // the first and last lines, to tell whether the next chunk starts a session
+    ends: Option<(String, String)>,
+}
+
+// what stitching needs of a line near a chunk boundary
+#[derive(Clone, Debug, Default)]
+struct EdgeLine {
+    tokens: Vec<u32>,
+    value: Option<u32>,
+    // whether it starts a session, as far as its chunk can tell (never the first line)
+    starts_session: bool,
+}
+
+impl ChunkEdges {
+    fn new<S: AsRef<str>>(blocks: &[S], tokenizer: &dyn Tokenizer, interner: &Interner, context: ContextWindow) -> Self {
+        let reach = if context.tokens == 0 { 0 } else { (2 * context.lines).min(blocks.len()) };
+        let edge_line = |i: usize| {
+            let line = blocks[i].as_ref();
+            EdgeLine { tokens: token_ids(tokenizer, interner, line).into_vec(), value: breakdown_id(tokenizer, interner, line), starts_session: starts_session(blocks, i, tokenizer) }
+        };
+        ChunkEdges {
+            len: blocks.len(),
+            head: (0..reach).map(edge_line).collect(),
+            tail: (blocks.len() - reach..blocks.len()).map(edge_line).collect(),
+            ends: blocks.first().zip(blocks.last()).map(|(first, last)| (first.as_ref().to_string(), last.as_ref().to_string())),
+        }
+    }
+
+    fn line(&self, i: usize) -> &EdgeLine {
+        if i < self.head.len() {
+            &self.head[i]
+        } else {
+            &self.tail[self.tail.len() - (self.len - i)]
+        }
+    }
+}
+
+// The workers only see their own chunk, so the lines next to a chunk boundary miss the context on the
+// other side of it. Since a worker's padded line is a contiguous piece of the sequential one, the
+// missing n-grams are exactly the windows that reach past the chunk; this counts those, for the
+// consecutive chunks of one input, so the result matches a single-chunk run.
+fn stitch_chunk_boundaries(edges: &[ChunkEdges], tokenizer: &dyn Tokenizer, context: ContextWindow, dicts: Map) {
+    let starts: Vec<usize> = edges.iter().scan(0, |start, e| { *start += e.len; Some(*start - e.len) }).collect();
+    let total: usize = edges.iter().map(|e| e.len).sum();
+    let chunk_of = |i: usize| starts.partition_point(|s| *s <= i) - 1;
+    let line_of = |i: usize| { let c = chunk_of(i); edges[c].line(i - starts[c]) };
+    let line = |i: usize| line_of(i).tokens.as_slice();
+    // the first line of a chunk starts a session if it does after the last line of the chunk before
+    let new_session = |i: usize| {
+        let c = chunk_of(i);
+        if i > starts[c] {
+            return line_of(i).starts_session;
+        }
+        match (c.checked_sub(1).and_then(|b| edges[b].ends.as_ref()), &edges[c].ends) {
+            (Some((_, last)), Some((first, _))) => tokenizer.breaks_context(last, first),
+            _ => false,
+        }
+    };
+
+    let mut affected = BTreeSet::new();
+    if context.tokens > 0 {
+        for start in starts.iter().skip(1) {
+            affected.extend(start.saturating_sub(context.lines)..(start + context.lines).min(total));
+        }
+    }
+
+    // the same prev/next process_dictionary_builder_line gets, seeing only the lines in lo..hi
+    let prev_tokens = |i: usize, lo: usize| {
+        let lo = (lo.max(i.saturating_sub(context.lines))..=i).rev().find(|j| new_session(*j)).unwrap_or(lo);
+        let mut prev: Vec<u32> = (i.saturating_sub(context.lines).max(lo)..i)
+            .flat_map(|j| { let l = line(j); l[l.len() - context.tokens.min(l.len())..].to_vec() })
+            .collect();
+        prev.drain(..prev.len().saturating_sub(context.tokens));
+        prev
+    };
+    let next_tokens = |i: usize, hi: usize| {
+        let hi = (i + 1..(i + 1 + context.lines).min(hi)).find(|j| new_session(*j)).unwrap_or(hi);
+        let mut next = vec![];
+        for j in i + 1..(i + 1 + context.lines).min(hi) {
+            if next.len() >= context.tokens {
+                break;
+            }
+            next.extend_from_slice(line(j));
+        }
+        next.truncate(context.tokens);
+        next
+    };
+
+    let mut missing: Vec<(usize, NGram)> = vec![];
+    let keys: Vec<usize> = match &dicts {
+        TypeHash(dicts) => dicts.keys().copied().filter(|n| skip_distance(*n).is_none()).collect(),
+        #[cfg(feature = "concurrent")]
+        TypeDash(dicts) => dicts.keys().copied().filter(|n| skip_distance(*n).is_none()).collect(),
+    };
+    for i in affected {
+        let tokens = line(i);
+        if tokens.is_empty() {
+            continue;
+        }
+        let c = chunk_of(i);
+        let (lo, hi) = (starts[c], starts[c] + edges[c].len);
+        let (prev, next) = (prev_tokens(i, 0), next_tokens(i, total));
+        let (local_prev, local_next) = (prev_tokens(i, lo), next_tokens(i, hi));
+        for key in &keys {
+            // a breakdown's n-grams are the others with the line's value in front
+            let (n, value) = match breakdown_size(*key) {
+                Some(n) => match line_of(i).value {
+                    Some(value) => (n, vec![value]),
+                    None => continue,
+                },
+                None => (*key, vec![]),
+            };
+            let k = context.tokens.min(n - 1);
+            let padded = with_context(tokens, &prev, &next, k);
+            // where the worker's padded line sits inside this one
+            let skip = k.min(prev.len()) - k.min(local_prev.len());
+            let end = k.min(prev.len()) + tokens.len() + k.min(local_next.len());
+            for (s, ngram) in padded.windows(n).enumerate() {
+                if s < skip || s + n > end {
+                    missing.push((*key, [value.as_slice(), ngram].concat()));
+                }
+            }
+        }
+    }
+
+    match dicts {
+        TypeHash(dicts) => {
+            for (n, key) in missing {
+                *dicts.get_mut(&n).unwrap().entry(key).or_default() += 1;
+            }
+        },
+        #[cfg(feature = "concurrent")]
+        TypeDash(dicts) => {
+            for (n, key) in missing {
+                *dicts[&n].entry(key).or_default() += 1;
+            }
+        }
+    }
+}
+
+/// Gets told how far a run is: the lines counted so far and the lines there are in all. The workers
+/// call it every few thousand lines each, so it may be called from several threads at once; the last
+/// call has both numbers the same.
+#[derive(Clone)]
+pub struct Progress(Arc<dyn Fn(u64, u64) + Send + Sync>);
+
+impl std::fmt::Debug for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Progress(..)")
+    }
+}
+
+const PROGRESS_EVERY: u64 = 4096;
+
+// the lines the workers of one run have counted, reported to opts.progress (if any)
+struct LineCounter<'a> {
+    progress: Option<&'a Progress>,
+    done: AtomicU64,
+    // all the lines, or for batches the ones that came in so far
+    total: AtomicU64,
+}
+
+impl<'a> LineCounter<'a> {
+    fn new<S>(lines: &Lines<S>, opts: &'a DictionaryBuilder) -> Self {
+        let total = match lines {
+            Lines::Chunks(inputs) => inputs.iter().map(|lines| lines.len() as u64).sum(),
+            #[cfg(feature = "concurrent")]
+            Lines::Batches(_) => 0,
+        };
+        LineCounter { progress: opts.progress.as_ref(), done: AtomicU64::new(0), total: AtomicU64::new(total) }
+    }
+
+    // another batch of lines came in
+    #[cfg(feature = "concurrent")]
+    fn read(&self, lines: usize) {
+        self.total.fetch_add(lines as u64, Ordering::Relaxed);
+    }
+
+    fn add(&self, lines: u64) {
+        if let Some(progress) = self.progress {
+            let done = self.done.fetch_add(lines, Ordering::Relaxed) + lines;
+            (progress.0)(done, self.total.load(Ordering::Relaxed));
+        }
+    }
+
+    // a worker's lines past its last add
+    fn add_rest(&self, lines: usize) {
+        let rest = lines as u64 % PROGRESS_EVERY;
+        if rest > 0 {
+            self.add(rest);
+        }
+    }
+}
+
+// counts another line of a worker that has done i + 1 of its lines, reporting every PROGRESS_EVERY
+fn count_line(counter: &LineCounter, i: usize) {
+    if (i as u64 + 1).is_multiple_of(PROGRESS_EVERY) {
+        counter.add(PROGRESS_EVERY);
+    }
+}
+
+// roughly what an n-gram takes in a builder's map, under dictionary key n: its key's Vec and token
+// ids, its count and its share of the table
+fn entry_bytes(n: usize) -> usize {
+    let tokens = match (breakdown_size(n), skip_distance(n)) {
+        (Some(n), _) => n + 1,
+        (None, Some(_)) => 2,
+        (None, None) => n,
+    };
+    std::mem::size_of::<NGram>() + 4 * tokens + 8 + 16
+}
+
+fn dicts_bytes<D>(dicts: &BTreeMap<usize, D>, len: impl Fn(&D) -> usize) -> usize {
+    dicts.iter().map(|(n, dict)| len(dict) * entry_bytes(*n)).sum()
+}
+
+const BUDGET_EVERY: usize = 1024;
+
+// a run's max_memory_bytes, checked by the workers every BUDGET_EVERY lines and once they're done
+struct Budget {
+    limit: Option<usize>,
+    policy: MemoryLimit,
+    // the bytes in the workers' own maps, as of their last checks
+    local: AtomicUsize,
+    exceeded: AtomicBool,
+    // one worker at a time is enough to prune the shared maps
+    pruning: Mutex<()>,
+}
+
+// what a worker's own maps held at its last check
+type LocalDicts<'a> = (&'a mut BTreeMap<usize, NGramMap>, &'a mut usize);
+
+impl Budget {
+    fn new(opts: &DictionaryBuilder) -> Self {
+        let (limit, policy) = opts.max_memory;
+        Budget { limit, policy, local: AtomicUsize::new(0), exceeded: AtomicBool::new(false), pruning: Mutex::new(()) }
+    }
+
+    fn exceeded(&self) -> bool {
+        self.exceeded.load(Ordering::Relaxed)
+    }
+
+    fn update(&self, (dicts, last): &mut LocalDicts) {
+        let bytes = dicts_bytes(dicts, HashMap::len);
+        if bytes >= **last {
+            self.local.fetch_add(bytes - **last, Ordering::Relaxed);
+        } else {
+            self.local.fetch_sub(**last - bytes, Ordering::Relaxed);
+        }
+        **last = bytes;
+    }
+
+    // checks a worker's own maps, if it has any, and the shared ones, if there are any, pruning them
+    // if they're too big and that's the policy; true once the worker should stop
+    fn check(&self, mut local: Option<LocalDicts>, shared: Option<&SharedDicts>) -> bool {
+        let Some(limit) = self.limit else { return false };
+        if self.exceeded() {
+            return true;
+        }
+        if let Some(local) = &mut local {
+            self.update(local);
+        }
+        let shared_bytes = shared.map_or(0, shared_bytes);
+        if self.local.load(Ordering::Relaxed) + shared_bytes <= limit {
+            return false;
+        }
+        if self.policy == MemoryLimit::Fail {
+            self.exceeded.store(true, Ordering::Relaxed);
+            return true;
+        }
+        if let Some(mut local) = local {
+            for dict in local.0.values_mut() {
+                let mut keeps = upper_half(dict.values().copied().collect());
+                dict.retain(|_, count| keeps(*count));
+            }
+            self.update(&mut local);
+        }
+        if let (Some(dicts), Ok(_pruning)) = (shared, self.pruning.try_lock()) {
+            prune_shared(dicts);
+        }
+        false
+    }
+}
+
+// says, count by count in the order retain goes through them, whether an n-gram is in the upper
+// half by count: those below the median are dropped, and as many of those at it as it takes to drop
+// half, so a prune always keeps some n-grams even when they're all counted the same
+fn upper_half(mut counts: Vec<u64>) -> impl FnMut(u64) -> bool {
+    let mid = counts.len() / 2;
+    let (median, mut ties) = match counts.get(mid) {
+        Some(_) => {
+            let (below, median, _) = counts.select_nth_unstable(mid);
+            (*median, below.iter().filter(|count| **count == *median).count())
+        },
+        None => (0, 0),
+    };
+    move |count| match count.cmp(&median) {
+        std::cmp::Ordering::Less => false,
+        std::cmp::Ordering::Equal if ties > 0 => {
+            ties -= 1;
+            false
+        },
+        _ => true,
+    }
+}
+
+#[cfg(feature = "concurrent")]
+fn shared_bytes(dicts: &SharedDicts) -> usize {
+    dicts_bytes(dicts, DashMap::len)
+}
+
+#[cfg(not(feature = "concurrent"))]
+fn shared_bytes(dicts: &SharedDicts) -> usize {
+    match *dicts {}
+}
+
+// drops the lower half of the n-grams of the shared maps by count
+#[cfg(feature = "concurrent")]
+fn prune_shared(dicts: &SharedDicts) {
+    for dict in dicts.values() {
+        let mut keeps = upper_half(dict.iter().map(|entry| *entry.value()).collect());
+        dict.retain(|_, count| keeps(*count));
+    }
+}
+
+#[cfg(not(feature = "concurrent"))]
+fn prune_shared(dicts: &SharedDicts) {
+    match *dicts {}
+}
+
+fn dictionary_builder<S: AsRef<str> + Send + Sync, R: FromCounts>(lines: Lines<S>, tokenizer: &dyn Tokenizer, opts: &DictionaryBuilder) -> Result<R, ParserError> {
+    // only the merged maps get the capacity hint, a worker's own hold some part of them
+    let mut dicts: BTreeMap<usize, NGramMap> = opts.dict_keys()
+        .map(|n| (n, HashMap::with_capacity_and_hasher(opts.estimated_ngrams.unwrap_or(0), Default::default())))
+        .collect();
+    let interner = Interner::default();
+    let interner = &interner;
+
+    let num_workers = opts.num_workers()?;
+
+    let counter = LineCounter::new(&lines, opts);
+    let budget = Budget::new(opts);
+
+    let (states, edges) = run_lines(lines, num_workers, &counter, opts, || (opts.empty_dicts(), 0), |(dicts, bytes), chunk| {
+        let Ok(edges) = count_chunk(chunk, tokenizer, interner, &counter, opts.context, &mut OwnMaps { dicts: (dicts, bytes), budget: &budget });
+        edges
+    })?;
+    span!("aggregate");
+
+    for (dicts_rx, _) in states {
+        merge_worker_dicts(&mut dicts, dicts_rx, opts);
+    }
+    for input_edges in &edges {
+        stitch_chunk_boundaries(input_edges, tokenizer, opts.context, Map::TypeHash(&mut dicts));
+    }
+    // only now are the counts final
+    if opts.min_count > 1 {
+        dicts.values_mut().for_each(|dict| dict.retain(|_, count| *count >= opts.min_count));
+    }
+    if budget.exceeded() {
+        return Err(ParserError::MemoryLimitExceeded(Box::new(DictionaryResult::from_counts(dicts, interner.names(), opts))));
+    }
+    Ok(R::from_counts(dicts, interner.names(), opts))
+}
+
+// the workers hand their maps back, so merging them moves their keys instead of copying them
+fn merge_worker_dicts(dicts: &mut BTreeMap<usize, NGramMap>, worker_dicts: BTreeMap<usize, NGramMap>, opts: &DictionaryBuilder) {
+    for (n, dict_rx) in worker_dicts {
+        let dict = dicts.get_mut(&n).unwrap();
+        if dict.is_empty() {
+            *dict = dict_rx;
+            dict.reserve(opts.estimated_ngrams.unwrap_or(0).saturating_sub(dict.len()));
+            continue;
+        }
+        for (key, value) in dict_rx {
+            *dict.entry(key).or_default() += value;
+        }
+    }
+}
+
+// What a worker does with the n-grams of its chunk: the maps it counts them into, and what it does
+// with those as it goes. count_chunk feeds it the lines.
+trait Sink {
+    type Error;
+
+    // the maps the next line's n-grams go into
+    fn map(&mut self) -> Map<'_>;
+
+    // after line i of the chunk is counted, e.g. to flush what's been counted so far
+    fn counted(&mut self, _i: usize) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    // after the last line, or the one the budget stopped the worker at
+    fn done(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    // checks max_memory_bytes against the maps it counts into; true once the worker should stop
+    fn over_budget(&mut self) -> bool {
+        false
+    }
+}
+
+// counts the lines of a chunk into sink, each with the context of the lines around it in its
+// session, checking the budget every BUDGET_EVERY lines and reporting the lines done to counter
+fn count_chunk<S: AsRef<str>, K: Sink>(blocks: &[S], tokenizer: &dyn Tokenizer, interner: &Interner, counter: &LineCounter,
+                                      context: ContextWindow, sink: &mut K) -> Result<ChunkEdges, K::Error> {
+    span!("count", lines = blocks.len());
+    // tails of the last context.lines lines, oldest first
+    let mut history = VecDeque::new();
+
+    for (i, ip) in blocks.iter().enumerate() {
+        if starts_session(blocks, i, tokenizer) {
+            history.clear();
+        }
+        let lookahead = session_lookahead(blocks, i, tokenizer, context);
+        let prev = trailing_context(&history, context.tokens);
+        let last = process_dictionary_builder_line(ip.as_ref(), lookahead, tokenizer, interner, sink.map(), &prev, context);
+        history.push_back(last);
+        if history.len() > context.lines {
+            history.pop_front();
+        }
+        count_line(counter, i);
+        sink.counted(i)?;
+        if (i + 1).is_multiple_of(BUDGET_EVERY) && sink.over_budget() {
+            break;
+        }
+    }
+    sink.done()?;
+    sink.over_budget();
+    counter.add_rest(blocks.len());
+    Ok(ChunkEdges::new(blocks, tokenizer, interner, context))
+}
+
+// whether a worker's own maps are due to be flushed after line i, as flush_every has it
+fn flush_due(local: &BTreeMap<usize, NGramMap>, i: usize, (flush_lines, flush_entries): (usize, usize)) -> bool {
+    (i + 1).is_multiple_of(flush_lines) || local.values().map(HashMap::len).sum::<usize>() >= flush_entries
+}
+
+// the separate-maps backend: the worker's own maps, which held bytes as of its last budget check
+struct OwnMaps<'a> {
+    dicts: LocalDicts<'a>,
+    budget: &'a Budget,
+}
+
+impl Sink for OwnMaps<'_> {
+    type Error = Infallible;
+
+    fn map(&mut self) -> Map<'_> {
+        Map::TypeHash(self.dicts.0)
+    }
+
+    fn over_budget(&mut self) -> bool {
+        self.budget.check(Some((&mut *self.dicts.0, &mut *self.dicts.1)), None)
+    }
+}
+
+// the dedupe_lines pre-pass and then separate maps: every distinct line is counted once, on its own,
+// and its n-grams added as many times as it came up
+fn dictionary_builder_dedup<S: AsRef<str> + Sync, R: FromCounts>(inputs: &[Vec<S>], tokenizer: &dyn Tokenizer, opts: &DictionaryBuilder) -> Result<R, ParserError> {
+    let mut dicts: BTreeMap<usize, NGramMap> = opts.dict_keys()
+        .map(|n| (n, HashMap::with_capacity_and_hasher(opts.estimated_ngrams.unwrap_or(0), Default::default())))
+        .collect();
+    let interner = Interner::default();
+    let interner = &interner;
+
+    let num_workers = opts.num_workers()?;
+    let seen = run_chunks(&opts.chunks(inputs, num_workers), num_workers, |chunk| {
+        let mut seen: HashMap<&str, u64, BuildNGramHasher> = HashMap::default();
+        for line in chunk {
+            *seen.entry(line.as_ref()).or_default() += 1;
+        }
+        seen
+    })?;
+    let mut distinct = HashMap::<&str, u64, BuildNGramHasher>::default();
+    for seen in seen {
+        if distinct.is_empty() {
+            distinct = seen;
+            continue;
+        }
+        for (line, times) in seen {
+            *distinct.entry(line).or_default() += times;
+        }
+    }
+    let distinct = [distinct.into_iter().collect::<Vec<_>>()];
+
+    // progress goes by the lines of the input, each distinct line reporting as many as it stands for,
+    // rather than by the distinct lines count_chunk goes through
+    let counter = LineCounter::new(&Lines::Chunks(inputs), opts);
+    let uncounted = LineCounter { progress: None, done: AtomicU64::new(0), total: AtomicU64::new(0) };
+    let budget = Budget::new(opts);
+
+    let (states, _) = run_lines(Lines::Chunks(&distinct), num_workers, &counter, opts, || (opts.empty_dicts(), 0), |(dicts, bytes), chunk| {
+        let (lines, times): (Vec<&str>, Vec<u64>) = chunk.iter().copied().unzip();
+        let mut sink = DedupMaps { own: OwnMaps { dicts: (dicts, bytes), budget: &budget }, line_dicts: opts.empty_dicts(), times: &times, counter: &counter, unreported: 0 };
+        let Ok(_) = count_chunk(&lines, tokenizer, interner, &uncounted, ContextWindow::disabled(), &mut sink);
+    })?;
+    span!("aggregate");
+    for (dicts_rx, _) in states {
+        merge_worker_dicts(&mut dicts, dicts_rx, opts);
+    }
+    if opts.min_count > 1 {
+        dicts.values_mut().for_each(|dict| dict.retain(|_, count| *count >= opts.min_count));
+    }
+    if budget.exceeded() {
+        return Err(ParserError::MemoryLimitExceeded(Box::new(DictionaryResult::from_counts(dicts, interner.names(), opts))));
+    }
+    Ok(R::from_counts(dicts, interner.names(), opts))
+}
+
+// dedupe_lines: each distinct line counted on its own into line_dicts, then added to the worker's own
+// maps as many times as it came up, and reported to counter as that many lines
+struct DedupMaps<'a> {
+    own: OwnMaps<'a>,
+    line_dicts: BTreeMap<usize, NGramMap>,
+    times: &'a [u64],
+    counter: &'a LineCounter<'a>,
+    unreported: u64,
+}
+
+impl Sink for DedupMaps<'_> {
+    type Error = Infallible;
+
+    fn map(&mut self) -> Map<'_> {
+        Map::TypeHash(&mut self.line_dicts)
+    }
+
+    fn counted(&mut self, i: usize) -> Result<(), Infallible> {
+        for (n, line_dict) in self.line_dicts.iter_mut() {
+            let dict = self.own.dicts.0.get_mut(n).unwrap();
+            for (ngram, count) in line_dict.drain() {
+                *dict.entry(ngram).or_default() += count * self.times[i];
+            }
+        }
+        self.unreported += self.times[i];
+        if self.unreported >= PROGRESS_EVERY {
+            self.counter.add(std::mem::take(&mut self.unreported));
+        }
+        Ok(())
+    }
+
+    fn done(&mut self) -> Result<(), Infallible> {
+        if self.unreported > 0 {
+            self.counter.add(std::mem::take(&mut self.unreported));
+        }
+        Ok(())
+    }
+
+    fn over_budget(&mut self) -> bool {
+        self.own.over_budget()
+    }
+}
+
+// the concurrent-map and buffered backends, which only differ in their workers
+#[cfg(feature = "concurrent")]
+fn dictionary_builder_conc<S: AsRef<str> + Send + Sync, R: FromCounts>(lines: Lines<S>, tokenizer: &dyn Tokenizer, opts: &DictionaryBuilder,
+                                                                      backend: Backend) -> Result<R, ParserError> {
+    let dicts: BTreeMap<usize, NGramDashMap> = opts.dict_keys().map(|n| (n, opts.empty_dash_dict())).collect();
+    let interner = Interner::default();
+    let interner = &interner;
+
+    let num_workers = opts.num_workers()?;
+
+    let counter = LineCounter::new(&lines, opts);
+    let budget = Budget::new(opts);
+
+    // the workers count straight into dicts, all they hand back is their edges
+    let (_, edges) = run_lines(lines, num_workers, &counter, opts, || (), |_, chunk| {
+        let Ok(edges) = match backend {
+            Backend::Buffered => {
+                let mut sink = BufferedMaps { local: opts.empty_dicts(), bytes: 0, dicts: &dicts, budget: &budget, flush_every: opts.flush_every };
+                count_chunk(chunk, tokenizer, interner, &counter, opts.context, &mut sink)
+            },
+            _ => count_chunk(chunk, tokenizer, interner, &counter, opts.context, &mut SharedMaps { dicts: &dicts, budget: &budget }),
+        };
+        edges
+    })?;
+    span!("aggregate");
+    for input_edges in &edges {
+        stitch_chunk_boundaries(input_edges, tokenizer, opts.context, Map::TypeDash(&dicts));
+    }
+    // only now are the counts final; pruning before resolving means the rare n-grams never get joined
+    if opts.min_count > 1 {
+        dicts.values().for_each(|dict| dict.retain(|_, count| *count >= opts.min_count));
+    }
+    if budget.exceeded() {
+        return Err(ParserError::MemoryLimitExceeded(Box::new(DictionaryResult::from_counts(dicts, interner.names(), opts))));
+    }
+
+    Ok(R::from_counts(dicts, interner.names(), opts))
+}
+
+// the concurrent-map backend: straight into the shared maps
+#[cfg(feature = "concurrent")]
+struct SharedMaps<'a> {
+    dicts: &'a SharedDicts,
+    budget: &'a Budget,
+}
+
+#[cfg(feature = "concurrent")]
+impl Sink for SharedMaps<'_> {
+    type Error = Infallible;
+
+    fn map(&mut self) -> Map<'_> {
+        Map::TypeDash(self.dicts)
+    }
+
+    fn over_budget(&mut self) -> bool {
+        self.budget.check(None, Some(self.dicts))
+    }
+}
+
+// adds the counts in local to dicts, leaving local empty
+#[cfg(feature = "concurrent")]
+fn flush(local: &mut BTreeMap<usize, NGramMap>, dicts: &BTreeMap<usize, NGramDashMap>) {
+    for (n, dict) in local.iter_mut() {
+        let shared = &dicts[n];
+        for (key, value) in dict.drain() {
+            *shared.entry(key).or_default() += value;
+        }
+    }
+}
+
+// the buffered backend: into the worker's own maps, which held bytes as of its last budget check, and
+// from those into the shared ones every flush_every
+#[cfg(feature = "concurrent")]
+struct BufferedMaps<'a> {
+    local: BTreeMap<usize, NGramMap>,
+    bytes: usize,
+    dicts: &'a SharedDicts,
+    budget: &'a Budget,
+    flush_every: (usize, usize),
+}
+
+#[cfg(feature = "concurrent")]
+impl Sink for BufferedMaps<'_> {
+    type Error = Infallible;
+
+    fn map(&mut self) -> Map<'_> {
+        Map::TypeHash(&mut self.local)
+    }
+
+    fn counted(&mut self, i: usize) -> Result<(), Infallible> {
+        if flush_due(&self.local, i, self.flush_every) {
+            flush(&mut self.local, self.dicts);
+        }
+        Ok(())
+    }
+
+    fn done(&mut self) -> Result<(), Infallible> {
+        flush(&mut self.local, self.dicts);
+        Ok(())
+    }
+
+    fn over_budget(&mut self) -> bool {
+        self.budget.check(Some((&mut self.local, &mut self.bytes)), Some(self.dicts))
+    }
+}
+
+// ngram_stream: like the buffered backend, but handing the worker's own maps to emit when they flush
+struct StreamMaps<'a> {
+    local: BTreeMap<usize, NGramMap>,
+    emit: &'a (dyn Fn(&mut BTreeMap<usize, NGramMap>) + Sync),
+    flush_every: (usize, usize),
+}
+
+impl Sink for StreamMaps<'_> {
+    type Error = Infallible;
+
+    fn map(&mut self) -> Map<'_> {
+        Map::TypeHash(&mut self.local)
+    }
+
+    fn counted(&mut self, i: usize) -> Result<(), Infallible> {
+        if flush_due(&self.local, i, self.flush_every) {
+            (self.emit)(&mut self.local);
+        }
+        Ok(())
+    }
+
+    fn done(&mut self) -> Result<(), Infallible> {
+        (self.emit)(&mut self.local);
+        Ok(())
+    }
+}
+
+// a sketch per dictionary, and how its n-grams hash
+struct Sketches {
+    sketches: BTreeMap<usize, CountMin>,
+    hasher: BuildNGramHasher,
+}
+
+impl Sketches {
+    // adds the counts in local to the sketches and the n-grams to hitters, leaving local empty
+    fn flush(&self, local: &mut BTreeMap<usize, NGramMap>, hitters: &mut BTreeMap<usize, HeavyHitters<NGram>>) {
+        for (n, dict) in local.iter_mut() {
+            let (sketch, hitters) = (&self.sketches[n], hitters.get_mut(n).unwrap());
+            for (key, count) in dict.drain() {
+                hitters.offer(key.clone(), sketch.add(self.hasher.hash_one(&key), count));
+            }
+        }
+    }
+}
+
+// the count-min backend: counts like the buffered one, but into sketches, and sends back the n-grams
+// that might be heavy hitters with the edges
+fn dictionary_builder_sketch<S: AsRef<str> + Send + Sync, R: FromCounts>(lines: Lines<S>, tokenizer: &dyn Tokenizer, opts: &DictionaryBuilder) -> Result<R, ParserError> {
+    let SketchSize { width, depth, heavy_hitters } = opts.sketch;
+    let sketches = Sketches { sketches: opts.dict_keys().map(|n| (n, CountMin::new(width, depth))).collect(), hasher: Default::default() };
+    let empty_hitters = || opts.dict_keys().map(|n| (n, HeavyHitters::new(heavy_hitters))).collect::<BTreeMap<_, _>>();
+    let interner = Interner::default();
+    let interner = &interner;
+
+    let num_workers = opts.num_workers()?;
+
+    let counter = LineCounter::new(&lines, opts);
+
+    let (states, edges) = run_lines(lines, num_workers, &counter, opts, empty_hitters, |hitters, chunk| {
+        let mut sink = SketchMaps { local: opts.empty_dicts(), sketches: &sketches, hitters, flush_every: opts.flush_every };
+        let Ok(edges) = count_chunk(chunk, tokenizer, interner, &counter, opts.context, &mut sink);
+        edges
+    })?;
+    span!("aggregate");
+
+    let mut candidates: BTreeMap<usize, HashSet<NGram>> = opts.dict_keys().map(|n| (n, HashSet::new())).collect();
+    for hitters_rx in states {
+        for (n, hitters) in hitters_rx {
+            candidates.get_mut(&n).unwrap().extend(hitters.into_keys());
+        }
+    }
+    let mut spanning = opts.empty_dicts();
+    for input_edges in &edges {
+        stitch_chunk_boundaries(input_edges, tokenizer, opts.context, Map::TypeHash(&mut spanning));
+    }
+    let mut hitters = empty_hitters();
+    sketches.flush(&mut spanning, &mut hitters);
+    for (n, hitters) in hitters {
+        candidates.get_mut(&n).unwrap().extend(hitters.into_keys());
+    }
+
+    // only now are the estimates final
+    let dicts: BTreeMap<usize, NGramMap> = candidates.into_iter().map(|(n, keys)| {
+        let sketch = &sketches.sketches[&n];
+        let estimates: NGramMap = keys.into_iter().map(|key| {
+            let estimate = sketch.estimate(sketches.hasher.hash_one(&key));
+            (key, estimate)
+        }).filter(|(_, estimate)| *estimate >= opts.min_count).collect();
+        if estimates.len() <= heavy_hitters {
+            return (n, estimates);
+        }
+        (n, top_k_entries(estimates.iter(), heavy_hitters).into_iter().collect())
+    }).collect();
+    Ok(R::from_counts(dicts, interner.names(), opts))
+}
+
+// the count-min backend: like the buffered backend, but flushing into the sketches and the worker's
+// heavy hitters
+struct SketchMaps<'a> {
+    local: BTreeMap<usize, NGramMap>,
+    sketches: &'a Sketches,
+    hitters: &'a mut BTreeMap<usize, HeavyHitters<NGram>>,
+    flush_every: (usize, usize),
+}
+
+impl Sink for SketchMaps<'_> {
+    type Error = Infallible;
+
+    fn map(&mut self) -> Map<'_> {
+        Map::TypeHash(&mut self.local)
+    }
+
+    fn counted(&mut self, i: usize) -> Result<(), Infallible> {
+        if flush_due(&self.local, i, self.flush_every) {
+            self.sketches.flush(&mut self.local, self.hitters);
+        }
+        Ok(())
+    }
+
+    fn done(&mut self) -> Result<(), Infallible> {
+        self.sketches.flush(&mut self.local, self.hitters);
+        Ok(())
+    }
+}
+
+// the external backend up to the merge: the runs of the workers and of the n-grams across their
+// chunks, and the tokens by id
+fn spill_runs<S: AsRef<str> + Send + Sync>(lines: Lines<S>, tokenizer: &dyn Tokenizer, opts: &DictionaryBuilder) -> Result<(Vec<Run>, Vec<String>), ParserError> {
+    let dir = opts.spill_dir.clone().unwrap_or_else(std::env::temp_dir);
+    let interner = Interner::default();
+    let interner = &interner;
+
+    let num_workers = opts.num_workers()?;
+
+    let counter = LineCounter::new(&lines, opts);
+
+    // each worker's share of max_memory_bytes
+    let share = opts.max_memory.0.map(|limit| limit / num_workers as usize);
+    let (states, results) = run_lines(lines, num_workers, &counter, opts, Vec::new, |runs, chunk| {
+        let mut sink = SpillMaps { local: opts.empty_dicts(), runs, dir: &dir, share, spill_entries: opts.spill_entries };
+        count_chunk(chunk, tokenizer, interner, &counter, opts.context, &mut sink)
+    })?;
+    span!("aggregate");
+
+    let mut runs: Vec<Run> = states.into_iter().flatten().collect();
+    let mut spanning = opts.empty_dicts();
+    for input_results in results {
+        let input_edges = input_results.into_iter().collect::<io::Result<Vec<_>>>()?;
+        stitch_chunk_boundaries(&input_edges, tokenizer, opts.context, Map::TypeHash(&mut spanning));
+    }
+    runs.push(spill::write_run(&dir, drain_entries(&mut spanning))?);
+    Ok((runs, interner.names()))
+}
+
+fn drain_entries(dicts: &mut BTreeMap<usize, NGramMap>) -> Vec<spill::Entry> {
+    dicts.iter_mut().flat_map(|(n, dict)| dict.drain().map(|(ngram, count)| (*n, ngram, count))).collect()
+}
+
+fn dictionary_builder_external<S: AsRef<str> + Send + Sync, R: FromCounts>(lines: Lines<S>, tokenizer: &dyn Tokenizer, opts: &DictionaryBuilder) -> Result<R, ParserError> {
+    let (runs, names) = spill_runs(lines, tokenizer, opts)?;
+    let mut dicts = opts.empty_dicts();
+    // the counts are final as they come out of the merge
+    spill::merge(&runs, |(n, ngram, count)| {
+        if count >= opts.min_count {
+            dicts.get_mut(&n).unwrap().insert(ngram, count);
+        }
+        Ok(())
+    })?;
+    Ok(R::from_counts(dicts, names, opts))
+}
+
+// the external backend: into the worker's own maps, written out as a sorted run whenever they reach
+// spill_entries n-grams or the worker's share of max_memory_bytes
+struct SpillMaps<'a> {
+    local: BTreeMap<usize, NGramMap>,
+    runs: &'a mut Vec<Run>,
+    dir: &'a Path,
+    share: Option<usize>,
+    spill_entries: usize,
+}
+
+impl Sink for SpillMaps<'_> {
+    type Error = io::Error;
+
+    fn map(&mut self) -> Map<'_> {
+        Map::TypeHash(&mut self.local)
+    }
+
+    fn counted(&mut self, _i: usize) -> io::Result<()> {
+        if self.local.values().map(HashMap::len).sum::<usize>() >= self.spill_entries
+            || self.share.is_some_and(|share| dicts_bytes(&self.local, HashMap::len) >= share) {
+            self.runs.push(spill::write_run(self.dir, drain_entries(&mut self.local))?);
+        }
+        Ok(())
+    }
+
+    fn done(&mut self) -> io::Result<()> {
+        self.runs.push(spill::write_run(self.dir, drain_entries(&mut self.local))?);
+        Ok(())
+    }
+}
+
+#[test]
+fn test_parse_raw_external() {
+    let builder = DictionaryBuilder::new().num_threads(Some(3)).min_chunk_lines(100).skip_grams(2);
+    let exact = builder.parse_raw("data/HDFS_2k.log".to_string(), &HDFS);
+    let dir = std::env::temp_dir().join(format!("logram-test-{}-spill", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    // a run every 100 n-grams, several per worker
+    let external = builder.clone().backend(Backend::External).spill_dir(Some(dir.clone())).spill_entries(100);
+    assert_eq!(external.parse_raw("data/HDFS_2k.log".to_string(), &HDFS), exact);
+    assert_eq!(external.clone().min_count(3).parse_raw_ids("data/HDFS_2k.log".to_string(), &HDFS),
+               builder.clone().min_count(3).parse_raw_ids("data/HDFS_2k.log".to_string(), &HDFS));
+
+    let csv = dir.join("csv");
+    external.parse_raw_to_csv("data/HDFS_2k.log".to_string(), &HDFS, &csv).unwrap();
+    let mut lines: Vec<String> = fs::read_to_string(csv.join("triple.csv")).unwrap().lines().map(String::from).collect();
+    let mut expected = vec!["ngram,count".to_string()];
+    expected.extend(exact.most_frequent(3).into_iter().map(|(key, count)| format!("{},{}", csv_field(key), count)));
+    lines[1..].sort();
+    expected[1..].sort();
+    assert_eq!(lines, expected);
+    assert_eq!(fs::read_to_string(csv.join("tokens.csv")).unwrap().lines().count(), exact.all_token_list.len() + 1);
+    fs::remove_dir_all(&csv).unwrap();
+    // the runs are gone once they're merged
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+    fs::remove_dir(&dir).unwrap();
+}
+
+#[test]
+fn test_max_memory_bytes() {
+    let builder = DictionaryBuilder::new().num_threads(Some(2)).min_chunk_lines(100);
+    let exact = builder.parse_raw("data/HDFS_2k.log".to_string(), &HDFS);
+    assert_eq!(builder.clone().max_memory_bytes(Some(1 << 30)).parse_raw("data/HDFS_2k.log".to_string(), &HDFS), exact);
+    for backend in [Backend::SeparateMaps, Backend::ConcurrentMap, Backend::Buffered] {
+        let limited = builder.clone().backend(backend).max_memory_bytes(Some(20_000));
+        let Err(ParserError::MemoryLimitExceeded(partial)) = limited.parse_raw_checked("data/HDFS_2k.log".to_string(), &HDFS) else {
+            panic!("{:?} went past the limit", backend);
+        };
+        assert!(!partial.ngram_dicts[&2].is_empty());
+        assert!(partial.ngram_dicts[&2].iter().all(|(key, count)| *count <= exact.ngram_dicts[&2][key]));
+
+        // pruned instead: what's left is never overcounted
+        let pruned = limited.on_memory_limit(MemoryLimit::Prune).parse_raw("data/HDFS_2k.log".to_string(), &HDFS);
+        for (n, dict) in &pruned.ngram_dicts {
+            assert!(dict.len() < exact.ngram_dicts[n].len(), "{:?}", backend);
+            assert!(dict.iter().all(|(key, count)| *count <= exact.ngram_dicts[n][key]));
+        }
+        assert_eq!(pruned.all_token_list, exact.all_token_list);
+    }
+    // spilled, still exact
+    let external = builder.backend(Backend::External).max_memory_bytes(Some(20_000));
+    assert_eq!(external.parse_raw("data/HDFS_2k.log".to_string(), &HDFS), exact);
+}
+
+#[test]
+fn test_prune_equal_counts() {
+    let mut keeps = upper_half(vec![3; 10]);
+    assert_eq!((0..10).filter(|_| keeps(3)).count(), 5);
+    let mut keeps = upper_half(vec![1, 2, 2, 2, 5]);
+    assert_eq!([1, 2, 2, 2, 5].into_iter().filter(|count| keeps(*count)).collect::<Vec<_>>(), [2, 2, 5]);
+
+    // every n-gram counted once: a prune keeps half of them rather than none (the tokens are
+    // spelled in letters, since numbers would be censored)
+    let word = |i: usize| -> String { i.to_string().bytes().map(|digit| (digit - b'0' + b'a') as char).collect() };
+    let text: Vec<String> = (0..3000).map(|i| format!("a{} b{} c{}", word(i), word(i), word(i))).collect();
+    let lf = LogFormat::Custom { format: "<Content>".to_string(), censor: vec![] };
+    let builder = DictionaryBuilder::new().num_threads(Some(2)).min_chunk_lines(100).context(ContextWindow::disabled());
+    let exact = builder.parse_str(&text.join("\n"), &lf);
+    assert!(exact.ngram_dicts.values().all(|dict| dict.values().all(|count| *count == 1)));
+    for backend in [Backend::SeparateMaps, Backend::ConcurrentMap, Backend::Buffered] {
+        let limited = builder.clone().backend(backend).max_memory_bytes(Some(20_000)).on_memory_limit(MemoryLimit::Prune);
+        let pruned = limited.parse_str(&text.join("\n"), &lf);
+        for (n, dict) in &pruned.ngram_dicts {
+            assert!(!dict.is_empty() && dict.len() < exact.ngram_dicts[n].len(), "{:?}", backend);
+        }
+    }
+}
+
+#[test]
+fn test_parse_raw_count_min() {
+    let builder = DictionaryBuilder::new().num_threads(Some(3)).min_chunk_lines(100);
+    let exact = builder.parse_raw("data/HDFS_2k.log".to_string(), &HDFS);
+    // sketches wide enough to hold every n-gram without a collision come out exact
+    let roomy = builder.clone().backend(Backend::CountMin).sketch(SketchSize { width: 1 << 16, depth: 4, heavy_hitters: 100_000 });
+    assert_eq!(roomy.parse_raw("data/HDFS_2k.log".to_string(), &HDFS), exact);
+
+    let small = SketchSize { width: 256, depth: 4, heavy_hitters: 20 };
+    let approx = builder.clone().backend(Backend::CountMin).sketch(small).parse_raw("data/HDFS_2k.log".to_string(), &HDFS);
+    assert_eq!(approx.all_token_list, exact.all_token_list);
+    for (n, dict) in &approx.ngram_dicts {
+        assert_eq!(dict.len(), 20);
+        // never an undercount, and the most frequent ones are the same
+        assert!(dict.iter().all(|(key, estimate)| *estimate >= exact.ngram_dicts[n][key]));
+        let top: Vec<&str> = exact.most_frequent(*n).iter().take(5).map(|(key, _)| *key).collect();
+        assert!(top.iter().all(|key| dict.contains_key(*key)), "{:?}", top);
+    }
+}
+
+#[test]
+fn test_dictionary_builder_process_line_lookahead_is_none() {
+    let line = "Jun 14 15:16:02 combo sshd(pam_unix)[19937]: check pass; user unknown".to_string();
+    let tokenizer = RegexTokenizer::new(&Linux).unwrap();
+    let mut dicts: BTreeMap<usize, NGramMap> = BTreeMap::from([(2, HashMap::default()), (3, HashMap::default())]);
+    let interner = Interner::default();
+    let last = process_dictionary_builder_line(&line, &[] as &[&str], &tokenizer, &interner, Map::TypeHash(&mut dicts), &[], ContextWindow::default());
+    assert_eq!(last.as_slice(), [interner.intern("user"), interner.intern("unknown")]);
+    let dicts = resolve(dicts, &interner.names(), "^");
+
+    let mut dbl_oracle = HashMap::new();
+    dbl_oracle.insert("user^unknown".to_string(), 1);
+    dbl_oracle.insert("pass;^user".to_string(), 1);
+    dbl_oracle.insert("check^pass;".to_string(), 1);
+    assert_eq!(dicts[&2], dbl_oracle);
+
+    let mut trpl_oracle = HashMap::new();
+    trpl_oracle.insert("pass;^user^unknown".to_string(), 1);
+    trpl_oracle.insert("check^pass;^user".to_string(), 1);
+    assert_eq!(dicts[&3], trpl_oracle);
+}
+
+#[test]
+fn test_dictionary_builder_process_line_lookahead_is_some() {
+    let line = "Jun 14 15:16:02 combo sshd(pam_unix)[19937]: check pass; user unknown".to_string();
+    let next_line = "Jun 14 15:16:02 combo sshd(pam_unix)[19937]: baz bad".to_string();
+    let tokenizer = RegexTokenizer::new(&Linux).unwrap();
+    let mut dicts: BTreeMap<usize, NGramMap> = BTreeMap::from([(2, HashMap::default()), (3, HashMap::default())]);
+    let interner = Interner::default();
+    let prev = vec![interner.intern("bar"), interner.intern("foo")];
+    let last = process_dictionary_builder_line(&line, &[next_line], &tokenizer, &interner, Map::TypeHash(&mut dicts), &prev, ContextWindow::default());
+    assert_eq!(last.as_slice(), [interner.intern("user"), interner.intern("unknown")]);
+    let dicts = resolve(dicts, &interner.names(), "^");
+
+    let mut dbl_oracle = HashMap::new();
+    dbl_oracle.insert("unknown^baz".to_string(), 1);
+    dbl_oracle.insert("foo^check".to_string(), 1);
+    dbl_oracle.insert("user^unknown".to_string(), 1);
+    dbl_oracle.insert("pass;^user".to_string(), 1);
+    dbl_oracle.insert("check^pass;".to_string(), 1);
+    assert_eq!(dicts[&2], dbl_oracle);
+
+    let mut trpl_oracle = HashMap::new();
+    trpl_oracle.insert("pass;^user^unknown".to_string(), 1);
+    trpl_oracle.insert("check^pass;^user".to_string(), 1);
+    trpl_oracle.insert("unknown^baz^bad".to_string(), 1);
+    trpl_oracle.insert("foo^check^pass;".to_string(), 1);
+    trpl_oracle.insert("bar^foo^check".to_string(), 1);
+    trpl_oracle.insert("user^unknown^baz".to_string(), 1);
+    assert_eq!(dicts[&3], trpl_oracle);
+}
+
+#[test]
+fn test_dictionary_builder_process_line_wider_context() {
+    let line = "Jun 14 15:16:02 combo sshd(pam_unix)[19937]: check pass".to_string();
+    let lookahead = vec!["Jun 14 15:16:02 combo sshd(pam_unix)[19937]: baz".to_string(),
+                         "Jun 14 15:16:02 combo sshd(pam_unix)[19937]: bad".to_string()];
+    let tokenizer = RegexTokenizer::new(&Linux).unwrap();
+    let mut dicts: BTreeMap<usize, NGramMap> = BTreeMap::from([(2, HashMap::default()), (3, HashMap::default())]);
+    let interner = Interner::default();
+    let prev = vec![interner.intern("foo")];
+    let context = ContextWindow { tokens: 1, lines: 2 };
+    let last = process_dictionary_builder_line(&line, &lookahead, &tokenizer, &interner, Map::TypeHash(&mut dicts), &prev, context);
+    assert_eq!(last.as_slice(), [interner.intern("pass")]);
+    let dicts = resolve(dicts, &interner.names(), "^");
+
+    let mut trpl_oracle = HashMap::new();
+    trpl_oracle.insert("foo^check^pass".to_string(), 1);
+    trpl_oracle.insert("check^pass^baz".to_string(), 1);
+    assert_eq!(dicts[&3], trpl_oracle);
+
+    // with two lines of lookahead, an unmatched next line is skipped in favour of the one after it
+    let mut dicts: BTreeMap<usize, NGramMap> = BTreeMap::from([(2, HashMap::default())]);
+    let lookahead = vec!["garbage".to_string(), "Jun 14 15:16:02 combo sshd(pam_unix)[19937]: baz".to_string()];
+    let context = ContextWindow { tokens: 2, lines: 2 };
+    process_dictionary_builder_line("Jun 14 15:16:02 combo sshd(pam_unix)[19937]: check", &lookahead, &tokenizer, &interner, Map::TypeHash(&mut dicts), &[], context);
+    assert_eq!(resolve::<_, RandomState>(dicts, &interner.names(), "^")[&2].get("check^baz"), Some(&1));
+}
+
+#[test]
+fn test_dictionary_builder_process_line_larger_ngrams() {
+    let line = "Jun 14 15:16:02 combo sshd(pam_unix)[19937]: check pass; user unknown".to_string();
+    let tokenizer = RegexTokenizer::new(&Linux).unwrap();
+    let mut dicts: BTreeMap<usize, NGramMap> = BTreeMap::from([(1, HashMap::default()), (4, HashMap::default()), (5, HashMap::default())]);
+    let interner = Interner::default();
+    let prev = vec![interner.intern("foo"), interner.intern("bar")];
+    let context = ContextWindow { tokens: 4, lines: 1 };
+    process_dictionary_builder_line(&line, &[] as &[&str], &tokenizer, &interner, Map::TypeHash(&mut dicts), &prev, context);
+    let dicts = resolve(dicts, &interner.names(), "^");
+
+    assert_eq!(dicts[&1].len(), 4);
+    let mut quad_oracle = HashMap::new();
+    quad_oracle.insert("foo^bar^check^pass;".to_string(), 1);
+    quad_oracle.insert("bar^check^pass;^user".to_string(), 1);
+    quad_oracle.insert("check^pass;^user^unknown".to_string(), 1);
+    assert_eq!(dicts[&4], quad_oracle);
+    let mut quint_oracle = HashMap::new();
+    quint_oracle.insert("foo^bar^check^pass;^user".to_string(), 1);
+    quint_oracle.insert("bar^check^pass;^user^unknown".to_string(), 1);
+    assert_eq!(dicts[&5], quint_oracle);
+}
+
+#[test]
+fn test_parse_raw_independent_lines() {
+    let builder = DictionaryBuilder::new().context(ContextWindow::disabled());
+    let result = builder.clone().num_threads(Some(1)).parse_raw_single("data/from_paper.log".to_string(), &Linux);
+    // the from_paper.log lines that match the Linux format carry one token each, so nothing is left to pair up
+    assert!(result.ngram_dicts[&2].is_empty());
+    assert!(result.ngram_dicts[&3].is_empty());
+
+    let sequential = builder.clone().num_threads(Some(1)).parse_raw_single("data/HDFS_2k.log".to_string(), &HDFS);
+    let chunked = builder.num_threads(Some(7)).min_chunk_lines(1).parse_raw_conc("data/HDFS_2k.log".to_string(), &HDFS);
+    assert_eq!(sequential, chunked);
+}
+
+#[test]
+fn test_parse_raw_separator() {
+    let caret = DictionaryBuilder::new().num_threads(Some(2)).parse_raw_single("data/Linux_2k.log".to_string(), &Linux);
+    let colon = DictionaryBuilder::new().num_threads(Some(2)).separator(":").parse_raw_single("data/Linux_2k.log".to_string(), &Linux);
+    // Linux logs are full of colons, so a lot of these keys need escaping
+    for (n, dict) in &caret.ngram_dicts {
+        let resplit: HashMap<String, u64> = dict.iter().map(|(k, v)| (join_ngram(&split_ngram(k, "^"), ":"), *v)).collect();
+        assert_eq!(&resplit, &colon.ngram_dicts[n]);
+    }
+}
+
+#[test]
+fn test_parse_raw_min_count() {
+    let builder = DictionaryBuilder::new().num_threads(Some(3));
+    let full = builder.parse_raw_conc("data/HDFS_2k.log".to_string(), &HDFS);
+    for backend in [Backend::SeparateMaps, Backend::ConcurrentMap] {
+        let pruned = builder.clone().min_count(5).backend(backend).parse_raw_dir(Path::new("data"), Some("HDFS_2k.log"), &HDFS);
+        for (n, dict) in &full.ngram_dicts {
+            let expected: HashMap<String, u64> = dict.iter().filter(|(_, v)| **v >= 5).map(|(k, v)| (k.clone(), *v)).collect();
+            assert_eq!(pruned.ngram_dicts[n], expected);
+        }
+        // the token list isn't pruned
+        assert_eq!(pruned.all_token_list, full.all_token_list);
+    }
+}
+
+#[test]
+fn test_parse_reader() {
+    let builder = DictionaryBuilder::new().num_threads(Some(2));
+    let log = fs::read("data/HDFS_2k.log").unwrap();
+    let from_reader = builder.parse_reader(&log[..], &HDFS).unwrap();
+    assert_eq!(from_reader, builder.parse_raw_conc("data/HDFS_2k.log".to_string(), &HDFS));
+}
+
+#[test]
+fn test_parse_raw_with() {
+    use crate::packages::tokenizer::StripPunctuation;
+    let builder = DictionaryBuilder::new().num_threads(Some(3));
+    let tokenizer = RegexTokenizer::new(&Linux).unwrap();
+    assert_eq!(builder.parse_raw_with("data/Linux_2k.log".to_string(), &tokenizer),
+               builder.parse_raw_conc("data/Linux_2k.log".to_string(), &Linux));
+
+    let stripped = builder.backend(Backend::SeparateMaps).parse_raw_with("data/Linux_2k.log".to_string(), &StripPunctuation(tokenizer));
+    assert!(stripped.all_token_list.contains(&"pass".to_string()));
+    assert!(!stripped.all_token_list.contains(&"pass;".to_string()));
+    assert_eq!(stripped.ngram_dicts[&2]["check^pass"], 117);
+}
+
+#[test]
+fn test_parse_raw_normalize() {
+    let builder = DictionaryBuilder::new().num_threads(Some(3)).normalize(Normalization { numbers: true, ..Default::default() });
+    let result = builder.parse_raw_conc("data/HDFS_2k.log".to_string(), &HDFS);
+    assert!(result.all_token_list.contains(&"<NUM>".to_string()));
+    assert!(result.all_token_list.iter().all(|t| !t.chars().all(|c| c.is_ascii_digit())));
+    assert_eq!(result, builder.parse_raw_single("data/HDFS_2k.log".to_string(), &HDFS));
+}
+
+#[cfg(all(unix, feature = "mmap"))]
+#[test]
+fn test_parse_raw_mmap() {
+    let builder = DictionaryBuilder::new().num_threads(Some(3));
+    let mapped = builder.clone().mmap(true);
+    assert_eq!(mapped.parse_raw_single("data/HDFS_2k.log".to_string(), &HDFS), builder.parse_raw_single("data/HDFS_2k.log".to_string(), &HDFS));
+    assert_eq!(mapped.parse_raw_dir(Path::new("data"), Some("*_2k.log"), &Linux), builder.parse_raw_dir(Path::new("data"), Some("*_2k.log"), &Linux));
+    assert!(matches!(mapped.parse_raw_conc_checked("-".to_string(), &Linux), Err(ParserError::Io(e)) if e.kind() == io::ErrorKind::InvalidInput));
+}
+
+#[cfg(not(all(unix, feature = "mmap")))]
+#[test]
+fn test_parse_raw_mmap_without_feature() {
+    let err = DictionaryBuilder::new().mmap(true).parse_raw_conc_checked("data/HDFS_2k.log".to_string(), &HDFS).err().unwrap();
+    assert!(matches!(err, ParserError::Io(e) if e.kind() == io::ErrorKind::Unsupported));
+}
+
+#[test]
+fn test_parse_raw_progress() {
+    use std::sync::atomic::AtomicBool;
+    let lines = fs::read_to_string("data/HealthApp_2k.log").unwrap().lines().count() as u64;
+    for backend in [Backend::SeparateMaps, Backend::ConcurrentMap] {
+        let (calls, last, finished) = (Arc::new(AtomicU64::new(0)), Arc::new(AtomicU64::new(0)), Arc::new(AtomicBool::new(false)));
+        let (c, l, f) = (calls.clone(), last.clone(), finished.clone());
+        let builder = DictionaryBuilder::new().num_threads(Some(3)).min_chunk_lines(1).backend(backend).progress(move |done, total| {
+            assert!(done <= total && total == lines);
+            l.fetch_max(done, Ordering::Relaxed);
+            c.fetch_add(1, Ordering::Relaxed);
+            f.fetch_or(done == total, Ordering::Relaxed);
+        });
+        builder.parse_raw_with("data/HealthApp_2k.log".to_string(), &RegexTokenizer::new(&HealthApp).unwrap());
+        assert_eq!(last.load(Ordering::Relaxed), lines);
+        assert!(finished.load(Ordering::Relaxed));
+        // once per worker at least
+        assert!(calls.load(Ordering::Relaxed) >= if cfg!(feature = "concurrent") { 3 } else { 1 });
+    }
+}
+
+#[test]
+fn test_redact_pii_stats() {
+    let builder = DictionaryBuilder::new().redact_pii(true);
+    let (result, stats) = builder.parse_raw_stats("data/HDFS_2k.log".to_string(), &HDFS);
+    // every IP of HDFS_2k is in the content, none of them escaped
+    let ips = Regex::new(r"(\d+\.){3}\d+").unwrap();
+    let log = fs::read_to_string("data/HDFS_2k.log").unwrap();
+    assert_eq!(stats.redactions, BTreeMap::from([("<IP>".to_string(), ips.find_iter(&log).count() as u64)]));
+    assert!(result.all_token_list.iter().any(|t| t.contains("<IP>")));
+    assert!(!result.all_token_list.iter().any(|t| ips.is_match(t)));
+    assert!(DictionaryBuilder::new().parse_raw_stats("data/HDFS_2k.log".to_string(), &HDFS).1.redactions.is_empty());
+
+    // the lines that don't match leave whole, so they're redacted whole
+    let path = std::env::temp_dir().join(format!("logram-test-{}-pii.log", std::process::id()));
+    fs::write(&path, "not HDFS: mail jane.doe@example.com from 10.0.0.7\n081109 203615 148 INFO dfs.DataNode: from 10.0.0.8\n").unwrap();
+    let (_, stats) = builder.keep_unmatched(10).parse_raw_stats(path.to_string_lossy().to_string(), &HDFS);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(stats.unmatched_lines, [(1, "not HDFS: mail <EMAIL> from <IP>".to_string())]);
+    assert_eq!(stats.redactions, BTreeMap::from([("<EMAIL>".to_string(), 1), ("<IP>".to_string(), 2)]));
+}
+
+#[test]
+fn test_parse_raw_stats() {
+    let mut log = fs::read("data/HDFS_2k.log").unwrap();
+    log.extend_from_slice(b"081111 000000 1 INFO dfs.DataNode: caf\xe9 au lait\nnot an HDFS line at all\n");
+    let path = std::env::temp_dir().join(format!("logram-test-{}-stats.log", std::process::id()));
+    fs::write(&path, &log).unwrap();
+    let builder = DictionaryBuilder::new();
+    let (strict, strict_stats) = builder.parse_raw_stats(path.to_string_lossy().to_string(), &HDFS);
+    let (lossy, lossy_stats) = builder.clone().lossy_utf8(true).parse_raw_stats(path.to_string_lossy().to_string(), &HDFS);
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(strict_stats, ParseStats { invalid_utf8: 1, matched: 2000, unmatched: 1, total: 2002, unmatched_lines: vec![], redactions: BTreeMap::new() });
+    assert_eq!(lossy_stats, ParseStats { matched: 2001, ..strict_stats.clone() });
+    assert_eq!(strict, builder.parse_raw("data/HDFS_2k.log".to_string(), &HDFS));
+    assert!(lossy.all_token_list.iter().any(|t| t == "caf\u{fffd}"));
+    assert!((strict_stats.match_rate() - 2000.0 / 2002.0).abs() < 1e-9);
+
+    let (_, stats) = builder.keep_unmatched(5).parse_raw_stats("data/Linux_2k.log".to_string(), &HealthApp);
+    assert_eq!(stats.matched, 0);
+    assert_eq!(stats.unmatched, 2000);
+    assert_eq!(stats.unmatched_lines.len(), 5);
+    assert_eq!(stats.unmatched_lines[4].0, 5);
+    assert_eq!(stats.unmatched_lines[0].1, read_log("data/Linux_2k.log").unwrap()[0]);
+}
+
+#[cfg(all(feature = "tracing", feature = "concurrent"))]
+#[test]
+fn test_tracing_spans() {
+    use tracing::span::{Attributes, Id};
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+    use tracing_subscriber::registry::{LookupSpan, Registry};
+
+    // the name of every span and of its parent, as the registry has them
+    type Names = Vec<(&'static str, Option<&'static str>)>;
+    #[derive(Clone, Default)]
+    struct Spans(Arc<Mutex<Names>>);
+    impl<S: tracing::Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Spans {
+        fn on_new_span(&self, _: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+            let span = ctx.span(id).unwrap();
+            self.0.lock().unwrap().push((span.name(), span.parent().map(|parent| parent.name())));
+        }
+    }
+    let record = |builder: DictionaryBuilder| {
+        let spans = Spans::default();
+        tracing::subscriber::with_default(Registry::default().with(spans.clone()), || builder.parse_raw("data/HDFS_2k.log".to_string(), &HDFS));
+        let spans = spans.0.lock().unwrap().clone();
+        move |name: &str| spans.iter().filter(|(n, _)| *n == name).map(|(_, parent)| *parent).collect::<Vec<_>>()
+    };
+
+    let builder = DictionaryBuilder::new().num_threads(Some(3)).min_chunk_lines(1);
+    for backend in [Backend::SeparateMaps, Backend::ConcurrentMap] {
+        let parent = record(builder.clone().backend(backend));
+        assert_eq!(parent("read"), [None]);
+        assert_eq!(parent("build"), [None]);
+        assert_eq!(parent("chunk"), [Some("build")]);
+        // the workers' spans, on threads of their own, still go under the run's
+        assert_eq!(parent("count"), [Some("build"); 3], "{:?}", backend);
+        assert_eq!(parent("aggregate"), [Some("build")]);
+    }
+    // the reader thread's too
+    let parent = record(builder.pipeline(Some(500)));
+    assert_eq!((parent("read"), parent("count")), (vec![None], vec![Some("build"); 4]));
+}
+
+#[cfg(feature = "concurrent")]
+#[test]
+fn test_parse_raw_report() {
+    let builder = DictionaryBuilder::new().num_threads(Some(3)).min_chunk_lines(1);
+    for backend in [Backend::SeparateMaps, Backend::ConcurrentMap, Backend::External] {
+        let (result, report) = builder.clone().backend(backend).parse_raw_report("data/HDFS_2k.log".to_string(), &HDFS);
+        assert_eq!(result, builder.parse_raw("data/HDFS_2k.log".to_string(), &HDFS));
+        assert_eq!((report.backend, report.threads), (backend, 3));
+        assert_eq!(report.worker_lines, [667, 667, 666]);
+        assert!(report.tokenize > Duration::ZERO && report.count > Duration::ZERO && report.read > Duration::ZERO);
+        assert_eq!(report.total(), report.read + report.count + report.aggregate);
+        assert!(report.to_string().starts_with(&format!("{:?} backend, 3 threads, lines per chunk [667, 667, 666]\nread ", backend)));
+    }
+    let (_, report) = builder.clone().skip_lines(1000).parse_raw_report("data/HDFS_2k.log".to_string(), &HDFS);
+    assert_eq!(report.worker_lines, [334, 334, 332]);
+
+    // the threads take the smaller chunks in turn, and the counts come out the same
+    for backend in [Backend::SeparateMaps, Backend::ConcurrentMap, Backend::CountMin, Backend::External] {
+        let one = builder.clone().backend(backend);
+        let (result, report) = one.clone().chunks_per_thread(4).parse_raw_report("data/HDFS_2k.log".to_string(), &HDFS);
+        assert_eq!(result, one.parse_raw("data/HDFS_2k.log".to_string(), &HDFS));
+        assert_eq!(report.worker_lines.len(), 12);
+        assert_eq!(report.worker_lines.iter().sum::<usize>(), 2000);
+    }
+    let (_, report) = builder.num_threads(Some(1)).chunks_per_thread(4).parse_raw_report("data/HDFS_2k.log".to_string(), &HDFS);
+    assert_eq!(report.worker_lines, [2000]);
+}
+
+#[cfg(not(feature = "concurrent"))]
+#[test]
+fn test_without_concurrent() {
+    let builder = DictionaryBuilder::new().num_threads(Some(3)).min_chunk_lines(1);
+    let expected = builder.clone().backend(Backend::SeparateMaps).parse_raw("data/HDFS_2k.log".to_string(), &HDFS);
+    for backend in [Backend::ConcurrentMap, Backend::Buffered] {
+        let (result, report) = builder.clone().backend(backend).parse_raw_report("data/HDFS_2k.log".to_string(), &HDFS);
+        assert_eq!(result, expected);
+        assert_eq!((report.backend, report.threads, report.worker_lines), (Backend::SeparateMaps, 1, vec![2000]));
+    }
+    assert!(matches!(builder.num_threads(Some(0)).parse_raw_checked("data/HDFS_2k.log".to_string(), &HDFS), Err(ParserError::InvalidConfig(_))));
+}
+
+#[test]
+fn test_parse_raw_buffered() {
+    let builder = DictionaryBuilder::new().num_threads(Some(3)).min_chunk_lines(1).ngram_sizes(&[2, 3, 4]);
+    let expected = builder.parse_raw_conc("data/HDFS_2k.log".to_string(), &HDFS);
+    let buffered = builder.backend(Backend::Buffered);
+    assert_eq!(buffered.parse_raw("data/HDFS_2k.log".to_string(), &HDFS), expected);
+    // flushing after every line, and whenever a single n-gram is waiting
+    assert_eq!(buffered.clone().flush_every(1, usize::MAX).parse_raw("data/HDFS_2k.log".to_string(), &HDFS), expected);
+    assert_eq!(buffered.flush_every(usize::MAX, 1).parse_raw("data/HDFS_2k.log".to_string(), &HDFS), expected);
+}
+
+#[test]
+fn test_parse_raw_shards_capacity() {
+    let builder = DictionaryBuilder::new().num_threads(Some(3)).min_chunk_lines(1);
+    let expected = builder.parse_raw_conc("data/HDFS_2k.log".to_string(), &HDFS);
+    let tuned = builder.clone().shard_amount(Some(2)).estimated_ngrams(Some(5000));
+    assert_eq!(tuned.parse_raw_conc("data/HDFS_2k.log".to_string(), &HDFS), expected);
+    assert_eq!(tuned.parse_raw_single("data/HDFS_2k.log".to_string(), &HDFS), expected);
+    assert!(panic::catch_unwind(|| DictionaryBuilder::new().shard_amount(Some(48))).is_err());
+}
+
+#[test]
+fn test_parse_raw_levels() {
+    let builder = DictionaryBuilder::new().num_threads(Some(3));
+    let warnings = builder.clone().levels(&["WARN"]).parse_raw_conc("data/HDFS_2k.log".to_string(), &HDFS);
+
+    // the same as blanking out the other lines, which keeps the warnings that aren't adjacent apart
+    let path = std::env::temp_dir().join(format!("logram-test-{}-warn.log", std::process::id()));
+    let log = fs::read_to_string("data/HDFS_2k.log").unwrap();
+    fs::write(&path, log.lines().map(|l| if l.contains(" WARN ") { format!("{}\n", l) } else { "\n".to_string() }).collect::<String>()).unwrap();
+    let blanked = builder.parse_raw_single(path.to_string_lossy().to_string(), &HDFS);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(warnings, blanked);
+    assert!(warnings.ngram_dicts[&2].len() < builder.parse_raw_conc("data/HDFS_2k.log".to_string(), &HDFS).ngram_dicts[&2].len());
+
+    let no_level = LogFormat::Custom { format: "<Time> <Content>".to_string(), censor: vec![] };
+    assert!(matches!(builder.levels(&["WARN"]).parse_raw_conc_checked("data/HDFS_2k.log".to_string(), &no_level), Err(ParserError::FormatMismatch(_))));
+}
+
+#[test]
+fn test_parse_raw_time_range() {
+    use crate::packages::time::parse_time;
+    // 21:00:00 to 21:02:00 on the 10th of November 2008, in the middle of the file
+    let (start, end) = (parse_time("081110 210000", "%y%m%d %H%M%S").unwrap(), parse_time("081110 210200", "%y%m%d %H%M%S").unwrap());
+    let builder = DictionaryBuilder::new().num_threads(Some(3));
+    let windowed = builder.clone().time_range(Some(TimeRange::new(start, end))).parse_raw_conc("data/HDFS_2k.log".to_string(), &HDFS);
+
+    let path = std::env::temp_dir().join(format!("logram-test-{}-window.log", std::process::id()));
+    let log = fs::read_to_string("data/HDFS_2k.log").unwrap();
+    let in_window = |l: &str| l.starts_with("081110 2100") || l.starts_with("081110 2101");
+    fs::write(&path, log.lines().map(|l| if in_window(l) { format!("{}\n", l) } else { "\n".to_string() }).collect::<String>()).unwrap();
+    let blanked = builder.parse_raw_single(path.to_string_lossy().to_string(), &HDFS);
+    fs::remove_file(&path).unwrap();
+    assert!(log.lines().any(in_window));
+    assert_eq!(windowed, blanked);
+
+    // a custom parser for a format without one
+    let by_hour = TimeRange::new(15, 16).parser(Arc::new(|m: &regex::Captures| m.name("Time")?.as_str().get(..2)?.parse().ok()));
+    let linux = builder.clone().time_range(Some(by_hour)).parse_raw_single("data/Linux_2k.log".to_string(), &Linux);
+    assert!(!linux.ngram_dicts[&2].is_empty());
+    assert!(matches!(builder.time_range(Some(TimeRange::new(0, 1))).parse_raw_single_checked("data/Linux_2k.log".to_string(), &Linux), Err(ParserError::FormatMismatch(_))));
+}
+
+#[test]
+fn test_session_gap() {
+    // HDFS_2k has a dozen or so pauses of over 20 minutes
+    let context = ContextWindow { tokens: 3, lines: 2 };
+    let builder = DictionaryBuilder::new().min_chunk_lines(1).num_threads(Some(3)).context(context);
+    let sessions = builder.clone().session_gap(Some(SessionGap::new(20 * 60 * 1000)));
+
+    // the same as blank lines between the sessions, enough of them that no context reaches past
+    let path = std::env::temp_dir().join(format!("logram-test-{}-sessions.log", std::process::id()));
+    let log = fs::read_to_string("data/HDFS_2k.log").unwrap();
+    let time = |l: &str| crate::packages::time::parse_time(&l[..13], "%y%m%d %H%M%S").unwrap();
+    let (mut blanked, mut breaks) = (String::new(), 0);
+    for (prev, l) in std::iter::once(None).chain(log.lines().map(Some)).zip(log.lines()) {
+        if prev.is_some_and(|prev| time(l) - time(prev) > 20 * 60 * 1000) {
+            blanked.push_str("\n\n");
+            breaks += 1;
+        }
+        blanked.push_str(l);
+        blanked.push('\n');
+    }
+    fs::write(&path, blanked).unwrap();
+    let expected = builder.parse_raw_single(path.to_string_lossy().to_string(), &HDFS);
+    fs::remove_file(&path).unwrap();
+    assert!(breaks > 5);
+    assert_eq!(sessions.parse_raw_single("data/HDFS_2k.log".to_string(), &HDFS), expected);
+    for backend in [Backend::SeparateMaps, Backend::ConcurrentMap, Backend::Buffered, Backend::External] {
+        assert_eq!(sessions.clone().backend(backend).parse_raw("data/HDFS_2k.log".to_string(), &HDFS), expected, "{:?}", backend);
+    }
+    assert_ne!(builder.parse_raw("data/HDFS_2k.log".to_string(), &HDFS), expected);
+
+    // and in batches
+    let lines = read_log("data/HDFS_2k.log").unwrap();
+    let (mut batched, mut previous) = (DictionaryResult::default(), vec![]);
+    for batch in lines.chunks(300) {
+        sessions.update_batch(&mut batched, &mut previous, batch.to_vec(), &HDFS).unwrap();
+    }
+    assert_eq!(batched, expected);
+
+    assert!(matches!(builder.session_gap(Some(SessionGap::new(1000))).parse_raw_checked("data/Linux_2k.log".to_string(), &Linux), Err(ParserError::FormatMismatch(_))));
+}
+
+#[test]
+fn test_split_on_change() {
+    let builder = DictionaryBuilder::new().min_chunk_lines(1).num_threads(Some(3));
+    let split = builder.clone().split_on_change(&["Component"]);
+
+    // the same as a blank line wherever the component changes
+    let path = std::env::temp_dir().join(format!("logram-test-{}-components.log", std::process::id()));
+    let log = fs::read_to_string("data/Linux_2k.log").unwrap();
+    let format = regex_generator(format_string(&Linux));
+    let component = |l: &str| format.captures(l.trim()).and_then(|m| m.name("Component")).map(|c| c.as_str().to_string());
+    let (mut blanked, mut changes) = (String::new(), 0);
+    for (prev, l) in std::iter::once(None).chain(log.lines().map(Some)).zip(log.lines()) {
+        if let (Some(p), Some(c)) = (prev.and_then(component), component(l)) {
+            if p != c {
+                blanked.push('\n');
+                changes += 1;
+            }
+        }
+        blanked.push_str(l);
+        blanked.push('\n');
+    }
+    fs::write(&path, blanked).unwrap();
+    let expected = builder.parse_raw_single(path.to_string_lossy().to_string(), &Linux);
+    fs::remove_file(&path).unwrap();
+    assert!(changes > 100);
+    for backend in [Backend::SeparateMaps, Backend::ConcurrentMap, Backend::Buffered, Backend::External] {
+        assert_eq!(split.clone().backend(backend).parse_raw("data/Linux_2k.log".to_string(), &Linux), expected, "{:?}", backend);
+    }
+    assert!(expected.ngram_dicts[&2].len() < builder.parse_raw("data/Linux_2k.log".to_string(), &Linux).ngram_dicts[&2].len());
+
+    assert!(matches!(builder.split_on_change(&["Nothing"]).parse_raw_checked("data/HDFS_2k.log".to_string(), &HDFS), Err(ParserError::FormatMismatch(_))));
+}
+
+#[test]
+fn test_group_by() {
+    let builder = DictionaryBuilder::new().min_chunk_lines(1).num_threads(Some(3));
+    let by_block = builder.clone().group_by(Some(GroupBy::Pattern(Regex::new(r"(blk_-?\d+)").unwrap())));
+
+    // the same as the blocks one after the other, a blank line between them
+    let path = std::env::temp_dir().join(format!("logram-test-{}-blocks.log", std::process::id()));
+    let log = fs::read_to_string("data/HDFS_2k.log").unwrap();
+    let block = Regex::new(r"blk_-?\d+").unwrap();
+    let mut blocks: Vec<(&str, Vec<&str>)> = vec![];
+    for l in log.lines() {
+        let id = block.find(l).unwrap().as_str();
+        match blocks.iter_mut().find(|(b, _)| *b == id) {
+            Some((_, lines)) => lines.push(l),
+            None => blocks.push((id, vec![l])),
+        }
+    }
+    fs::write(&path, blocks.iter().map(|(_, lines)| lines.join("\n") + "\n\n").collect::<String>()).unwrap();
+    let expected = builder.parse_raw_single(path.to_string_lossy().to_string(), &HDFS);
+    fs::remove_file(&path).unwrap();
+    assert!(blocks.len() > 100);
+    for backend in [Backend::SeparateMaps, Backend::ConcurrentMap, Backend::Buffered, Backend::External] {
+        assert_eq!(by_block.clone().backend(backend).parse_raw("data/HDFS_2k.log".to_string(), &HDFS), expected, "{:?}", backend);
+    }
+    assert_ne!(builder.parse_raw("data/HDFS_2k.log".to_string(), &HDFS), expected);
+
+    // a field, and the lines without one left out
+    let by_pid = builder.clone().group_by(Some(GroupBy::Field("Pid".to_string())));
+    let mut result = by_pid.parse_raw("data/HDFS_2k.log".to_string(), &HDFS);
+    assert_eq!(result.all_token_list, builder.parse_raw("data/HDFS_2k.log".to_string(), &HDFS).all_token_list);
+    let tokenizer = RegexTokenizer::new(&HDFS).unwrap().group_by(Some(GroupBy::Pattern(Regex::new("WARN").unwrap()))).unwrap();
+    assert_eq!(group_lines(&[log.lines().collect::<Vec<_>>()], &tokenizer)[0].len(), log.matches(" WARN ").count());
+
+    assert!(matches!(by_pid.update_lines(&mut result, &[], vec![], &HDFS), Err(ParserError::Io(_))));
+    assert!(matches!(builder.group_by(Some(GroupBy::Field("Nothing".to_string()))).parse_raw_checked("data/HDFS_2k.log".to_string(), &HDFS), Err(ParserError::FormatMismatch(_))));
+}
+
+#[test]
+fn test_parse_raw_by_component() {
+    let builder = DictionaryBuilder::new().num_threads(Some(3));
+    let by_component = builder.parse_raw_by_component("data/Linux_2k.log".to_string(), &Linux);
+    assert!(by_component.contains_key("sshd(pam_unix)"));
+    assert!(!by_component.contains_key("sshd(pam_unix)[19937]"));
+    assert!(by_component.contains_key("kernel"));
+    assert!(by_component["kernel"].ngram_dicts[&2].keys().all(|k| !k.contains("pam_unix")));
+
+    // the same as parsing only the lines of one component
+    let path = std::env::temp_dir().join(format!("logram-test-{}-kernel.log", std::process::id()));
+    let log = fs::read_to_string("data/Linux_2k.log").unwrap();
+    fs::write(&path, log.lines().filter(|l| l.contains(" kernel: ")).map(|l| format!("{}\n", l)).collect::<String>()).unwrap();
+    let kernel = builder.parse_raw_single(path.to_string_lossy().to_string(), &Linux);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(by_component["kernel"], kernel);
+
+    let no_component = LogFormat::Custom { format: "<Time> <Content>".to_string(), censor: vec![] };
+    assert!(matches!(builder.parse_raw_by_component_checked("data/Linux_2k.log".to_string(), &no_component), Err(ParserError::FormatMismatch(_))));
+
+    // the lines of the file a slice of it leaves, not of each component
+    let slice = fs::read_to_string("data/Linux_2k.log").unwrap().lines().skip(50).take(100).map(|l| format!("{}\n", l)).collect::<String>();
+    let path = std::env::temp_dir().join(format!("logram-test-{}-slice.log", std::process::id()));
+    fs::write(&path, slice).unwrap();
+    let expected = builder.parse_raw_by_component(path.to_string_lossy().to_string(), &Linux);
+    let limited = builder.clone().skip_lines(50).max_lines(Some(100));
+    assert_eq!(limited.parse_raw_by_component("data/Linux_2k.log".to_string(), &Linux), expected);
+    if cfg!(all(unix, feature = "mmap")) {
+        assert_eq!(limited.mmap(true).parse_raw_by_component("data/Linux_2k.log".to_string(), &Linux), expected);
+    }
+    let start = fs::read("data/Linux_2k.log").unwrap().iter().enumerate().filter(|(_, b)| **b == b'\n').nth(49).unwrap().0 as u64 + 1;
+    let end = start + fs::metadata(&path).unwrap().len();
+    assert_eq!(builder.clone().start_offset(start).end_offset(Some(end)).parse_raw_by_component("data/Linux_2k.log".to_string(), &Linux), expected);
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_parse_raw_chunk_boundaries() {
+    let sequential = DictionaryBuilder::new().num_threads(Some(1)).min_chunk_lines(1);
+    let expected = sequential.parse_raw_single("data/HDFS_2k.log".to_string(), &HDFS);
+    for threads in [2, 7, 8] {
+        let chunked = sequential.clone().num_threads(Some(threads));
+        assert_eq!(chunked.parse_raw_single("data/HDFS_2k.log".to_string(), &HDFS), expected);
+        assert_eq!(chunked.parse_raw_conc("data/HDFS_2k.log".to_string(), &HDFS), expected);
+        let buffered = chunked.clone().backend(Backend::Buffered).flush_every(7, 100);
+        assert_eq!(buffered.parse_raw("data/HDFS_2k.log".to_string(), &HDFS), expected);
+        assert_eq!(chunked.parse_raw_ids("data/HDFS_2k.log".to_string(), &HDFS).to_strings("^"), expected);
+    }
+
+    // one-line chunks, where a line's context reaches across several chunks
+    let wide = DictionaryBuilder::new().context(ContextWindow { tokens: 3, lines: 2 }).ngram_sizes(&[2, 3, 4]).min_chunk_lines(1);
+    let expected = wide.clone().num_threads(Some(1)).parse_raw_single("data/from_paper.log".to_string(), &Linux);
+    let chunked = wide.num_threads(Some(64));
+    assert_eq!(chunked.parse_raw_single("data/from_paper.log".to_string(), &Linux), expected);
+    assert_eq!(chunked.parse_raw_conc("data/from_paper.log".to_string(), &Linux), expected);
+    assert_eq!(chunked.backend(Backend::Buffered).parse_raw("data/from_paper.log".to_string(), &Linux), expected);
+}
+
+/// How much of the input a run could use: of the `total` lines read, `invalid_utf8` weren't UTF-8
+/// (and were left out, unless `lossy_utf8` is on), `matched` matched the format and `unmatched`
+/// didn't. The first `keep_unmatched` of those are in `unmatched_lines`, with their line numbers
+/// (from 1, not counting the lines left out), redacted like the content of the others when
+/// `DictionaryBuilder::redact_pii` or `extra_censors` are set. `redactions` counts what their typed
+/// placeholders replaced, by placeholder.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParseStats {
+    pub invalid_utf8: usize,
+    pub matched: usize,
+    pub unmatched: usize,
+    pub total: usize,
+    pub unmatched_lines: Vec<(usize, String)>,
+    #[serde(default)]
+    pub redactions: BTreeMap<String, u64>,
+}
+
+impl ParseStats {
+    /// The fraction of the lines read that matched the format; 1 for no lines.
+    pub fn match_rate(&self) -> f64 {
+        if self.total == 0 { 1.0 } else { self.matched as f64 / self.total as f64 }
+    }
+
+    // counts the lines read into inputs, once invalid_utf8 is known
+    fn count<S: AsRef<str>>(&mut self, inputs: &[Vec<S>], format: Option<&RegexTokenizer>, opts: &DictionaryBuilder) {
+        let kept: usize = inputs.iter().map(Vec::len).sum();
+        self.total = if opts.lossy_utf8 { kept } else { kept + self.invalid_utf8 };
+        let Some(format) = format else { return };
+        // the lines kept whole get the redactions the content of the others does, the PII first
+        let pii = if opts.redact_pii && opts.keep_unmatched > 0 { pii_censors() } else { vec![] };
+        for (i, line) in inputs.iter().flat_map(|input| input.iter().enumerate()) {
+            if format.matches(line.as_ref()) {
+                self.matched += 1;
+                format.redactions(line.as_ref(), &mut self.redactions);
+                continue;
+            }
+            self.unmatched += 1;
+            if self.unmatched_lines.len() < opts.keep_unmatched {
+                let mut line = line.as_ref().to_string();
+                for censor in pii.iter().chain(&opts.extra_censors) {
+                    let (redacted, hits) = censor.replace_all(&line);
+                    if hits > 0 {
+                        if censor.replacement != "<*>" {
+                            *self.redactions.entry(censor.replacement.clone()).or_default() += hits;
+                        }
+                        line = redacted.into_owned();
+                    }
+                }
+                self.unmatched_lines.push((i + 1, line));
+            }
+        }
+    }
+}
+
+/// Where the time of a run went, from `DictionaryBuilder::parse_raw_report`, and what it ran on:
+/// the `backend` that counted (the one set, or the one the entry point picks), the worker `threads`
+/// and the lines each worker's chunk had, in input order (one chunk per worker per input). `read` is
+/// reading and decoding the input; `count` is the workers' wall time, up to the last line any of them
+/// tokenized, and `aggregate` the rest of the build: the final flushes, merging the workers'
+/// maps, stitching the chunk boundaries and turning token ids back into strings. `tokenize` is the
+/// time spent in the tokenizer, added up over the workers, so with several threads it can be more
+/// than `count`. With `DictionaryBuilder::pipeline` the reading happens during `count`, so `read`
+/// is zero and `worker_lines` has the lines of each batch.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ParseReport {
+    pub backend: Backend,
+    pub threads: u32,
+    pub worker_lines: Vec<usize>,
+    pub read: Duration,
+    pub tokenize: Duration,
+    pub count: Duration,
+    pub aggregate: Duration,
+}
+
+impl ParseReport {
+    /// The wall time of the whole run.
+    pub fn total(&self) -> Duration {
+        self.read + self.count + self.aggregate
+    }
+}
+
+impl std::fmt::Display for ParseReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        writeln!(f, "{:?} backend, {} threads, lines per chunk {:?}", self.backend, self.threads, self.worker_lines)?;
+        write!(f, "read {:.1} ms, count {:.1} ms (tokenizing {:.1} ms over all workers), aggregate {:.1} ms, total {:.1} ms",
+               ms(self.read), ms(self.count), ms(self.tokenize), ms(self.aggregate), ms(self.total()))
+    }
+}
+
+// a tokenizer that keeps the time it spends tokenizing, for ParseReport
+struct Timed<T> {
+    inner: T,
+    start: Instant,
+    tokenizing: AtomicU64,
+    // when the last tokenizing so far ended, in nanoseconds since start
+    last: AtomicU64,
+}
+
+impl<T: Tokenizer> Timed<T> {
+    fn new(inner: T) -> Self {
+        Timed { inner, start: Instant::now(), tokenizing: AtomicU64::new(0), last: AtomicU64::new(0) }
+    }
+
+    fn time<R>(&self, f: impl FnOnce() -> R) -> R {
+        let start = Instant::now();
+        let r = f();
+        self.tokenizing.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        self.last.fetch_max(self.start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        r
+    }
+}
+
+impl<T: Tokenizer> Tokenizer for Timed<T> {
+    fn tokenize<'a>(&self, line: &'a str) -> Vec<Cow<'a, str>> {
+        self.time(|| self.inner.tokenize(line))
+    }
+
+    // f interns the tokens, which isn't tokenizing
+    fn for_each_token(&self, line: &str, f: &mut dyn FnMut(&str)) {
+        self.tokenize(line).iter().for_each(|t| f(t))
+    }
+
+    fn breakdown<'a>(&self, line: &'a str) -> Option<&'a str> {
+        self.time(|| self.inner.breakdown(line))
+    }
+
+    fn breaks_context(&self, prev: &str, line: &str) -> bool {
+        self.inner.breaks_context(prev, line)
+    }
+
+    fn group<'a>(&self, line: &'a str) -> Option<&'a str> {
+        self.inner.group(line)
+    }
+}
+
+/// The output of a dictionary-building run: one n-gram dictionary per requested size (keyed by that
+/// size), one skip-gram dictionary per distance if any were asked for, the same n-grams broken down
+/// by a field if that was asked for, and the sorted list of distinct tokens. It serializes with the
+/// entries of every dictionary sorted by key, so the same result always serializes the same; see
+/// `to_sorted_vec` for a fixed order to iterate in. Its maps hash with `S`, the standard library's
+/// `RandomState` unless the run was asked for another with `DictionaryBuilder::parse_raw_hashed`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(bound(serialize = "S: BuildHasher", deserialize = "S: BuildHasher + Default"))]
+pub struct DictionaryResult<S = RandomState> {
+    #[serde(serialize_with = "serialize_sorted")]
+    pub ngram_dicts: BTreeMap<usize, NGramDict<S>>,
+    /// The pairs of tokens `d` apart on a line (keyed by `d`), joined like doubles; see
+    /// `DictionaryBuilder::skip_grams`.
+    #[serde(default, serialize_with = "serialize_sorted")]
+    pub skip_grams: BTreeMap<usize, NGramDict<S>>,
+    /// The n-gram dictionaries again for the lines of each value of a field (keyed by the value,
+    /// then the size), so their counts add up to the n-grams of the lines that have the field; see
+    /// `DictionaryBuilder::breakdown_by`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty", serialize_with = "serialize_breakdown")]
+    pub breakdown: Breakdown<S>,
+    pub all_token_list: TokenList,
+}
+
+// by hand, as the derive would want S: PartialEq, which RandomState isn't
+impl<S: BuildHasher> PartialEq for DictionaryResult<S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.ngram_dicts == other.ngram_dicts && self.skip_grams == other.skip_grams && self.breakdown == other.breakdown
+            && self.all_token_list == other.all_token_list
+    }
+}
+
+/// One dictionary of a `DictionaryResult`: each n-gram, its tokens joined with the separator, and
+/// how many times it occurred.
+pub type NGramDict<S = RandomState> = HashMap<String, u64, S>;
+
+/// The n-gram dictionaries of the lines of each value of a field, keyed by the value.
+pub type Breakdown<S = RandomState> = BTreeMap<String, BTreeMap<usize, NGramDict<S>>>;
+
+/// The distinct tokens of a run, sorted.
+pub type TokenList = Vec<String>;
+
+impl<S: BuildHasher + Default> DictionaryResult<S> {
+    pub fn ngram_dict(&self, n: usize) -> Option<&NGramDict<S>> {
+        self.ngram_dicts.get(&n)
+    }
+
+    pub fn skip_gram_dict(&self, distance: usize) -> Option<&NGramDict<S>> {
+        self.skip_grams.get(&distance)
+    }
+
+    /// Adds the counts and tokens of `other`, as though both had come from one run over two inputs.
+    pub fn merge(&mut self, other: DictionaryResult<S>) {
+        for (n, dict) in other.ngram_dicts {
+            let merged = self.ngram_dicts.entry(n).or_default();
+            for (key, value) in dict {
+                *merged.entry(key).or_default() += value;
+            }
+        }
+        for (d, dict) in other.skip_grams {
+            let merged = self.skip_grams.entry(d).or_default();
+            for (key, value) in dict {
+                *merged.entry(key).or_default() += value;
+            }
+        }
+        for (field_value, dicts) in other.breakdown {
+            let merged = self.breakdown.entry(field_value).or_default();
+            for (n, dict) in dicts {
+                let merged = merged.entry(n).or_default();
+                for (key, value) in dict {
+                    *merged.entry(key).or_default() += value;
+                }
+            }
+        }
+        self.all_token_list.extend(other.all_token_list);
+        self.all_token_list.sort_unstable();
+        self.all_token_list.dedup();
+    }
+
+    /// The n-grams of size `n`, most frequent first (ties by key).
+    pub fn most_frequent(&self, n: usize) -> Vec<(&str, u64)> {
+        by_frequency(self.ngram_dicts.get(&n))
+    }
+
+    /// Every n-gram as `(n, key, count)`: the sizes in order, and each size most frequent first
+    /// (ties by key), which is the same from run to run unlike iterating the dictionaries.
+    pub fn to_sorted_vec(&self) -> Vec<(usize, &str, u64)> {
+        self.ngram_dicts.keys()
+            .flat_map(|n| self.most_frequent(*n).into_iter().map(move |(key, count)| (*n, key, count)))
+            .collect()
+    }
+
+    /// The skip-grams of tokens `distance` apart, most frequent first (ties by key).
+    pub fn most_frequent_skip_grams(&self, distance: usize) -> Vec<(&str, u64)> {
+        by_frequency(self.skip_grams.get(&distance))
+    }
+
+    /// How unusual `line` is next to the lines these dictionaries were built from: the mean of
+    /// 1 / (count + 1) over its n-grams of every size there's a dictionary of (the doubles and
+    /// triples by default), so an unseen n-gram scores 1 and a common one close to 0. A line too
+    /// short for any of them scores 0. Split it with the tokenizer the dictionaries were built with,
+    /// and pass their separator.
+    pub fn score_line(&self, line: &str, tokenizer: &dyn Tokenizer, separator: &str) -> f64 {
+        self.score_tokens(&tokenizer.tokenize(line), separator)
+    }
+
+    /// `score_line` for an already split line.
+    pub fn score_tokens<T: AsRef<str>>(&self, tokens: &[T], separator: &str) -> f64 {
+        let (mut total, mut ngrams) = (0.0, 0);
+        for (n, dict) in &self.ngram_dicts {
+            for ngram in tokens.windows(*n) {
+                let count = dict.get(&join_ngram(ngram, separator)).copied().unwrap_or(0);
+                total += 1.0 / (count as f64 + 1.0);
+                ngrams += 1;
+            }
+        }
+        if ngrams == 0 { 0.0 } else { total / ngrams as f64 }
+    }
+
+    /// Writes every dictionary to `dir` as a CSV file named after its size (double.csv, triple.csv,
+    /// 4-gram.csv...) with `ngram,count` rows, most frequent first, and the tokens to tokens.csv. A
+    /// breakdown goes to breakdown.csv, with `value,n,ngram,count` rows. The n-grams stay joined with
+    /// the separator; fields with commas, quotes or line breaks are quoted.
+    pub fn write_csv<P: AsRef<Path>>(&self, dir: P) -> io::Result<()> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        for n in self.ngram_dicts.keys() {
+            let mut w = io::BufWriter::new(File::create(dir.join(format!("{}.csv", ngram_name(*n))))?);
+            writeln!(w, "ngram,count")?;
+            for (key, count) in self.most_frequent(*n) {
+                writeln!(w, "{},{}", csv_field(key), count)?;
+            }
+            w.flush()?;
+        }
+        if !self.breakdown.is_empty() {
+            let mut w = io::BufWriter::new(File::create(dir.join("breakdown.csv"))?);
+            writeln!(w, "value,n,ngram,count")?;
+            for (value, dicts) in &self.breakdown {
+                for (n, dict) in dicts {
+                    for (key, count) in by_frequency(Some(dict)) {
+                        writeln!(w, "{},{},{},{}", csv_field(value), n, csv_field(key), count)?;
+                    }
+                }
+            }
+            w.flush()?;
+        }
+        let mut w = io::BufWriter::new(File::create(dir.join("tokens.csv"))?);
+        writeln!(w, "token")?;
+        for token in &self.all_token_list {
+            writeln!(w, "{}", csv_field(token))?;
+        }
+        w.flush()
+    }
+
+    /// Writes the result to `path` in a small binary format that `load` reads back: a magic number and
+    /// version, then every dictionary (its size, then its entries sorted by key, so the same result
+    /// always gives the same file), the token list, the skip-gram dictionaries (by distance, like
+    /// the others) and the breakdown (how many values, then each value and its dictionaries), with
+    /// all integers as little-endian u64s and strings as their length followed by their UTF-8 bytes.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut w = io::BufWriter::new(File::create(path)?);
+        w.write_all(SAVE_MAGIC)?;
+        write_u64(&mut w, SAVE_VERSION)?;
+        write_dicts(&mut w, &self.ngram_dicts)?;
+        write_u64(&mut w, self.all_token_list.len() as u64)?;
+        for token in &self.all_token_list {
+            write_str(&mut w, token)?;
+        }
+        write_dicts(&mut w, &self.skip_grams)?;
+        write_u64(&mut w, self.breakdown.len() as u64)?;
+        for (value, dicts) in &self.breakdown {
+            write_str(&mut w, value)?;
+            write_dicts(&mut w, dicts)?;
+        }
+        w.flush()
+    }
+
+    /// `load` into maps that hash with `S`.
+    pub fn load_hashed<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut r = io::BufReader::new(File::open(path)?);
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        let version = read_u64(&mut r)?;
+        if &magic != SAVE_MAGIC || !(1..=SAVE_VERSION).contains(&version) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a saved dictionary file"));
+        }
+        let mut result = DictionaryResult { ngram_dicts: read_dicts(&mut r)?, ..Default::default() };
+        for _ in 0..read_u64(&mut r)? {
+            result.all_token_list.push(read_str(&mut r)?);
+        }
+        // version 1 had no skip-grams
+        if version > 1 {
+            result.skip_grams = read_dicts(&mut r)?;
+        }
+        // and version 2 no breakdown
+        if version > 2 {
+            for _ in 0..read_u64(&mut r)? {
+                let value = read_str(&mut r)?;
+                result.breakdown.insert(value, read_dicts(&mut r)?);
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl DictionaryResult {
+    /// Reads back a result written by `save`; anything else is an `InvalidData` error.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::load_hashed(path)
+    }
+}
+
+fn serialize_sorted<H, S: serde::Serializer>(dicts: &BTreeMap<usize, HashMap<String, u64, H>>, serializer: S) -> Result<S::Ok, S::Error> {
+    let sorted: BTreeMap<usize, BTreeMap<&String, &u64>> = dicts.iter().map(|(n, dict)| (*n, dict.iter().collect())).collect();
+    sorted.serialize(serializer)
+}
+
+fn serialize_breakdown<H, S: serde::Serializer>(breakdown: &Breakdown<H>, serializer: S) -> Result<S::Ok, S::Error> {
+    let sorted: BTreeMap<&String, BTreeMap<usize, BTreeMap<&String, &u64>>> = breakdown.iter()
+        .map(|(value, dicts)| (value, dicts.iter().map(|(n, dict)| (*n, dict.iter().collect())).collect()))
+        .collect();
+    sorted.serialize(serializer)
+}
+
+fn by_frequency<H>(dict: Option<&HashMap<String, u64, H>>) -> Vec<(&str, u64)> {
+    let mut entries: Vec<(&str, u64)> = dict.into_iter().flatten().map(|(k, v)| (k.as_str(), *v)).collect();
+    entries.sort_unstable_by(|(k1, v1), (k2, v2)| v2.cmp(v1).then(k1.cmp(k2)));
+    entries
+}
+
+const SAVE_MAGIC: &[u8; 4] = b"LGRM";
+const SAVE_VERSION: u64 = 3;
+
+fn write_dicts<H>(w: &mut impl Write, dicts: &BTreeMap<usize, HashMap<String, u64, H>>) -> io::Result<()> {
+    write_u64(w, dicts.len() as u64)?;
+    for (n, dict) in dicts {
+        write_u64(w, *n as u64)?;
+        write_u64(w, dict.len() as u64)?;
+        let mut entries: Vec<(&String, &u64)> = dict.iter().collect();
+        entries.sort_unstable();
+        for (key, count) in entries {
+            write_str(w, key)?;
+            write_u64(w, *count)?;
+        }
+    }
+    Ok(())
+}
+
+fn read_dicts<H: BuildHasher + Default>(r: &mut impl Read) -> io::Result<BTreeMap<usize, HashMap<String, u64, H>>> {
+    let mut dicts = BTreeMap::new();
+    for _ in 0..read_u64(r)? {
+        let n = read_u64(r)? as usize;
+        let len = read_u64(r)?;
+        let mut dict = HashMap::default();
+        for _ in 0..len {
+            let key = read_str(r)?;
+            dict.insert(key, read_u64(r)?);
+        }
+        dicts.insert(n, dict);
+    }
+    Ok(dicts)
+}
+
+fn write_u64(w: &mut impl Write, x: u64) -> io::Result<()> {
+    w.write_all(&x.to_le_bytes())
+}
+
+fn write_str(w: &mut impl Write, s: &str) -> io::Result<()> {
+    write_u64(w, s.len() as u64)?;
+    w.write_all(s.as_bytes())
+}
+
+fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_str(r: &mut impl Read) -> io::Result<String> {
+    let len = read_u64(r)?;
+    let mut buf = vec![];
+    // take() rather than a vec![0; len], so a corrupt length can't make us allocate the moon
+    if r.take(len).read_to_end(&mut buf)? as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[test]
+fn test_to_sorted_vec() {
+    let result = DictionaryBuilder::new().parse_raw_conc("data/Linux_2k.log".to_string(), &Linux);
+    let sorted = result.to_sorted_vec();
+    assert_eq!(sorted.len(), result.ngram_dicts.values().map(HashMap::len).sum::<usize>());
+    assert!(sorted.windows(2).all(|w| (w[0].0, Reverse(w[0].2), w[0].1) < (w[1].0, Reverse(w[1].2), w[1].1)));
+    // the same once it has been through maps with different hash seeds
+    let copy: DictionaryResult = serde_json::from_str(&serde_json::to_string(&result).unwrap()).unwrap();
+    assert_eq!(copy.to_sorted_vec(), sorted);
+    assert_eq!(serde_json::to_string(&copy).unwrap(), serde_json::to_string(&result).unwrap());
+}
+
+#[test]
+fn test_parse_raw_hashed() {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::BuildHasherDefault;
+    type Fixed = BuildHasherDefault<DefaultHasher>;
+
+    let builder = DictionaryBuilder::new().skip_grams(1).top_k(Some(50));
+    let result = builder.parse_raw("data/Linux_2k.log".to_string(), &Linux);
+    let hashed: DictionaryResult<Fixed> = builder.parse_raw_hashed("data/Linux_2k.log".to_string(), &Linux);
+    assert_eq!(hashed.to_sorted_vec(), result.to_sorted_vec());
+    assert_eq!(hashed.most_frequent_skip_grams(1), result.most_frequent_skip_grams(1));
+    assert_eq!(serde_json::to_string(&hashed).unwrap(), serde_json::to_string(&result).unwrap());
+    let path = std::env::temp_dir().join(format!("logram-test-{}-hashed.dict", std::process::id()));
+    hashed.save(&path).unwrap();
+    let loaded = DictionaryResult::<Fixed>::load_hashed(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.unwrap(), hashed);
+    let mut merged = hashed.clone();
+    merged.merge(hashed.clone());
+    assert_eq!(merged.ngram_dict(2).unwrap().values().sum::<u64>(), hashed.ngram_dict(2).unwrap().values().sum::<u64>() * 2);
+}
+
+#[test]
+fn test_save_load() {
+    let result = DictionaryBuilder::new().ngram_sizes(&[2, 3, 4]).skip_grams(1).parse_raw_conc("data/HDFS_2k.log".to_string(), &HDFS);
+    let path = std::env::temp_dir().join(format!("logram-test-{}.dict", std::process::id()));
+    result.save(&path).unwrap();
+    let loaded = DictionaryResult::load(&path);
+    std::fs::write(&path, b"LGRM garbage").unwrap();
+    let garbage = DictionaryResult::load(&path);
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(loaded.unwrap(), result);
+    assert_eq!(garbage.unwrap_err().kind(), io::ErrorKind::InvalidData);
+}
+
+/// A `DictionaryResult` with the n-grams keyed by token ids instead of joined strings: id `i` is
+/// `all_token_list[i]`, so the ids follow the tokens' sorted order. Made by `parse_raw_ids`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TokenIdResult {
+    #[serde(with = "id_dicts")]
+    pub ngram_dicts: BTreeMap<usize, HashMap<Vec<u32>, u64>>,
+    #[serde(default, with = "id_dicts")]
+    pub skip_grams: BTreeMap<usize, HashMap<Vec<u32>, u64>>,
+    pub all_token_list: TokenList,
+}
+
+// id-keyed dictionaries as sorted lists of [ids, count] pairs, since JSON keys have to be strings
+mod id_dicts {
+    use std::collections::{BTreeMap, HashMap};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    type IdDicts = BTreeMap<usize, HashMap<Vec<u32>, u64>>;
+
+    pub fn serialize<S: Serializer>(dicts: &IdDicts, serializer: S) -> Result<S::Ok, S::Error> {
+        let sorted: BTreeMap<usize, Vec<(&Vec<u32>, &u64)>> = dicts.iter().map(|(n, dict)| {
+            let mut entries: Vec<_> = dict.iter().collect();
+            entries.sort_unstable();
+            (*n, entries)
+        }).collect();
+        sorted.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<IdDicts, D::Error> {
+        let lists = BTreeMap::<usize, Vec<(Vec<u32>, u64)>>::deserialize(deserializer)?;
+        Ok(lists.into_iter().map(|(n, entries)| (n, entries.into_iter().collect())).collect())
+    }
+}
+
+impl TokenIdResult {
+    pub fn ngram_dict(&self, n: usize) -> Option<&HashMap<Vec<u32>, u64>> {
+        self.ngram_dicts.get(&n)
+    }
+
+    pub fn token(&self, id: u32) -> Option<&str> {
+        self.all_token_list.get(id as usize).map(String::as_str)
+    }
+
+    pub fn id(&self, token: &str) -> Option<u32> {
+        self.all_token_list.binary_search_by(|t| t.as_str().cmp(token)).ok().map(|i| i as u32)
+    }
+
+    /// The doubles as pairs of ids.
+    pub fn doubles(&self) -> impl Iterator<Item = ((u32, u32), u64)> + '_ {
+        self.ngram_dicts.get(&2).into_iter().flatten().map(|(ngram, count)| ((ngram[0], ngram[1]), *count))
+    }
+
+    /// The triples as triples of ids.
+    pub fn triples(&self) -> impl Iterator<Item = ((u32, u32, u32), u64)> + '_ {
+        self.ngram_dicts.get(&3).into_iter().flatten().map(|(ngram, count)| ((ngram[0], ngram[1], ngram[2]), *count))
+    }
+
+    /// The same dictionaries with the tokens of each n-gram joined with `separator`, as the other
+    /// `parse_raw_*` entry points make them.
+    pub fn to_strings(&self, separator: &str) -> DictionaryResult {
+        let strings = |dicts: &BTreeMap<usize, HashMap<Vec<u32>, u64>>| dicts.iter()
+            .map(|(n, dict)| (*n, dict.iter().map(|(ngram, count)| {
+                let tokens: Vec<&str> = ngram.iter().map(|id| self.all_token_list[*id as usize].as_str()).collect();
+                (join_ngram(&tokens, separator), *count)
+            }).collect()))
+            .collect();
+        DictionaryResult { ngram_dicts: strings(&self.ngram_dicts), skip_grams: strings(&self.skip_grams), all_token_list: self.all_token_list.clone(), ..Default::default() }
+    }
+}
+
+#[test]
+fn test_parse_raw_ids() {
+    let builder = DictionaryBuilder::new().ngram_sizes(&[2, 3, 4]).min_count(2);
+    for backend in [Backend::SeparateMaps, Backend::ConcurrentMap, Backend::Buffered] {
+        let builder = builder.clone().backend(backend);
+        let ids = builder.parse_raw_ids("data/HDFS_2k.log".to_string(), &HDFS);
+        assert_eq!(ids.to_strings("^"), builder.parse_raw("data/HDFS_2k.log".to_string(), &HDFS));
+        let ((a, b), count) = ids.doubles().max_by_key(|(_, count)| *count).unwrap();
+        assert_eq!(ids.id(ids.token(a).unwrap()), Some(a));
+        assert_eq!(ids.ngram_dict(2).unwrap()[&vec![a, b]], count);
+        assert_eq!(ids.triples().count(), ids.ngram_dicts[&3].len());
+        let json = serde_json::to_string(&ids).unwrap();
+        assert_eq!(serde_json::from_str::<TokenIdResult>(&json).unwrap(), ids);
+    }
+}
+
+#[test]
+fn test_skip_grams() {
+    let builder = DictionaryBuilder::new().context(ContextWindow { tokens: 2, lines: 1 }).min_chunk_lines(1).num_threads(Some(3));
+    let plain = builder.parse_raw("data/Linux_2k.log".to_string(), &Linux);
+    let tokenizer = RegexTokenizer::new(&Linux).unwrap();
+    let lines: Vec<Vec<String>> = read_log("data/Linux_2k.log").unwrap().iter()
+        .map(|line| tokenizer.tokenize(line).into_iter().map(|t| t.into_owned()).collect())
+        .collect();
+    for backend in [Backend::SeparateMaps, Backend::ConcurrentMap, Backend::Buffered] {
+        let result = builder.clone().skip_grams(2).backend(backend).parse_raw("data/Linux_2k.log".to_string(), &Linux);
+        assert_eq!(result.ngram_dicts, plain.ngram_dicts);
+        assert_eq!(result.skip_grams.keys().copied().collect::<Vec<_>>(), [2, 3]);
+        for d in [2, 3] {
+            // only ever within a line, whatever the context and chunks
+            let expected: usize = lines.iter().map(|tokens| tokens.len().saturating_sub(d)).sum();
+            assert_eq!(result.skip_gram_dict(d).unwrap().values().sum::<u64>() as usize, expected);
+        }
+        let (first, third) = (&lines[0][0], &lines[0][2]);
+        assert!(result.skip_grams[&2].contains_key(&join_ngram(&[first, third], "^")));
+    }
+    let ids = builder.clone().skip_grams(1).parse_raw_ids("data/Linux_2k.log".to_string(), &Linux);
+    assert_eq!(ids.to_strings("^"), builder.clone().skip_grams(1).parse_raw("data/Linux_2k.log".to_string(), &Linux));
+}
+
+#[test]
+fn test_breakdown_by() {
+    let builder = DictionaryBuilder::new().min_chunk_lines(1).num_threads(Some(3));
+    let plain = builder.parse_raw("data/Linux_2k.log".to_string(), &Linux);
+    let by_component = builder.clone().breakdown_by(Some("Component"));
+    for backend in [Backend::SeparateMaps, Backend::ConcurrentMap, Backend::Buffered, Backend::External] {
+        let result = by_component.clone().backend(backend).parse_raw("data/Linux_2k.log".to_string(), &Linux);
+        assert_eq!((&result.ngram_dicts, &result.all_token_list), (&plain.ngram_dicts, &plain.all_token_list));
+        assert!(result.breakdown.contains_key("sshd(pam_unix)") && result.breakdown.contains_key("kernel"));
+        // every line has a component, so the breakdown adds back up to the whole
+        for (n, dict) in &plain.ngram_dicts {
+            let mut summed = NGramDict::new();
+            for (key, count) in result.breakdown.values().flat_map(|dicts| &dicts[n]) {
+                *summed.entry(key.clone()).or_default() += count;
+            }
+            assert_eq!(&summed, dict);
+        }
+    }
+    let result = by_component.parse_raw("data/Linux_2k.log".to_string(), &Linux);
+    assert!(result.breakdown["kernel"][&2].keys().all(|k| !k.contains("pam_unix")));
+
+    // in batches as in one go, and saved and loaded back
+    let lines = read_log("data/Linux_2k.log").unwrap();
+    let (mut batched, mut previous) = (DictionaryResult::default(), vec![]);
+    for batch in lines.chunks(700) {
+        by_component.update_batch(&mut batched, &mut previous, batch.to_vec(), &Linux).unwrap();
+    }
+    assert_eq!(batched, result);
+    let path = std::env::temp_dir().join(format!("logram-breakdown-{}.bin", std::process::id()));
+    result.save(&path).unwrap();
+    assert_eq!(DictionaryResult::load(&path).unwrap(), result);
+    fs::remove_file(path).unwrap();
+
+    let by_nothing = builder.clone().breakdown_by(Some("Nothing"));
+    assert!(matches!(by_nothing.parse_raw_checked("data/Linux_2k.log".to_string(), &Linux), Err(ParserError::FormatMismatch(_))));
+    assert!(builder.breakdown_by(Some("Level")).parse_raw("data/HDFS_2k.log".to_string(), &HDFS).breakdown.contains_key("INFO"));
+}
+
+#[test]
+fn test_score_line() {
+    let result = DictionaryBuilder::new().parse_raw_conc("data/HDFS_2k.log".to_string(), &HDFS);
+    let tokenizer = RegexTokenizer::new(&HDFS).unwrap();
+    let score = |line: &str| result.score_line(line, &tokenizer, "^");
+    let usual = score("081109 204925 673 INFO dfs.DataNode$DataXceiver: Receiving block blk_-5623176793330377570 src: /10.251.75.228:53725 dest: /10.251.75.228:50010");
+    let odd = score("081109 204925 673 INFO dfs.DataNode$DataXceiver: Receiving nothing at all from nobody");
+    assert!(usual < 0.05, "{}", usual);
+    assert!(odd > 0.5, "{}", odd);
+    assert_eq!(score("081109 204925 673 INFO dfs.DataNode$DataXceiver: nothing"), 0.0);
+    assert_eq!(result.score_tokens(&["never", "seen", "before"], "^"), 1.0);
+
+    // the sizes the dictionaries were built with: a line seen as is scores low on its 4-grams too,
+    // and isn't held against triples there's no dictionary of
+    let content = LogFormat::Custom { format: "<Content>".to_string(), censor: vec![] };
+    let sizes = DictionaryBuilder::new().ngram_sizes(&[2, 4]).parse_str("a b c d e\na b c d e\na b c d e", &content);
+    let tokens = ["a", "b", "c", "d", "e"];
+    assert_eq!(sizes.ngram_dicts.keys().copied().collect::<Vec<_>>(), [2, 4]);
+    assert!(sizes.score_tokens(&tokens, "^") < 0.3, "{}", sizes.score_tokens(&tokens, "^"));
+    // one new token: 2 of its 4 doubles and both its 4-grams are unseen
+    let odd = sizes.score_tokens(&["a", "b", "c", "x", "e"], "^");
+    let seen = |ngram: &[&str]| 1.0 / (sizes.ngram_dicts[&2][&join_ngram(ngram, "^")] as f64 + 1.0);
+    assert!((odd - (4.0 + seen(&["a", "b"]) + seen(&["b", "c"])) / 6.0).abs() < 1e-9, "{}", odd);
+}
+
+// quotes field as RFC 4180 says, if it has to be
+pub(crate) fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+#[test]
+fn test_write_csv() {
+    let mut result = DictionaryBuilder::new().parse_raw_conc("data/from_paper.log".to_string(), &Linux);
+    result.ngram_dicts.get_mut(&2).unwrap().insert(join_ngram(&["a,b", "say \"hi\"^"], "^"), 1000);
+    let dir = std::env::temp_dir().join(format!("logram-test-{}-csv", std::process::id()));
+    result.write_csv(&dir).unwrap();
+    let doubles = fs::read_to_string(dir.join("double.csv")).unwrap();
+    let tokens = fs::read_to_string(dir.join("tokens.csv")).unwrap();
+    assert!(dir.join("triple.csv").exists());
+    fs::remove_dir_all(&dir).unwrap();
+
+    let rows: Vec<&str> = doubles.lines().collect();
+    assert_eq!(rows[0], "ngram,count");
+    assert_eq!(rows[1], r#""a,b^say ""hi""\^",1000"#);
+    assert_eq!(rows.len(), result.ngram_dicts[&2].len() + 1);
+    assert_eq!(tokens.lines().count(), result.all_token_list.len() + 1);
+}
+
+// the k most frequent entries, most frequent first (ties by key), through a min-heap that never holds
+// more than k of them
+fn top_k_entries<'a, K: Ord + Clone + 'a, I>(entries: I, k: usize) -> Vec<(K, u64)>
+where I: IntoIterator<Item = (&'a K, &'a u64)>, {
+    let mut heap = BinaryHeap::with_capacity(k + 1);
+    for (key, count) in entries {
+        heap.push(Reverse((*count, Reverse(key))));
+        if heap.len() > k {
+            heap.pop();
+        }
+    }
+    heap.into_sorted_vec().into_iter().map(|Reverse((count, Reverse(key)))| (key.clone(), count)).collect()
+}
+
+#[test]
+fn test_top_k_entries() {
+    let dict: HashMap<String, u64> = [("a", 3), ("b", 7), ("c", 3), ("d", 1), ("e", 7)].iter().map(|(k, v)| (k.to_string(), *v)).collect();
+    let top = top_k_entries(dict.iter(), 3);
+    assert_eq!(top, vec![("b".to_string(), 7), ("e".to_string(), 7), ("a".to_string(), 3)]);
+    assert_eq!(top_k_entries(dict.iter(), 10).len(), 5);
+    assert!(top_k_entries(dict.iter(), 0).is_empty());
+
+    let builder = DictionaryBuilder::new().num_threads(Some(3));
+    let full = builder.parse_raw_conc("data/HDFS_2k.log".to_string(), &HDFS);
+    let top = builder.top_k(Some(50)).parse_raw_single("data/HDFS_2k.log".to_string(), &HDFS);
+    assert_eq!(top.most_frequent(3), full.most_frequent(3)[..50].to_vec());
+}
+
+/// "double" and "triple" for the classic sizes, "<n>-gram" for the rest.
+pub fn ngram_name(n: usize) -> String {
+    match n {
+        2 => "double".to_string(),
+        3 => "triple".to_string(),
+        n => format!("{}-gram", n),
+    }
+}
+
+/// Which implementation aggregates the workers' counts: per-worker HashMaps merged at the end
+/// (`--single-map`), DashMaps (the default), or per-worker HashMaps flushed into DashMaps every so
+/// often (`--buffered`; see `DictionaryBuilder::flush_every`), which touches the shared maps far less
+/// often than counting straight into them. `CountMin` (`--count-min`) is flushed the same way, into
+/// count-min sketches instead; it only estimates the counts, and only keeps the most frequent n-grams
+/// (see `DictionaryBuilder::sketch`), but its memory doesn't grow with the distinct n-grams.
+/// `External` (`--external`) counts exactly, in per-worker HashMaps that are written out to sorted
+/// runs on disk whenever they fill up (see `DictionaryBuilder::spill_entries`), then merges the runs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Backend {
+    SeparateMaps,
+    #[default]
+    ConcurrentMap,
+    Buffered,
+    CountMin,
+    External,
+}
+
+/// What a run does once its dictionaries get bigger than `DictionaryBuilder::max_memory_bytes`:
+/// `Prune` drops the n-grams counted the fewest times so far, about half of them each time, so the
+/// run goes on but undercounts whatever came back after being dropped; `Fail` (the default) stops
+/// the workers and returns `ParserError::MemoryLimitExceeded` with the counts up to then. The
+/// `External` backend spills its runs to disk instead, and `CountMin` never grows to begin with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MemoryLimit {
+    Prune,
+    #[default]
+    Fail,
+}
+
+/// The sketches of the `CountMin` backend: `depth` rows of `width` counters (8 bytes each) per
+/// dictionary, and the `heavy_hitters` n-grams of each size it keeps, the ones it estimates the most
+/// of. An estimate is never below the real count, and is over it by at most a 1/`width` share of
+/// all the n-grams of that size, with a probability of 1 - 2^-`depth`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SketchSize {
+    pub width: usize,
+    pub depth: usize,
+    pub heavy_hitters: usize,
+}
+
+impl Default for SketchSize {
+    fn default() -> Self {
+        SketchSize { width: 1 << 20, depth: 4, heavy_hitters: 10_000 }
+    }
+}
+
+/// Which lines `DictionaryBuilder::sample` counts, by their number among the lines `skip_lines`
+/// leaves (from 0 at the first of them, across all the files of a run): every `n`th line starting
+/// with that first one, or each line with probability `p`, drawn from a hash of `seed` and the line
+/// number so that a seed always picks the same lines.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Sample {
+    EveryNth(usize),
+    Bernoulli { p: f64, seed: u64 },
+}
+
+impl Sample {
+    pub fn keeps(&self, line: usize) -> bool {
+        match *self {
+            Sample::EveryNth(n) => line.is_multiple_of(n.max(1)),
+            Sample::Bernoulli { p, seed } => {
+                // splitmix64's finalizer, as a hash of the seed and the line number
+                let mut z = seed.wrapping_add((line as u64).wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+                z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+                z ^= z >> 31;
+                ((z >> 11) as f64 / (1u64 << 53) as f64) < p
+            },
+        }
+    }
+}
+
+// numbers the lines of a run as they're read, and says which of them skip_lines, max_lines and
+// sample leave
+#[derive(Clone, Debug)]
+struct Pick {
+    range: std::ops::Range<usize>,
+    sample: Option<Sample>,
+    line: usize,
+}
+
+impl Pick {
+    // every line
+    fn all() -> Self {
+        Pick { range: 0..usize::MAX, sample: None, line: 0 }
+    }
+
+    // whether the next line is taken
+    fn takes(&mut self) -> bool {
+        self.line += 1;
+        self.range.contains(&(self.line - 1)) && self.sample.is_none_or(|sample| sample.keeps(self.line - 1 - self.range.start))
+    }
+
+    // whether the lines after this one are all left out, so there's no need to read them
+    fn done(&self) -> bool {
+        self.line >= self.range.end
+    }
+}
+
+/// Collects the options for a dictionary-building run; `parse_raw_single` and `parse_raw_conc` then
+/// run the separate-maps or concurrent-map implementation with them, while the other entry points
+/// use whichever `backend` is set.
+#[derive(Clone, Debug)]
+pub struct DictionaryBuilder {
+    num_threads: Option<u32>,
+    context: ContextWindow,
+    ngram_sizes: Vec<usize>,
+    backend: Backend,
+    separator: String,
+    min_count: u64,
+    top_k: Option<usize>,
+    normalization: Normalization,
+    mmap: bool,
+    levels: Vec<String>,
+    time_range: Option<TimeRange>,
+    session_gap: Option<SessionGap>,
+    split_on_change: Vec<String>,
+    group_by: Option<GroupBy>,
+    timestamp_locales: Option<Vec<Locale>>,
+    extra_censors: Vec<Censor>,
+    redact_pii: bool,
+    breakdown: Option<String>,
+    progress: Option<Progress>,
+    min_chunk_lines: usize,
+    chunks_per_thread: u32,
+    pipeline: Option<usize>,
+    dedupe_lines: bool,
+    shard_amount: Option<usize>,
+    estimated_ngrams: Option<usize>,
+    flush_every: (usize, usize),
+    lossy_utf8: bool,
+    keep_unmatched: usize,
+    skip_grams: usize,
+    sample: Option<Sample>,
+    skip_lines: usize,
+    max_lines: Option<usize>,
+    offsets: (u64, Option<u64>),
+    sketch: SketchSize,
+    spill_dir: Option<PathBuf>,
+    spill_entries: usize,
+    max_memory: (Option<usize>, MemoryLimit),
+    // where parse_raw_report_checked has the run it's timing fill its report in
+    report: Option<Arc<Mutex<ParseReport>>>,
+}
+
+impl Default for DictionaryBuilder {
+    fn default() -> Self {
+        DictionaryBuilder {
+            num_threads: None,
+            context: Default::default(),
+            ngram_sizes: vec![2, 3],
+            backend: Default::default(),
+            separator: "^".to_string(),
+            min_count: 1,
+            top_k: None,
+            normalization: Default::default(),
+            mmap: false,
+            levels: vec![],
+            time_range: None,
+            session_gap: None,
+            split_on_change: vec![],
+            group_by: None,
+            timestamp_locales: None,
+            extra_censors: vec![],
+            redact_pii: false,
+            breakdown: None,
+            progress: None,
+            min_chunk_lines: 1000,
+            chunks_per_thread: 1,
+            pipeline: None,
+            dedupe_lines: false,
+            shard_amount: None,
+            estimated_ngrams: None,
+            flush_every: (10_000, 100_000),
+            lossy_utf8: false,
+            keep_unmatched: 0,
+            skip_grams: 0,
+            sample: None,
+            skip_lines: 0,
+            max_lines: None,
+            offsets: (0, None),
+            sketch: Default::default(),
+            spill_dir: None,
+            spill_entries: 1_000_000,
+            max_memory: (None, MemoryLimit::Fail),
+            report: None,
+        }
+    }
+}
+
+impl DictionaryBuilder {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Number of worker threads, and of chunks each input is split into (fewer if it has fewer lines);
+    /// defaults to `std::thread::available_parallelism`, or 8 when that can't be told. The n-grams
+    /// that span two chunks are counted once the workers are done, so the result doesn't depend on it.
+    /// Without the `concurrent` feature there is always one.
+    pub fn num_threads(mut self, num_threads: Option<u32>) -> Self {
+        self.num_threads = num_threads;
+        self
+    }
+
+    /// The fewest lines worth a chunk of their own: every chunk but the last of an input gets at least
+    /// this many, so an input no longer than this is parsed by one worker with all its context in one
+    /// place. 1 splits every input as evenly as `num_threads` allows. Defaults to 1000.
+    pub fn min_chunk_lines(mut self, min_chunk_lines: usize) -> Self {
+        self.min_chunk_lines = min_chunk_lines;
+        self
+    }
+
+    /// How many chunks to split each input into per thread. With more than one, a thread that is done
+    /// with its chunk takes the next one nobody has started, so a stretch of lines much slower to
+    /// tokenize than the rest (huge stack traces, say) holds up one small chunk instead of a thread's
+    /// whole share. Every chunk still gets at least `min_chunk_lines`. Defaults to 1.
+    pub fn chunks_per_thread(mut self, chunks_per_thread: u32) -> Self {
+        assert!(chunks_per_thread > 0, "every thread needs at least one chunk");
+        self.chunks_per_thread = chunks_per_thread;
+        self
+    }
+
+    /// Counts every distinct line once, and its n-grams as many times as it came up, for logs that
+    /// repeat the same lines over and over. Each line is counted on its own, as with
+    /// `ContextWindow::disabled()`: the n-grams that reach into the neighbouring lines are left out,
+    /// whatever `context` says. The counts go into separate maps, whatever the backend. Defaults to
+    /// false.
+    pub fn dedupe_lines(mut self, dedupe_lines: bool) -> Self {
+        self.dedupe_lines = dedupe_lines;
+        self
+    }
+
+    /// Reads the input files on a thread of their own while the workers count, sending them
+    /// `batch_lines` lines at a time, instead of reading everything before counting anything. The
+    /// result is the same. Runs that need the whole input first (`group_by`, `dedupe_lines` and the
+    /// format matching of `parse_raw_stats`) and `mmap` ones, which don't read ahead anyway, still
+    /// do; so does every run without the `concurrent` feature. Defaults to None.
+    pub fn pipeline(mut self, batch_lines: Option<usize>) -> Self {
+        assert!(batch_lines != Some(0), "a batch needs at least one line");
+        self.pipeline = batch_lines;
+        self
+    }
+
+    /// How many tokens from how many neighbouring lines get stitched onto each line.
+    pub fn context(mut self, context: ContextWindow) -> Self {
+        self.context = context;
+        self
+    }
+
+    /// Which n-gram dictionaries to build; defaults to doubles and triples.
+    pub fn ngram_sizes(mut self, ngram_sizes: &[usize]) -> Self {
+        assert!(ngram_sizes.iter().all(|n| (1..SKIP_GRAM).contains(n)), "n-gram sizes must be at least 1 (and not absurd)");
+        let mut sizes = ngram_sizes.to_vec();
+        sizes.sort_unstable();
+        sizes.dedup();
+        self.ngram_sizes = sizes;
+        self
+    }
+
+    /// Also counts the skip-grams: the pairs of tokens on a line with up to `max_skip` tokens between
+    /// them, one dictionary per distance (2 to `max_skip + 1`), which see past the dynamic token in
+    /// the middle of `user <*> logged`. They never borrow tokens from the neighbouring lines. None
+    /// by default.
+    pub fn skip_grams(mut self, max_skip: usize) -> Self {
+        self.skip_grams = max_skip;
+        self
+    }
+
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// How many shards the concurrent-map backend splits each DashMap into; more of them means less
+    /// contention between many workers. Must be a power of two above 1; defaults to dashmap's own
+    /// choice of four per CPU.
+    pub fn shard_amount(mut self, shard_amount: Option<usize>) -> Self {
+        assert!(shard_amount.is_none_or(|n| n > 1 && n.is_power_of_two()), "the shard amount must be a power of two above 1");
+        self.shard_amount = shard_amount;
+        self
+    }
+
+    /// How often the workers of the buffered backend add their counts to the shared maps (and those
+    /// of `ngram_stream` pass them on): every `lines` lines, or as soon as they hold `entries`
+    /// distinct n-grams, whichever comes first. Defaults to every 10000 lines or 100000 n-grams.
+    pub fn flush_every(mut self, lines: usize, entries: usize) -> Self {
+        assert!(lines > 0, "can't flush every 0 lines");
+        self.flush_every = (lines, entries);
+        self
+    }
+
+    /// How big the sketches of the `CountMin` backend are, and how many n-grams of each size it keeps;
+    /// see `SketchSize`.
+    pub fn sketch(mut self, sketch: SketchSize) -> Self {
+        assert!(sketch.width > 0 && sketch.depth > 0, "a sketch needs at least one row of one counter");
+        self.sketch = sketch;
+        self
+    }
+
+    /// Roughly how many bytes the n-gram counts of a run may take while the workers count them (the
+    /// tokens and the finished result aside); see `MemoryLimit` for what happens past it. The
+    /// workers check every 1024 lines. No limit by default.
+    pub fn max_memory_bytes(mut self, max_memory_bytes: Option<usize>) -> Self {
+        self.max_memory.0 = max_memory_bytes;
+        self
+    }
+
+    pub fn on_memory_limit(mut self, on_memory_limit: MemoryLimit) -> Self {
+        self.max_memory.1 = on_memory_limit;
+        self
+    }
+
+    /// Where the `External` backend writes its runs; the system's temporary directory by default.
+    pub fn spill_dir(mut self, spill_dir: Option<PathBuf>) -> Self {
+        self.spill_dir = spill_dir;
+        self
+    }
+
+    /// How many distinct n-grams (of all sizes together) each worker of the `External` backend
+    /// counts in memory before it writes them out to a run; 1,000,000 by default. The merge holds
+    /// one n-gram per run at a time, on top of the result.
+    pub fn spill_entries(mut self, spill_entries: usize) -> Self {
+        assert!(spill_entries > 0, "can't spill every 0 n-grams");
+        self.spill_entries = spill_entries;
+        self
+    }
+
+    /// About how many distinct n-grams of each size to expect, so that the shared maps are allocated
+    /// once instead of growing as they fill; only a hint.
+    pub fn estimated_ngrams(mut self, estimated_ngrams: Option<usize>) -> Self {
+        self.estimated_ngrams = estimated_ngrams;
+        self
+    }
+
+    /// What the tokens of an n-gram key are joined with; defaults to `^`. See `join_ngram`.
+    pub fn separator(mut self, separator: &str) -> Self {
+        assert!(!separator.is_empty() && !separator.contains('\\'), "the separator must be non-empty and can't contain the \\ escape");
+        self.separator = separator.to_string();
+        self
+    }
+
+    /// Drops the n-grams seen fewer than `min_count` times from the result; defaults to 1 (keep everything).
+    pub fn min_count(mut self, min_count: u64) -> Self {
+        self.min_count = min_count;
+        self
+    }
+
+    /// Keeps only the `top_k` most frequent n-grams of each size (ties go to the smaller key); see
+    /// `DictionaryResult::most_frequent` for them in order.
+    pub fn top_k(mut self, top_k: Option<usize>) -> Self {
+        self.top_k = top_k;
+        self
+    }
+
+    /// How to normalize the tokens once they're censored, before any n-gram is made of them; defaults
+    /// to leaving them alone.
+    pub fn normalize(mut self, normalization: Normalization) -> Self {
+        self.normalization = normalization;
+        self
+    }
+
+    /// Decodes the lines that aren't valid UTF-8 with U+FFFD for the bad bytes, instead of leaving
+    /// them out (the default). `parse_raw_stats` counts them either way.
+    pub fn lossy_utf8(mut self, lossy_utf8: bool) -> Self {
+        self.lossy_utf8 = lossy_utf8;
+        self
+    }
+
+    /// How many of the lines that don't match the format `parse_raw_stats` keeps, to see why; none
+    /// by default.
+    pub fn keep_unmatched(mut self, keep_unmatched: usize) -> Self {
+        self.keep_unmatched = keep_unmatched;
+        self
+    }
+
+    /// Only counts a sample of the lines, for a quick approximate dictionary of a big log. The lines
+    /// are picked before they're split into chunks, so every backend and thread count counts the
+    /// same ones, and the n-grams that borrow from neighbouring lines borrow from the neighbouring
+    /// lines of the sample. All of them by default.
+    pub fn sample(mut self, sample: Option<Sample>) -> Self {
+        self.sample = sample;
+        self
+    }
+
+    /// Leaves out the first `skip_lines` lines of the input (across all its files, after the byte
+    /// offsets), e.g. the ones an interrupted run already counted. None by default.
+    pub fn skip_lines(mut self, skip_lines: usize) -> Self {
+        self.skip_lines = skip_lines;
+        self
+    }
+
+    /// Stops after `max_lines` lines (once `skip_lines` are skipped), without reading the rest of
+    /// the files; handy to try a format string on a huge log. All of them by default. A `sample`
+    /// is taken from these lines.
+    pub fn max_lines(mut self, max_lines: Option<usize>) -> Self {
+        self.max_lines = max_lines;
+        self
+    }
+
+    /// Only reads the lines of each input file that start at or after byte `start_offset`: a line
+    /// that starts before it and runs past it belongs to the part before. 0 by default.
+    pub fn start_offset(mut self, start_offset: u64) -> Self {
+        self.offsets.0 = start_offset;
+        self
+    }
+
+    /// Only reads the lines of each input file that start before byte `end_offset`, to its end by
+    /// default. Cutting a file at the same offsets, as `end_offset` of one part and `start_offset`
+    /// of the next, counts each line in exactly one part. Offsets into compressed files count
+    /// decompressed bytes.
+    pub fn end_offset(mut self, end_offset: Option<u64>) -> Self {
+        self.offsets.1 = end_offset;
+        self
+    }
+
+    /// Memory-maps the input files, which must be uncompressed, and hands the workers slices of the
+    /// mapping instead of reading every line into a `String` first; needs the mmap feature.
+    pub fn mmap(mut self, mmap: bool) -> Self {
+        self.mmap = mmap;
+        self
+    }
+
+    /// Only counts the lines whose `<Level>` is one of `levels` (e.g. `&["ERROR", "WARN"]`, ignoring
+    /// case). The lines filtered out still break the context like lines that don't match the format,
+    /// so no n-gram spans them; see `RegexTokenizer::levels`. Doesn't apply to `parse_raw_with`,
+    /// whose tokenizer is the caller's.
+    pub fn levels(mut self, levels: &[&str]) -> Self {
+        self.levels = levels.iter().map(|l| l.to_string()).collect();
+        self
+    }
+
+    /// Only counts the lines whose time falls in `time_range`; like `levels`, the others still break
+    /// the context. The run fails if the range has no timestamp parser and the format no
+    /// `time::timestamp_parser` of its own.
+    pub fn time_range(mut self, time_range: Option<TimeRange>) -> Self {
+        self.time_range = time_range;
+        self
+    }
+
+    /// Starts a new session wherever more than `session_gap` went by between two lines: their context
+    /// stops there, so that no double joins the last line before a quiet spell to the first one
+    /// after. Like `time_range`, the run fails without a timestamp parser. Off by default.
+    pub fn session_gap(mut self, session_gap: Option<SessionGap>) -> Self {
+        self.session_gap = session_gap;
+        self
+    }
+
+    /// Starts a new session wherever one of `fields` (e.g. `&["Component"]` or `&["Pid"]`) changes
+    /// between two lines, like `session_gap`, so that the lines of interleaved processes don't make
+    /// n-grams across each other; see `RegexTokenizer::split_on_change`. The run fails if the format
+    /// lacks a field. None by default.
+    pub fn split_on_change(mut self, fields: &[&str]) -> Self {
+        self.split_on_change = fields.iter().map(|f| f.to_string()).collect();
+        self
+    }
+
+    /// Also censors what `censors` match, each with its own replacement token, after the format's own
+    /// censoring regexes; e.g. `Censor::new(email, "<EMAIL>")` to mask addresses. None by default.
+    pub fn extra_censors(mut self, censors: &[Censor]) -> Self {
+        self.extra_censors = censors.to_vec();
+        self
+    }
+
+    /// Redacts personal data from every line before anything else is censored: email addresses, IP
+    /// addresses, MACs, card numbers and JSON web tokens, each with a placeholder of its own such as
+    /// `<EMAIL>`; see `tokenizer::pii_censors`. `parse_raw_stats` counts the redactions in
+    /// `ParseStats::redactions`, for proof that they were made. Off by default.
+    pub fn redact_pii(mut self, redact_pii: bool) -> Self {
+        self.redact_pii = redact_pii;
+        self
+    }
+
+    /// Censors the dates and times in the lines of any format, written with the month and day names of
+    /// `locales` (English for none), on top of the format's own censoring, which only knows the shapes
+    /// its logs use in English; see `time::timestamp_censor`. Off (`None`) by default.
+    pub fn censor_timestamps(mut self, locales: Option<&[Locale]>) -> Self {
+        self.timestamp_locales = locales.map(<[Locale]>::to_vec);
+        self
+    }
+
+    /// Sorts the lines of each input into streams by `group_by` (e.g. `GroupBy::Field("Pid")`, or a
+    /// `GroupBy::Pattern` for the `blk_` ids of HDFS) and counts the n-grams of each stream on its
+    /// own, its lines in the order they came in, as if the streams had been separate logs. The lines
+    /// without a group are left out. It needs the whole input at once, so `update` and the other
+    /// streaming entry points fail with it. Off (`None`) by default.
+    pub fn group_by(mut self, group_by: Option<GroupBy>) -> Self {
+        self.group_by = group_by;
+        self
+    }
+
+    /// Also counts the n-grams of the lines with each value of `field` (e.g. `Level` or `Component`)
+    /// in `DictionaryResult::breakdown`, in the same pass, each n-gram going to the value of the line
+    /// it was counted on. The run fails if the format has no such field. Off (`None`) by default,
+    /// since it counts every n-gram twice; `parse_raw_ids` and `parse_raw_with` leave it out.
+    pub fn breakdown_by(mut self, field: Option<&str>) -> Self {
+        self.breakdown = field.map(str::to_string);
+        self
+    }
+
+    /// Calls `progress` with the lines counted so far and the lines in all as the workers go; see
+    /// `Progress`. The total is the whole input when it's read (or mapped) before counting; with
+    /// `pipeline`, it's the lines read so far, which grows as the batches come in.
+    pub fn progress<F: Fn(u64, u64) + Send + Sync + 'static>(mut self, progress: F) -> Self {
+        self.progress = Some(Progress(Arc::new(progress)));
+        self
+    }
+
+    // the chunks of every input, for num_workers threads to take in turn
+    fn chunks<'a, S>(&self, inputs: &'a [Vec<S>], num_workers: u32) -> Vec<Vec<&'a [S]>> {
+        span!("chunk", inputs = inputs.len(), workers = num_workers);
+        let per_thread = if num_workers > 1 { self.chunks_per_thread } else { 1 };
+        chunk_inputs(inputs, num_workers.saturating_mul(per_thread), self.min_chunk_lines)
+    }
+
+    pub(crate) fn num_workers(&self) -> Result<u32, ParserError> {
+        match self.num_threads {
+            // no threads to spawn in a browser
+            None if cfg!(target_arch = "wasm32") => Ok(1),
+            None if cfg!(not(feature = "concurrent")) => Ok(1),
+            None => Ok(thread::available_parallelism().map_or(8, |n| n.get() as u32)),
+            Some(0) => Err(ParserError::InvalidConfig("need at least one worker thread".to_string())),
+            // there are no worker threads without the concurrent feature
+            Some(_) if cfg!(not(feature = "concurrent")) => Ok(1),
+            Some(n) => Ok(n),
+        }
+    }
+
+    fn tokenizer(&self, lf: &LogFormat) -> Result<RegexTokenizer, ParserError> {
+        let mut tokenizer = RegexTokenizer::new(lf)?.levels(&self.levels)?.breakdown_by(self.breakdown.as_deref())?.split_on_change(&self.split_on_change)?
+            .group_by(self.group_by.clone())?;
+        tokenizer = tokenizer.extra_censors(&self.extra_censors);
+        if let Some(locales) = &self.timestamp_locales {
+            tokenizer = tokenizer.censor_timestamps(locales);
+        }
+        if self.redact_pii {
+            tokenizer = tokenizer.redact_pii();
+        }
+        if let Some(range) = &self.time_range {
+            tokenizer = tokenizer.time_range(range.for_format(lf)?);
+        }
+        if let Some(gap) = &self.session_gap {
+            tokenizer = tokenizer.session_gap(gap.for_format(lf)?);
+        }
+        Ok(tokenizer)
+    }
+
+    // what the builders tokenize with, normalization and all
+    pub(crate) fn normalized_tokenizer(&self, lf: &LogFormat) -> Result<Normalize<RegexTokenizer>, ParserError> {
+        Ok(Normalize::new(self.tokenizer(lf)?, self.normalization))
+    }
+
+    pub(crate) fn is_lossy_utf8(&self) -> bool {
+        self.lossy_utf8
+    }
+
+    #[cfg(feature = "server")]
+    pub(crate) fn separator_str(&self) -> &str {
+        &self.separator
+    }
+
+    #[cfg(feature = "concurrent")]
+    fn empty_dash_dict(&self) -> NGramDashMap {
+        let capacity = self.estimated_ngrams.unwrap_or(0);
+        match self.shard_amount {
+            Some(shards) => DashMap::with_capacity_and_hasher_and_shard_amount(capacity, Default::default(), shards),
+            None => DashMap::with_capacity_and_hasher(capacity, Default::default()),
+        }
+    }
+
+    // the n-gram sizes, then the skip-gram keys, then the breakdown ones
+    fn dict_keys(&self) -> impl Iterator<Item = usize> + '_ {
+        let breakdown = self.breakdown.iter().flat_map(|_| self.ngram_sizes.iter().map(|n| BREAKDOWN + n));
+        self.ngram_sizes.iter().copied().chain((2..self.skip_grams + 2).map(|d| SKIP_GRAM + d)).chain(breakdown)
+    }
+
+    fn empty_dicts(&self) -> BTreeMap<usize, NGramMap> {
+        self.dict_keys().map(|n| (n, HashMap::default())).collect()
+    }
+
+    /// Panics on any `ParserError`; see `parse_raw_single_checked`.
+    pub fn parse_raw_single(&self, raw_fn: String, lf:&LogFormat) -> DictionaryResult {
+        self.parse_raw_single_checked(raw_fn, lf).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Panics on any `ParserError`; see `parse_raw_conc_checked`.
+    pub fn parse_raw_conc(&self, raw_fn: String, lf:&LogFormat) -> DictionaryResult {
+        self.parse_raw_conc_checked(raw_fn, lf).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Panics on any `ParserError`; see `parse_raw_checked`.
+    pub fn parse_raw(&self, raw_fn: String, lf:&LogFormat) -> DictionaryResult {
+        self.parse_raw_checked(raw_fn, lf).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Parses `raw_fn` with whichever `backend` is set.
+    pub fn parse_raw_checked(&self, raw_fn: String, lf:&LogFormat) -> Result<DictionaryResult, ParserError> {
+        self.build_files(&[raw_fn], &self.tokenizer(lf)?, self.backend)
+    }
+
+    /// Panics on any `ParserError`; see `parse_raw_hashed_checked`.
+    pub fn parse_raw_hashed<S: BuildHasher + Default>(&self, raw_fn: String, lf:&LogFormat) -> DictionaryResult<S> {
+        self.parse_raw_hashed_checked(raw_fn, lf).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like `parse_raw_checked`, but the result's maps hash with `S` instead of `RandomState`, e.g.
+    /// `rustc_hash::FxBuildHasher` for cheaper lookups into dictionaries built from trusted logs.
+    pub fn parse_raw_hashed_checked<S: BuildHasher + Default>(&self, raw_fn: String, lf:&LogFormat) -> Result<DictionaryResult<S>, ParserError> {
+        self.build_files(&[raw_fn], &self.tokenizer(lf)?, self.backend)
+    }
+
+    /// Panics on any `ParserError`; see `parse_raw_ids_checked`.
+    pub fn parse_raw_ids(&self, raw_fn: String, lf:&LogFormat) -> TokenIdResult {
+        self.parse_raw_ids_checked(raw_fn, lf).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like `parse_raw_checked`, but keeps the n-grams as token ids instead of joining them into
+    /// strings; `separator` plays no part.
+    pub fn parse_raw_ids_checked(&self, raw_fn: String, lf:&LogFormat) -> Result<TokenIdResult, ParserError> {
+        self.build_files(&[raw_fn], &self.tokenizer(lf)?, self.backend)
+    }
+
+    /// Panics on any `ParserError`; see `parse_raw_stats_checked`.
+    pub fn parse_raw_stats(&self, raw_fn: String, lf:&LogFormat) -> (DictionaryResult, ParseStats) {
+        self.parse_raw_stats_checked(raw_fn, lf).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like `parse_raw_checked`, but also tells how much of the input went unused, and what was
+    /// redacted; matching every line against the format for that costs a little extra.
+    pub fn parse_raw_stats_checked(&self, raw_fn: String, lf:&LogFormat) -> Result<(DictionaryResult, ParseStats), ParserError> {
+        let tokenizer = self.tokenizer(lf)?;
+        self.build_files_stats(&[raw_fn], &tokenizer, self.backend, Some(&tokenizer))
+    }
+
+    /// Panics on any `ParserError`; see `parse_raw_report_checked`.
+    pub fn parse_raw_report(&self, raw_fn: String, lf:&LogFormat) -> (DictionaryResult, ParseReport) {
+        self.parse_raw_report_checked(raw_fn, lf).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like `parse_raw_checked`, but also tells where the time went and what the run ran on; timing
+    /// the tokenizer costs a little extra.
+    pub fn parse_raw_report_checked(&self, raw_fn: String, lf:&LogFormat) -> Result<(DictionaryResult, ParseReport), ParserError> {
+        let report = Arc::new(Mutex::new(ParseReport::default()));
+        let timed = DictionaryBuilder { report: Some(report.clone()), ..self.clone() };
+        let result = timed.parse_raw_checked(raw_fn, lf)?;
+        let report = report.lock().unwrap().clone();
+        Ok((result, report))
+    }
+
+    pub fn parse_raw_single_checked(&self, raw_fn: String, lf:&LogFormat) -> Result<DictionaryResult, ParserError> {
+        self.build_files(&[raw_fn], &self.tokenizer(lf)?, Backend::SeparateMaps)
+    }
+
+    pub fn parse_raw_conc_checked(&self, raw_fn: String, lf:&LogFormat) -> Result<DictionaryResult, ParserError> {
+        self.build_files(&[raw_fn], &self.tokenizer(lf)?, Backend::ConcurrentMap)
+    }
+
+    /// Panics on any `ParserError`; see `parse_raw_with_checked`.
+    pub fn parse_raw_with(&self, raw_fn: String, tokenizer: &dyn Tokenizer) -> DictionaryResult {
+        self.parse_raw_with_checked(raw_fn, tokenizer).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Like the other `parse_raw_*` entry points, but splits the lines with `tokenizer` (with whichever
+    /// `backend` is set) instead of the `RegexTokenizer` of a `LogFormat`.
+    pub fn parse_raw_with_checked(&self, raw_fn: String, tokenizer: &dyn Tokenizer) -> Result<DictionaryResult, ParserError> {
+        self.build_files(&[raw_fn], tokenizer, self.backend)
+    }
+
+    /// Parses the lines of any reader, e.g. `io::stdin().lock()`, with whichever `backend` is set. The
+    /// `parse_raw_*` entry points also read stdin when given "-" as the file name.
+    pub fn parse_reader<R: BufRead>(&self, reader: R, lf:&LogFormat) -> Result<DictionaryResult, ParserError> {
+        self.build_all(&[read_log_from(reader, self.lossy_utf8, &mut 0, &mut self.picker())?], &self.tokenizer(lf)?, self.backend)
+    }
+
+    /// Panics on any `ParserError`; see `parse_str_checked`.
+    pub fn parse_str(&self, text: &str, lf:&LogFormat) -> DictionaryResult {
+        self.parse_str_checked(text, lf).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Parses the lines of `text`, already in memory, with whichever `backend` is set. With one
+    /// thread (the only choice on wasm) the whole run stays on the calling thread.
+    pub fn parse_str_checked(&self, text: &str, lf:&LogFormat) -> Result<DictionaryResult, ParserError> {
+        self.build(&[text.lines().collect::<Vec<_>>()], &self.tokenizer(lf)?, self.backend)
+    }
+
+    /// Panics on any `ParserError`; see `parse_raw_dir_checked`.
+    pub fn parse_raw_dir(&self, dir: &Path, pattern: Option<&str>, lf:&LogFormat) -> DictionaryResult {
+        self.parse_raw_dir_checked(dir, pattern, lf).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Parses every file under `dir` (recursively) whose name matches the `*`/`?` glob `pattern`, if
+    /// given, into one merged result. The chunks of all files share one worker pool, and no n-gram
+    /// spans two files.
+    pub fn parse_raw_dir_checked(&self, dir: &Path, pattern: Option<&str>, lf:&LogFormat) -> Result<DictionaryResult, ParserError> {
+        self.build_files(&log_files(dir, pattern)?, &self.tokenizer(lf)?, self.backend)
+    }
+
+    /// Panics on any `ParserError`; see `parse_rotated_checked`.
+    pub fn parse_rotated<P: AsRef<Path>>(&self, base: P, lf:&LogFormat) -> DictionaryResult {
+        self.parse_rotated_checked(base, lf).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Parses the files of the rotated log `base` (see `rotated_files`) as one log, oldest line
+    /// first, so the n-grams across a rotation are counted as though it had never happened.
+    pub fn parse_rotated_checked<P: AsRef<Path>>(&self, base: P, lf:&LogFormat) -> Result<DictionaryResult, ParserError> {
+        let (mut lines, mut pick) = (vec![], self.picker());
+        for path in rotated_files(base)? {
+            lines.extend(read_log_from(read_lines(&path)?, self.lossy_utf8, &mut 0, &mut pick)?);
+        }
+        self.build_all(&[lines], &self.tokenizer(lf)?, self.backend)
+    }
+
+    /// Parses `raw_fn` with the `External` backend (whichever `backend` is set) and writes the
+    /// n-grams to `dir` as they come out of the merge, in the CSV files `DictionaryResult::write_csv`
+    /// would write: the dictionaries themselves are never in memory at once. The n-grams come in no
+    /// particular order, rather than most frequent first.
+    pub fn parse_raw_to_csv<P: AsRef<Path>>(&self, raw_fn: String, lf:&LogFormat, dir: P) -> Result<(), ParserError> {
+        let dir = dir.as_ref();
+        let inputs = [self.picked_lines(Path::new(&raw_fn))?];
+        let tokenizer = self.tokenizer(lf)?;
+        let tokenizer = Normalize::new(&tokenizer, self.normalization);
+        let (runs, names) = spill_runs(Lines::Chunks(&inputs), &tokenizer, self)?;
+        fs::create_dir_all(dir)?;
+        let mut writers = BTreeMap::new();
+        for n in &self.ngram_sizes {
+            let mut w = io::BufWriter::new(File::create(dir.join(format!("{}.csv", ngram_name(*n))))?);
+            writeln!(w, "ngram,count")?;
+            writers.insert(*n, w);
+        }
+        spill::merge(&runs, |(n, ngram, count)| {
+            // the skip-grams don't go in the CSV files
+            let Some(w) = writers.get_mut(&n).filter(|_| count >= self.min_count) else { return Ok(()) };
+            let tokens: Vec<&str> = ngram.iter().map(|id| names[*id as usize].as_str()).collect();
+            writeln!(w, "{},{}", csv_field(&join_ngram(&tokens, &self.separator)), count)
+        })?;
+        for w in writers.values_mut() {
+            w.flush()?;
+        }
+        let tokens = token_list(names);
+        let mut w = io::BufWriter::new(File::create(dir.join("tokens.csv"))?);
+        writeln!(w, "token")?;
+        for token in &tokens {
+            writeln!(w, "{}", csv_field(token))?;
+        }
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Parses `raw_fn` and hands `f` the counts of its n-grams as they're counted, as `(n, ngram,
+    /// delta)`: the n-gram of size n, joined with `separator`, came up delta more times. The workers
+    /// pass on and forget what they've counted as often as the buffered backend's flush (see
+    /// `flush_every`), so the same n-gram comes up in many calls, its count the sum of their deltas,
+    /// and the dictionaries are never held at all; `f` can add them up in a store of its own. Only the
+    /// `ngram_sizes` n-grams come out, without skip-grams or breakdowns, and `min_count` and `top_k`
+    /// don't apply. Returns the token list.
+    pub fn ngram_stream<F>(&self, raw_fn: String, lf: &LogFormat, f: F) -> Result<Vec<String>, ParserError>
+    where F: FnMut(usize, &str, u64) + Send, {
+        let lines = [self.picked_lines(Path::new(&raw_fn))?];
+        let tokenizer = self.normalized_tokenizer(lf)?;
+        let inputs = match self.group_by {
+            Some(_) => group_lines(&lines, &tokenizer),
+            None => vec![lines[0].iter().map(String::as_str).collect()],
+        };
+        let interner = Interner::default();
+        let interner = &interner;
+        let empty = || self.ngram_sizes.iter().map(|n| (*n, NGramMap::default())).collect::<BTreeMap<_, _>>();
+
+        // one worker at a time calls f, with the tokens as of its call
+        let sink = Mutex::new((f, Vec::<Option<String>>::new()));
+        let emit = |counts: &mut BTreeMap<usize, NGramMap>| {
+            let mut sink = sink.lock().unwrap();
+            let (f, names) = &mut *sink;
+            // the tokens of these ids were all in the interner before their n-grams were counted,
+            // whichever others are still being added
+            if counts.values().flat_map(HashMap::keys).flatten().any(|id| names.get(*id as usize).is_none_or(Option::is_none)) {
+                *names = interner.names_so_far();
+            }
+            for (n, dict) in counts.iter_mut() {
+                for (ngram, delta) in dict.drain() {
+                    let tokens: Vec<&str> = ngram.iter().map(|id| names[*id as usize].as_deref().unwrap()).collect();
+                    f(*n, &join_ngram(&tokens, &self.separator), delta);
+                }
+            }
+        };
+
+        let num_workers = self.num_workers()?;
+        let lines = Lines::Chunks(&inputs);
+        let counter = LineCounter::new(&lines, self);
+        let (_, edges) = run_lines(lines, num_workers, &counter, self, || (), |_, chunk| {
+            let mut sink = StreamMaps { local: empty(), emit: &emit, flush_every: self.flush_every };
+            let Ok(edges) = count_chunk(chunk, &tokenizer, interner, &counter, self.context, &mut sink);
+            edges
+        })?;
+        span!("aggregate");
+        let mut spanning = empty();
+        for input_edges in &edges {
+            stitch_chunk_boundaries(input_edges, &tokenizer, self.context, Map::TypeHash(&mut spanning));
+        }
+        emit(&mut spanning);
+        Ok(token_list(interner.names()))
+    }
+
+    /// Panics on any `ParserError`; see `parse_raw_by_component_checked`.
+    pub fn parse_raw_by_component(&self, raw_fn: String, lf:&LogFormat) -> HashMap<String, DictionaryResult> {
+        self.parse_raw_by_component_checked(raw_fn, lf).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Builds separate dictionaries for the lines of each `<Component>` (e.g. sshd, kernel, cron), keyed
+    /// by component; the format needs a `<Component>` field. The context of a line comes from the lines
+    /// of the same component around it, and the lines that don't match the format are left out. The
+    /// byte offsets, `skip_lines`, `max_lines` and `sample` pick the lines of the file as for any run,
+    /// before they're sorted by component.
+    pub fn parse_raw_by_component_checked(&self, raw_fn: String, lf:&LogFormat) -> Result<HashMap<String, DictionaryResult>, ParserError> {
+        let format = format_string(lf);
+        let re = regex_generator_checked(format.clone())?;
+        if !re.capture_names().any(|name| name == Some("Component")) {
+            return Err(ParserError::FormatMismatch(format!("no <Component> field in {}", format)));
+        }
+        let maps = self.map_inputs(&[&raw_fn])?;
+        let lines = self.read_inputs(&[&raw_fn], &maps, &mut 0)?;
+        let mut groups: HashMap<&str, Vec<&str>> = HashMap::new();
+        for line in lines.iter().flatten() {
+            if let Some(component) = re.captures(line.trim()).and_then(|m| m.name("Component")) {
+                groups.entry(component.as_str()).or_default().push(line);
+            }
+        }
+        let tokenizer = self.tokenizer(lf)?;
+        groups.into_iter()
+            .map(|(component, lines)| Ok((component.to_string(), self.build_all(&[lines], &tokenizer, self.backend)?)))
+            .collect()
+    }
+
+    /// Adds the lines of the (uncompressed) `raw_fn` from byte `offset` on to `result`, which must hold
+    /// the counts for everything before `offset`, and returns the offset to resume from next time: just
+    /// past the last complete line, so a half-written line is left for the next call. Start from an
+    /// empty `DictionaryResult` and offset 0. The counts come out the same as parsing the whole file in
+    /// one go, n-grams spanning the two parts included, except that `min_count` and `top_k` prune the
+    /// merged result each time, so whatever an earlier call dropped starts from zero again.
+    pub fn update(&self, result: &mut DictionaryResult, raw_fn: String, offset: u64, lf:&LogFormat) -> Result<u64, ParserError> {
+        let (previous, lines, next) = self.read_from(raw_fn, offset)?;
+        self.update_lines(result, &previous, lines, lf)?;
+        Ok(next)
+    }
+
+    // the complete lines of raw_fn from offset on, the ones before them that carry their context, and
+    // the offset just past them
+    fn read_from<P: AsRef<Path>>(&self, raw_fn: P, offset: u64) -> Result<(Vec<String>, Vec<String>, u64), ParserError> {
+        let mut file = File::open(raw_fn)?;
+        if offset > file.metadata()?.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "the file is shorter than the offset; was it truncated or rotated?").into());
+        }
+        file.seek(SeekFrom::Start(offset))?;
+        let mut buf = vec![];
+        file.read_to_end(&mut buf)?;
+        let complete = buf.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1);
+        let lines = split_lines(&buf[..complete], self.lossy_utf8);
+        let previous = lines_before(&mut file, offset, 2 * self.context.lines, self.lossy_utf8)?;
+        Ok((previous, lines, offset + complete as u64))
+    }
+
+    /// Follows the (uncompressed) `raw_fn` like `tail -f`: adds its lines to `result`, then checks
+    /// every `interval` for lines appended since and adds those, as `update` would. After each batch
+    /// it calls `on_update` with the result and the new lines, and stops once that returns false. A
+    /// file that shrinks (truncated, or rotated under the same name) is read again from the start,
+    /// on top of the counts so far. With the notify feature it also watches the file, and reads new
+    /// lines as soon as they're written; the checks every `interval` stay for the file systems
+    /// that don't send change events, network mounts among them. Without it, new lines are noticed
+    /// every `interval`.
+    pub fn follow<P, F>(&self, result: &mut DictionaryResult, raw_fn: P, lf:&LogFormat, interval: Duration, mut on_update: F) -> Result<(), ParserError>
+    where P: AsRef<Path>, F: FnMut(&DictionaryResult, &[String]) -> bool, {
+        let raw_fn = raw_fn.as_ref();
+        let watch = FileWatch::new(raw_fn);
+        let mut offset = 0;
+        loop {
+            if fs::metadata(raw_fn)?.len() < offset {
+                offset = 0;
+            }
+            let (previous, lines, next) = self.read_from(raw_fn, offset)?;
+            offset = next;
+            if !lines.is_empty() {
+                self.update_lines(result, &previous, lines.clone(), lf)?;
+                if !on_update(result, &lines) {
+                    return Ok(());
+                }
+            }
+            watch.wait(interval);
+        }
+    }
+
+    /// Adds `lines` to `result`, where `previous` are the lines right before them that `result` already
+    /// counted; the last 2 * context.lines of them are enough to count the n-grams spanning the two.
+    pub fn update_lines(&self, result: &mut DictionaryResult, previous: &[String], lines: Vec<String>, lf:&LogFormat) -> Result<(), ParserError> {
+        if self.group_by.is_some() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "group_by needs the whole input at once, not batches of it").into());
+        }
+        let previous = &previous[previous.len().saturating_sub(2 * self.context.lines)..];
+        let unpruned = self.unpruned();
+        let tokenizer = self.tokenizer(lf)?;
+        let normalized = Normalize::new(&tokenizer, self.normalization);
+        let interner = Interner::default();
+        let edges = [ChunkEdges::new(previous, &normalized, &interner, self.context), ChunkEdges::new(&lines, &normalized, &interner, self.context)];
+        let mut spanning = self.empty_dicts();
+        stitch_chunk_boundaries(&edges, &normalized, self.context, Map::TypeHash(&mut spanning));
+        // no skip-grams (they stay within their line), and tokens the lines have already
+        let spanning = DictionaryResult::from_counts(spanning, interner.names(), self);
+
+        let new = unpruned.build(&[lines], &tokenizer, self.backend)?;
+        result.merge(new);
+        result.merge(spanning);
+        self.prune(result);
+        Ok(())
+    }
+
+    /// `update_lines` for lines that come in batches: `previous` holds the end of the batches so far
+    /// (empty at first), and is left holding what the next batch needs of it.
+    pub fn update_batch(&self, result: &mut DictionaryResult, previous: &mut Vec<String>, lines: Vec<String>, lf:&LogFormat) -> Result<(), ParserError> {
+        let keep = 2 * self.context.lines;
+        let tail = lines[lines.len().saturating_sub(keep)..].to_vec();
+        self.update_lines(result, previous, lines, lf)?;
+        // a short batch leaves some of the ones before it in the context
+        previous.extend(tail);
+        previous.drain(..previous.len().saturating_sub(keep));
+        Ok(())
+    }
+
+    // the builder for a part of the input, that counts everything in it; skip_lines and max_lines
+    // are about the input as a whole, not each part
+    pub(crate) fn unpruned(&self) -> DictionaryBuilder {
+        DictionaryBuilder { min_count: 1, top_k: None, skip_lines: 0, max_lines: None, ..self.clone() }
+    }
+
+    // the lines of a file read into buf that a run would count, in the chunks its workers would take,
+    // each after the lines before it that update_lines needs
+    #[cfg(feature = "tokio")]
+    pub(crate) fn picked_chunks(&self, buf: &[u8]) -> Result<Vec<[Vec<String>; 2]>, ParserError> {
+        let inputs = [decode_lines(io::Cursor::new(&buf[byte_range(buf, self.offsets)]).split(b'\n'), self.lossy_utf8, &mut 0, &mut self.picker())?];
+        let chunks = self.chunks(&inputs, self.num_workers()?).remove(0);
+        let mut previous: &[String] = &[];
+        Ok(chunks.into_iter().map(|chunk| {
+            let before = previous[previous.len().saturating_sub(2 * self.context.lines)..].to_vec();
+            previous = chunk;
+            [before, chunk.to_vec()]
+        }).collect())
+    }
+
+    // reads (or maps) every file, then builds from all of them
+    fn build_files<P: AsRef<Path> + Sync, R: FromCounts>(&self, paths: &[P], tokenizer: &dyn Tokenizer, backend: Backend) -> Result<R, ParserError> {
+        Ok(self.build_files_stats(paths, tokenizer, backend, None)?.0)
+    }
+
+    // build_files, also counting what was read, and with a format the lines that don't match it and the redactions
+    fn build_files_stats<P: AsRef<Path> + Sync, R: FromCounts>(&self, paths: &[P], tokenizer: &dyn Tokenizer, backend: Backend,
+                                                        format: Option<&RegexTokenizer>) -> Result<(R, ParseStats), ParserError> {
+        let mut stats = ParseStats::default();
+        let start = Instant::now();
+        let read = || if let Some(report) = &self.report {
+            report.lock().unwrap().read = start.elapsed();
+        };
+        #[cfg(feature = "concurrent")]
+        if let Some(batch_lines) = self.pipeline.filter(|_| !self.mmap && !self.dedupe_lines && self.group_by.is_none() && format.is_none()) {
+            return self.build_pipelined(paths, tokenizer, backend, batch_lines);
+        }
+        let maps = self.map_inputs(paths)?;
+        let inputs = self.read_inputs(paths, &maps, &mut stats.invalid_utf8)?;
+        read();
+        stats.count(&inputs, format, self);
+        Ok((self.build_all(&inputs, tokenizer, backend)?, stats))
+    }
+
+    // the files of paths memory-mapped for read_inputs with mmap; none without
+    fn map_inputs<P: AsRef<Path>>(&self, paths: &[P]) -> io::Result<Vec<Mapped>> {
+        if !self.mmap {
+            return Ok(vec![]);
+        }
+        paths.iter().map(|p| map_file(p.as_ref())).collect()
+    }
+
+    // the lines of paths a run counts, the ones the byte offsets, skip_lines, max_lines and sample
+    // leave: read into memory, or with mmap split out of maps, from map_inputs
+    fn read_inputs<'m, P: AsRef<Path>>(&self, paths: &[P], maps: &'m [Mapped], invalid_utf8: &mut usize) -> Result<Vec<Vec<Cow<'m, str>>>, ParserError> {
+        // one pick over all the inputs, which stops the reading after the last line max_lines takes
+        let mut pick = self.picker();
+        if !self.mmap {
+            let mut inputs = vec![];
+            for path in paths {
+                span!("read", path = %path.as_ref().display());
+                let lines = decode_lines(self.read_range(path.as_ref())?.split(b'\n'), self.lossy_utf8, invalid_utf8, &mut pick)?;
+                inputs.push(lines.into_iter().map(Cow::Owned).collect());
+            }
+            return Ok(inputs);
+        }
+        // the pages are read as the lines are split
+        span!("read", files = maps.len());
+        Ok(maps.iter().map(|m| str_lines(&m[byte_range(m, self.offsets)], self.lossy_utf8, invalid_utf8, &mut pick)).collect())
+    }
+
+    // build_files with a reader thread sending the workers batch_lines lines at a time as it reads
+    // them, so that reading the files overlaps counting them
+    #[cfg(feature = "concurrent")]
+    fn build_pipelined<P: AsRef<Path> + Sync, R: FromCounts>(&self, paths: &[P], tokenizer: &dyn Tokenizer, backend: Backend,
+                                                             batch_lines: usize) -> Result<(R, ParseStats), ParserError> {
+        // a couple of batches waiting per worker keep them busy without the reader getting far ahead
+        let (tx, rx) = mpsc::sync_channel(2 * self.num_workers()? as usize);
+        #[cfg(feature = "tracing")]
+        let parent = &TraceContext::current();
+        thread::scope(|scope| {
+            let reader = scope.spawn(move || {
+                #[cfg(feature = "tracing")]
+                let _parent = parent.enter();
+                self.send_batches(paths, batch_lines, tx)
+            });
+            let built = self.build_lines(Lines::Batches(rx), tokenizer, backend);
+            // the workers are gone by now, so a reader still sending has stopped
+            let (stats, batches) = reader.join().map_err(|_| ParserError::Worker("the reader panicked".to_string()))??;
+            let result = built?;
+            if let Some(report) = &self.report {
+                report.lock().unwrap().worker_lines = batches;
+            }
+            Ok((result, stats))
+        })
+    }
+
+    // reads paths for build_pipelined, sending the lines a run would count batch_lines at a time, each
+    // batch with the number of its input; what was read, and the lines of each batch
+    #[cfg(feature = "concurrent")]
+    fn send_batches<P: AsRef<Path>>(&self, paths: &[P], batch_lines: usize, batches: SyncSender<(usize, Vec<String>)>)
+                                    -> Result<(ParseStats, Vec<usize>), ParserError> {
+        let (mut stats, mut sizes) = (ParseStats::default(), vec![]);
+        let mut pick = self.picker();
+        for (input, path) in paths.iter().enumerate() {
+            span!("read", path = %path.as_ref().display());
+            let mut lines = self.read_range(path.as_ref())?.split(b'\n');
+            while !pick.done() {
+                // a batch of the lines taken out of the next batch_lines read
+                let mut read = 0;
+                let batch = decode_lines((&mut lines).take(batch_lines).inspect(|_| read += 1), self.lossy_utf8, &mut stats.invalid_utf8, &mut pick)?;
+                let done = read < batch_lines;
+                if !batch.is_empty() {
+                    stats.total += batch.len();
+                    sizes.push(batch.len());
+                    // the workers only stop early when the run failed
+                    if batches.send((input, batch)).is_err() {
+                        return Ok((stats, sizes));
+                    }
+                }
+                if done {
+                    break;
+                }
+            }
+        }
+        if !self.lossy_utf8 {
+            stats.total += stats.invalid_utf8;
+        }
+        Ok((stats, sizes))
+    }
+
+    // reads path, or just the lines between the byte offsets; seeks to the start offset when it can
+    fn read_range(&self, path: &Path) -> io::Result<Box<dyn BufRead>> {
+        let (start, end) = self.offsets;
+        if (start, end) == (0, None) {
+            return Ok(Box::new(read_lines(path)?));
+        }
+        let plain = path != Path::new("-") && !path.to_string_lossy().contains("://")
+            && !matches!(path.extension().and_then(|ext| ext.to_str()), Some("gz" | "zst"));
+        let lines = if plain && start > 0 {
+            let mut file = File::open(path)?;
+            file.seek(SeekFrom::Start(start - 1))?;
+            read_between(io::BufReader::new(file), start - 1, start, end)?
+        } else {
+            read_between(read_lines(path)?, 0, start, end)?
+        };
+        Ok(Box::new(io::Cursor::new(lines)))
+    }
+
+    // the lines of the input skip_lines, max_lines and sample leave, picked as they're read
+    fn picker(&self) -> Pick {
+        Pick { range: self.skip_lines..self.max_lines.map_or(usize::MAX, |max| self.skip_lines.saturating_add(max)), sample: self.sample, line: 0 }
+    }
+
+    // the lines of inputs already in memory that skip_lines, max_lines and sample leave, if they leave
+    // out any
+    fn pick<'a, S: AsRef<str>>(&self, inputs: &'a [Vec<S>]) -> Option<Vec<Vec<&'a str>>> {
+        let mut pick = self.picker();
+        if pick.range == (0..usize::MAX) && pick.sample.is_none() {
+            return None;
+        }
+        Some(inputs.iter().map(|input| input.iter().filter(|_| pick.takes()).map(AsRef::as_ref).collect()).collect())
+    }
+
+    // the lines of path a run would count, in order
+    pub(crate) fn picked_lines(&self, path: &Path) -> Result<Vec<String>, ParserError> {
+        decode_lines(self.read_range(path)?.split(b'\n'), self.lossy_utf8, &mut 0, &mut self.picker())
+    }
+
+    fn build<S: AsRef<str> + Send + Sync, R: FromCounts>(&self, inputs: &[Vec<S>], tokenizer: &dyn Tokenizer, backend: Backend) -> Result<R, ParserError> {
+        match self.pick(inputs) {
+            Some(picked) => self.build_all(&picked, tokenizer, backend),
+            None => self.build_all(inputs, tokenizer, backend),
+        }
+    }
+
+    fn build_all<S: AsRef<str> + Send + Sync, R: FromCounts>(&self, inputs: &[Vec<S>], tokenizer: &dyn Tokenizer, backend: Backend) -> Result<R, ParserError> {
+        if self.group_by.is_some() {
+            return self.build_streams(&group_lines(inputs, tokenizer), tokenizer, backend);
+        }
+        self.build_streams(inputs, tokenizer, backend)
+    }
+
+    fn build_streams<S: AsRef<str> + Send + Sync, R: FromCounts>(&self, inputs: &[Vec<S>], tokenizer: &dyn Tokenizer, backend: Backend) -> Result<R, ParserError> {
+        self.build_lines(Lines::Chunks(inputs), tokenizer, backend)
+    }
+
+    fn build_lines<S: AsRef<str> + Send + Sync, R: FromCounts>(&self, lines: Lines<S>, tokenizer: &dyn Tokenizer, backend: Backend) -> Result<R, ParserError> {
+        let tokenizer = Normalize::new(tokenizer, self.normalization);
+        let timed = self.report.as_ref().map(|_| Timed::new(&tokenizer));
+        let tokenizer: &dyn Tokenizer = match &timed {
+            Some(timed) => timed,
+            None => &tokenizer,
+        };
+        // on one thread, which is all there is without the concurrent feature, separate maps are the
+        // shared ones
+        let backend = match backend {
+            Backend::ConcurrentMap | Backend::Buffered if cfg!(not(feature = "concurrent")) => Backend::SeparateMaps,
+            backend => backend,
+        };
+        // the batches' lines are only known once they've been read
+        let worker_lines = match &lines {
+            Lines::Chunks(inputs) if self.report.is_some() => self.chunks(inputs, self.num_workers()?).iter().flatten().map(|chunk| chunk.len()).collect(),
+            _ => vec![],
+        };
+        // deduplicating needs every line at hand, and counts into separate maps
+        let dedup = match &lines {
+            Lines::Chunks(inputs) if self.dedupe_lines => Some(*inputs),
+            _ => None,
+        };
+        let backend = if dedup.is_some() { Backend::SeparateMaps } else { backend };
+        span!("build", ?backend, threads = self.num_workers()?);
+        let mut result: R = if let Some(inputs) = dedup {
+            dictionary_builder_dedup(inputs, tokenizer, self)?
+        } else {
+            match backend {
+                Backend::SeparateMaps => dictionary_builder(lines, tokenizer, self)?,
+                #[cfg(feature = "concurrent")]
+                Backend::ConcurrentMap | Backend::Buffered => dictionary_builder_conc(lines, tokenizer, self, backend)?,
+                #[cfg(not(feature = "concurrent"))]
+                Backend::ConcurrentMap | Backend::Buffered => unreachable!(),
+                Backend::CountMin => dictionary_builder_sketch(lines, tokenizer, self)?,
+                Backend::External => dictionary_builder_external(lines, tokenizer, self)?,
+            }
+        };
+        result.prune(self);
+        if let (Some(report), Some(timed)) = (&self.report, &timed) {
+            let mut report = report.lock().unwrap();
+            report.backend = backend;
+            report.threads = self.num_workers()?;
+            report.worker_lines = worker_lines;
+            report.tokenize = Duration::from_nanos(timed.tokenizing.load(Ordering::Relaxed));
+            report.count = Duration::from_nanos(timed.last.load(Ordering::Relaxed));
+            report.aggregate = timed.start.elapsed().saturating_sub(report.count);
+        }
+        Ok(result)
+    }
+
+    /// Drops what `min_count` and `top_k` leave out of `result`. The builds do that already; this is
+    /// for results merged from parts counted without them, like `parse_raw_async`'s.
+    pub fn prune<S: BuildHasher + Default>(&self, result: &mut DictionaryResult<S>) {
+        let breakdown = result.breakdown.values_mut().flat_map(|dicts| dicts.values_mut());
+        for dict in result.ngram_dicts.values_mut().chain(result.skip_grams.values_mut()).chain(breakdown) {
+            if self.min_count > 1 {
+                dict.retain(|_, count| *count >= self.min_count);
+            }
+            if let Some(k) = self.top_k {
+                *dict = top_k_entries(dict.iter(), k).into_iter().collect();
+            }
+        }
+    }
+}
+
+// where the first line that starts at or after offset does, in buf
+fn line_start(buf: &[u8], offset: u64) -> usize {
+    let offset = (offset as usize).min(buf.len());
+    if offset == 0 {
+        return 0;
+    }
+    buf[offset - 1..].iter().position(|b| *b == b'\n').map_or(buf.len(), |i| offset + i)
+}
+
+// the bytes of the lines of buf that start between the offsets
+fn byte_range(buf: &[u8], (start, end): (u64, Option<u64>)) -> std::ops::Range<usize> {
+    let start = line_start(buf, start);
+    start..end.map_or(buf.len(), |end| line_start(buf, end).max(start))
+}
+
+// the bytes of the lines of reader that start between start and end, where reader is at byte `at`
+// (start - 1 at most) of the input
+fn read_between<R: BufRead>(mut reader: R, mut at: u64, start: u64, end: Option<u64>) -> io::Result<Vec<u8>> {
+    if start > 0 {
+        at += io::copy(&mut (&mut reader).take(start - 1 - at), &mut io::sink())?;
+        // the rest of the line byte start - 1 is in, which is just that byte if it ends a line
+        at += reader.read_until(b'\n', &mut vec![])? as u64;
+    }
+    let mut lines = vec![];
+    while end.is_none_or(|end| at < end) {
+        let read = reader.read_until(b'\n', &mut lines)?;
+        if read == 0 {
+            break;
+        }
+        at += read as u64;
+    }
+    Ok(lines)
+}
+
+// the complete lines of buf, as read_log_from would read them
+fn split_lines(buf: &[u8], lossy: bool) -> Vec<String> {
+    buf.split(|b| *b == b'\n')
+        .take(buf.iter().filter(|b| **b == b'\n').count())
+        .filter_map(|l| decode_line(l, lossy, &mut 0).map(Cow::into_owned))
+        .collect()
+}
+
+// the last count lines before offset, which is 0 or just past a newline; reads backwards from offset in
+// growing windows until it has them all
+fn lines_before(file: &mut File, offset: u64, count: usize, lossy: bool) -> io::Result<Vec<String>> {
+    if offset == 0 || count == 0 {
+        return Ok(vec![]);
+    }
+    let mut window = 4096;
+    loop {
+        let start = offset.saturating_sub(window);
+        file.seek(SeekFrom::Start(start))?;
+        let mut buf = vec![];
+        Read::by_ref(file).take(offset - start).read_to_end(&mut buf)?;
+        let newlines = buf.iter().filter(|b| **b == b'\n').count();
+        // unless we're at the start of the file, the first piece may be the end of a longer line
+        if newlines > count || start == 0 {
+            let first = if start == 0 { 0 } else { buf.iter().position(|b| *b == b'\n').unwrap() + 1 };
+            let lines = split_lines(&buf[first..], lossy);
+            return Ok(lines[lines.len().saturating_sub(count)..].to_vec());
+        }
+        window *= 2;
+    }
+}
+
+#[test]
+fn test_update() {
+    let log = fs::read_to_string("data/HDFS_2k.log").unwrap();
+    // stop halfway through a line
+    let cut = log.match_indices('\n').nth(1200).unwrap().0 + 20;
+    let path = std::env::temp_dir().join(format!("logram-test-{}-update.log", std::process::id()));
+    for builder in [DictionaryBuilder::new().num_threads(Some(3)), DictionaryBuilder::new().num_threads(Some(2)).context(ContextWindow { tokens: 3, lines: 3 })] {
+        fs::write(&path, &log[..cut]).unwrap();
+        let mut result = DictionaryResult::default();
+        let offset = builder.update(&mut result, path.to_str().unwrap().to_string(), 0, &HDFS).unwrap();
+        assert_eq!(&log[offset as usize - 1..offset as usize + 1], "\n0");
+        fs::write(&path, &log).unwrap();
+        let offset = builder.update(&mut result, path.to_str().unwrap().to_string(), offset, &HDFS).unwrap();
+        assert_eq!(offset as usize, log.len());
+        assert_eq!(result, builder.parse_raw_single("data/HDFS_2k.log".to_string(), &HDFS));
+    }
+    fs::write(&path, "").unwrap();
+    let truncated = DictionaryBuilder::new().update(&mut DictionaryResult::default(), path.to_str().unwrap().to_string(), 10, &HDFS);
+    fs::remove_file(&path).unwrap();
+    assert!(matches!(truncated, Err(ParserError::Io(_))));
+}
+
+#[test]
+fn test_follow() {
+    let log = fs::read_to_string("data/HDFS_2k.log").unwrap();
+    let lines: Vec<&str> = log.lines().collect();
+    let path = std::env::temp_dir().join(format!("logram-test-{}-follow.log", std::process::id()));
+    fs::write(&path, lines[..500].join("\n") + "\n").unwrap();
+    let writer = {
+        let (path, rest) = (path.clone(), lines[500..].iter().map(|l| format!("{}\n", l)).collect::<Vec<_>>());
+        thread::spawn(move || {
+            for batch in rest.chunks(300) {
+                thread::sleep(Duration::from_millis(20));
+                let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+                file.write_all(batch.concat().as_bytes()).unwrap();
+            }
+        })
+    };
+    let builder = DictionaryBuilder::new().num_threads(Some(2));
+    let mut result = DictionaryResult::default();
+    let (mut seen, mut batches) = (0, 0);
+    builder.follow(&mut result, &path, &HDFS, Duration::from_millis(5), |_, new| {
+        seen += new.len();
+        batches += 1;
+        seen < lines.len()
+    }).unwrap();
+    writer.join().unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(seen, lines.len());
+    assert!(batches > 1);
+    assert_eq!(result, builder.parse_raw_single("data/HDFS_2k.log".to_string(), &HDFS));
+}
+
+#[cfg(feature = "notify")]
+#[test]
+fn test_follow_notify() {
+    let path = std::env::temp_dir().join(format!("logram-test-{}-follow-notify.log", std::process::id()));
+    fs::write(&path, "081109 203615 148 INFO dfs.DataNode$PacketResponder: PacketResponder 1 terminating\n").unwrap();
+    assert!(FileWatch::new(&path).is_watching());
+    let writer = {
+        let path = path.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+            file.write_all(b"081109 203616 148 INFO dfs.DataNode$PacketResponder: PacketResponder 2 terminating\n").unwrap();
+        })
+    };
+    // an interval far longer than the test may take: only the change event can wake it in time
+    let start = Instant::now();
+    let mut batches = 0;
+    DictionaryBuilder::new().follow(&mut DictionaryResult::default(), &path, &HDFS, Duration::from_secs(60), |_, _| {
+        batches += 1;
+        batches < 2
+    }).unwrap();
+    writer.join().unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(start.elapsed() < Duration::from_secs(30), "{:?}", start.elapsed());
+}
+
+/// Matches a file name against a glob with `*` (any run of characters) and `?` (any one character).
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let (p, n): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    // classic backtracking matcher: remember the last `*` and how much of the name it has eaten
+    let (mut pi, mut ni) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while ni < n.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == n[ni]) {
+            pi += 1;
+            ni += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ni));
+            pi += 1;
+        } else if let Some((sp, sn)) = star {
+            pi = sp + 1;
+            ni = sn + 1;
+            star = Some((sp, sn + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+#[test]
+fn test_glob_match() {
+    assert!(glob_match("*.log", "HDFS_2k.log"));
+    assert!(glob_match("*_2k.*", "HPC_2k.log"));
+    assert!(glob_match("HP?.log", "HPC.log"));
+    assert!(glob_match("*", "anything"));
+    assert!(!glob_match("*.log", "OpenStack.tar.gz"));
+    assert!(!glob_match("HP?.log", "HPC_2k.log"));
+}
+
+// every file under dir, recursively, that matches pattern; sorted so merges are reproducible
+fn log_files(dir: &Path, pattern: Option<&str>) -> io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(d) = dirs.pop() {
+        for entry in fs::read_dir(d)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else if pattern.is_none_or(|p| path.file_name().is_some_and(|name| glob_match(p, &name.to_string_lossy()))) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// The files of the rotated log `base` (e.g. app.log), oldest first: its rotations `app.log.N`,
+/// compressed or not (`app.log.2.gz`), from the highest N down, then `base` itself if it's there.
+pub fn rotated_files<P: AsRef<Path>>(base: P) -> io::Result<Vec<PathBuf>> {
+    let base = base.as_ref();
+    let name = base.file_name().and_then(|n| n.to_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} isn't a file name", base.display())))?;
+    let dir = match base.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    let mut rotations = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(suffix) = path.file_name().and_then(|n| n.to_str()).and_then(|n| n.strip_prefix(name)?.strip_prefix('.')) else { continue };
+        let number = suffix.strip_suffix(".gz").or_else(|| suffix.strip_suffix(".zst")).unwrap_or(suffix);
+        if let Ok(number) = number.parse::<u64>() {
+            rotations.push((number, path));
+        }
+    }
+    rotations.sort_unstable_by(|(a, _), (b, _)| b.cmp(a));
+    let mut files: Vec<PathBuf> = rotations.into_iter().map(|(_, path)| path).collect();
+    if base.exists() {
+        files.push(base.to_path_buf());
+    }
+    if files.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("no {} or rotations of it", base.display())));
+    }
+    Ok(files)
+}
+
+#[test]
+fn test_parse_rotated() {
+    let dir = std::env::temp_dir().join(format!("logram-test-{}-rotated", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let lines = read_log("data/HDFS_2k.log").unwrap();
+    let write = |name: &str, lines: &[String]| fs::write(dir.join(name), lines.iter().map(|l| format!("{}\n", l)).collect::<String>()).unwrap();
+    // app.log.10 is the oldest, and sorts before app.log.2 as a string
+    write("app.log.10", &lines[..300]);
+    write("app.log.2", &lines[300..1000]);
+    write("app.log.1", &lines[1000..1700]);
+    write("app.log", &lines[1700..]);
+    write("app.log.bak", &lines[..10]);
+    write("other.log.1", &lines[..10]);
+    let files = rotated_files(dir.join("app.log")).unwrap();
+    let names: Vec<&str> = files.iter().map(|f| f.file_name().unwrap().to_str().unwrap()).collect();
+    assert_eq!(names, ["app.log.10", "app.log.2", "app.log.1", "app.log"]);
+
+    let builder = DictionaryBuilder::new().num_threads(Some(3));
+    let rotated = builder.parse_rotated(dir.join("app.log"), &HDFS);
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(rotated, builder.parse_raw("data/HDFS_2k.log".to_string(), &HDFS));
+    assert_eq!(rotated_files(dir.join("app.log")).unwrap_err().kind(), io::ErrorKind::NotFound);
+}
+
+#[cfg(all(feature = "flate2", feature = "zstd"))]
+#[test]
+fn test_parse_rotated_compressed() {
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    let dir = std::env::temp_dir().join(format!("logram-test-{}-rotated-compressed", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let lines = read_log("data/HDFS_2k.log").unwrap();
+    let text = |lines: &[String]| lines.iter().map(|l| format!("{}\n", l)).collect::<String>();
+    // logrotate's compress leaves the newest rotation as it is and compresses the older ones
+    let mut encoder = GzEncoder::new(File::create(dir.join("app.log.3.gz")).unwrap(), Compression::default());
+    encoder.write_all(text(&lines[..500]).as_bytes()).unwrap();
+    encoder.finish().unwrap();
+    fs::write(dir.join("app.log.2.zst"), zstd::encode_all(text(&lines[500..1200]).as_bytes(), 0).unwrap()).unwrap();
+    fs::write(dir.join("app.log.1"), text(&lines[1200..1900])).unwrap();
+    fs::write(dir.join("app.log"), text(&lines[1900..])).unwrap();
+    let files = rotated_files(dir.join("app.log")).unwrap();
+    let names: Vec<&str> = files.iter().map(|f| f.file_name().unwrap().to_str().unwrap()).collect();
+    assert_eq!(names, ["app.log.3.gz", "app.log.2.zst", "app.log.1", "app.log"]);
+
+    let builder = DictionaryBuilder::new().num_threads(Some(3));
+    let rotated = builder.parse_rotated(dir.join("app.log"), &HDFS);
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(rotated, builder.parse_raw("data/HDFS_2k.log".to_string(), &HDFS));
+}
+
+#[test]
+fn test_sample() {
+    let builder = DictionaryBuilder::new().min_chunk_lines(1);
+    let full = builder.parse_raw("data/HDFS_2k.log".to_string(), &HDFS);
+    assert_eq!(builder.clone().sample(Some(Sample::EveryNth(1))).parse_raw("data/HDFS_2k.log".to_string(), &HDFS), full);
+    assert_eq!(builder.clone().sample(Some(Sample::Bernoulli { p: 1.0, seed: 7 })).parse_raw("data/HDFS_2k.log".to_string(), &HDFS), full);
+
+    // the even lines, counted as though they were all there was
+    let lines = read_log("data/HDFS_2k.log").unwrap();
+    let even: Vec<&str> = lines.iter().step_by(2).map(String::as_str).collect();
+    let every_2nd = builder.clone().sample(Some(Sample::EveryNth(2)));
+    assert_eq!(every_2nd.parse_raw("data/HDFS_2k.log".to_string(), &HDFS), builder.parse_str(&even.join("\n"), &HDFS));
+
+    let tenth = Sample::Bernoulli { p: 0.1, seed: 42 };
+    let kept = (0..lines.len()).filter(|i| tenth.keeps(*i)).count();
+    assert!((150..250).contains(&kept), "{}", kept);
+    let sampled = builder.clone().sample(Some(tenth));
+    let expected = sampled.clone().num_threads(Some(1)).parse_raw_single("data/HDFS_2k.log".to_string(), &HDFS);
+    for backend in [Backend::SeparateMaps, Backend::ConcurrentMap, Backend::Buffered] {
+        assert_eq!(sampled.clone().num_threads(Some(4)).backend(backend).parse_raw("data/HDFS_2k.log".to_string(), &HDFS), expected);
+    }
+    let other_seed = builder.clone().sample(Some(Sample::Bernoulli { p: 0.1, seed: 43 }));
+    assert_ne!(other_seed.parse_raw("data/HDFS_2k.log".to_string(), &HDFS), expected);
+
+    // after skip_lines, the lines are numbered from the first one left, so that one is taken
+    let from_3rd: Vec<&str> = lines[3..].iter().step_by(2).map(String::as_str).collect();
+    let skipped = every_2nd.clone().skip_lines(3).parse_raw("data/HDFS_2k.log".to_string(), &HDFS);
+    assert_eq!(skipped, builder.parse_str(&from_3rd.join("\n"), &HDFS));
+    let skipped_tenth = sampled.skip_lines(5).num_threads(Some(1)).parse_raw_single("data/HDFS_2k.log".to_string(), &HDFS);
+    let kept_tenth: Vec<&str> = lines[5..].iter().enumerate().filter(|(i, _)| tenth.keeps(*i)).map(|(_, line)| line.as_str()).collect();
+    assert_eq!(skipped_tenth, builder.parse_str(&kept_tenth.join("\n"), &HDFS));
+}
+
+#[test]
+fn test_parse_raw_checked_errors() {
+    let builder = DictionaryBuilder::new();
+    let missing = builder.parse_raw_single_checked("data/does_not_exist.log".to_string(), &Linux);
+    assert!(matches!(missing, Err(ParserError::Io(_))));
+
+    let unbalanced = LogFormat::Custom { format: "<Date> (<Content>".to_string(), censor: vec![] };
+    let bad_regex = builder.parse_raw_conc_checked("data/from_paper.log".to_string(), &unbalanced);
+    assert!(matches!(bad_regex, Err(ParserError::RegexCompile(_))));
+
+    let no_content = LogFormat::Custom { format: "<Date> <Time> <Message>".to_string(), censor: vec![] };
+    let mismatch = builder.parse_raw_single_checked("data/from_paper.log".to_string(), &no_content);
+    assert!(matches!(mismatch, Err(ParserError::FormatMismatch(_))));
+
+    let no_workers = builder.num_threads(Some(0)).parse_raw_conc_checked("data/from_paper.log".to_string(), &Linux);
+    assert!(matches!(no_workers, Err(ParserError::InvalidConfig(_))));
+}
+
+#[test]
+fn test_parse_str() {
+    let text = std::fs::read_to_string("data/HDFS_2k.log").unwrap();
+    let expected = DictionaryBuilder::new().parse_raw("data/HDFS_2k.log".to_string(), &HDFS);
+    for backend in [Backend::SeparateMaps, Backend::ConcurrentMap, Backend::Buffered] {
+        for threads in [1, 4] {
+            let builder = DictionaryBuilder::new().backend(backend).num_threads(Some(threads)).min_chunk_lines(1);
+            assert_eq!(builder.parse_str(&text, &HDFS), expected);
+        }
+    }
+
+    // a worker panicking on this thread fails the run the same way as on a thread of its own
+    struct Panics;
+    impl Tokenizer for Panics {
+        fn tokenize<'a>(&self, _: &'a str) -> Vec<Cow<'a, str>> {
+            panic!("no tokens for you");
+        }
+    }
+    for threads in [1, 2] {
+        let failed = DictionaryBuilder::new().num_threads(Some(threads)).parse_raw_with_checked("data/from_paper.log".to_string(), &Panics);
+        assert!(matches!(failed, Err(ParserError::Worker(_))));
+    }
+}
+
+#[test]
+fn test_parse_raw_dir() {
+    let builder = DictionaryBuilder::new().num_threads(Some(2));
+    let merged = builder.clone().backend(Backend::SeparateMaps).parse_raw_dir(Path::new("data"), Some("H*_2k.log"), &HDFS);
+    let mut expected = DictionaryResult::default();
+    for file in ["data/HDFS_2k.log", "data/HPC_2k.log", "data/HealthApp_2k.log"] {
+        expected.merge(builder.parse_raw_single(file.to_string(), &HDFS));
+    }
+    assert_eq!(merged, expected);
+}
+
+#[cfg(feature = "concurrent")]
+#[test]
+fn test_pipeline() {
+    let builder = DictionaryBuilder::new().num_threads(Some(3)).min_chunk_lines(1).skip_grams(1);
+    for backend in [Backend::SeparateMaps, Backend::ConcurrentMap, Backend::Buffered, Backend::CountMin, Backend::External] {
+        let whole = builder.clone().backend(backend);
+        let expected = whole.parse_raw("data/HDFS_2k.log".to_string(), &HDFS);
+        let (result, report) = whole.pipeline(Some(300)).parse_raw_report("data/HDFS_2k.log".to_string(), &HDFS);
+        assert_eq!(result, expected, "{:?}", backend);
+        assert_eq!(report.worker_lines, [300, 300, 300, 300, 300, 300, 200]);
+    }
+
+    // the lines left out are the same, numbered over all the files
+    let picked = builder.clone().skip_lines(1500).max_lines(Some(1000)).sample(Some(Sample::EveryNth(3)));
+    let files = (Path::new("data"), Some("H*_2k.log"));
+    assert_eq!(picked.clone().pipeline(Some(128)).parse_raw_dir(files.0, files.1, &HDFS), picked.parse_raw_dir(files.0, files.1, &HDFS));
+
+    let (calls, total) = (Arc::new(AtomicU64::new(0)), Arc::new(AtomicU64::new(0)));
+    let (c, t) = (calls.clone(), total.clone());
+    let progress = builder.clone().pipeline(Some(1)).progress(move |done, total| {
+        assert!(done <= total);
+        c.fetch_add(1, Ordering::Relaxed);
+        t.store(total, Ordering::Relaxed);
+    });
+    progress.parse_raw("data/HDFS_2k.log".to_string(), &HDFS);
+    assert_eq!((calls.load(Ordering::Relaxed), total.load(Ordering::Relaxed)), (2000, 2000));
+
+    let missing = builder.pipeline(Some(100)).parse_raw_checked("data/no_such.log".to_string(), &HDFS);
+    assert!(matches!(missing, Err(ParserError::Io(_))));
+}
+
+#[test]
+fn test_dedupe_lines() {
+    let builder = DictionaryBuilder::new().min_chunk_lines(1).ngram_sizes(&[1, 2, 3]).skip_grams(2).breakdown_by(Some("Level"));
+    let alone = builder.clone().context(ContextWindow::disabled()).parse_raw("data/HDFS_2k.log".to_string(), &HDFS);
+    for threads in [1, 3] {
+        let deduped = builder.clone().num_threads(Some(threads)).dedupe_lines(true);
+        assert_eq!(deduped.parse_raw("data/HDFS_2k.log".to_string(), &HDFS), alone);
+        assert_eq!(deduped.clone().min_count(5).parse_raw_ids("data/HDFS_2k.log".to_string(), &HDFS),
+                   builder.clone().context(ContextWindow::disabled()).min_count(5).parse_raw_ids("data/HDFS_2k.log".to_string(), &HDFS));
+    }
+
+    // the same lines three times over count three times as much
+    let text = std::fs::read_to_string("data/HDFS_2k.log").unwrap().repeat(3);
+    let tripled = builder.dedupe_lines(true).parse_str(&text, &HDFS);
+    assert_eq!(tripled.all_token_list, alone.all_token_list);
+    for (n, dict) in &alone.ngram_dicts {
+        assert!(dict.iter().all(|(ngram, count)| tripled.ngram_dicts[n][ngram] == 3 * count));
+    }
+
+    // progress counts the lines of the input, not the distinct ones
+    let last = Arc::new(Mutex::new((0, 0)));
+    let l = last.clone();
+    let progress = DictionaryBuilder::new().num_threads(Some(3)).min_chunk_lines(1).dedupe_lines(true).progress(move |done, total| {
+        assert!(done <= total);
+        let mut last = l.lock().unwrap();
+        *last = (*last).max((done, total));
+    });
+    progress.parse_str(&text, &HDFS);
+    assert_eq!(*last.lock().unwrap(), (6000, 6000));
+}
+
+#[test]
+fn test_ngram_stream() {
+    let builder = DictionaryBuilder::new().num_threads(Some(3)).min_chunk_lines(1).flush_every(100, 1000).skip_grams(1);
+    let expected = builder.parse_raw("data/HDFS_2k.log".to_string(), &HDFS);
+    let mut counts: BTreeMap<usize, HashMap<String, u64>> = BTreeMap::new();
+    let mut calls = 0;
+    let tokens = builder.ngram_stream("data/HDFS_2k.log".to_string(), &HDFS, |n, ngram, delta| {
+        *counts.entry(n).or_default().entry(ngram.to_string()).or_default() += delta;
+        calls += 1;
+    }).unwrap();
+    assert_eq!(counts, expected.ngram_dicts);
+    assert_eq!(tokens, expected.all_token_list);
+    // flushed many times over, so the n-grams come up more than once
+    assert!(calls > counts.values().map(HashMap::len).sum::<usize>());
+}
+
+#[cfg(feature = "concurrent")]
+#[test]
+fn test_interner_names_so_far() {
+    // id 1 is in the map while id 0 is still on its way in, as when two shards are inserting at once
+    let interner = Interner::default();
+    interner.next.store(2, Ordering::Relaxed);
+    interner.ids.insert("b".to_string(), 1);
+    assert_eq!(interner.names_so_far(), [None, Some("b".to_string())]);
+    interner.ids.insert("a".to_string(), 0);
+    assert_eq!(interner.names(), ["a", "b"]);
+    assert_eq!(interner.intern("c"), 2);
+    assert_eq!(interner.names(), ["a", "b", "c"]);
+}
+
+#[test]
+fn test_parse_raw_linux() {
+    let result = DictionaryBuilder::new().parse_raw_single("data/from_paper.log".to_string(), &Linux);
+    // the token list comes back sorted
+    let all_token_list_oracle = vec![
+        "hdfs://hostname/2kSOSP.log:0+7292".to_string(),
+        "hdfs://hostname/2kSOSP.log:14584+7292".to_string(),
+        "hdfs://hostname/2kSOSP.log:21876+7292".to_string(),
+        "hdfs://hostname/2kSOSP.log:29168+7292".to_string(),
+        "hdfs://hostname/2kSOSP.log:7292+7292".to_string()
+    ];
+    assert_eq!(result.all_token_list, all_token_list_oracle);
+    let mut double_dict_oracle = HashMap::new();
+    double_dict_oracle.insert("hdfs://hostname/2kSOSP.log:14584+7292^hdfs://hostname/2kSOSP.log:0+7292".to_string(), 2);
+    double_dict_oracle.insert("hdfs://hostname/2kSOSP.log:21876+7292^hdfs://hostname/2kSOSP.log:14584+7292".to_string(), 2);
+    double_dict_oracle.insert("hdfs://hostname/2kSOSP.log:7292+7292^hdfs://hostname/2kSOSP.log:29168+7292".to_string(), 2);
+    double_dict_oracle.insert("hdfs://hostname/2kSOSP.log:0+7292^hdfs://hostname/2kSOSP.log:7292+7292".to_string(), 2);
+    assert_eq!(result.ngram_dicts[&2], double_dict_oracle);
+    let mut triple_dict_oracle = HashMap::new();
+    triple_dict_oracle.insert("hdfs://hostname/2kSOSP.log:0+7292^hdfs://hostname/2kSOSP.log:7292+7292^hdfs://hostname/2kSOSP.log:29168+7292".to_string(), 1);
+    triple_dict_oracle.insert("hdfs://hostname/2kSOSP.log:14584+7292^hdfs://hostname/2kSOSP.log:0+7292^hdfs://hostname/2kSOSP.log:7292+7292".to_string(), 1);
+    triple_dict_oracle.insert("hdfs://hostname/2kSOSP.log:21876+7292^hdfs://hostname/2kSOSP.log:14584+7292^hdfs://hostname/2kSOSP.log:0+7292".to_string(), 1);
+    assert_eq!(result.ngram_dicts[&3], triple_dict_oracle);
+}
+
+/// Which end of the counts `reverse_dict` and `print_dict` start from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Order {
+    #[default]
+    Ascending,
+    Descending,
+}
+
+/// The standard mapreduce invert map: the keys of `d` grouped by count, the counts in `order` and
+/// the keys sorted within each, so `{k1: v1, k2: v2, k3: v1}` gives `(v1, [k1, k3])` then `(v2, [k2])`.
+/// Groups are handed out one at a time; the only copy made is a list of references to sort.
+pub fn reverse_dict<'a, K: Ord, V: Ord, S>(d: &'a HashMap<K, V, S>, order: Order) -> impl Iterator<Item = (&'a V, Vec<&'a K>)> + 'a {
+    let mut entries: Vec<(&V, &K)> = d.iter().map(|(key, val)| (val, key)).collect();
+    entries.sort_unstable_by(|a, b| match order {
+        Order::Ascending => a.0.cmp(b.0),
+        Order::Descending => b.0.cmp(a.0),
+    }.then_with(|| a.1.cmp(b.1)));
+    let mut entries = entries.into_iter().peekable();
+    std::iter::from_fn(move || {
+        let (val, key) = entries.next()?;
+        let mut keys = vec![key];
+        while let Some((_, key)) = entries.next_if(|(next, _)| *next == val) {
+            keys.push(key);
+        }
+        Some((val, keys))
+    })
+}
+
+pub fn print_most_frequent(s: &str, entries: &[(&str, u64)]) {
+    println!("printing most frequent: {}", s);
+    for (key, count) in entries {
+        println!("{}: {}", count, key);
+    }
+    println!("---");
+}
+
+/// Prints the keys of `d` grouped by count, in `order`, stopping after `top_n` counts if given.
+pub fn print_dict<K: Ord + std::fmt::Debug, V: Ord + std::fmt::Display, S>(s: &str, d: &HashMap<K, V, S>, order: Order, top_n: Option<usize>) {
+    println!("printing dict: {}", s);
+    for (val, keys) in reverse_dict(d, order).take(top_n.unwrap_or(usize::MAX)) {
+        println!("{}: {:?}", val, keys);
+    }
+    println!("---");
+}
+
+#[test]
+fn test_reverse_dict() {
+    let d: HashMap<&str, i32> = HashMap::from([("b", 2), ("a", 1), ("c", 2), ("d", -1)]);
+    let ascending: Vec<(i32, Vec<&str>)> = reverse_dict(&d, Order::Ascending).map(|(v, keys)| (*v, keys.into_iter().copied().collect())).collect();
+    assert_eq!(ascending, [(-1, vec!["d"]), (1, vec!["a"]), (2, vec!["b", "c"])]);
+    let descending: Vec<i32> = reverse_dict(&d, Order::Descending).map(|(v, _)| *v).collect();
+    assert_eq!(descending, [2, 1, -1]);
+    assert_eq!(reverse_dict(&HashMap::<String, u64>::new(), Order::Descending).count(), 0);
+}
+
+#[test]
+fn test_line_and_byte_ranges() {
+    let builder = DictionaryBuilder::new().min_chunk_lines(1);
+    let lines = read_log("data/HDFS_2k.log").unwrap();
+    let slice = builder.clone().skip_lines(500).max_lines(Some(1000)).parse_raw("data/HDFS_2k.log".to_string(), &HDFS);
+    assert_eq!(slice, builder.parse_str(&lines[500..1500].join("\n"), &HDFS));
+    let (head, stats) = builder.clone().max_lines(Some(10)).parse_raw_stats("data/HDFS_2k.log".to_string(), &HDFS);
+    assert_eq!(head, builder.parse_str(&lines[..10].join("\n"), &HDFS));
+    assert_eq!((stats.total, stats.matched), (10, 10));
+
+    // cut in the middle of line 3: it goes with the part before
+    let log = std::fs::read("data/HDFS_2k.log").unwrap();
+    let starts: Vec<usize> = std::iter::once(0).chain(log.iter().enumerate().filter(|(_, b)| **b == b'\n').map(|(i, _)| i + 1)).collect();
+    let cut = (starts[2] + 5) as u64;
+    let before = builder.clone().end_offset(Some(cut)).parse_raw("data/HDFS_2k.log".to_string(), &HDFS);
+    assert_eq!(before, builder.parse_str(&lines[..3].join("\n"), &HDFS));
+    let after = builder.clone().start_offset(cut);
+    assert_eq!(after.parse_raw("data/HDFS_2k.log".to_string(), &HDFS), builder.parse_str(&lines[3..].join("\n"), &HDFS));
+    // right at the start of a line, that line goes with the part after
+    let at = builder.clone().start_offset(starts[3] as u64).end_offset(Some(starts[7] as u64));
+    assert_eq!(at.parse_raw("data/HDFS_2k.log".to_string(), &HDFS), builder.parse_str(&lines[3..7].join("\n"), &HDFS));
+    #[cfg(all(unix, feature = "mmap"))]
+    assert_eq!(at.mmap(true).parse_raw("data/HDFS_2k.log".to_string(), &HDFS), builder.parse_str(&lines[3..7].join("\n"), &HDFS));
+    assert_eq!(byte_range(b"one\ntwo\nthree", (1, Some(5))), 4..8);
+    assert_eq!(byte_range(b"one\ntwo", (9, Some(2))), 7..7);
 }
@@ -0,0 +1,113 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::{self, IsTerminal, Write};
+
+use crate::packages::parser::{reverse_dict, Order};
+
+// The human-friendly way of printing a dictionary: one n-gram a row under its count, the counts
+// right-aligned with thousands separators and colored by how many digits they have, and n-grams
+// too long for the terminal cut short.
+
+/// How `write_table` lays a table out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TableStyle {
+    /// Color the counts with ANSI escapes, brighter the bigger they are.
+    pub color: bool,
+    /// Cut every row down to this many characters.
+    pub width: Option<usize>,
+}
+
+impl TableStyle {
+    /// Color and the terminal's width when stdout is a terminal (the width from `$COLUMNS`, or 80)
+    /// and `$NO_COLOR` isn't set; neither when it's a pipe or a file.
+    pub fn detect() -> Self {
+        if !io::stdout().is_terminal() {
+            return TableStyle::default();
+        }
+        let width = std::env::var("COLUMNS").ok().and_then(|columns| columns.parse().ok()).unwrap_or(80);
+        TableStyle { color: std::env::var_os("NO_COLOR").is_none(), width: Some(width) }
+    }
+}
+
+/// `count` with a comma between every three digits, e.g. `-1,234,567`.
+pub fn thousands<V: Display>(count: V) -> String {
+    let count = count.to_string();
+    let (sign, digits) = count.split_at(count.starts_with('-') as usize);
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return count;
+    }
+    let mut grouped = String::with_capacity(count.len() + digits.len() / 3);
+    grouped.push_str(sign);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+// at most width characters of s, the last of them an ellipsis if it had to be cut
+fn truncate(s: &str, width: usize) -> Cow<'_, str> {
+    if s.chars().count() <= width {
+        return Cow::Borrowed(s);
+    }
+    let end = s.char_indices().nth(width.saturating_sub(1)).map_or(s.len(), |(end, _)| end);
+    Cow::Owned(format!("{}…", &s[..end]))
+}
+
+// the escape a count of this many digits is written in
+fn color(digits: usize) -> Option<&'static str> {
+    match digits {
+        0..=1 => Some("\x1b[2m"),
+        2..=3 => None,
+        4..=6 => Some("\x1b[36m"),
+        _ => Some("\x1b[1;33m"),
+    }
+}
+
+/// Writes `rows` of (n-gram, count) under the heading `title` as a table in `style`.
+pub fn write_table<W, K, V, I>(w: &mut W, title: &str, rows: I, style: TableStyle) -> io::Result<()>
+where W: Write, K: Display, V: Display, I: IntoIterator<Item = (K, V)>, {
+    let rows: Vec<(String, String)> = rows.into_iter().map(|(key, count)| (thousands(count), key.to_string())).collect();
+    let count_width = rows.iter().map(|(count, _)| count.len()).max().unwrap_or(0).max("count".len());
+    let key_width = style.width.map(|width| width.saturating_sub(count_width + 2));
+    let bold = |s: &str| if style.color { format!("\x1b[1m{}\x1b[0m", s) } else { s.to_string() };
+    writeln!(w, "{}", bold(title))?;
+    writeln!(w, "{}", bold(&format!("{:>count_width$}  ngram", "count")))?;
+    for (count, key) in &rows {
+        let key = key_width.map_or(Cow::Borrowed(key.as_str()), |width| truncate(key, width));
+        let digits = count.bytes().filter(u8::is_ascii_digit).count();
+        match color(digits).filter(|_| style.color) {
+            Some(escape) => writeln!(w, "{}{:>count_width$}\x1b[0m  {}", escape, count, key)?,
+            None => writeln!(w, "{:>count_width$}  {}", count, key)?,
+        }
+    }
+    writeln!(w)
+}
+
+/// `print_dict` as a table in `style`, one n-gram a row, stopping after `top_n` rows if given.
+pub fn print_table<K: Ord + Display, V: Ord + Display, S>(title: &str, d: &HashMap<K, V, S>, order: Order, top_n: Option<usize>, style: TableStyle) {
+    let rows = reverse_dict(d, order).flat_map(|(count, keys)| keys.into_iter().map(move |key| (key, count)));
+    write_table(&mut io::stdout().lock(), title, rows.take(top_n.unwrap_or(usize::MAX)), style).unwrap();
+}
+
+#[test]
+fn test_write_table() {
+    assert_eq!(thousands(1234567u64), "1,234,567");
+    assert_eq!(thousands(-1000i32), "-1,000");
+    assert_eq!(thousands(999), "999");
+    assert_eq!(truncate("abcdef", 4), "abc…");
+    assert_eq!(truncate("abcd", 4), "abcd");
+    assert_eq!(truncate("ñandú", 3), "ña…");
+
+    let mut out = vec![];
+    let rows = [("block^blk_1^terminating", 12_345u64), ("PacketResponder^1", 7)];
+    write_table(&mut out, "double", rows, TableStyle { color: false, width: Some(20) }).unwrap();
+    assert_eq!(String::from_utf8(out).unwrap(), "double\n count  ngram\n12,345  block^blk_1\u{2026}\n     7  PacketRespo\u{2026}\n\n");
+
+    let mut out = vec![];
+    write_table(&mut out, "double", [("a^b", 5)], TableStyle { color: true, width: None }).unwrap();
+    assert!(String::from_utf8(out).unwrap().contains("\x1b[2m    5\x1b[0m  a^b"));
+}
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use numpy::{IntoPyArray, PyArray1};
+use pyo3::exceptions::{PyIOError, PyKeyError, PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use regex::Regex;
+
+use crate::LogFormat;
+use crate::packages::error::ParserError;
+use crate::packages::parser::{DictionaryBuilder, DictionaryResult};
+
+// The `logram` Python module, which maturin builds out of the cdylib (see pyproject.toml):
+// parse_raw_conc and parse_raw_single build the dictionaries of a log into a Dictionaries object,
+// which hands them out as dicts or NumPy arrays and saves them, and load reads them back.
+
+fn py_err(e: ParserError) -> PyErr {
+    match e {
+        ParserError::Io(e) => PyIOError::new_err(e.to_string()),
+        e @ ParserError::Worker(_) => PyRuntimeError::new_err(e.to_string()),
+        e => PyValueError::new_err(e.to_string()),
+    }
+}
+
+// a format name like "hdfs", or a <Field> format string with the regexes to censor in its content
+fn log_format(format: &str, censor: &[String]) -> PyResult<LogFormat> {
+    if !format.contains('<') {
+        if !censor.is_empty() {
+            return Err(PyValueError::new_err("censor only goes with a <Field> format string; the built-in formats censor their own"));
+        }
+        return format.parse().map_err(PyValueError::new_err);
+    }
+    let censor = censor.iter().map(|re| Regex::new(re)).collect::<Result<_, _>>().map_err(|e| PyValueError::new_err(e.to_string()))?;
+    Ok(LogFormat::Custom { format: format.to_string(), censor })
+}
+
+/// The dictionaries of a log, as `parse_raw_conc`, `parse_raw_single` and `load` return them.
+#[pyclass(name = "Dictionaries", module = "logram", frozen)]
+struct Dictionaries(DictionaryResult);
+
+#[pymethods]
+impl Dictionaries {
+    /// The n-gram sizes there are dictionaries of, smallest first.
+    #[getter]
+    fn sizes(&self) -> Vec<usize> {
+        self.0.ngram_dicts.keys().copied().collect()
+    }
+
+    /// The distinct tokens, sorted.
+    #[getter]
+    fn tokens(&self) -> Vec<String> {
+        self.0.all_token_list.clone()
+    }
+
+    /// The n-grams of size `n` as a dict of key (the tokens joined with the separator) to count.
+    fn ngrams(&self, n: usize) -> PyResult<HashMap<String, u64>> {
+        self.0.ngram_dicts.get(&n).cloned().ok_or_else(|| PyKeyError::new_err(n))
+    }
+
+    /// The n-grams of size `n`, most frequent first, as a list of keys and a NumPy uint64 array of
+    /// their counts.
+    fn to_numpy<'py>(&self, py: Python<'py>, n: usize) -> PyResult<(Vec<String>, Bound<'py, PyArray1<u64>>)> {
+        if !self.0.ngram_dicts.contains_key(&n) {
+            return Err(PyKeyError::new_err(n));
+        }
+        // an ImportError without NumPy, rather than the panic making the array would be
+        py.import("numpy")?;
+        let (keys, counts): (Vec<String>, Vec<u64>) = self.0.most_frequent(n).into_iter().map(|(key, count)| (key.to_string(), count)).unzip();
+        Ok((keys, counts.into_pyarray(py)))
+    }
+
+    /// Writes the dictionaries to `path` in logram's `--save` format, for `load`.
+    fn save(&self, py: Python<'_>, path: PathBuf) -> PyResult<()> {
+        py.detach(|| self.0.save(path)).map_err(|e| PyIOError::new_err(e.to_string()))
+    }
+
+    fn __repr__(&self) -> String {
+        let sizes: Vec<String> = self.0.ngram_dicts.iter().map(|(n, dict)| format!("{}: {}", n, dict.len())).collect();
+        format!("<Dictionaries of {} tokens, n-grams {{{}}}>", self.0.all_token_list.len(), sizes.join(", "))
+    }
+}
+
+/// Builds the dictionaries of the log at `path` with the concurrent-map backend. `format` is a
+/// format name as `--format` takes it ("hdfs") or a `<Field>` format string, which `censor` gives
+/// the regexes to censor for. `threads` defaults to one per CPU.
+#[pyfunction]
+#[pyo3(signature = (path, format, censor = Vec::new(), threads = None))]
+fn parse_raw_conc(py: Python<'_>, path: String, format: &str, censor: Vec<String>, threads: Option<u32>) -> PyResult<Dictionaries> {
+    let lf = log_format(format, &censor)?;
+    let builder = DictionaryBuilder::new().num_threads(threads);
+    // the build doesn't touch Python, so other Python threads can run meanwhile
+    py.detach(|| builder.parse_raw_conc_checked(path, &lf)).map(Dictionaries).map_err(py_err)
+}
+
+/// `parse_raw_conc` with separate maps per worker, merged at the end.
+#[pyfunction]
+#[pyo3(signature = (path, format, censor = Vec::new(), threads = None))]
+fn parse_raw_single(py: Python<'_>, path: String, format: &str, censor: Vec<String>, threads: Option<u32>) -> PyResult<Dictionaries> {
+    let lf = log_format(format, &censor)?;
+    let builder = DictionaryBuilder::new().num_threads(threads);
+    py.detach(|| builder.parse_raw_single_checked(path, &lf)).map(Dictionaries).map_err(py_err)
+}
+
+/// Reads dictionaries written by `Dictionaries.save` or `--save`.
+#[pyfunction]
+fn load(py: Python<'_>, path: PathBuf) -> PyResult<Dictionaries> {
+    py.detach(|| DictionaryResult::load(path)).map(Dictionaries).map_err(|e| PyIOError::new_err(e.to_string()))
+}
+
+#[pymodule]
+fn logram(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Dictionaries>()?;
+    m.add_function(wrap_pyfunction!(parse_raw_conc, m)?)?;
+    m.add_function(wrap_pyfunction!(parse_raw_single, m)?)?;
+    m.add_function(wrap_pyfunction!(load, m)?)?;
+    Ok(())
+}
+
+#[test]
+fn test_python_module() {
+    use pyo3::types::PyDict;
+    use crate::LogFormat::HDFS;
+
+    let expected = DictionaryBuilder::new().parse_raw_conc("data/HDFS_2k.log".to_string(), &HDFS);
+    let path = std::env::temp_dir().join(format!("logram-test-{}-python.dict", std::process::id()));
+    Python::initialize();
+    Python::attach(|py| {
+        let module = PyModule::new(py, "logram").unwrap();
+        logram(&module).unwrap();
+        let locals = PyDict::new(py);
+        locals.set_item("logram", &module).unwrap();
+        locals.set_item("path", &path).unwrap();
+        let run = |code: &str| py.run(&std::ffi::CString::new(code).unwrap(), None, Some(&locals));
+
+        run("d = logram.parse_raw_conc('data/HDFS_2k.log', 'hdfs', threads=2)").unwrap();
+        let d = locals.get_item("d").unwrap().unwrap();
+        assert_eq!(d.getattr("sizes").unwrap().extract::<Vec<usize>>().unwrap(), [2, 3]);
+        assert_eq!(d.call_method1("ngrams", (3,)).unwrap().extract::<HashMap<String, u64>>().unwrap(), expected.ngram_dicts[&3]);
+        assert_eq!(d.getattr("tokens").unwrap().extract::<Vec<String>>().unwrap(), expected.all_token_list);
+        run("logram.parse_raw_single('data/HDFS_2k.log', '<Date> <Time> <Pid> <Level> <Component>: <Content>', censor=[r'blk_-?\\d+'])").unwrap();
+
+        run("d.save(path); again = logram.load(path)").unwrap();
+        let again = locals.get_item("again").unwrap().unwrap();
+        assert_eq!(again.call_method1("ngrams", (2,)).unwrap().extract::<HashMap<String, u64>>().unwrap(), expected.ngram_dicts[&2]);
+
+        assert!(run("logram.parse_raw_conc('data/does_not_exist.log', 'hdfs')").unwrap_err().is_instance_of::<pyo3::exceptions::PyOSError>(py));
+        assert!(run("logram.parse_raw_conc('data/HDFS_2k.log', 'nope')").unwrap_err().is_instance_of::<PyValueError>(py));
+        assert!(run("logram.parse_raw_conc('data/HDFS_2k.log', 'hdfs', censor=['x'])").unwrap_err().is_instance_of::<PyValueError>(py));
+        assert!(run("d.ngrams(7)").unwrap_err().is_instance_of::<PyKeyError>(py));
+
+        // to_numpy needs NumPy itself, and says so when it isn't there
+        if py.import("numpy").is_err() {
+            assert!(run("d.to_numpy(3)").unwrap_err().is_instance_of::<pyo3::exceptions::PyImportError>(py));
+        } else {
+            run("keys, counts = d.to_numpy(3)").unwrap();
+            let keys: Vec<String> = locals.get_item("keys").unwrap().unwrap().extract().unwrap();
+            let counts: Vec<u64> = locals.get_item("counts").unwrap().unwrap().call_method0("tolist").unwrap().extract().unwrap();
+            let most_frequent: Vec<(String, u64)> = expected.most_frequent(3).into_iter().map(|(key, count)| (key.to_string(), count)).collect();
+            assert_eq!(keys.into_iter().zip(counts).collect::<Vec<_>>(), most_frequent);
+        }
+    });
+    std::fs::remove_file(&path).unwrap();
+}
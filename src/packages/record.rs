@@ -0,0 +1,128 @@
+use std::fs::File;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+use crate::LogFormat;
+use crate::packages::error::ParserError;
+use crate::packages::parser::{csv_field, decode_line, read_lines, DictionaryBuilder, LineParser, ParsedLine};
+use crate::packages::tokenizer::{RegexTokenizer, Tokenizer};
+
+/// A line of a log as structured data: its fields, its `<Content>` as censored and the tokens the
+/// builders count n-grams of. `line_id` counts the lines from 1, like `Events::write_csv`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    pub line_id: usize,
+    pub fields: ParsedLine,
+    pub censored: String,
+    pub tokens: Vec<String>,
+}
+
+// {"LineId": 1, "Month": "Jun", ..., "CensoredContent": "...", "Tokens": [...]}, fields in the format's order
+impl Serialize for Record {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.fields.fields.len() + 3))?;
+        map.serialize_entry("LineId", &self.line_id)?;
+        for (name, value) in &self.fields.fields {
+            map.serialize_entry(name, value)?;
+        }
+        map.serialize_entry("CensoredContent", &self.censored)?;
+        map.serialize_entry("Tokens", &self.tokens)?;
+        map.end()
+    }
+}
+
+/// How `write_records` writes records: a JSON object per line, or CSV with a column per field (empty
+/// where an optional one didn't take part) and the tokens joined with spaces.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RecordFormat {
+    #[default]
+    Jsonl,
+    Csv,
+}
+
+impl RecordFormat {
+    /// CSV for a path ending in .csv, JSON lines otherwise.
+    pub fn for_path<P: AsRef<Path>>(path: P) -> Self {
+        if path.as_ref().extension().is_some_and(|ext| ext == "csv") { RecordFormat::Csv } else { RecordFormat::Jsonl }
+    }
+}
+
+// the lines of raw_fn that match lf, as records, one at a time
+pub(crate) fn for_each_record<P: AsRef<Path>>(builder: &DictionaryBuilder, raw_fn: P, lf: &LogFormat,
+                                   mut f: impl FnMut(Record) -> io::Result<()>) -> Result<usize, ParserError> {
+    let parser = LineParser::new(lf)?;
+    let censor = RegexTokenizer::new(lf)?;
+    let tokenizer = builder.normalized_tokenizer(lf)?;
+    let (mut line_id, mut written) = (0, 0);
+    for bytes in read_lines(raw_fn)?.split(b'\n') {
+        let bytes = bytes?;
+        let Some(line) = decode_line(&bytes, builder.is_lossy_utf8(), &mut 0) else { continue };
+        line_id += 1;
+        let Some(fields) = parser.parse(&line) else { continue };
+        let censored = censor.censor(fields.content()).into_owned();
+        let tokens = tokenizer.tokenize(&line).into_iter().map(|t| t.into_owned()).collect();
+        f(Record { line_id, fields, censored, tokens })?;
+        written += 1;
+    }
+    Ok(written)
+}
+
+impl DictionaryBuilder {
+    /// Writes a record of every line of `raw_fn` that matches the format to `w` as it reads them,
+    /// tokenized with this builder's options, and returns how many it wrote. The lines that aren't
+    /// UTF-8 are left out unless `lossy_utf8` is on, as when building.
+    pub fn write_records_to<P: AsRef<Path>, W: Write>(&self, raw_fn: P, lf: &LogFormat, w: W, format: RecordFormat) -> Result<usize, ParserError> {
+        let mut w = io::BufWriter::new(w);
+        let written = match format {
+            RecordFormat::Jsonl => for_each_record(self, raw_fn, lf, |record| {
+                serde_json::to_writer(&mut w, &record)?;
+                writeln!(w)
+            })?,
+            RecordFormat::Csv => {
+                let names: Vec<String> = LineParser::new(lf)?.fields().map(str::to_string).collect();
+                writeln!(w, "LineId,{},CensoredContent,Tokens", names.join(","))?;
+                for_each_record(self, raw_fn, lf, |record| {
+                    write!(w, "{}", record.line_id)?;
+                    for name in &names {
+                        write!(w, ",{}", csv_field(record.fields.get(name).unwrap_or_default()))?;
+                    }
+                    writeln!(w, ",{},{}", csv_field(&record.censored), csv_field(&record.tokens.join(" ")))
+                })?
+            },
+        };
+        w.flush()?;
+        Ok(written)
+    }
+
+    /// `write_records_to` a file at `path`, in the `RecordFormat` its extension picks.
+    pub fn write_records<P: AsRef<Path>, Q: AsRef<Path>>(&self, raw_fn: P, lf: &LogFormat, path: Q) -> Result<usize, ParserError> {
+        self.write_records_to(raw_fn, lf, File::create(&path)?, RecordFormat::for_path(&path))
+    }
+}
+
+#[test]
+fn test_write_records() {
+    use crate::LogFormat::Linux;
+    let builder = DictionaryBuilder::new();
+    let mut jsonl = vec![];
+    let written = builder.write_records_to("data/Linux_2k.log", &Linux, &mut jsonl, RecordFormat::Jsonl).unwrap();
+    let jsonl = String::from_utf8(jsonl).unwrap();
+    assert_eq!(jsonl.lines().count(), written);
+    let second: serde_json::Value = serde_json::from_str(jsonl.lines().nth(1).unwrap()).unwrap();
+    assert_eq!(second["LineId"], 2);
+    assert_eq!(second["Component"], "sshd(pam_unix)");
+    assert_eq!(second["Content"], "check pass; user unknown");
+    assert_eq!(second["Tokens"], serde_json::json!(["check", "pass;", "user", "unknown"]));
+    let first: serde_json::Value = serde_json::from_str(jsonl.lines().next().unwrap()).unwrap();
+    assert!(first["CensoredContent"].as_str().unwrap().contains("rhost=<*>"), "{}", first["CensoredContent"]);
+
+    let path = std::env::temp_dir().join(format!("logram-test-{}-records.csv", std::process::id()));
+    assert_eq!(builder.write_records("data/Linux_2k.log", &Linux, &path).unwrap(), written);
+    let csv = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let rows: Vec<&str> = csv.lines().collect();
+    assert_eq!(rows[0], "LineId,Month,Date,Time,Level,Component,PID,Content,CensoredContent,Tokens");
+    assert_eq!(rows.len(), written + 1);
+}
@@ -0,0 +1,231 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{TcpListener, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+
+use axum::body::Bytes;
+use axum::extract::{Query, State as Shared};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::Router;
+use serde::{Deserialize, Serialize};
+
+use crate::LogFormat;
+use crate::packages::error::ParserError;
+use crate::packages::http::{self, blocking, error};
+use crate::packages::parser::{decode_line, DictionaryBuilder, DictionaryResult};
+use crate::packages::tokenizer::{Normalize, RegexTokenizer};
+
+/// A dictionary that grows with the lines POSTed to it, over HTTP:
+///
+/// - `POST /ingest` with log lines as the body adds them, as `update_lines` would, and answers
+///   `{"lines": <lines in the body>, "tokens": <distinct tokens now>}`.
+/// - `GET /dict` answers the whole `DictionaryResult` as JSON; with `?n=3` just the triples, as
+///   `[n-gram, count]` pairs most frequent first, and with `&top=20` only the first 20 of those.
+/// - `GET /score?line=...` answers `{"score": ...}`, how unusual the line is (see `score_line`);
+///   `POST /score` scores every line of the body and answers a list.
+pub struct Server {
+    builder: DictionaryBuilder,
+    lf: LogFormat,
+    tokenizer: Normalize<RegexTokenizer>,
+    state: Mutex<State>,
+}
+
+struct State {
+    result: DictionaryResult,
+    // the end of the lines ingested so far, for update_batch
+    previous: Vec<String>,
+}
+
+/// What `Server::ingest` added: how many lines, and how many distinct tokens there are now.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ingested {
+    pub lines: usize,
+    pub tokens: usize,
+}
+
+#[derive(Deserialize)]
+struct DictQuery {
+    n: Option<usize>,
+    top: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct ScoreQuery {
+    line: Option<String>,
+}
+
+fn json(body: Vec<u8>) -> Response {
+    ([(header::CONTENT_TYPE, "application/json")], body).into_response()
+}
+
+impl Server {
+    /// A server adding to `result` (e.g. a baseline built from a file, or an empty one) with the
+    /// options of `builder`.
+    pub fn new(builder: DictionaryBuilder, lf: &LogFormat, result: DictionaryResult) -> Result<Self, ParserError> {
+        let tokenizer = builder.normalized_tokenizer(lf)?;
+        Ok(Server { builder, lf: lf.clone(), tokenizer, state: Mutex::new(State { result, previous: vec![] }) })
+    }
+
+    /// Adds the lines of `text` to the dictionaries, and returns how many there were along with
+    /// the distinct tokens after them.
+    pub fn ingest(&self, text: &[u8]) -> Result<Ingested, ParserError> {
+        let text = text.strip_suffix(b"\n").unwrap_or(text);
+        let lines: Vec<String> = if text.is_empty() { vec![] } else {
+            text.split(|b| *b == b'\n')
+                .filter_map(|line| decode_line(line, self.builder.is_lossy_utf8(), &mut 0).map(|line| line.into_owned()))
+                .collect()
+        };
+        let count = lines.len();
+        let mut state = self.state.lock().unwrap();
+        let State { result, previous } = &mut *state;
+        self.builder.update_batch(result, previous, lines, &self.lf)?;
+        Ok(Ingested { lines: count, tokens: result.all_token_list.len() })
+    }
+
+    /// The dictionaries so far.
+    pub fn result(&self) -> DictionaryResult {
+        self.state.lock().unwrap().result.clone()
+    }
+
+    /// How unusual `line` is next to the dictionaries so far.
+    pub fn score(&self, line: &str) -> f64 {
+        self.state.lock().unwrap().result.score_line(line, &self.tokenizer, self.builder.separator_str())
+    }
+
+    // the n-grams of size n, most frequent first, with the dictionary copied under the lock and
+    // sorted after it
+    fn most_frequent(&self, n: usize, top: usize) -> Option<Vec<(String, u64)>> {
+        let dict = self.state.lock().unwrap().result.ngram_dict(n).cloned()?;
+        let snapshot = DictionaryResult { ngram_dicts: BTreeMap::from([(n, dict)]), ..Default::default() };
+        Some(snapshot.most_frequent(n).into_iter().take(top).map(|(key, count)| (key.to_string(), count)).collect())
+    }
+
+    fn router(self: Arc<Self>) -> Router {
+        Router::new()
+            .route("/ingest", post(ingest))
+            .route("/dict", get(dict))
+            .route("/score", get(score).post(score_lines))
+            .with_state(self)
+    }
+
+    /// Answers requests on `addr` until serving fails.
+    pub fn serve<A: ToSocketAddrs>(self: Arc<Self>, addr: A) -> io::Error {
+        match TcpListener::bind(addr) {
+            Ok(listener) => self.serve_on(listener),
+            Err(e) => e,
+        }
+    }
+
+    /// `serve` on a listener that's already bound, e.g. to port 0.
+    pub fn serve_on(self: Arc<Self>, listener: TcpListener) -> io::Error {
+        http::serve(listener, self.router())
+    }
+}
+
+async fn ingest(Shared(server): Shared<Arc<Server>>, body: Bytes) -> Response {
+    match blocking(move || server.ingest(&body)).await {
+        Ok(Ok(ingested)) => json(serde_json::to_vec(&ingested).unwrap()),
+        Ok(Err(e)) => error(StatusCode::INTERNAL_SERVER_ERROR, e),
+        Err(response) => response,
+    }
+}
+
+async fn dict(Shared(server): Shared<Arc<Server>>, Query(query): Query<DictQuery>) -> Response {
+    let Some(n) = query.n else {
+        // a copy taken under the lock, serialized after it
+        return match blocking(move || serde_json::to_vec(&server.result()).unwrap()).await {
+            Ok(body) => json(body),
+            Err(response) => response,
+        };
+    };
+    let top = query.top.unwrap_or(usize::MAX);
+    match blocking(move || server.most_frequent(n, top).map(|entries| serde_json::to_vec(&entries).unwrap())).await {
+        Ok(Some(body)) => json(body),
+        Ok(None) => error(StatusCode::NOT_FOUND, format!("no dictionary of {}-grams", n)),
+        Err(response) => response,
+    }
+}
+
+async fn score(Shared(server): Shared<Arc<Server>>, Query(query): Query<ScoreQuery>) -> Response {
+    let Some(line) = query.line else {
+        return error(StatusCode::BAD_REQUEST, "missing line parameter");
+    };
+    match blocking(move || server.score(&line)).await {
+        Ok(score) => json(serde_json::json!({ "score": score }).to_string().into_bytes()),
+        Err(response) => response,
+    }
+}
+
+async fn score_lines(Shared(server): Shared<Arc<Server>>, body: Bytes) -> Response {
+    let scores = blocking(move || String::from_utf8_lossy(&body).lines().map(|line| server.score(line)).collect::<Vec<f64>>());
+    match scores.await {
+        Ok(scores) => json(serde_json::to_vec(&scores).unwrap()),
+        Err(response) => response,
+    }
+}
+
+#[test]
+fn test_server() {
+    use std::io::{Read, Write};
+    use crate::LogFormat::HDFS;
+    let builder = DictionaryBuilder::new();
+    let server = Arc::new(Server::new(builder.clone(), &HDFS, DictionaryResult::default()).unwrap());
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn({
+        let server = server.clone();
+        move || server.serve_on(listener)
+    });
+    let request = |method: &str, target: &str, body: &[u8]| {
+        let mut stream = std::net::TcpStream::connect(addr).unwrap();
+        write!(stream, "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n", method, target, body.len()).unwrap();
+        stream.write_all(body).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.lines().next().unwrap().to_string(), body.to_string())
+    };
+
+    // two batches add up to the whole file, n-grams across them included
+    let log = std::fs::read("data/HDFS_2k.log").unwrap();
+    let half = log[..log.len() / 2].iter().rposition(|b| *b == b'\n').unwrap() + 1;
+    let (status, body) = request("POST", "/ingest", &log[..half]);
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    let first: Ingested = serde_json::from_str(&body).unwrap();
+    let (_, body) = request("POST", "/ingest", &log[half..]);
+    let second: Ingested = serde_json::from_str(&body).unwrap();
+    let expected = builder.parse_raw("data/HDFS_2k.log".to_string(), &HDFS);
+    assert_eq!(server.result(), expected);
+    assert_eq!(first.lines, log[..half].iter().filter(|b| **b == b'\n').count());
+    assert_eq!(second.tokens, expected.all_token_list.len());
+
+    let (_, body) = request("GET", "/dict", b"");
+    assert_eq!(serde_json::from_str::<DictionaryResult>(&body).unwrap(), expected);
+    let (_, body) = request("GET", "/dict?n=2&top=3", b"");
+    let top: Vec<(String, u64)> = serde_json::from_str(&body).unwrap();
+    assert_eq!(top.iter().map(|(k, c)| (k.as_str(), *c)).collect::<Vec<_>>(), expected.most_frequent(2)[..3]);
+    assert_eq!(request("GET", "/dict?n=9", b"").0, "HTTP/1.1 404 Not Found");
+    assert_eq!(request("GET", "/dict?n=two", b"").0, "HTTP/1.1 400 Bad Request");
+
+    let line = "081109 203615 148 INFO dfs.DataNode$PacketResponder: PacketResponder 1 for block blk_38865049064139660 terminating";
+    let (_, body) = request("GET", &format!("/score?line={}", line.replace(' ', "+").replace('$', "%24")), b"");
+    let score: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(score["score"].as_f64().unwrap(), server.score(line));
+    let (_, body) = request("POST", "/score", format!("{}\n081109 203615 148 INFO dfs.DataNode: nothing this node ever said\n", line).as_bytes());
+    let scores: Vec<f64> = serde_json::from_str(&body).unwrap();
+    assert!(scores[0] < scores[1]);
+    assert_eq!(request("DELETE", "/dict", b"").0, "HTTP/1.1 405 Method Not Allowed");
+    assert_eq!(request("GET", "/score", b"").0, "HTTP/1.1 400 Bad Request");
+    assert_eq!(request("GET", "/nothing", b"").0, "HTTP/1.1 404 Not Found");
+
+    // a chunked upload, as curl sends from a pipe
+    let mut stream = std::net::TcpStream::connect(addr).unwrap();
+    let chunk = format!("{}\n", line);
+    write!(stream, "POST /ingest HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n{}\r\n0\r\n\r\n", chunk.len(), chunk).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let ingested: Ingested = serde_json::from_str(response.split_once("\r\n\r\n").unwrap().1).unwrap();
+    assert_eq!(ingested.lines, 1);
+}
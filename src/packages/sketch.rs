@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// The approximate counting of the count-min backend: a count-min sketch per dictionary, which never
+// undercounts and overcounts by the collisions in its least crowded row, and the candidates for the
+// heavy hitters, the n-grams that come out of the sketch with the highest estimates.
+
+/// A count-min sketch of `depth` rows of `width` counters, shared by all the workers of a run.
+pub(crate) struct CountMin {
+    width: usize,
+    depth: usize,
+    counters: Vec<AtomicU64>,
+}
+
+impl CountMin {
+    pub fn new(width: usize, depth: usize) -> Self {
+        assert!(width > 0 && depth > 0, "a count-min sketch needs at least one counter per row and one row");
+        CountMin { width, depth, counters: (0..width * depth).map(|_| AtomicU64::new(0)).collect() }
+    }
+
+    // the counter of each row for an item that hashes to hash, from two halves of it (Kirsch and
+    // Mitzenmacher) rather than depth hashes
+    fn slots(&self, hash: u64) -> impl Iterator<Item = usize> + '_ {
+        let (h1, h2) = (hash as u32 as usize, ((hash >> 32) | 1) as usize);
+        (0..self.depth).map(move |row| row * self.width + h1.wrapping_add(row.wrapping_mul(h2)) % self.width)
+    }
+
+    /// Counts `count` more of the item that hashes to `hash`, and returns its estimate with them.
+    pub fn add(&self, hash: u64, count: u64) -> u64 {
+        self.slots(hash).map(|slot| self.counters[slot].fetch_add(count, Ordering::Relaxed) + count).min().unwrap()
+    }
+
+    pub fn estimate(&self, hash: u64) -> u64 {
+        self.slots(hash).map(|slot| self.counters[slot].load(Ordering::Relaxed)).min().unwrap()
+    }
+}
+
+/// The items worth asking the sketch about at the end: every one whose estimate was among the
+/// `capacity` highest at some point. Holds up to twice that many, then drops the lower half.
+pub(crate) struct HeavyHitters<K> {
+    capacity: usize,
+    entries: HashMap<K, u64>,
+    // what an item not already in entries needs to beat to get in
+    floor: u64,
+}
+
+impl<K: Hash + Eq> HeavyHitters<K> {
+    pub fn new(capacity: usize) -> Self {
+        HeavyHitters { capacity, entries: HashMap::new(), floor: 0 }
+    }
+
+    /// Offers `key` with its current `estimate`.
+    pub fn offer(&mut self, key: K, estimate: u64) {
+        if let Some(count) = self.entries.get_mut(&key) {
+            *count = estimate;
+            return;
+        }
+        if estimate <= self.floor || self.capacity == 0 {
+            return;
+        }
+        self.entries.insert(key, estimate);
+        if self.entries.len() >= 2 * self.capacity {
+            let mut counts: Vec<u64> = self.entries.values().copied().collect();
+            let (_, nth, _) = counts.select_nth_unstable_by(self.capacity - 1, |a, b| b.cmp(a));
+            self.floor = *nth;
+            let floor = self.floor;
+            self.entries.retain(|_, count| *count >= floor);
+        }
+    }
+
+    pub fn into_keys(self) -> impl Iterator<Item = K> {
+        self.entries.into_keys()
+    }
+}
+
+#[test]
+fn test_count_min() {
+    let sketch = CountMin::new(64, 4);
+    for hash in 0..1000u64 {
+        sketch.add(hash.wrapping_mul(0x9e37_79b9_7f4a_7c15), 1);
+    }
+    let heavy = 7u64.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    assert!(sketch.add(heavy, 500) >= 500);
+    // 1000 singles over 64 counters a row: about 16 of them collide with it in each
+    assert!(sketch.estimate(heavy) < 500 + 60, "{}", sketch.estimate(heavy));
+
+    let mut hitters = HeavyHitters::new(2);
+    for (key, estimate) in [("a", 5), ("b", 1), ("c", 9), ("d", 3), ("b", 2), ("e", 1)] {
+        hitters.offer(key, estimate);
+    }
+    let mut keys: Vec<&str> = hitters.into_keys().collect();
+    keys.sort();
+    assert_eq!(keys, ["a", "c"]);
+}
@@ -0,0 +1,282 @@
+use std::borrow::Cow;
+use std::io::{self, BufRead, BufReader, Read};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::LogFormat;
+use crate::packages::error::ParserError;
+use crate::packages::parser::{decode_line, DictionaryBuilder, DictionaryResult};
+
+/// Where `consume` gets its lines from: any stream of log lines that arrive in batches, like the
+/// messages of a Kafka topic. A queue's consumer fits by polling up to a batch's worth of messages
+/// in `next_batch` and committing their offsets in `commit`, so that a restart picks up after the
+/// last batch that made it into the dictionaries; the `kafka` feature's `kafka::KafkaSource` does
+/// that for Kafka.
+pub trait LineSource {
+    /// The next batch of lines, waiting for one if need be; `None` once there won't be any more.
+    fn next_batch(&mut self) -> io::Result<Option<Vec<String>>>;
+
+    /// Called once the batch `next_batch` last returned is in the result. Does nothing by default.
+    fn commit(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The lines of a reader (a pipe from `kcat`, say) in batches of `batch_lines`, or fewer at the
+/// end. A batch waits until it's full, so a slow stream gets counted in bursts. Bytes that aren't
+/// UTF-8 become U+FFFD.
+pub struct ReaderSource<R> {
+    reader: R,
+    batch_lines: usize,
+}
+
+impl<R: BufRead> ReaderSource<R> {
+    pub fn new(reader: R, batch_lines: usize) -> Self {
+        assert!(batch_lines > 0, "batches need at least one line");
+        ReaderSource { reader, batch_lines }
+    }
+}
+
+impl<R: BufRead> LineSource for ReaderSource<R> {
+    fn next_batch(&mut self) -> io::Result<Option<Vec<String>>> {
+        let mut lines = Vec::with_capacity(self.batch_lines);
+        let mut buf = vec![];
+        while lines.len() < self.batch_lines {
+            buf.clear();
+            if self.reader.read_until(b'\n', &mut buf)? == 0 {
+                break;
+            }
+            let line = buf.strip_suffix(b"\n").unwrap_or(&buf);
+            lines.push(decode_line(line, true, &mut 0).map_or_else(String::new, Cow::into_owned));
+        }
+        Ok((!lines.is_empty()).then_some(lines))
+    }
+}
+
+/// A syslog receiver: listens on UDP and/or TCP ports, and hands the messages it gets out as lines,
+/// in batches of up to `batch_lines` that it doesn't hold back longer than `max_wait` after the
+/// first one arrives. A UDP datagram is one message; on TCP, messages are newline-terminated or
+/// octet-counted (`<length> <message>`, RFC 6587), whichever each one starts out as. With
+/// `strip_pri` the `<PRI>` a message starts with goes, as it does when rsyslog writes RFC 3164 to a
+/// file (which is what `LogFormat::Syslog3164` expects); `Syslog5424` wants it kept.
+pub struct SyslogListener {
+    tx: Sender<String>,
+    rx: Receiver<String>,
+    batch_lines: usize,
+    max_wait: Duration,
+    strip_pri: bool,
+    udp_addr: Option<SocketAddr>,
+    tcp_addr: Option<SocketAddr>,
+}
+
+// a syslog message is at most this long over UDP, and octet-counted TCP frames are held to it too
+const MAX_MESSAGE: usize = 64 << 10;
+
+fn strip_pri(message: &str) -> &str {
+    let Some(rest) = message.strip_prefix('<') else { return message };
+    match rest.find('>') {
+        Some(end) if (1..=3).contains(&end) && rest[..end].bytes().all(|b| b.is_ascii_digit()) => &rest[end + 1..],
+        _ => message,
+    }
+}
+
+// a message as a line, without the PRI if strip
+fn message_line(bytes: &[u8], strip: bool) -> String {
+    let message = String::from_utf8_lossy(bytes);
+    let message = message.trim_end_matches(['\r', '\n']);
+    (if strip { strip_pri(message) } else { message }).to_string()
+}
+
+// the messages of one TCP connection, until it closes or sends garbage
+fn read_tcp_messages(stream: TcpStream, strip: bool, tx: &Sender<String>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut buf = vec![];
+    loop {
+        buf.clear();
+        let first = match reader.fill_buf()? {
+            [] => return Ok(()),
+            buffered => buffered[0],
+        };
+        if first.is_ascii_digit() {
+            reader.read_until(b' ', &mut buf)?;
+            let length: usize = std::str::from_utf8(&buf).ok().and_then(|l| l.trim_end().parse().ok())
+                .filter(|l| *l <= MAX_MESSAGE)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad octet count"))?;
+            buf.resize(length, 0);
+            reader.read_exact(&mut buf)?;
+        } else {
+            reader.read_until(b'\n', &mut buf)?;
+        }
+        if tx.send(message_line(&buf, strip)).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+impl SyslogListener {
+    /// A listener on no ports yet; add some with `udp` and `tcp`.
+    pub fn new(batch_lines: usize, max_wait: Duration) -> Self {
+        assert!(batch_lines > 0, "batches need at least one line");
+        let (tx, rx) = mpsc::channel();
+        SyslogListener { tx, rx, batch_lines, max_wait, strip_pri: false, udp_addr: None, tcp_addr: None }
+    }
+
+    /// Set before adding ports; the ones already listening keep what they had.
+    pub fn strip_pri(mut self, strip_pri: bool) -> Self {
+        self.strip_pri = strip_pri;
+        self
+    }
+
+    /// Also listens for datagrams on `addr`.
+    pub fn udp<A: ToSocketAddrs>(mut self, addr: A) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        self.udp_addr = Some(socket.local_addr()?);
+        let (tx, strip) = (self.tx.clone(), self.strip_pri);
+        thread::spawn(move || {
+            let mut buf = vec![0; MAX_MESSAGE];
+            while let Ok(len) = socket.recv(&mut buf) {
+                if tx.send(message_line(&buf[..len], strip)).is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(self)
+    }
+
+    /// Also accepts connections on `addr`, each read on a thread of its own.
+    pub fn tcp<A: ToSocketAddrs>(mut self, addr: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        self.tcp_addr = Some(listener.local_addr()?);
+        let (tx, strip) = (self.tx.clone(), self.strip_pri);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let tx = tx.clone();
+                // a sender that hangs up or garbles a frame only loses its own connection
+                thread::spawn(move || read_tcp_messages(stream, strip, &tx));
+            }
+        });
+        Ok(self)
+    }
+
+    /// Where it listens for datagrams, e.g. to find the port when bound to port 0.
+    pub fn udp_addr(&self) -> Option<SocketAddr> {
+        self.udp_addr
+    }
+
+    pub fn tcp_addr(&self) -> Option<SocketAddr> {
+        self.tcp_addr
+    }
+}
+
+impl LineSource for SyslogListener {
+    /// Waits for as long as it takes for a first message.
+    fn next_batch(&mut self) -> io::Result<Option<Vec<String>>> {
+        let Ok(first) = self.rx.recv() else { return Ok(None) };
+        let deadline = Instant::now() + self.max_wait;
+        let mut lines = vec![first];
+        while lines.len() < self.batch_lines {
+            match self.rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(line) => lines.push(line),
+                Err(_) => break,
+            }
+        }
+        Ok(Some(lines))
+    }
+}
+
+impl DictionaryBuilder {
+    /// Adds each batch of `source` to `result` in turn, as `update_batch` does, commits it, then
+    /// calls `on_update` with the result and the batch. Stops when the source runs out or
+    /// `on_update` returns false.
+    pub fn consume<S, F>(&self, result: &mut DictionaryResult, source: &mut S, lf: &LogFormat, mut on_update: F) -> Result<(), ParserError>
+    where S: LineSource + ?Sized, F: FnMut(&DictionaryResult, &[String]) -> bool, {
+        let mut previous = vec![];
+        while let Some(lines) = source.next_batch()? {
+            if lines.is_empty() {
+                continue;
+            }
+            self.update_batch(result, &mut previous, lines.clone(), lf)?;
+            source.commit()?;
+            if !on_update(result, &lines) {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_consume() {
+    use crate::LogFormat::HDFS;
+    use crate::packages::parser::read_log;
+
+    // batches from memory, counting the commits the way a queue would see them
+    struct Batches {
+        batches: std::vec::IntoIter<Vec<String>>,
+        commits: usize,
+    }
+    impl LineSource for Batches {
+        fn next_batch(&mut self) -> io::Result<Option<Vec<String>>> {
+            Ok(self.batches.next())
+        }
+        fn commit(&mut self) -> io::Result<()> {
+            self.commits += 1;
+            Ok(())
+        }
+    }
+
+    let builder = DictionaryBuilder::new().num_threads(Some(2));
+    let expected = builder.parse_raw("data/HDFS_2k.log".to_string(), &HDFS);
+    let lines = read_log("data/HDFS_2k.log").unwrap();
+    // a one-line batch too, which leaves the context reaching back into the one before it
+    let batches = vec![lines[..999].to_vec(), lines[999..1000].to_vec(), vec![], lines[1000..].to_vec()];
+    let mut source = Batches { batches: batches.into_iter(), commits: 0 };
+    let mut result = DictionaryResult::default();
+    builder.consume(&mut result, &mut source, &HDFS, |_, _| true).unwrap();
+    assert_eq!(result, expected);
+    assert_eq!(source.commits, 3);
+
+    let mut source = ReaderSource::new(io::BufReader::new(std::fs::File::open("data/HDFS_2k.log").unwrap()), 700);
+    let (mut result, mut batches) = (DictionaryResult::default(), vec![]);
+    builder.consume(&mut result, &mut source, &HDFS, |_, lines| {
+        batches.push(lines.len());
+        true
+    }).unwrap();
+    assert_eq!(result, expected);
+    assert_eq!(batches, [700, 700, 600]);
+}
+
+#[test]
+fn test_syslog_listener() {
+    use std::io::Write;
+    use crate::LogFormat::Syslog3164;
+    assert_eq!(strip_pri("<34>Oct 11 22:14:15 host su: failed"), "Oct 11 22:14:15 host su: failed");
+    assert_eq!(strip_pri("<no pri"), "<no pri");
+
+    let mut listener = SyslogListener::new(3, Duration::from_secs(10)).strip_pri(true)
+        .udp("127.0.0.1:0").unwrap()
+        .tcp("127.0.0.1:0").unwrap();
+    let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+    udp.send_to(b"<34>Oct 11 22:14:15 mymachine su: 'su root' failed for lonvick on /dev/pts/8\n", listener.udp_addr().unwrap()).unwrap();
+    let mut tcp = TcpStream::connect(listener.tcp_addr().unwrap()).unwrap();
+    let framed = "<13>Oct 11 22:14:16 mymachine sshd[4721]: Accepted publickey for alice";
+    write!(tcp, "<13>Oct 11 22:14:16 mymachine cron[19]: (root) CMD (run-parts /etc/cron.hourly)\r\n{} {}", framed.len(), framed).unwrap();
+    drop(tcp);
+
+    let builder = DictionaryBuilder::new();
+    let mut result = DictionaryResult::default();
+    let mut batch = vec![];
+    builder.consume(&mut result, &mut listener, &Syslog3164, |_, lines| {
+        batch = lines.to_vec();
+        false
+    }).unwrap();
+    batch.sort();
+    assert_eq!(batch, [
+        "Oct 11 22:14:15 mymachine su: 'su root' failed for lonvick on /dev/pts/8",
+        "Oct 11 22:14:16 mymachine cron[19]: (root) CMD (run-parts /etc/cron.hourly)",
+        "Oct 11 22:14:16 mymachine sshd[4721]: Accepted publickey for alice",
+    ]);
+    assert!(result.all_token_list.iter().any(|t| t == "publickey"));
+}
@@ -0,0 +1,121 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// The runs of the external backend: counts a worker spilled to disk, sorted by dictionary and then
+// n-gram, so that any number of them can be merged a line at a time. An entry is the dictionary key
+// as a u64, the n-gram's length as a u32 and its token ids as u32s, then the count as a u64, all
+// little-endian.
+
+/// The dictionary key, an n-gram's token ids and its count.
+pub(crate) type Entry = (usize, Vec<u32>, u64);
+
+// numbers the runs of every builder in this process
+static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+/// A run on disk, deleted when dropped.
+#[derive(Debug)]
+pub(crate) struct Run {
+    path: PathBuf,
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Sorts `entries` and writes them to a new run in `dir`.
+pub(crate) fn write_run(dir: &Path, mut entries: Vec<Entry>) -> io::Result<Run> {
+    entries.sort_unstable_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+    let run = Run { path: dir.join(format!("logram-{}-{}.run", std::process::id(), RUNS.fetch_add(1, Ordering::Relaxed))) };
+    let mut w = BufWriter::new(File::create(&run.path)?);
+    for (key, ngram, count) in entries {
+        w.write_all(&(key as u64).to_le_bytes())?;
+        w.write_all(&(ngram.len() as u32).to_le_bytes())?;
+        for id in ngram {
+            w.write_all(&id.to_le_bytes())?;
+        }
+        w.write_all(&count.to_le_bytes())?;
+    }
+    w.flush()?;
+    Ok(run)
+}
+
+struct RunReader {
+    r: BufReader<File>,
+}
+
+impl RunReader {
+    fn open(run: &Run) -> io::Result<Self> {
+        Ok(RunReader { r: BufReader::new(File::open(&run.path)?) })
+    }
+
+    fn next(&mut self) -> io::Result<Option<Entry>> {
+        let mut u64_buf = [0; 8];
+        match self.r.read_exact(&mut u64_buf) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?,
+        }
+        let key = u64::from_le_bytes(u64_buf) as usize;
+        let mut u32_buf = [0; 4];
+        self.r.read_exact(&mut u32_buf)?;
+        let mut ngram = Vec::with_capacity(u32::from_le_bytes(u32_buf) as usize);
+        for _ in 0..ngram.capacity() {
+            self.r.read_exact(&mut u32_buf)?;
+            ngram.push(u32::from_le_bytes(u32_buf));
+        }
+        self.r.read_exact(&mut u64_buf)?;
+        Ok(Some((key, ngram, u64::from_le_bytes(u64_buf))))
+    }
+}
+
+/// Merges `runs`, handing every distinct (key, n-gram) to `f` once, in order, with its counts
+/// added up. Holds one entry per run in memory.
+pub(crate) fn merge<F>(runs: &[Run], mut f: F) -> io::Result<()>
+where F: FnMut(Entry) -> io::Result<()>, {
+    let mut readers = runs.iter().map(RunReader::open).collect::<io::Result<Vec<_>>>()?;
+    let mut heap = BinaryHeap::with_capacity(readers.len());
+    for (i, reader) in readers.iter_mut().enumerate() {
+        if let Some((key, ngram, count)) = reader.next()? {
+            heap.push(Reverse((key, ngram, count, i)));
+        }
+    }
+    let mut current: Option<Entry> = None;
+    while let Some(Reverse((key, ngram, count, i))) = heap.pop() {
+        if let Some((key, ngram, count)) = readers[i].next()? {
+            heap.push(Reverse((key, ngram, count, i)));
+        }
+        match &mut current {
+            Some(entry) if (entry.0, &entry.1) == (key, &ngram) => entry.2 += count,
+            _ => {
+                if let Some(entry) = current.replace((key, ngram, count)) {
+                    f(entry)?;
+                }
+            },
+        }
+    }
+    current.map_or(Ok(()), f)
+}
+
+#[test]
+fn test_merge_runs() {
+    let dir = std::env::temp_dir();
+    let runs = vec![
+        write_run(&dir, vec![(3, vec![1, 2, 3], 1), (2, vec![4, 1], 2), (2, vec![1, 2], 5)]).unwrap(),
+        write_run(&dir, vec![]).unwrap(),
+        write_run(&dir, vec![(2, vec![1, 2], 1), (3, vec![1, 2, 3], 4), (2, vec![0, 9], 1)]).unwrap(),
+    ];
+    let mut merged = vec![];
+    merge(&runs, |entry| {
+        merged.push(entry);
+        Ok(())
+    }).unwrap();
+    assert_eq!(merged, [(2, vec![0, 9], 1), (2, vec![1, 2], 6), (2, vec![4, 1], 2), (3, vec![1, 2, 3], 5)]);
+    let path = runs[0].path.clone();
+    drop(runs);
+    assert!(!path.exists());
+}
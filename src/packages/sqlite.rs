@@ -0,0 +1,170 @@
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::hash::BuildHasher;
+use std::io;
+use std::path::Path;
+use std::ptr;
+
+use crate::packages::parser::{ngram_name, DictionaryResult};
+
+// just the part of the C API that writing a result needs, linked against the system libsqlite3
+#[allow(non_camel_case_types)]
+type sqlite3 = c_void;
+#[allow(non_camel_case_types)]
+type sqlite3_stmt = c_void;
+
+const SQLITE_OK: c_int = 0;
+const SQLITE_DONE: c_int = 101;
+// SQLITE_TRANSIENT: sqlite copies the bound text before the call returns
+const SQLITE_TRANSIENT: isize = -1;
+
+#[link(name = "sqlite3")]
+extern "C" {
+    fn sqlite3_open(filename: *const c_char, db: *mut *mut sqlite3) -> c_int;
+    fn sqlite3_close(db: *mut sqlite3) -> c_int;
+    fn sqlite3_errmsg(db: *mut sqlite3) -> *const c_char;
+    fn sqlite3_exec(db: *mut sqlite3, sql: *const c_char, callback: *const c_void, arg: *mut c_void, errmsg: *mut *mut c_char) -> c_int;
+    fn sqlite3_prepare_v2(db: *mut sqlite3, sql: *const c_char, len: c_int, stmt: *mut *mut sqlite3_stmt, tail: *mut *const c_char) -> c_int;
+    fn sqlite3_bind_text(stmt: *mut sqlite3_stmt, i: c_int, text: *const c_char, len: c_int, destructor: isize) -> c_int;
+    fn sqlite3_bind_int64(stmt: *mut sqlite3_stmt, i: c_int, value: i64) -> c_int;
+    fn sqlite3_step(stmt: *mut sqlite3_stmt) -> c_int;
+    fn sqlite3_reset(stmt: *mut sqlite3_stmt) -> c_int;
+    fn sqlite3_finalize(stmt: *mut sqlite3_stmt) -> c_int;
+    #[cfg(test)]
+    fn sqlite3_column_int64(stmt: *mut sqlite3_stmt, i: c_int) -> i64;
+}
+
+struct Connection {
+    db: *mut sqlite3,
+}
+
+struct Statement<'a> {
+    conn: &'a Connection,
+    stmt: *mut sqlite3_stmt,
+}
+
+impl Connection {
+    fn open(path: &Path) -> io::Result<Self> {
+        let path = CString::new(path.to_string_lossy().as_bytes()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "NUL in the database path"))?;
+        let mut db = ptr::null_mut();
+        let rc = unsafe { sqlite3_open(path.as_ptr(), &mut db) };
+        // sqlite hands back a handle to report the error with even when the open fails
+        let conn = Connection { db };
+        conn.check(rc)?;
+        Ok(conn)
+    }
+
+    fn check(&self, rc: c_int) -> io::Result<()> {
+        if rc == SQLITE_OK || rc == SQLITE_DONE {
+            return Ok(());
+        }
+        let msg = if self.db.is_null() { "out of memory".into() } else { unsafe { CStr::from_ptr(sqlite3_errmsg(self.db)) }.to_string_lossy() };
+        Err(io::Error::other(format!("sqlite: {}", msg)))
+    }
+
+    fn exec(&self, sql: &str) -> io::Result<()> {
+        let sql = CString::new(sql).unwrap();
+        self.check(unsafe { sqlite3_exec(self.db, sql.as_ptr(), ptr::null(), ptr::null_mut(), ptr::null_mut()) })
+    }
+
+    fn prepare(&self, sql: &str) -> io::Result<Statement<'_>> {
+        let sql = CString::new(sql).unwrap();
+        let mut stmt = ptr::null_mut();
+        self.check(unsafe { sqlite3_prepare_v2(self.db, sql.as_ptr(), -1, &mut stmt, ptr::null_mut()) })?;
+        Ok(Statement { conn: self, stmt })
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        unsafe { sqlite3_close(self.db) };
+    }
+}
+
+impl Statement<'_> {
+    // binds text and then count, if any, to the ? placeholders and runs the statement once
+    fn insert(&mut self, text: &str, count: Option<u64>) -> io::Result<()> {
+        let len = c_int::try_from(text.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "n-gram too long for sqlite"))?;
+        unsafe {
+            self.conn.check(sqlite3_bind_text(self.stmt, 1, text.as_ptr() as *const c_char, len, SQLITE_TRANSIENT))?;
+            if let Some(count) = count {
+                self.conn.check(sqlite3_bind_int64(self.stmt, 2, count as i64))?;
+            }
+            let rc = sqlite3_step(self.stmt);
+            sqlite3_reset(self.stmt);
+            self.conn.check(rc)
+        }
+    }
+}
+
+#[cfg(test)]
+impl Connection {
+    // the first column of the first row
+    fn query_i64(&self, sql: &str) -> io::Result<i64> {
+        let stmt = self.prepare(sql)?;
+        if unsafe { sqlite3_step(stmt.stmt) } != 100 {
+            return Err(io::Error::other(format!("no rows from {}", sql)));
+        }
+        Ok(unsafe { sqlite3_column_int64(stmt.stmt, 0) })
+    }
+}
+
+impl Drop for Statement<'_> {
+    fn drop(&mut self) {
+        unsafe { sqlite3_finalize(self.stmt) };
+    }
+}
+
+// doubles, triples, then "4-grams" and so on
+fn table_name(n: usize) -> String {
+    format!("\"{}s\"", ngram_name(n))
+}
+
+impl<S: BuildHasher + Default> DictionaryResult<S> {
+    /// Writes the result to the SQLite database at `path`, replacing the tables it writes: one per
+    /// n-gram size (`doubles`, `triples`, `"4-grams"`...) of `(ngram TEXT PRIMARY KEY, count INTEGER)`
+    /// rows, indexed by count, and `tokens (token TEXT PRIMARY KEY)`. It all goes in one transaction,
+    /// so the database never holds half a result. Needs the sqlite feature and the system libsqlite3.
+    pub fn write_sqlite<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let conn = Connection::open(path.as_ref())?;
+        conn.exec("BEGIN")?;
+        for (n, dict) in &self.ngram_dicts {
+            let table = table_name(*n);
+            conn.exec(&format!("DROP TABLE IF EXISTS {table}; CREATE TABLE {table} (ngram TEXT PRIMARY KEY, count INTEGER NOT NULL) WITHOUT ROWID"))?;
+            let mut insert = conn.prepare(&format!("INSERT INTO {} VALUES (?, ?)", table))?;
+            for (key, count) in dict {
+                insert.insert(key, Some(*count))?;
+            }
+            conn.exec(&format!("CREATE INDEX \"{}s_by_count\" ON {} (count)", ngram_name(*n), table))?;
+        }
+        conn.exec("DROP TABLE IF EXISTS tokens; CREATE TABLE tokens (token TEXT PRIMARY KEY) WITHOUT ROWID")?;
+        let mut insert = conn.prepare("INSERT INTO tokens VALUES (?)")?;
+        for token in &self.all_token_list {
+            insert.insert(token, None)?;
+        }
+        drop(insert);
+        conn.exec("COMMIT")
+    }
+}
+
+#[test]
+fn test_write_sqlite() {
+    use crate::LogFormat::HDFS;
+    use crate::packages::parser::DictionaryBuilder;
+    let result = DictionaryBuilder::new().ngram_sizes(&[2, 3, 4]).parse_raw_conc("data/HDFS_2k.log".to_string(), &HDFS);
+    let path = std::env::temp_dir().join(format!("logram-test-{}.sqlite", std::process::id()));
+    result.write_sqlite(&path).unwrap();
+    // writing again replaces the tables instead of failing on the duplicate keys
+    result.write_sqlite(&path).unwrap();
+
+    let conn = Connection::open(&path).unwrap();
+    let counts = ["SELECT COUNT(*) FROM doubles", "SELECT COUNT(*) FROM tokens", "SELECT SUM(count) FROM \"4-grams\""].map(|sql| conn.query_i64(sql));
+    let (key, count) = result.most_frequent(3)[0];
+    let top = conn.query_i64(&format!("SELECT count FROM triples WHERE ngram = '{}'", key.replace('\'', "''")));
+    drop(conn);
+    std::fs::remove_file(&path).unwrap();
+    let [doubles, tokens, fourgrams] = counts.map(Result::unwrap);
+    assert_eq!(doubles as usize, result.ngram_dicts[&2].len());
+    assert_eq!(tokens as usize, result.all_token_list.len());
+    assert_eq!(fourgrams as u64, result.ngram_dicts[&4].values().sum::<u64>());
+    assert_eq!(top.unwrap() as u64, count);
+}
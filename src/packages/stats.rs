@@ -0,0 +1,154 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::BuildHasher;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::LogFormat;
+use crate::packages::error::ParserError;
+use crate::packages::parser::{split_ngram, DictionaryBuilder, DictionaryResult};
+use crate::packages::tokenizer::Tokenizer;
+
+// The summary numbers of a corpus that researchers report for every log they look at: how its
+// tokens are distributed, how many n-grams come up how often, and how fast the vocabulary grows.
+
+/// Summary numbers for the dictionaries of a corpus; see `DictionaryResult::stats`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DictStats {
+    /// The distinct tokens.
+    pub vocabulary: usize,
+    /// How many times each token occurred, most frequent first, so the token of rank r occurred
+    /// `token_frequencies[r - 1]` times.
+    pub token_frequencies: Vec<u64>,
+    /// The `s` of Zipf's law, frequency ∝ 1 / rank^s, fitted by least squares on the log-log plot
+    /// of `token_frequencies`; about 1 for natural language. 0 with fewer than two tokens.
+    pub zipf_exponent: f64,
+    /// For each n-gram size, how many distinct n-grams have each count: `{1: 500, 2: 80, ...}` for
+    /// 500 seen once, 80 twice...
+    pub count_histograms: BTreeMap<usize, BTreeMap<u64, usize>>,
+    /// The Shannon entropy, in bits, of which double an occurrence of a double is.
+    pub double_entropy: f64,
+}
+
+// how many times each token occurred: the 1-grams if there are any, or else the doubles each token
+// starts, which misses the last token of a line and counts the ones a line's context borrows twice
+fn token_counts<S: BuildHasher + Default>(result: &DictionaryResult<S>, separator: &str) -> HashMap<String, u64> {
+    let (dict, first_only) = match result.ngram_dict(1) {
+        Some(unigrams) => (unigrams, false),
+        None => match result.ngram_dict(2) {
+            Some(doubles) => (doubles, true),
+            None => return HashMap::new(),
+        },
+    };
+    let mut counts: HashMap<String, u64> = HashMap::new();
+    for (key, count) in dict {
+        let mut tokens = split_ngram(key, separator);
+        if first_only {
+            tokens.truncate(1);
+        }
+        for token in tokens {
+            *counts.entry(token).or_default() += count;
+        }
+    }
+    counts
+}
+
+// minus the slope of ln(frequency) over ln(rank)
+fn zipf_exponent(frequencies: &[u64]) -> f64 {
+    if frequencies.len() < 2 {
+        return 0.0;
+    }
+    let points: Vec<(f64, f64)> = frequencies.iter().enumerate().map(|(i, f)| (((i + 1) as f64).ln(), (*f as f64).ln())).collect();
+    let n = points.len() as f64;
+    let (mean_x, mean_y) = (points.iter().map(|p| p.0).sum::<f64>() / n, points.iter().map(|p| p.1).sum::<f64>() / n);
+    let covariance: f64 = points.iter().map(|(x, y)| (x - mean_x) * (y - mean_y)).sum();
+    let variance: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    -covariance / variance
+}
+
+fn entropy<'a>(counts: impl Iterator<Item = &'a u64> + Clone) -> f64 {
+    let total = counts.clone().sum::<u64>() as f64;
+    counts.filter(|c| **c > 0).map(|c| { let p = *c as f64 / total; -p * p.log2() }).sum()
+}
+
+impl<S: BuildHasher + Default> DictionaryResult<S> {
+    /// The summary numbers of these dictionaries, for n-grams joined with `separator`. The token
+    /// frequencies come from the 1-grams when they were built (`ngram_sizes(&[1, 2, 3])`); from the
+    /// doubles otherwise, which is close but not exact.
+    pub fn stats(&self, separator: &str) -> DictStats {
+        let mut token_frequencies: Vec<u64> = token_counts(self, separator).into_values().collect();
+        token_frequencies.sort_unstable_by(|a, b| b.cmp(a));
+        let count_histograms = self.ngram_dicts.iter().map(|(n, dict)| {
+            let mut histogram = BTreeMap::new();
+            for count in dict.values() {
+                *histogram.entry(*count).or_default() += 1;
+            }
+            (*n, histogram)
+        }).collect();
+        DictStats {
+            vocabulary: self.all_token_list.len(),
+            zipf_exponent: zipf_exponent(&token_frequencies),
+            token_frequencies,
+            count_histograms,
+            double_entropy: self.ngram_dict(2).map_or(0.0, |doubles| entropy(doubles.values())),
+        }
+    }
+}
+
+impl DictionaryBuilder {
+    /// Panics on any `ParserError`; see `vocabulary_growth_checked`.
+    pub fn vocabulary_growth(&self, raw_fn: String, lf: &LogFormat, every: usize) -> Vec<(usize, usize)> {
+        self.vocabulary_growth_checked(raw_fn, lf, every).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// The distinct tokens after every `every` lines of `raw_fn` (and after the last one), as
+    /// `(lines, tokens)`, for a plot of the vocabulary against the lines processed. It tokenizes the
+    /// lines a run would count, in order, without counting any n-grams.
+    pub fn vocabulary_growth_checked(&self, raw_fn: String, lf: &LogFormat, every: usize) -> Result<Vec<(usize, usize)>, ParserError> {
+        assert!(every > 0, "the vocabulary needs measuring at least every line");
+        let lines = self.picked_lines(Path::new(&raw_fn))?;
+        let tokenizer = self.normalized_tokenizer(lf)?;
+        let mut vocabulary = HashSet::new();
+        let mut growth = vec![];
+        for (i, line) in lines.iter().enumerate() {
+            for token in tokenizer.tokenize(line) {
+                if !vocabulary.contains(token.as_ref()) {
+                    vocabulary.insert(token.into_owned());
+                }
+            }
+            if (i + 1) % every == 0 || i + 1 == lines.len() {
+                growth.push((i + 1, vocabulary.len()));
+            }
+        }
+        Ok(growth)
+    }
+}
+
+#[test]
+fn test_stats() {
+    use crate::LogFormat::HDFS;
+    assert!((zipf_exponent(&[1000, 500, 333, 250, 200]) - 1.0).abs() < 0.01);
+    assert_eq!(entropy([4, 4, 4, 4].iter()), 2.0);
+
+    let builder = DictionaryBuilder::new().ngram_sizes(&[1, 2, 3]);
+    let result = builder.parse_raw("data/HDFS_2k.log".to_string(), &HDFS);
+    let stats = result.stats("^");
+    assert_eq!(stats.vocabulary, result.all_token_list.len());
+    assert_eq!(stats.token_frequencies.len(), result.ngram_dicts[&1].len());
+    assert_eq!(stats.token_frequencies.iter().sum::<u64>(), result.ngram_dicts[&1].values().sum::<u64>());
+    assert!(stats.token_frequencies.windows(2).all(|w| w[0] >= w[1]));
+    for (n, histogram) in &stats.count_histograms {
+        assert_eq!(histogram.values().sum::<usize>(), result.ngram_dicts[n].len());
+    }
+    assert!(stats.double_entropy > 0.0 && stats.double_entropy <= (result.ngram_dicts[&2].len() as f64).log2());
+
+    // the doubles are close to the 1-grams
+    let from_doubles = DictionaryBuilder::new().parse_raw("data/HDFS_2k.log".to_string(), &HDFS).stats("^");
+    assert!((from_doubles.double_entropy - stats.double_entropy).abs() < 1e-9);
+    assert!((from_doubles.zipf_exponent - stats.zipf_exponent).abs() < 0.1, "{} {}", from_doubles.zipf_exponent, stats.zipf_exponent);
+
+    let growth = builder.vocabulary_growth("data/HDFS_2k.log".to_string(), &HDFS, 500);
+    assert_eq!(growth.iter().map(|g| g.0).collect::<Vec<_>>(), [500, 1000, 1500, 2000]);
+    assert!(growth.windows(2).all(|w| w[0].1 <= w[1].1));
+    assert_eq!(growth.last().unwrap().1, stats.vocabulary);
+}
@@ -0,0 +1,339 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::packages::error::ParserError;
+use crate::packages::parser::{csv_field, join_ngram, read_log, split_ngram, ContextWindow, DictionaryResult};
+use crate::packages::tokenizer::Tokenizer;
+
+/// What a dynamic token turns into in a template.
+pub const WILDCARD: &str = "<*>";
+
+/// Which of `tokens` are dynamic, by the rule main's `--to-parse` check uses: the doubles taken from
+/// the triples rarer than `cutoff` are rare when they're rarer than `cutoff` too, and a token is
+/// dynamic when the doubles on both sides of it are rare. The first and last tokens only have one
+/// side, so pad `tokens` with those of the neighbouring lines to judge them.
+pub fn dynamic_tokens<S: AsRef<str>>(tokens: &[S], result: &DictionaryResult, separator: &str, cutoff: u64) -> Vec<bool> {
+    let empty = HashMap::new();
+    let doubles = result.ngram_dict(2).unwrap_or(&empty);
+    let triples = result.ngram_dict(3).unwrap_or(&empty);
+
+    let mut rare_doubles = HashSet::new();
+    for triple in tokens.windows(3) {
+        if triples.get(&join_ngram(triple, separator)).is_some_and(|count| *count < cutoff) {
+            for double in triple.windows(2) {
+                let key = join_ngram(double, separator);
+                // a double pruned by min_count was rarer than that
+                if doubles.get(&key).unwrap_or(&0) < &cutoff {
+                    rare_doubles.insert(key);
+                }
+            }
+        }
+    }
+
+    let mut dynamic = vec![false; tokens.len()];
+    for (i, triple) in tokens.windows(3).enumerate() {
+        dynamic[i + 1] = rare_doubles.contains(&join_ngram(&triple[..2], separator)) && rare_doubles.contains(&join_ngram(&triple[1..], separator));
+    }
+    dynamic
+}
+
+/// Whether a token is part of the fixed text of the messages it's in, or one of their variables.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TokenKind {
+    Static,
+    Dynamic,
+}
+
+/// How a token of the dictionaries looks overall; see `classify_tokens`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TokenClass {
+    pub token: String,
+    /// How many times it's in a double or a triple.
+    pub occurrences: u64,
+    /// The share of those in doubles and triples at least `cutoff` common, from 0 to 1.
+    pub score: f64,
+    /// `Static` for a score of at least 0.5.
+    pub kind: TokenKind,
+}
+
+/// Labels every token of `result` static or dynamic from the dictionaries alone, without the lines:
+/// a static token sits in the same place in every message it's in, so the doubles and triples
+/// around it recur, while the ones around a dynamic token are about as rare as its values. A token
+/// in no double or triple is static, one message's only token. Sorted like `all_token_list`.
+pub fn classify_tokens(result: &DictionaryResult, separator: &str, cutoff: u64) -> Vec<TokenClass> {
+    let mut counts: HashMap<String, (u64, u64)> = HashMap::new();
+    for n in [2, 3] {
+        for (key, count) in result.ngram_dict(n).into_iter().flatten() {
+            for token in split_ngram(key, separator) {
+                let (all, common) = counts.entry(token).or_default();
+                *all += count;
+                if *count >= cutoff {
+                    *common += count;
+                }
+            }
+        }
+    }
+    result.all_token_list.iter().map(|token| {
+        let (occurrences, common) = counts.get(token).copied().unwrap_or_default();
+        let score = if occurrences == 0 { 1.0 } else { common as f64 / occurrences as f64 };
+        let kind = if score >= 0.5 { TokenKind::Static } else { TokenKind::Dynamic };
+        TokenClass { token: token.clone(), occurrences, score, kind }
+    }).collect()
+}
+
+/// Writes `classes` to `path` as CSV, with `token`, `occurrences`, `score` and `kind` columns.
+pub fn write_token_classes<P: AsRef<Path>>(classes: &[TokenClass], path: P) -> io::Result<()> {
+    let mut w = io::BufWriter::new(File::create(path)?);
+    writeln!(w, "token,occurrences,score,kind")?;
+    for class in classes {
+        writeln!(w, "{},{},{:.4},{:?}", csv_field(&class.token), class.occurrences, class.score, class.kind)?;
+    }
+    w.flush()
+}
+
+/// A log template: the tokens its lines have in common, with `WILDCARD` where they differ.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Template {
+    pub tokens: Vec<String>,
+    pub count: u64,
+    /// The lines that have it, numbered from 0 in input order.
+    pub lines: Vec<usize>,
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.tokens.join(" "))
+    }
+}
+
+/// Which template a line has: its index in `Events::templates`. Shows as `E1` for the first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct EventId(pub u32);
+
+impl fmt::Display for EventId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "E{}", self.0 + 1)
+    }
+}
+
+/// The event sequence of some lines: the templates, most frequent first, and the event of every
+/// line, `None` for the lines without tokens.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Events {
+    pub templates: Vec<Template>,
+    pub lines: Vec<Option<EventId>>,
+}
+
+impl Events {
+    /// The template of line `i`.
+    pub fn template(&self, i: usize) -> Option<&Template> {
+        self.lines.get(i).copied().flatten().map(|id| &self.templates[id.0 as usize])
+    }
+
+    /// Writes the events to `path` as CSV in the layout of LogPAI's structured logs: `LineId` (from 1),
+    /// `EventId` and `EventTemplate` columns, a row per line with a template.
+    pub fn write_csv<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut w = io::BufWriter::new(File::create(path)?);
+        writeln!(w, "LineId,EventId,EventTemplate")?;
+        for (i, id) in self.lines.iter().enumerate() {
+            if let Some(id) = id {
+                writeln!(w, "{},{},{}", i + 1, id, csv_field(&self.templates[id.0 as usize].to_string()))?;
+            }
+        }
+        w.flush()
+    }
+}
+
+/// Turns lines into templates (`check pass; user <*>`) with the dictionaries built from them, the
+/// second stage of mining templates the Logram way. The separator, context and tokenizer must be
+/// the ones the dictionaries were built with; `cutoff` is how rare an n-gram has to be to make a
+/// token dynamic, 3 by default.
+#[derive(Clone, Debug)]
+pub struct TemplateMiner<'a> {
+    result: &'a DictionaryResult,
+    separator: String,
+    cutoff: u64,
+    context: ContextWindow,
+}
+
+impl<'a> TemplateMiner<'a> {
+    pub fn new(result: &'a DictionaryResult) -> Self {
+        TemplateMiner { result, separator: "^".to_string(), cutoff: 3, context: Default::default() }
+    }
+
+    pub fn separator(mut self, separator: &str) -> Self {
+        self.separator = separator.to_string();
+        self
+    }
+
+    pub fn cutoff(mut self, cutoff: u64) -> Self {
+        self.cutoff = cutoff;
+        self
+    }
+
+    pub fn context(mut self, context: ContextWindow) -> Self {
+        self.context = context;
+        self
+    }
+
+    /// The template of each line, or `None` for the lines without tokens (e.g. those that don't
+    /// match the format). A line is judged with the same context from its neighbours that it was
+    /// counted with.
+    pub fn line_templates<S: AsRef<str>>(&self, lines: &[S], tokenizer: &dyn Tokenizer) -> Vec<Option<Vec<String>>> {
+        let tokens: Vec<Vec<String>> = lines.iter()
+            .map(|line| tokenizer.tokenize(line.as_ref()).into_iter().map(|t| t.into_owned()).collect())
+            .collect();
+        // the triples are all a token is judged by, and they borrow at most two tokens a side
+        let pad = self.context.tokens.min(2);
+        (0..tokens.len()).map(|i| {
+            if tokens[i].is_empty() {
+                return None;
+            }
+            let prev: Vec<&String> = tokens[i.saturating_sub(self.context.lines)..i].iter().flatten().collect();
+            let next: Vec<&String> = tokens[i + 1..(i + 1 + self.context.lines).min(tokens.len())].iter().flatten().collect();
+            let before = &prev[prev.len() - pad.min(prev.len())..];
+            let after = &next[..pad.min(next.len())];
+            let padded: Vec<&String> = before.iter().copied().chain(&tokens[i]).chain(after.iter().copied()).collect();
+            let dynamic = dynamic_tokens(&padded, self.result, &self.separator, self.cutoff);
+            Some(tokens[i].iter().zip(&dynamic[before.len()..])
+                .map(|(token, dynamic)| if *dynamic { WILDCARD.to_string() } else { token.clone() })
+                .collect())
+        }).collect()
+    }
+
+    /// `classify_tokens` with this miner's separator and cutoff.
+    pub fn token_classes(&self) -> Vec<TokenClass> {
+        classify_tokens(self.result, &self.separator, self.cutoff)
+    }
+
+    /// The templates of `lines`, most frequent first (ties by their text).
+    pub fn templates<S: AsRef<str>>(&self, lines: &[S], tokenizer: &dyn Tokenizer) -> Vec<Template> {
+        self.events(lines, tokenizer).templates
+    }
+
+    /// `templates` for the lines of a file, read the way the `parse_raw_*` entry points read it.
+    pub fn templates_of_file<P: AsRef<Path>>(&self, raw_fn: P, tokenizer: &dyn Tokenizer) -> Result<Vec<Template>, ParserError> {
+        Ok(self.templates(&read_log(raw_fn)?, tokenizer))
+    }
+
+    /// The templates of `lines` along with the event of each line.
+    pub fn events<S: AsRef<str>>(&self, lines: &[S], tokenizer: &dyn Tokenizer) -> Events {
+        let line_templates = self.line_templates(lines, tokenizer);
+        let mut templates: HashMap<&[String], Template> = HashMap::new();
+        for (i, tokens) in line_templates.iter().enumerate() {
+            if let Some(tokens) = tokens {
+                let template = templates.entry(tokens).or_insert_with_key(|tokens| Template { tokens: tokens.to_vec(), count: 0, lines: vec![] });
+                template.count += 1;
+                template.lines.push(i);
+            }
+        }
+        let mut templates: Vec<Template> = templates.into_values().collect();
+        templates.sort_unstable_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tokens.cmp(&b.tokens)));
+
+        let mut lines = vec![None; line_templates.len()];
+        for (id, template) in templates.iter().enumerate() {
+            for i in &template.lines {
+                lines[*i] = Some(EventId(id as u32));
+            }
+        }
+        Events { templates, lines }
+    }
+
+    /// `events` for the lines of a file, read the way the `parse_raw_*` entry points read it.
+    pub fn events_of_file<P: AsRef<Path>>(&self, raw_fn: P, tokenizer: &dyn Tokenizer) -> Result<Events, ParserError> {
+        Ok(self.events(&read_log(raw_fn)?, tokenizer))
+    }
+}
+
+#[test]
+fn test_dynamic_tokens() {
+    let mut result = DictionaryResult::default();
+    let dict = |entries: &[(&str, u64)]| entries.iter().map(|(k, v)| (k.to_string(), *v)).collect();
+    result.ngram_dicts.insert(2, dict(&[("user^alice", 1), ("alice^logged", 1), ("logged^in", 50), ("user^bob", 1)]));
+    result.ngram_dicts.insert(3, dict(&[("user^alice^logged", 1), ("alice^logged^in", 1)]));
+    let tokens = ["user", "alice", "logged", "in"];
+    assert_eq!(dynamic_tokens(&tokens, &result, "^", 3), [false, true, false, false]);
+    // nothing is rare enough
+    assert_eq!(dynamic_tokens(&tokens, &result, "^", 1), [false; 4]);
+}
+
+#[test]
+fn test_classify_tokens() {
+    let mut result = DictionaryResult::default();
+    let dict = |entries: &[(&str, u64)]| entries.iter().map(|(k, v)| (k.to_string(), *v)).collect();
+    result.ngram_dicts.insert(2, dict(&[("user^alice", 1), ("alice^logged", 1), ("user^bob", 1), ("bob^logged", 1), ("logged^in", 50)]));
+    result.ngram_dicts.insert(3, dict(&[("user^alice^logged", 1), ("user^bob^logged", 1), ("alice^logged^in", 1), ("bob^logged^in", 1)]));
+    result.all_token_list = ["alice", "bob", "bye", "in", "logged", "user"].map(String::from).to_vec();
+    let classes = classify_tokens(&result, "^", 3);
+    let kinds: Vec<(&str, TokenKind)> = classes.iter().map(|c| (c.token.as_str(), c.kind)).collect();
+    assert_eq!(kinds, [("alice", TokenKind::Dynamic), ("bob", TokenKind::Dynamic), ("bye", TokenKind::Static),
+                       ("in", TokenKind::Static), ("logged", TokenKind::Static), ("user", TokenKind::Dynamic)]);
+    assert_eq!((classes[0].occurrences, classes[0].score), (4, 0.0));
+    // logged: in 56 n-grams, 50 of them "logged in"
+    assert_eq!(classes[4].occurrences, 56);
+
+    use crate::LogFormat::Linux;
+    use crate::packages::parser::DictionaryBuilder;
+    let result = DictionaryBuilder::new().parse_raw_conc("data/Linux_2k.log".to_string(), &Linux);
+    let classes = TemplateMiner::new(&result).token_classes();
+    let kind = |token: &str| classes.iter().find(|c| c.token == token).unwrap().kind;
+    assert_eq!(kind("ALERT"), TokenKind::Static);
+    // a memory address the kernel logged once
+    assert_eq!(kind("0000000000100000"), TokenKind::Dynamic);
+}
+
+#[test]
+fn test_templates() {
+    use crate::LogFormat::HDFS;
+    use crate::packages::parser::DictionaryBuilder;
+    use crate::packages::tokenizer::RegexTokenizer;
+    let result = DictionaryBuilder::new().parse_raw_conc("data/HDFS_2k.log".to_string(), &HDFS);
+    let tokenizer = RegexTokenizer::new(&HDFS).unwrap();
+    let templates = TemplateMiner::new(&result).templates_of_file("data/HDFS_2k.log", &tokenizer).unwrap();
+
+    // every line has exactly one template
+    let mut lines: Vec<usize> = templates.iter().flat_map(|t| t.lines.iter().copied()).collect();
+    lines.sort_unstable();
+    assert_eq!(lines, (0..2000).collect::<Vec<_>>());
+    assert!(templates.iter().all(|t| t.count as usize == t.lines.len()));
+    assert!(templates.windows(2).all(|w| w[0].count >= w[1].count));
+    // far fewer templates than lines, and some of them with wildcards
+    assert!(templates.len() < 200);
+    assert!(templates.iter().any(|t| t.tokens.iter().any(|token| token == WILDCARD)));
+}
+
+#[test]
+fn test_events() {
+    use crate::LogFormat::Linux;
+    use crate::packages::parser::DictionaryBuilder;
+    use crate::packages::tokenizer::RegexTokenizer;
+    let result = DictionaryBuilder::new().parse_raw_conc("data/Linux_2k.log".to_string(), &Linux);
+    let tokenizer = RegexTokenizer::new(&Linux).unwrap();
+    let miner = TemplateMiner::new(&result);
+    let events = miner.events_of_file("data/Linux_2k.log", &tokenizer).unwrap();
+    assert_eq!(events.lines.len(), 2000);
+    assert_eq!(events.templates, miner.templates_of_file("data/Linux_2k.log", &tokenizer).unwrap());
+    for (i, id) in events.lines.iter().enumerate() {
+        if let Some(id) = id {
+            assert!(events.templates[id.0 as usize].lines.contains(&i));
+        }
+    }
+    assert_eq!(events.template(events.templates[0].lines[0]), Some(&events.templates[0]));
+    assert_eq!(EventId(0).to_string(), "E1");
+    let json = serde_json::to_string(&events).unwrap();
+    assert_eq!(serde_json::from_str::<Events>(&json).unwrap(), events);
+
+    let path = std::env::temp_dir().join(format!("logram-test-{}-events.csv", std::process::id()));
+    events.write_csv(&path).unwrap();
+    let csv = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let rows: Vec<&str> = csv.lines().collect();
+    assert_eq!(rows[0], "LineId,EventId,EventTemplate");
+    assert_eq!(rows.len(), events.lines.iter().flatten().count() + 1);
+    assert!(rows[1].starts_with("1,E"));
+}
@@ -0,0 +1,403 @@
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::Arc;
+use regex::{Captures, Regex};
+
+use crate::LogFormat;
+use crate::LogFormat::*;
+use crate::packages::error::ParserError;
+use crate::packages::tokenizer::Censor;
+
+/// Reads the time of a line, in milliseconds since the Unix epoch, from the fields its format
+/// captured (usually `<Date>` and `<Time>`); `None` when the line doesn't say. Any
+/// `Fn(&Captures) -> Option<i64>` is one.
+pub trait TimestampParser: Send + Sync {
+    fn timestamp(&self, fields: &Captures) -> Option<i64>;
+}
+
+impl<F: Fn(&Captures) -> Option<i64> + Send + Sync> TimestampParser for F {
+    fn timestamp(&self, fields: &Captures) -> Option<i64> {
+        self(fields)
+    }
+}
+
+/// The built-in parsers: the named fields, joined with spaces, read with a `parse_time` layout.
+#[derive(Clone, Copy, Debug)]
+pub struct Layout {
+    pub fields: &'static [&'static str],
+    pub layout: &'static str,
+}
+
+impl TimestampParser for Layout {
+    fn timestamp(&self, fields: &Captures) -> Option<i64> {
+        let values = self.fields.iter().map(|f| fields.name(f).map(|m| m.as_str())).collect::<Option<Vec<_>>>()?;
+        parse_time(&values.join(" "), self.layout)
+    }
+}
+
+/// The timestamp parser of a built-in format. The formats whose lines don't carry a year (Linux,
+/// Syslog3164, Mac, Proxifier, Android) and the custom ones have none; give those your own.
+pub fn timestamp_parser(lf: &LogFormat) -> Option<Arc<dyn TimestampParser>> {
+    let (fields, layout): (&'static [&'static str], _) = match lf {
+        HDFS => (&["Date", "Time"], "%y%m%d %H%M%S"),
+        Spark => (&["Date", "Time"], "%y/%m/%d %H:%M:%S"),
+        HealthApp => (&["Time"], "%Y%m%d-%H:%M:%S:%f"),
+        OpenStack => (&["Date", "Time"], "%Y-%m-%d %H:%M:%S%.f"),
+        Zookeeper | Kafka | Cassandra => (&["Date", "Time"], "%Y-%m-%d %H:%M:%S,%f"),
+        Apache => (&["Time"], "%a %b %d %H:%M:%S %Y"),
+        Nginx => (&["Time"], "%d/%b/%Y:%H:%M:%S %z"),
+        Syslog5424 => (&["Timestamp"], "%Y-%m-%dT%H:%M:%S%.f%z"),
+        Kubernetes { .. } => (&["Time"], "%Y-%m-%dT%H:%M:%S%.f%z"),
+        HPC => (&["Time"], "%s"),
+        BGL | Thunderbird => (&["Timestamp"], "%s"),
+        _ => return None,
+    };
+    Some(Arc::new(Layout { fields, layout }))
+}
+
+// days from 1970-01-01 to y-m-d in the proleptic Gregorian calendar
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * (m + if m > 2 { -3 } else { 9 }) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+const MONTHS: [&str; 12] = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"];
+
+// takes up to max digits (at least one) off the front of s
+fn digits<'a>(s: &mut &'a str, max: usize) -> Option<&'a str> {
+    let n = s.bytes().take(max).take_while(u8::is_ascii_digit).count();
+    if n == 0 {
+        return None;
+    }
+    let (taken, rest) = s.split_at(n);
+    *s = rest;
+    Some(taken)
+}
+
+fn number(s: &mut &str, max: usize) -> Option<i64> {
+    digits(s, max)?.parse().ok()
+}
+
+// the first three digits of a fraction of a second, as milliseconds
+fn millis(fraction: &str) -> i64 {
+    format!("{:0<3}", &fraction[..fraction.len().min(3)]).parse().unwrap()
+}
+
+/// Reads `s` as laid out by `layout`, a strftime-like pattern: `%Y` (or `%y`, 20xx) years, `%m`
+/// months, `%b` month names, `%d` days, `%H`, `%M` and `%S` for the time, `%f` the digits of a
+/// fraction of a second, `%.f` an optional `.` and fraction, `%z` a `Z`, `+hh:mm` or `+hhmm` offset,
+/// `%a` a day name (ignored) and `%s` seconds since the epoch. Anything else must match exactly, and
+/// a space any run of spaces. Times without an offset are taken as UTC. Returns milliseconds since
+/// the epoch.
+pub fn parse_time(s: &str, layout: &str) -> Option<i64> {
+    let (mut year, mut month, mut day, mut hour, mut minute, mut second, mut ms, mut offset) = (1970, 1, 1, 0, 0, 0, 0, 0);
+    let mut epoch = None;
+    let mut s = s.trim();
+    let mut layout = layout.chars();
+    while let Some(c) = layout.next() {
+        if c == ' ' {
+            s = s.strip_prefix(' ')?.trim_start();
+            continue;
+        }
+        if c != '%' {
+            s = s.strip_prefix(c)?;
+            continue;
+        }
+        match layout.next()? {
+            'Y' => year = number(&mut s, 4)?,
+            'y' => year = 2000 + number(&mut s, 2)?,
+            'm' => month = number(&mut s, 2)?,
+            'd' => day = number(&mut s, 2)?,
+            'H' => hour = number(&mut s, 2)?,
+            'M' => minute = number(&mut s, 2)?,
+            'S' => second = number(&mut s, 2)?,
+            'f' => ms = millis(digits(&mut s, usize::MAX)?),
+            '.' => {
+                if layout.next()? != 'f' {
+                    return None;
+                }
+                if let Some(rest) = s.strip_prefix('.') {
+                    s = rest;
+                    ms = millis(digits(&mut s, usize::MAX)?);
+                }
+            },
+            'b' => {
+                let name = s.get(..3)?.to_ascii_lowercase();
+                month = MONTHS.iter().position(|m| *m == name)? as i64 + 1;
+                s = &s[3..];
+            },
+            'a' => s = s.trim_start_matches(|c: char| c.is_ascii_alphabetic()),
+            'z' => {
+                if let Some(rest) = s.strip_prefix('Z') {
+                    s = rest;
+                } else {
+                    let sign = if s.starts_with('-') { -1 } else if s.starts_with('+') { 1 } else { return None };
+                    s = &s[1..];
+                    let hours = number(&mut s, 2)?;
+                    s = s.strip_prefix(':').unwrap_or(s);
+                    offset = sign * (hours * 60 + number(&mut s, 2)?);
+                }
+            },
+            's' => {
+                let sign = if let Some(rest) = s.strip_prefix('-') { s = rest; -1 } else { 1 };
+                epoch = Some(sign * number(&mut s, 18)?);
+            },
+            _ => return None,
+        }
+    }
+    if !s.is_empty() || !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    if let Some(seconds) = epoch {
+        return Some(seconds * 1000 + ms);
+    }
+    let seconds = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset * 60;
+    Some(seconds * 1000 + ms)
+}
+
+/// A `[start, end)` window of time, in milliseconds since the epoch, to count the lines of; the
+/// lines outside it, and those whose time can't be read, have no tokens. The timestamps come from
+/// `parser`, or the format's own `timestamp_parser` if none is given.
+#[derive(Clone)]
+pub struct TimeRange {
+    pub start: i64,
+    pub end: i64,
+    parser: Option<Arc<dyn TimestampParser>>,
+}
+
+impl fmt::Debug for TimeRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TimeRange").field("start", &self.start).field("end", &self.end).field("parser", &self.parser.as_ref().map(|_| "..")).finish()
+    }
+}
+
+impl TimeRange {
+    pub fn new(start: i64, end: i64) -> Self {
+        TimeRange { start, end, parser: None }
+    }
+
+    pub fn parser(mut self, parser: Arc<dyn TimestampParser>) -> Self {
+        self.parser = Some(parser);
+        self
+    }
+
+    /// Fills in the parser of `lf` unless one was given, failing if the format has none.
+    pub fn for_format(&self, lf: &LogFormat) -> Result<TimeRange, ParserError> {
+        let parser = match &self.parser {
+            Some(parser) => parser.clone(),
+            None => timestamp_parser(lf).ok_or_else(|| ParserError::FormatMismatch(format!("no timestamp parser for {:?}; pass one to TimeRange::parser", lf)))?,
+        };
+        Ok(TimeRange { parser: Some(parser), ..*self })
+    }
+
+    /// Whether the line these fields were captured from falls in the window; false without a parser.
+    pub fn contains(&self, fields: &Captures) -> bool {
+        self.parser.as_ref().and_then(|p| p.timestamp(fields)).is_some_and(|t| self.start <= t && t < self.end)
+    }
+}
+
+/// How long a pause has to be, in milliseconds, for the lines on either side of it to be separate
+/// sessions: no n-gram reaches across it, as if a line that doesn't match the format stood there.
+/// The timestamps come from `parser`, or the format's own `timestamp_parser` if none is given.
+#[derive(Clone)]
+pub struct SessionGap {
+    pub gap: i64,
+    parser: Option<Arc<dyn TimestampParser>>,
+}
+
+impl fmt::Debug for SessionGap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionGap").field("gap", &self.gap).field("parser", &self.parser.as_ref().map(|_| "..")).finish()
+    }
+}
+
+impl SessionGap {
+    pub fn new(gap: i64) -> Self {
+        SessionGap { gap, parser: None }
+    }
+
+    pub fn parser(mut self, parser: Arc<dyn TimestampParser>) -> Self {
+        self.parser = Some(parser);
+        self
+    }
+
+    /// Fills in the parser of `lf` unless one was given, failing if the format has none.
+    pub fn for_format(&self, lf: &LogFormat) -> Result<SessionGap, ParserError> {
+        let parser = match &self.parser {
+            Some(parser) => parser.clone(),
+            None => timestamp_parser(lf).ok_or_else(|| ParserError::FormatMismatch(format!("no timestamp parser for {:?}; pass one to SessionGap::parser", lf)))?,
+        };
+        Ok(SessionGap { parser: Some(parser), ..*self })
+    }
+
+    /// Whether more than the gap went by between the lines these fields were captured from, either
+    /// way; false when either time can't be read, or without a parser.
+    pub fn splits(&self, prev: &Captures, line: &Captures) -> bool {
+        let timestamp = |fields| self.parser.as_ref().and_then(|p| p.timestamp(fields));
+        matches!((timestamp(prev), timestamp(line)), (Some(p), Some(t)) if (t - p).abs() > self.gap)
+    }
+}
+
+/// A language whose month and day names `timestamp_censor` knows, in full and abbreviated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Locale {
+    English,
+    French,
+    German,
+    Spanish,
+    Italian,
+    Portuguese,
+    Dutch,
+}
+
+impl Locale {
+    pub const ALL: [Locale; 7] = [Locale::English, Locale::French, Locale::German, Locale::Spanish, Locale::Italian, Locale::Portuguese, Locale::Dutch];
+
+    // the names of the months and of the days, lowercase
+    fn names(self) -> (&'static [&'static str], &'static [&'static str]) {
+        match self {
+            Locale::English => (
+                &["january", "february", "march", "april", "may", "june", "july", "august", "september", "october", "november", "december",
+                  "jan", "feb", "mar", "apr", "jun", "jul", "aug", "sep", "sept", "oct", "nov", "dec"],
+                &["monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday", "mon", "tue", "tues", "wed", "thu", "thurs", "fri", "sat", "sun"],
+            ),
+            Locale::French => (
+                &["janvier", "février", "mars", "avril", "mai", "juin", "juillet", "août", "septembre", "octobre", "novembre", "décembre",
+                  "janv", "févr", "fév", "avr", "juil", "sept", "oct", "nov", "déc"],
+                &["lundi", "mardi", "mercredi", "jeudi", "vendredi", "samedi", "dimanche", "lun", "mar", "mer", "jeu", "ven", "sam", "dim"],
+            ),
+            Locale::German => (
+                &["januar", "jänner", "februar", "märz", "april", "mai", "juni", "juli", "august", "september", "oktober", "november", "dezember",
+                  "jan", "feb", "mär", "mrz", "apr", "jun", "jul", "aug", "sep", "okt", "nov", "dez"],
+                &["montag", "dienstag", "mittwoch", "donnerstag", "freitag", "samstag", "sonntag", "mo", "di", "mi", "do", "fr", "sa", "so"],
+            ),
+            Locale::Spanish => (
+                &["enero", "febrero", "marzo", "abril", "mayo", "junio", "julio", "agosto", "septiembre", "setiembre", "octubre", "noviembre", "diciembre",
+                  "ene", "feb", "mar", "abr", "may", "jun", "jul", "ago", "sep", "sept", "oct", "nov", "dic"],
+                &["lunes", "martes", "miércoles", "jueves", "viernes", "sábado", "domingo", "lun", "mar", "mié", "jue", "vie", "sáb", "dom"],
+            ),
+            Locale::Italian => (
+                &["gennaio", "febbraio", "marzo", "aprile", "maggio", "giugno", "luglio", "agosto", "settembre", "ottobre", "novembre", "dicembre",
+                  "gen", "feb", "mar", "apr", "mag", "giu", "lug", "ago", "set", "ott", "nov", "dic"],
+                &["lunedì", "martedì", "mercoledì", "giovedì", "venerdì", "sabato", "domenica", "lun", "mar", "mer", "gio", "ven", "sab", "dom"],
+            ),
+            Locale::Portuguese => (
+                &["janeiro", "fevereiro", "março", "abril", "maio", "junho", "julho", "agosto", "setembro", "outubro", "novembro", "dezembro",
+                  "jan", "fev", "mar", "abr", "mai", "jun", "jul", "ago", "set", "out", "nov", "dez"],
+                &["segunda-feira", "terça-feira", "quarta-feira", "quinta-feira", "sexta-feira", "sábado", "domingo", "seg", "ter", "qua", "qui", "sex", "sáb", "dom"],
+            ),
+            Locale::Dutch => (
+                &["januari", "februari", "maart", "april", "mei", "juni", "juli", "augustus", "september", "oktober", "november", "december",
+                  "jan", "feb", "mrt", "apr", "jun", "jul", "aug", "sep", "okt", "nov", "dec"],
+                &["maandag", "dinsdag", "woensdag", "donderdag", "vrijdag", "zaterdag", "zondag", "ma", "di", "wo", "do", "vr", "za", "zo"],
+            ),
+        }
+    }
+}
+
+impl FromStr for Locale {
+    type Err = String;
+
+    /// The ISO 639-1 code of the language, e.g. `fr`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "en" => Ok(Locale::English),
+            "fr" => Ok(Locale::French),
+            "de" => Ok(Locale::German),
+            "es" => Ok(Locale::Spanish),
+            "it" => Ok(Locale::Italian),
+            "pt" => Ok(Locale::Portuguese),
+            "nl" => Ok(Locale::Dutch),
+            _ => Err(format!("unknown locale {}; expected one of en, fr, de, es, it, pt, nl", s)),
+        }
+    }
+}
+
+// the names as a regex alternation, longest first
+fn alternation<'a>(names: impl Iterator<Item = &'a str>) -> String {
+    let mut names: Vec<&str> = names.collect();
+    names.sort_unstable_by(|a, b| b.len().cmp(&a.len()).then(a.cmp(b)));
+    names.dedup();
+    names.iter().map(|name| regex::escape(name)).collect::<Vec<_>>().join("|")
+}
+
+// a date or time doesn't go on from a number or a dotted one: not the 1.10 of 192.168.1.10, nor
+// the 10.1.100 of 10.1.100.5
+fn timestamp_bounds(text: &str, at: Range<usize>) -> bool {
+    let (before, after) = (&text[..at.start], &text[at.end..]);
+    let digit_after = |s: &str| s.starts_with(|c: char| c.is_ascii_digit());
+    let goes_on = before.ends_with(|c: char| c.is_ascii_digit() || c == '.')
+        || digit_after(after)
+        || after.starts_with(['.', '/']) && digit_after(&after[1..]);
+    !goes_on
+}
+
+/// Censors the dates and times of any format, written as in `locales`, with `<*>`: ISO 8601 dates
+/// (`2017-12-23T22:15:29Z`), numeric ones (`23.12.2017` with two-digit days and months, `12/23/17`),
+/// month names next to the day and maybe the year (`Dec 23`, `23. Dezember 2017`, `sáb, 23 dic
+/// 2017`), each with any time after it, and times with seconds on their own (`22:15:29,606`). Names
+/// are matched ignoring case, and only next to a day, so `mai` or `may` in a message stay; numbers
+/// that go on with more digits or dots, like IP addresses and versions, stay too. No locales means
+/// English.
+pub fn timestamp_censor(locales: &[Locale]) -> Censor {
+    let locales = if locales.is_empty() { &[Locale::English][..] } else { locales };
+    let months = alternation(locales.iter().flat_map(|l| l.names().0.iter().copied()));
+    let days = alternation(locales.iter().flat_map(|l| l.names().1.iter().copied()));
+    // with the year after it in ctime's Sun Dec 04 04:51:18 2005
+    let time = r"\d{1,2}:\d{2}(?::\d{2}(?:[.,]\d+)?)?(?: ?(?:Z|[+-]\d{2}:?\d{2}))?(?: \d{4}\b)?";
+    let date = format!(
+        r"(?:\d{{4}}-\d{{2}}-\d{{2}}|\d{{2}}\.\d{{2}}\.(?:\d{{4}}|\d{{2}})|\d{{1,2}}/\d{{1,2}}/(?:\d{{4}}|\d{{2}})|(?:\b(?:{days})\b\.?,? )?(?:\b(?:{months})\b\.? \d{{1,2}}\b(?:,? \d{{4}})?|\b\d{{1,2}}\.? (?:{months})\b\.?(?: \d{{4}})?))"
+    );
+    let regex = Regex::new(&format!(r"(?i){date}(?:(?:T| |, | (?:at|à|um|a las|alle|às|om) ){time})?|\b\d{{1,2}}:\d{{2}}:\d{{2}}(?:[.,]\d+)?")).unwrap();
+    Censor::new(regex, "<*>").check(timestamp_bounds)
+}
+
+#[test]
+fn test_parse_time() {
+    assert_eq!(parse_time("1970-01-02 00:00:01", "%Y-%m-%d %H:%M:%S"), Some(86_401_000));
+    assert_eq!(parse_time("081109 203615", "%y%m%d %H%M%S"), Some(1_226_262_975_000));
+    assert_eq!(parse_time("20171223-22:15:29:606", "%Y%m%d-%H:%M:%S:%f"), Some(1_514_067_329_606));
+    assert_eq!(parse_time("Sun Dec 04 04:51:18 2005", "%a %b %d %H:%M:%S %Y"), Some(1_133_671_878_000));
+    assert_eq!(parse_time("14/Jun/2023:15:16:02 +0100", "%d/%b/%Y:%H:%M:%S %z"), Some(1_686_752_162_000));
+    assert_eq!(parse_time("2023-01-01T00:00:00.000000001Z", "%Y-%m-%dT%H:%M:%S%.f%z"), Some(1_672_531_200_000));
+    assert_eq!(parse_time("2003-10-11T22:14:15.5-07:00", "%Y-%m-%dT%H:%M:%S%.f%z"), Some(1_065_935_655_500));
+    assert_eq!(parse_time("2003-10-11T22:14:15Z", "%Y-%m-%dT%H:%M:%S%.f%z"), Some(1_065_910_455_000));
+    assert_eq!(parse_time("1117838570", "%s"), Some(1_117_838_570_000));
+    assert_eq!(parse_time("2015-07-29 19:04:12,394", "%Y-%m-%d %H:%M:%S,%f"), Some(1_438_196_652_394));
+    assert_eq!(parse_time("2015-13-29 19:04:12", "%Y-%m-%d %H:%M:%S"), None);
+    assert_eq!(parse_time("2015-07-29 19:04", "%Y-%m-%d %H:%M:%S"), None);
+    assert_eq!(parse_time("2015-07-29 19:04:12 trailing", "%Y-%m-%d %H:%M:%S"), None);
+}
+
+#[test]
+fn test_timestamp_censor() {
+    let censored = |locales: &[Locale], s: &str| timestamp_censor(locales).replace_all(s).0.into_owned();
+    assert_eq!(censored(&[], "at Sun Dec 04 04:51:18 2005 ok"), "at <*> ok");
+    assert_eq!(censored(&[], "started 2017-12-23T22:15:29.606Z by Dec 23, 2017 at 10:15"), "started <*> by <*>");
+    assert_eq!(censored(&[Locale::French], "ouverte le sam. 23 déc. 2017 à 22:15:29"), "ouverte le <*>");
+    assert_eq!(censored(&[Locale::German], "Sitzung am 23. Dezember 2017 um 22:15 geschlossen"), "Sitzung am <*> geschlossen");
+    assert_eq!(censored(&[Locale::German], "seit 23.12.2017 22:15:29,606"), "seit <*>");
+    assert_eq!(censored(&[Locale::Spanish, Locale::French], "el 3 de mayo, le 3 MAI à 10:00"), "el 3 de mayo, le <*>");
+    // names away from a day, and other numbers, stay
+    assert_eq!(censored(&Locale::ALL, "Mai ist may be 12 mars bars 1.5 of 10:15"), "Mai ist may be <*> bars 1.5 of 10:15");
+    // nor do IP addresses and versions
+    assert_eq!(censored(&[], "connect from 192.168.1.10"), "connect from 192.168.1.10");
+    assert_eq!(censored(&[], "rhost=10.1.100.5 via 10.10.10.10"), "rhost=10.1.100.5 via 10.10.10.10");
+    assert_eq!(censored(&[], "version 3.10.2024, 1.2.3 and 12.10.24.1"), "version 3.10.2024, 1.2.3 and 12.10.24.1");
+    assert_eq!(censored(&[], "on 1/5/2024 and 05.01.24."), "on <*> and <*>.");
+    assert_eq!("de,fr".split(',').map(str::parse).collect::<Result<Vec<Locale>, _>>(), Ok(vec![Locale::German, Locale::French]));
+    assert!("xx".parse::<Locale>().is_err());
+
+    use crate::packages::tokenizer::{RegexTokenizer, Tokenizer};
+    let tokenizer = RegexTokenizer::new(&Linux).unwrap().censor_timestamps(&[Locale::French]);
+    let tokens = tokenizer.tokenize("Jun 14 15:16:02 combo sshd(pam_unix)[19937]: session ouverte le 23 déc. 2017 à 22:15:29");
+    assert_eq!(tokens, ["session", "ouverte", "le", "<*>"]);
+    // the IPs are left for the format's own censoring
+    let tokenizer = RegexTokenizer::new(&Linux).unwrap().censor_timestamps(&[]);
+    assert_eq!(tokenizer.censor("connect from 192.168.1.10 at 2017-12-23 22:15:29"), "connect from <*> at <*>");
+}
@@ -0,0 +1,700 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ops::Range;
+use std::str::FromStr;
+use regex::{Captures, Regex, RegexSet};
+use serde::{Deserialize, Serialize};
+
+use crate::LogFormat;
+use crate::packages::error::ParserError;
+use crate::packages::parser::{censored_regexps, format_string, regex_generator_checked};
+use crate::packages::time::{timestamp_censor, Locale, SessionGap, TimeRange};
+
+/// Turns a log line into the tokens its n-grams are made of. The builders share one tokenizer
+/// between all their worker threads, hence the `Sync`.
+pub trait Tokenizer: Sync {
+    fn tokenize<'a>(&self, line: &'a str) -> Vec<Cow<'a, str>>;
+
+    /// Calls `f` with the tokens of `line`, in order, for the callers that look at each of them once.
+    /// A tokenizer can lend them out of its own buffer instead of allocating every censored token
+    /// as `tokenize` has to; by default they come from `tokenize`.
+    fn for_each_token(&self, line: &str, f: &mut dyn FnMut(&str)) {
+        self.tokenize(line).iter().for_each(|t| f(t))
+    }
+
+    /// What the n-grams of `line` are broken down by in `DictionaryResult::breakdown`, if anything;
+    /// see `RegexTokenizer::breakdown`. Nothing by default.
+    fn breakdown<'a>(&self, _line: &'a str) -> Option<&'a str> {
+        None
+    }
+
+    /// Whether `line`, coming right after `prev`, starts a new session that the n-grams of the lines
+    /// before it don't reach into; see `RegexTokenizer::session_gap`. Never by default.
+    fn breaks_context(&self, _prev: &str, _line: &str) -> bool {
+        false
+    }
+
+    /// Which stream `line` belongs to when the builder sorts the lines into streams; see
+    /// `RegexTokenizer::group_by`. None by default.
+    fn group<'a>(&self, _line: &'a str) -> Option<&'a str> {
+        None
+    }
+}
+
+/// What `RegexTokenizer::group_by` sorts the lines into streams by.
+#[derive(Clone, Debug)]
+pub enum GroupBy {
+    /// The value of a field of the format, e.g. `Pid`.
+    Field(String),
+    /// The first match of a regex anywhere in the line, or of its first capture group if it has one,
+    /// e.g. `blk_-?\d+` for the blocks of HDFS.
+    Pattern(Regex),
+}
+
+/// A censoring regex of the caller's, and the token its matches are replaced with, taken literally
+/// (the built-in ones use `<*>`). A replacement with spaces in it makes several tokens.
+#[derive(Clone, Debug)]
+pub struct Censor {
+    pub regex: Regex,
+    pub replacement: String,
+    /// Only the matches this accepts are censored, if given: it gets the text being censored and
+    /// where in it the match is, e.g. for the Luhn check of card numbers.
+    pub check: Option<fn(&str, Range<usize>) -> bool>,
+}
+
+impl Censor {
+    pub fn new(regex: Regex, replacement: &str) -> Self {
+        Censor { regex, replacement: replacement.to_string(), check: None }
+    }
+
+    pub fn check(mut self, check: fn(&str, Range<usize>) -> bool) -> Self {
+        self.check = Some(check);
+        self
+    }
+
+    /// `text` with the matches the check accepts replaced, and how many there were.
+    pub fn replace_all<'a>(&self, text: &'a str) -> (Cow<'a, str>, u64) {
+        let mut replaced = 0;
+        let censored = self.regex.replace_all(text, |m: &Captures| {
+            if self.check.is_none_or(|check| check(text, m.get(0).unwrap().range())) {
+                replaced += 1;
+                self.replacement.clone()
+            } else {
+                m[0].to_string()
+            }
+        });
+        (censored, replaced)
+    }
+}
+
+// whether the digits of s pass the Luhn check of payment card numbers
+fn luhn(s: &str) -> bool {
+    let digits: Vec<u32> = s.chars().filter_map(|c| c.to_digit(10)).collect();
+    let sum: u32 = digits.iter().rev().enumerate().map(|(i, d)| if i % 2 == 1 { if d * 2 > 9 { d * 2 - 9 } else { d * 2 } } else { *d }).sum();
+    sum.is_multiple_of(10)
+}
+
+// a card number passes the Luhn check, and isn't the end of a negative id (blk_-123...) or of a
+// dotted number
+fn card_number(text: &str, at: Range<usize>) -> bool {
+    !text[..at.start].ends_with(['-', '.']) && luhn(&text[at])
+}
+
+/// The patterns of `RegexTokenizer::redact_pii`, each replaced with a placeholder of its own:
+/// email addresses with `<EMAIL>`, JSON web tokens `<JWT>`, MAC addresses `<MAC>`, IPv6 and IPv4
+/// addresses `<IP>`, and 13 to 19 digit numbers that pass the Luhn check, maybe in groups, `<CARD>`.
+/// The placeholders have no digits for the format's censoring to find.
+pub fn pii_censors() -> Vec<Censor> {
+    let h = "[0-9a-fA-F]{1,4}";
+    // leftmost-first, so the forms with fewer groups before the :: go first to take in all after it
+    let ipv6 = [
+        format!("(?:{h}:){{7}}{h}"),
+        format!("{h}:(?::{h}){{1,6}}"),
+        format!("(?:{h}:){{1,2}}(?::{h}){{1,5}}"),
+        format!("(?:{h}:){{1,3}}(?::{h}){{1,4}}"),
+        format!("(?:{h}:){{1,4}}(?::{h}){{1,3}}"),
+        format!("(?:{h}:){{1,5}}(?::{h}){{1,2}}"),
+        format!("(?:{h}:){{1,6}}:{h}"),
+    ].join("|");
+    vec![
+        Censor::new(Regex::new(r"\beyJ[\w-]+\.[\w-]+\.[\w-]+").unwrap(), "<JWT>"),
+        Censor::new(Regex::new(r"[\w.%+-]+@[\w-]+(\.[\w-]+)*\.[A-Za-z]{2,}\b").unwrap(), "<EMAIL>"),
+        Censor::new(Regex::new(r"\b[0-9a-fA-F]{2}([:-])[0-9a-fA-F]{2}(?:[:-][0-9a-fA-F]{2}){4}\b").unwrap(), "<MAC>"),
+        Censor::new(Regex::new(&format!(r"\b(?:{ipv6})\b|::{h}(?::{h}){{0,6}}\b|\b(?:\d{{1,3}}\.){{3}}\d{{1,3}}\b")).unwrap(), "<IP>"),
+        Censor::new(Regex::new(r"\b\d(?:[ -]?\d){12,18}\b").unwrap(), "<CARD>").check(card_number),
+    ]
+}
+
+/// The default tokenizer, the same as `token_splitter`: takes the `<Content>` field of a line matching
+/// the format, replaces whatever the censoring regexes match with `<*>`, and splits on whitespace.
+/// Lines that don't match the format have no tokens.
+#[derive(Clone, Debug)]
+pub struct RegexTokenizer {
+    regex: Regex,
+    censor: Vec<Censor>,
+    // all the censoring regexes at once, to find the ones that match a line in a single scan; none if
+    // they're too big to put together
+    censor_set: Option<RegexSet>,
+    levels: Vec<String>,
+    time_range: Option<TimeRange>,
+    breakdown: Option<String>,
+    session_gap: Option<SessionGap>,
+    split_on_change: Vec<String>,
+    group_by: Option<GroupBy>,
+}
+
+impl RegexTokenizer {
+    pub fn new(lf: &LogFormat) -> Result<Self, ParserError> {
+        let censor = censored_regexps(lf).into_iter().map(|re| Censor::new(re, "<*>")).collect();
+        let tokenizer = RegexTokenizer { regex: regex_generator_checked(format_string(lf))?, censor, censor_set: None, levels: vec![], time_range: None, breakdown: None, session_gap: None, split_on_change: vec![], group_by: None };
+        Ok(tokenizer.index_censors())
+    }
+
+    /// Only tokenizes the lines whose `<Level>` is one of `levels`, ignoring case; the other lines have
+    /// no tokens, as if they didn't match the format, so they still separate their neighbours. No
+    /// levels (the default) means every line. The format needs a `<Level>` field to filter on.
+    pub fn levels<S: AsRef<str>>(mut self, levels: &[S]) -> Result<Self, ParserError> {
+        if !levels.is_empty() && !self.regex.capture_names().any(|name| name == Some("Level")) {
+            return Err(ParserError::FormatMismatch(format!("no <Level> field in {} to filter on", self.regex)));
+        }
+        self.levels = levels.iter().map(|l| l.as_ref().to_string()).collect();
+        Ok(self)
+    }
+
+    /// Only tokenizes the lines whose time falls in `time_range`, like `levels`. Call
+    /// `TimeRange::for_format` first to give it the format's timestamp parser.
+    pub fn time_range(mut self, time_range: TimeRange) -> Self {
+        self.time_range = Some(time_range);
+        self
+    }
+
+    /// Breaks the counts of every line down by the value of its `field` too (e.g. `Level` or
+    /// `Component`). The format needs the field; `None` (the default) means no breakdown.
+    pub fn breakdown_by(mut self, field: Option<&str>) -> Result<Self, ParserError> {
+        if let Some(field) = field {
+            if !self.regex.capture_names().any(|name| name == Some(field)) {
+                return Err(ParserError::FormatMismatch(format!("no <{}> field in {} to break the counts down by", field, self.regex)));
+            }
+        }
+        self.breakdown = field.map(str::to_string);
+        Ok(self)
+    }
+
+    /// Starts a new session after every pause longer than `session_gap` between two lines, so that no
+    /// n-gram joins the last line before it to the first one after. Call `SessionGap::for_format`
+    /// first to give it the format's timestamp parser.
+    pub fn session_gap(mut self, session_gap: SessionGap) -> Self {
+        self.session_gap = Some(session_gap);
+        self
+    }
+
+    /// Starts a new session wherever one of `fields` (e.g. `Component` or `Pid`) changes from one line
+    /// to the next, so that interleaved processes don't make n-grams of each other's lines. The lines
+    /// that don't match the format don't change anything. The format needs the fields.
+    pub fn split_on_change<S: AsRef<str>>(mut self, fields: &[S]) -> Result<Self, ParserError> {
+        for field in fields {
+            if !self.regex.capture_names().any(|name| name == Some(field.as_ref())) {
+                return Err(ParserError::FormatMismatch(format!("no <{}> field in {} to split the context on", field.as_ref(), self.regex)));
+            }
+        }
+        self.split_on_change = fields.iter().map(|f| f.as_ref().to_string()).collect();
+        Ok(self)
+    }
+
+    /// Also censors the dates and times `time::timestamp_censor` finds, with the month and day names
+    /// of `locales`, before the format's own censoring regexes run.
+    pub fn censor_timestamps(mut self, locales: &[Locale]) -> Self {
+        self.censor.insert(0, timestamp_censor(locales));
+        self.index_censors()
+    }
+
+    /// Redacts personal data before anything else gets censored: the `pii_censors`, each with its
+    /// typed placeholder. `redactions` counts what they replaced.
+    pub fn redact_pii(mut self) -> Self {
+        self.censor.splice(0..0, pii_censors());
+        self.index_censors()
+    }
+
+    /// Also censors whatever each of `censors` matches, with its own replacement, after the format's
+    /// censoring regexes, e.g. to mask customer ids as `<CUSTOMER>`.
+    pub fn extra_censors(mut self, censors: &[Censor]) -> Self {
+        self.censor.extend_from_slice(censors);
+        self.index_censors()
+    }
+
+    /// Sorts the lines into streams by `group_by`, such as the lines of each process or of each HDFS
+    /// block, for the builders to count the n-grams of each stream on its own: this gives every line
+    /// its stream, and a new session wherever the stream changes. With a `GroupBy::Field`, the format
+    /// needs the field. `None` (the default) leaves the lines in one stream.
+    pub fn group_by(mut self, group_by: Option<GroupBy>) -> Result<Self, ParserError> {
+        if let Some(GroupBy::Field(field)) = &group_by {
+            if !self.regex.capture_names().any(|name| name == Some(field)) {
+                return Err(ParserError::FormatMismatch(format!("no <{}> field in {} to group the lines by", field, self.regex)));
+            }
+        }
+        self.group_by = group_by;
+        Ok(self)
+    }
+
+    // rebuilds censor_set once the censors change
+    fn index_censors(mut self) -> Self {
+        self.censor_set = RegexSet::new(self.censor.iter().map(|censor| censor.regex.as_str())).ok();
+        self
+    }
+
+    /// `content` with whatever the censoring regexes match replaced with `<*>` (or the replacement of
+    /// an extra censor), as it gets tokenized.
+    pub fn censor<'a>(&self, content: &'a str) -> Cow<'a, str> {
+        self.censor_counting(content, |_, _| {})
+    }
+
+    /// Counts what tokenizing `line` replaces with a placeholder other than `<*>` (those of
+    /// `redact_pii` and `extra_censors`) into `report`, by placeholder.
+    pub fn redactions(&self, line: &str, report: &mut BTreeMap<String, u64>) {
+        if let Some(content) = self.regex.captures(line.trim()).filter(|m| self.wanted(m)).and_then(|m| m.name("Content")) {
+            self.censor_counting(content.as_str(), |censor, hits| {
+                if censor.replacement != "<*>" {
+                    *report.entry(censor.replacement.clone()).or_default() += hits;
+                }
+            });
+        }
+    }
+
+    // censor, telling hits how many matches of each censoring regex it replaced
+    fn censor_counting<'a>(&self, content: &'a str, mut hits: impl FnMut(&Censor, u64)) -> Cow<'a, str> {
+        if self.censor.is_empty() {
+            return Cow::Borrowed(content);
+        }
+        // the same leading space as apply_domain_specific_re, which some censoring regexes rely on;
+        // most lines have nothing to censor, so the set first looks at them in a reused buffer
+        let mut matching = match &self.censor_set {
+            Some(set) => {
+                let matching = PADDED.with_borrow_mut(|padded| {
+                    padded.clear();
+                    padded.push(' ');
+                    padded.push_str(content);
+                    set.matches(padded)
+                });
+                if !matching.matched_any() {
+                    return Cow::Borrowed(content);
+                }
+                Some(matching)
+            },
+            None => None,
+        };
+        let mut censored = format!(" {}", content);
+        let mut changed = false;
+        // the regexes that don't match the line as it stands can't replace anything, so only the
+        // others run, and the set only looks again when one of them has changed the line
+        for (i, censor) in self.censor.iter().enumerate() {
+            if matching.as_ref().is_some_and(|matching| !matching.matched(i)) {
+                continue;
+            }
+            let (replaced, replaced_here) = censor.replace_all(&censored);
+            if let Cow::Owned(replaced) = replaced {
+                censored = replaced;
+            }
+            if replaced_here > 0 {
+                changed = true;
+                hits(censor, replaced_here);
+                matching = self.censor_set.as_ref().map(|set| set.matches(&censored));
+            }
+        }
+        if changed {
+            Cow::Owned(censored.strip_prefix(' ').map(str::to_string).unwrap_or(censored))
+        } else {
+            Cow::Borrowed(content)
+        }
+    }
+
+    // whether line matches the format
+    pub(crate) fn matches(&self, line: &str) -> bool {
+        self.regex.is_match(line.trim())
+    }
+
+    // the content of line, if it matches the format and is wanted
+    fn content<'a>(&self, line: &'a str) -> Option<&'a str> {
+        self.regex.captures(line.trim()).filter(|m| self.wanted(m)).and_then(|m| m.name("Content")).map(|content| content.as_str())
+    }
+
+    fn wanted(&self, m: &Captures) -> bool {
+        (self.levels.is_empty() || m.name("Level").is_some_and(|level| self.levels.iter().any(|l| l.eq_ignore_ascii_case(level.as_str()))))
+            && self.time_range.as_ref().is_none_or(|range| range.contains(m))
+    }
+}
+
+thread_local! {
+    // a line with the leading space censoring adds, for the censor set to look at
+    static PADDED: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// The tokens of `s`, as `split_whitespace` splits it. Machine logs are nearly always ASCII, and
+/// those are split a byte at a time rather than a char at a time.
+pub fn split_tokens(s: &str) -> impl Iterator<Item = &str> {
+    let ascii = s.is_ascii();
+    let bytes = if ascii { s.as_bytes() } else { &[] };
+    // the ASCII whitespace of split_whitespace, which unlike u8::is_ascii_whitespace has \x0b in it
+    let words = bytes.split(|b| matches!(b, b'\t'..=b'\r' | b' ')).filter(|token| !token.is_empty()).map(move |token| {
+        let start = token.as_ptr() as usize - bytes.as_ptr() as usize;
+        &s[start..start + token.len()]
+    });
+    words.chain(if ascii { "" } else { s }.split_whitespace())
+}
+
+impl Tokenizer for RegexTokenizer {
+    fn tokenize<'a>(&self, line: &'a str) -> Vec<Cow<'a, str>> {
+        let Some(content) = self.content(line) else {
+            return vec![];
+        };
+        match self.censor(content) {
+            Cow::Borrowed(content) => split_tokens(content).map(Cow::Borrowed).collect(),
+            Cow::Owned(censored) => split_tokens(&censored).map(|s| Cow::Owned(s.to_string())).collect(),
+        }
+    }
+
+    // the tokens of a censored line are slices of the line, which is dropped when they've been seen
+    fn for_each_token(&self, line: &str, f: &mut dyn FnMut(&str)) {
+        if let Some(content) = self.content(line) {
+            split_tokens(&self.censor(content)).for_each(f)
+        }
+    }
+
+    fn breakdown<'a>(&self, line: &'a str) -> Option<&'a str> {
+        let field = self.breakdown.as_deref()?;
+        self.regex.captures(line.trim()).filter(|m| self.wanted(m)).and_then(|m| m.name(field)).map(|value| value.as_str())
+    }
+
+    fn breaks_context(&self, prev: &str, line: &str) -> bool {
+        if self.group_by.is_some() && self.group(prev) != self.group(line) {
+            return true;
+        }
+        if self.session_gap.is_none() && self.split_on_change.is_empty() {
+            return false;
+        }
+        let changed = |p: &Captures, l: &Captures, field: &str| matches!((p.name(field), l.name(field)), (Some(a), Some(b)) if a.as_str() != b.as_str());
+        match (self.regex.captures(prev.trim()), self.regex.captures(line.trim())) {
+            (Some(p), Some(l)) => self.session_gap.as_ref().is_some_and(|gap| gap.splits(&p, &l)) || self.split_on_change.iter().any(|field| changed(&p, &l, field)),
+            _ => false,
+        }
+    }
+
+    fn group<'a>(&self, line: &'a str) -> Option<&'a str> {
+        let m = match self.group_by.as_ref()? {
+            GroupBy::Field(field) => self.regex.captures(line.trim())?.name(field),
+            GroupBy::Pattern(pattern) => pattern.captures(line).and_then(|c| c.get(1).or_else(|| c.get(0))),
+        };
+        m.map(|m| m.as_str())
+    }
+}
+
+// the part of token in range, borrowing from the line whenever token does
+fn slice<'a>(token: &Cow<'a, str>, range: Range<usize>) -> Cow<'a, str> {
+    match token {
+        Cow::Borrowed(s) => Cow::Borrowed(&s[range]),
+        Cow::Owned(s) => Cow::Owned(s[range].to_string()),
+    }
+}
+
+/// Splits the tokens of another tokenizer at their camelCase humps, so that `NullPointerException`
+/// becomes `Null`, `Pointer` and `Exception`, and `getHTTPResponse` becomes `get`, `HTTP` and `Response`.
+/// A capital after punctuation starts a word too, which takes `java.lang.` off a qualified class
+/// name; wrap this in `StripPunctuation` to clean up the pieces.
+#[derive(Clone, Debug)]
+pub struct SplitCamelCase<T>(pub T);
+
+// where a new word starts: a capital after a lowercase letter, a digit or punctuation, or the last
+// capital of a run that a lowercase letter follows
+fn camel_case_words(s: &str) -> Vec<Range<usize>> {
+    let chars: Vec<(usize, char)> = s.char_indices().collect();
+    let mut words = vec![];
+    let mut start = 0;
+    for i in 1..chars.len() {
+        let (at, c) = chars[i];
+        let prev = chars[i - 1].1;
+        let next_lower = chars.get(i + 1).is_some_and(|(_, n)| n.is_lowercase());
+        if c.is_uppercase() && (!prev.is_uppercase() || next_lower) {
+            words.push(start..at);
+            start = at;
+        }
+    }
+    words.push(start..s.len());
+    words
+}
+
+impl<T: Tokenizer> Tokenizer for SplitCamelCase<T> {
+    fn tokenize<'a>(&self, line: &'a str) -> Vec<Cow<'a, str>> {
+        self.0.tokenize(line).into_iter()
+            .flat_map(|t| camel_case_words(&t).into_iter().map(|r| slice(&t, r)).collect::<Vec<_>>())
+            .collect()
+    }
+
+    fn breakdown<'a>(&self, line: &'a str) -> Option<&'a str> {
+        self.0.breakdown(line)
+    }
+
+    fn breaks_context(&self, prev: &str, line: &str) -> bool {
+        self.0.breaks_context(prev, line)
+    }
+
+    fn group<'a>(&self, line: &'a str) -> Option<&'a str> {
+        self.0.group(line)
+    }
+}
+
+/// Strips the punctuation off both ends of the tokens of another tokenizer, dropping the tokens
+/// that are nothing but punctuation. The `<`, `*` and `>` of the censoring placeholder are kept.
+#[derive(Clone, Debug)]
+pub struct StripPunctuation<T>(pub T);
+
+impl<T: Tokenizer> Tokenizer for StripPunctuation<T> {
+    fn tokenize<'a>(&self, line: &'a str) -> Vec<Cow<'a, str>> {
+        self.0.tokenize(line).into_iter()
+            .filter_map(|t| {
+                let trimmed = t.trim_matches(|c: char| !c.is_alphanumeric() && !"<*>".contains(c));
+                let start = trimmed.as_ptr() as usize - t.as_ptr() as usize;
+                (!trimmed.is_empty()).then(|| slice(&t, start..start + trimmed.len()))
+            })
+            .collect()
+    }
+
+    fn breakdown<'a>(&self, line: &'a str) -> Option<&'a str> {
+        self.0.breakdown(line)
+    }
+
+    fn breaks_context(&self, prev: &str, line: &str) -> bool {
+        self.0.breaks_context(prev, line)
+    }
+
+    fn group<'a>(&self, line: &'a str) -> Option<&'a str> {
+        self.0.group(line)
+    }
+}
+
+impl<T: Tokenizer + ?Sized> Tokenizer for &T {
+    fn tokenize<'a>(&self, line: &'a str) -> Vec<Cow<'a, str>> {
+        (**self).tokenize(line)
+    }
+
+    fn for_each_token(&self, line: &str, f: &mut dyn FnMut(&str)) {
+        (**self).for_each_token(line, f)
+    }
+
+    fn breakdown<'a>(&self, line: &'a str) -> Option<&'a str> {
+        (**self).breakdown(line)
+    }
+
+    fn breaks_context(&self, prev: &str, line: &str) -> bool {
+        (**self).breaks_context(prev, line)
+    }
+
+    fn group<'a>(&self, line: &'a str) -> Option<&'a str> {
+        (**self).group(line)
+    }
+}
+
+/// Which of `Normalize`'s rewrites to apply to each token, after censoring. They run in the order
+/// of the fields, so e.g. a path full of numbers becomes one `<PATH>`, not a path of `<NUM>`s.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Normalization {
+    pub lowercase: bool,
+    /// UUIDs become `<ID>`.
+    pub uuids: bool,
+    /// Unix and Windows paths become `<PATH>`.
+    pub paths: bool,
+    /// `0x` numbers, and runs of 8 or more hex digits with both letters and digits, become `<HEX>`.
+    pub hex: bool,
+    /// Decimal numbers, dotted ones like versions and IPs included, become `<NUM>`.
+    pub numbers: bool,
+}
+
+impl Normalization {
+    pub fn all() -> Self {
+        Normalization { lowercase: true, uuids: true, paths: true, hex: true, numbers: true }
+    }
+}
+
+/// Parses a comma-separated list of lowercase, uuids, paths, hex and numbers, or all or none.
+impl FromStr for Normalization {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut normalization = Normalization::default();
+        for name in s.split(',').map(str::trim) {
+            match name {
+                "lowercase" => normalization.lowercase = true,
+                "uuids" => normalization.uuids = true,
+                "paths" => normalization.paths = true,
+                "hex" => normalization.hex = true,
+                "numbers" => normalization.numbers = true,
+                "all" => normalization = Normalization::all(),
+                "none" => {}
+                _ => return Err(format!("unknown normalization {}", name)),
+            }
+        }
+        Ok(normalization)
+    }
+}
+
+fn is_hex_id(hex: &str) -> bool {
+    hex.starts_with("0x") || hex.starts_with("0X") || (hex.bytes().any(|b| b.is_ascii_digit()) && hex.bytes().any(|b| b.is_ascii_alphabetic()))
+}
+
+/// Rewrites the tokens of another tokenizer as its `Normalization` says, so that the variants
+/// the censoring regexes of a format let through collapse into one n-gram.
+#[derive(Clone, Debug)]
+pub struct Normalize<T> {
+    inner: T,
+    lowercase: bool,
+    rules: Vec<(Regex, &'static str)>,
+}
+
+impl<T> Normalize<T> {
+    pub fn new(inner: T, normalization: Normalization) -> Self {
+        let rules = [
+            (normalization.uuids, r"\b[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}\b", "<ID>"),
+            (normalization.paths, r"(?:~|\.{1,2}|[A-Za-z]:)?(?:[/\\][\w.@-]+)+[/\\]?", "<PATH>"),
+            (normalization.hex, r"\b(?:0[xX][0-9a-fA-F]+|[0-9a-fA-F]{8,})\b", "<HEX>"),
+            // not the tail of a word or a dotted version, like the 1.2 of v1.2
+            (normalization.numbers, r"(?P<pre>^|[^\w.])\d+(?:\.\d+)*\b", "<NUM>"),
+        ];
+        let rules = rules.into_iter().filter(|(on, _, _)| *on).map(|(_, re, to)| (Regex::new(re).unwrap(), to)).collect();
+        Normalize { inner, lowercase: normalization.lowercase, rules }
+    }
+}
+
+impl<T: Tokenizer> Tokenizer for Normalize<T> {
+    fn tokenize<'a>(&self, line: &'a str) -> Vec<Cow<'a, str>> {
+        let mut tokens = self.inner.tokenize(line);
+        for t in tokens.iter_mut() {
+            if self.lowercase && t.chars().any(char::is_uppercase) {
+                *t = Cow::Owned(t.to_lowercase());
+            }
+            for (re, to) in &self.rules {
+                let replaced = re.replace_all(t, |caps: &Captures| {
+                    let m = &caps[0];
+                    let pre = caps.name("pre").map_or("", |pre| pre.as_str());
+                    if *to != "<HEX>" || is_hex_id(m) { format!("{}{}", pre, to) } else { m.to_string() }
+                });
+                if let Cow::Owned(replaced) = replaced {
+                    *t = Cow::Owned(replaced);
+                }
+            }
+        }
+        tokens
+    }
+
+    fn for_each_token(&self, line: &str, f: &mut dyn FnMut(&str)) {
+        if !self.lowercase && self.rules.is_empty() {
+            self.inner.for_each_token(line, f)
+        } else {
+            self.tokenize(line).iter().for_each(|t| f(t))
+        }
+    }
+
+    fn breakdown<'a>(&self, line: &'a str) -> Option<&'a str> {
+        self.inner.breakdown(line)
+    }
+
+    fn breaks_context(&self, prev: &str, line: &str) -> bool {
+        self.inner.breaks_context(prev, line)
+    }
+
+    fn group<'a>(&self, line: &'a str) -> Option<&'a str> {
+        self.inner.group(line)
+    }
+}
+
+#[test]
+fn test_regex_tokenizer() {
+    use crate::packages::parser::{regex_generator, token_splitter};
+    let lines = std::fs::read_to_string("data/Linux_2k.log").unwrap();
+    let tokenizer = RegexTokenizer::new(&LogFormat::Linux).unwrap();
+    let (re, censor) = (regex_generator(format_string(&LogFormat::Linux)), censored_regexps(&LogFormat::Linux));
+    for line in lines.lines() {
+        assert_eq!(tokenizer.tokenize(line), token_splitter(line.to_string(), &re, &censor));
+        let mut lent = vec![];
+        tokenizer.for_each_token(line, &mut |t| lent.push(t.to_string()));
+        assert_eq!(tokenizer.tokenize(line), lent);
+    }
+    assert!(matches!(RegexTokenizer::new(&LogFormat::Custom { format: "<Time> <Level>".to_string(), censor: vec![] }),
+                     Err(ParserError::FormatMismatch(_))));
+}
+
+#[test]
+fn test_split_tokens() {
+    for s in ["", "  ", "a", " check  pass;\tuser\r\nunknown ", "a\x0bb\x0cc", "naïve\u{a0}café  au lait", "x\u{2003}y z"] {
+        assert_eq!(split_tokens(s).collect::<Vec<_>>(), s.split_whitespace().collect::<Vec<_>>(), "{:?}", s);
+    }
+}
+
+#[test]
+fn test_extra_censors() {
+    let lf = LogFormat::Custom { format: "<Level> <Content>".to_string(), censor: vec![Regex::new(r"\d+").unwrap()] };
+    let tokenizer = RegexTokenizer::new(&lf).unwrap().extra_censors(&[
+        Censor::new(Regex::new(r"[\w.+-]+@[\w-]+(\.[\w-]+)+").unwrap(), "<EMAIL>"),
+        Censor::new(Regex::new(r"cust-<\*>").unwrap(), "<CUSTOMER>"),
+    ]);
+    // after the format's own censoring, which has turned the digits into <*> already
+    assert_eq!(tokenizer.tokenize("INFO mail to jo.doe+x@mail.example.com for cust-4711 3 times"), vec!["mail", "to", "<EMAIL>", "for", "<CUSTOMER>", "<*>", "times"]);
+}
+
+#[test]
+fn test_redact_pii() {
+    let lf = LogFormat::Custom { format: "<Level> <Content>".to_string(), censor: vec![Regex::new(r"\d+").unwrap()] };
+    let tokenizer = RegexTokenizer::new(&lf).unwrap().redact_pii();
+    let line = "INFO user jo.doe@example.co.uk from fe80::1ff:fe23:4567:890a and 2001:db8::8a2e:370:7334 via 10.0.0.1 \
+                on 00:1A:2b:3c:4D:5e paid 4111 1111 1111 1111 not 4111 1111 1111 1112 at 22:15:29 \
+                with eyJhbGciOiJIUzI1NiJ9.eyJzdWIiOiIxIn0.sig-_x in std::vector";
+    assert_eq!(tokenizer.tokenize(line).join(" "),
+               "user <EMAIL> from <IP> and <IP> via <IP> on <MAC> paid <CARD> not <*> <*> <*> <*> at <*>:<*>:<*> with <JWT> in std::vector");
+    let mut report = BTreeMap::new();
+    tokenizer.redactions(line, &mut report);
+    tokenizer.redactions("INFO mail a@b.io and c@d.io", &mut report);
+    tokenizer.redactions("garbage", &mut report);
+    let expected = [("<CARD>", 1), ("<EMAIL>", 3), ("<IP>", 3), ("<JWT>", 1), ("<MAC>", 1)];
+    assert_eq!(report, expected.iter().map(|(k, v)| (k.to_string(), *v)).collect());
+    assert!(luhn("4111-1111-1111-1111") && !luhn("4111111111111112"));
+}
+
+#[test]
+fn test_regex_tokenizer_levels() {
+    let tokenizer = RegexTokenizer::new(&LogFormat::HDFS).unwrap().levels(&["warn", "ERROR"]).unwrap();
+    assert_eq!(tokenizer.tokenize("081109 213655 30 WARN dfs.FSDataset: Unexpected error trying to delete block"), vec!["Unexpected", "error", "trying", "to", "delete", "block"]);
+    assert!(tokenizer.tokenize("081109 203615 148 INFO dfs.DataNode$PacketResponder: PacketResponder 1 terminating").is_empty());
+    let unfiltered = RegexTokenizer::new(&LogFormat::HDFS).unwrap().levels(&[] as &[&str]).unwrap();
+    assert_eq!(unfiltered.tokenize("081109 203615 148 INFO dfs.DataNode$PacketResponder: PacketResponder 1 terminating").len(), 3);
+
+    let no_level = LogFormat::Custom { format: "<Time> <Content>".to_string(), censor: vec![] };
+    assert!(matches!(RegexTokenizer::new(&no_level).unwrap().levels(&["ERROR"]), Err(ParserError::FormatMismatch(_))));
+}
+
+#[test]
+fn test_split_camel_case_strip_punctuation() {
+    let lf = LogFormat::Custom { format: "<Level> <Content>".to_string(), censor: vec![Regex::new(r"\d+").unwrap()] };
+    let tokenizer = StripPunctuation(SplitCamelCase(RegexTokenizer::new(&lf).unwrap()));
+    let tokens = tokenizer.tokenize("ERROR java.lang.NullPointerException: at getHTTPResponse(Client.java:42) -- 7");
+    assert_eq!(tokens, vec!["java.lang", "Null", "Pointer", "Exception", "at", "get", "HTTP", "Response", "Client.java:<*>", "<*>"]);
+
+    // with nothing to censor, the tokens borrow from the line
+    let lf = LogFormat::Custom { format: "<Level> <Content>".to_string(), censor: vec![] };
+    let tokens = StripPunctuation(SplitCamelCase(RegexTokenizer::new(&lf).unwrap())).tokenize("INFO (fooBar)");
+    assert_eq!(tokens, vec!["foo", "Bar"]);
+    assert!(tokens.iter().all(|t| matches!(t, Cow::Borrowed(_))));
+}
+
+#[test]
+fn test_normalize() {
+    let lf = LogFormat::Custom { format: "<Level> <Content>".to_string(), censor: vec![] };
+    let line = "INFO Opened /var/log/app-2.log (FD 0x1F) for job 7c9e6679-7425-40de-944b-e07fc1f90ae7 at 10.0.0.1:8080 deadbeef 5e7b2a9f01";
+    let tokenizer = Normalize::new(RegexTokenizer::new(&lf).unwrap(), Normalization::all());
+    assert_eq!(tokenizer.tokenize(line), vec!["opened", "<PATH>", "(fd", "<HEX>)", "for", "job", "<ID>", "at", "<NUM>:<NUM>", "deadbeef", "<HEX>"]);
+
+    let numbers_only = Normalize::new(RegexTokenizer::new(&lf).unwrap(), "numbers".parse().unwrap());
+    assert_eq!(numbers_only.tokenize("INFO Retry 3 of v1.2.10 R02 blk_-1608999687919862906"), vec!["Retry", "<NUM>", "of", "v1.2.10", "R02", "blk_-<NUM>"]);
+    assert_eq!(numbers_only.tokenize("INFO retry 3.5 times"), vec!["retry", "<NUM>", "times"]);
+
+    assert_eq!("lowercase, hex".parse(), Ok(Normalization { lowercase: true, hex: true, ..Default::default() }));
+    assert_eq!("none".parse(), Ok(Normalization::default()));
+    assert!("lower".parse::<Normalization>().is_err());
+}
@@ -0,0 +1,56 @@
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+/// What `follow` waits on between reads of the file. With the notify feature, a change to it
+/// (through inotify, FSEvents, kqueue or ReadDirectoryChangesW) or `interval`, whichever comes
+/// first: network mounts don't send events, so the polling stays as the fallback. Without the
+/// feature, or when the file can't be watched, just `interval`.
+pub(crate) struct FileWatch {
+    #[cfg(feature = "notify")]
+    events: Option<(notify::RecommendedWatcher, std::sync::mpsc::Receiver<()>)>,
+}
+
+impl FileWatch {
+    #[cfg(feature = "notify")]
+    pub(crate) fn new(path: &Path) -> Self {
+        use notify::{RecursiveMode, Watcher};
+
+        // the directory rather than the file, to see it come back after a rotation
+        let (Some(name), Some(dir)) = (path.file_name().map(|name| name.to_owned()), path.parent()) else {
+            return FileWatch { events: None };
+        };
+        let dir = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+        let (tx, rx) = std::sync::mpsc::channel();
+        let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            if event.is_ok_and(|event| event.paths.iter().any(|p| p.file_name() == Some(&name))) {
+                let _ = tx.send(());
+            }
+        });
+        let events = watcher.and_then(|mut watcher| watcher.watch(dir, RecursiveMode::NonRecursive).map(|_| watcher)).ok();
+        FileWatch { events: events.map(|watcher| (watcher, rx)) }
+    }
+
+    #[cfg(not(feature = "notify"))]
+    pub(crate) fn new(_: &Path) -> Self {
+        FileWatch {}
+    }
+
+    // whether changes to the file wake wait early
+    #[cfg(all(test, feature = "notify"))]
+    pub(crate) fn is_watching(&self) -> bool {
+        self.events.is_some()
+    }
+
+    /// Returns once the file may have changed, or after `interval`.
+    pub(crate) fn wait(&self, interval: Duration) {
+        #[cfg(feature = "notify")]
+        if let Some((_, events)) = &self.events {
+            let _ = events.recv_timeout(interval);
+            // a burst of writes is one read
+            while events.try_recv().is_ok() {}
+            return;
+        }
+        thread::sleep(interval);
+    }
+}